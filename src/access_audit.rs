@@ -0,0 +1,233 @@
+//! A full, cross-referenced copy of users, cards, policies and door groups for access audits.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+
+use log::*;
+use serde::Serialize;
+
+use crate::assignments::invert_assignments;
+use crate::enrollment::is_transient;
+use crate::{
+    AccessPolicy, BulkExecutor, BulkProgress, DoorGroup, NfcCardRecord, PollingConfig, UnifiClient,
+    UnifiResult, User,
+};
+
+/// Attempts per request before [UnifiClient::audit_snapshot] gives up on a transient failure
+const AUDIT_ATTEMPTS: u32 = 3;
+
+/// The step [UnifiClient::audit_snapshot] is working on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AuditPhase {
+    Users,
+    /// Fetching each user's access policies, one request per user
+    UserPolicies,
+    NfcCards,
+    Policies,
+    DoorGroups,
+    /// Cross-referencing what was fetched
+    Linking,
+}
+
+/// Reported to the progress callback of [UnifiClient::audit_snapshot]
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditProgress {
+    pub phase: AuditPhase,
+    /// Items completed in this phase so far
+    pub done: usize,
+    /// Items in this phase, if known up front
+    pub total: Option<usize>,
+}
+
+/// Something in the controller's data that doesn't add up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum Inconsistency {
+    /// The card list says the card belongs to a user that doesn't exist
+    CardUserMissing { display_id: String, user_id: String },
+    /// A user holds a card that isn't in the card list
+    CardNotListed { user_id: String, display_id: String },
+    /// A user holds a policy that isn't in the policy list
+    PolicyMissing { user_id: String, policy_id: String },
+}
+
+/// Everything fetched by [UnifiClient::audit_snapshot], with cross-references resolved
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditSnapshot {
+    /// Users with their access policies filled in
+    pub users: Vec<User>,
+    pub nfc_cards: Vec<NfcCardRecord>,
+    pub policies: Vec<AccessPolicy>,
+    pub door_groups: Vec<DoorGroup>,
+    /// Display ids of the cards assigned to each user, by user id, according to the card list
+    pub cards_by_user: HashMap<String, Vec<String>>,
+    /// Ids of the users holding each policy, by policy id
+    pub users_by_policy: HashMap<String, Vec<String>>,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+/// Runs `operation`, trying again after a short pause while it fails transiently
+async fn with_retries<T, F, Fut>(
+    polling: &PollingConfig,
+    what: &str,
+    operation: F,
+) -> UnifiResult<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = UnifiResult<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Err(e) if is_transient(&e) && attempt + 1 < AUDIT_ATTEMPTS => {
+                warn!(
+                    "Fetching {what} failed (attempt {}), retrying: {e}",
+                    attempt + 1
+                );
+                polling.wait(attempt).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Checks the users, cards and policies against each other
+fn find_inconsistencies(
+    users: &[User],
+    nfc_cards: &[NfcCardRecord],
+    policies: &[AccessPolicy],
+) -> Vec<Inconsistency> {
+    let user_ids: HashSet<&str> = users.iter().map(|u| u.id.as_str()).collect();
+    let policy_ids: HashSet<&str> = policies.iter().map(|p| p.id.as_str()).collect();
+    let listed_tokens: HashSet<&str> = nfc_cards.iter().map(|c| c.token.expose()).collect();
+    let mut inconsistencies = vec![];
+    for card in nfc_cards {
+        if let Some(user_id) = card_owner(card) {
+            if !user_ids.contains(user_id) {
+                inconsistencies.push(Inconsistency::CardUserMissing {
+                    display_id: card.display_id.clone(),
+                    user_id: user_id.to_string(),
+                });
+            }
+        }
+    }
+    for user in users {
+        for card in &user.nfc_cards {
+            if !listed_tokens.contains(card.token.expose()) {
+                inconsistencies.push(Inconsistency::CardNotListed {
+                    user_id: user.id.clone(),
+                    display_id: card.id.clone(),
+                });
+            }
+        }
+        for policy in user.access_policies.iter().flatten() {
+            if !policy_ids.contains(policy.id.as_str()) {
+                inconsistencies.push(Inconsistency::PolicyMissing {
+                    user_id: user.id.clone(),
+                    policy_id: policy.id.clone(),
+                });
+            }
+        }
+    }
+    inconsistencies
+}
+
+/// The user a listed card is assigned to, some firmware sends an empty id for unassigned cards
+fn card_owner(card: &NfcCardRecord) -> Option<&str> {
+    card.user_id.as_deref().filter(|id| !id.is_empty())
+}
+
+impl UnifiClient {
+    /// Fetches all users with their policies, all NFC cards, all policies and all door groups,
+    /// links them together and lists any [Inconsistency] found.
+    ///
+    /// This takes one request per user, made through a [BulkExecutor], so `progress` is called as each
+    /// phase advances. Requests that fail transiently are retried a few times, after which the audit fails.
+    pub async fn audit_snapshot(
+        &self,
+        progress: impl Fn(AuditProgress) + Send + Sync + 'static,
+    ) -> UnifiResult<AuditSnapshot> {
+        let progress = Arc::new(progress);
+        let report = |phase, done, total| progress(AuditProgress { phase, done, total });
+
+        report(AuditPhase::Users, 0, None);
+        let mut users = with_retries(&self.polling, "users", || self.get_all_users()).await?;
+        report(AuditPhase::Users, users.len(), Some(users.len()));
+
+        report(AuditPhase::UserPolicies, 0, Some(users.len()));
+        let executor = BulkExecutor::default().on_progress({
+            let progress = progress.clone();
+            move |bulk: BulkProgress| {
+                progress(AuditProgress {
+                    phase: AuditPhase::UserPolicies,
+                    done: bulk.done,
+                    total: Some(bulk.total),
+                })
+            }
+        });
+        let user_policies = executor
+            .run(users.iter().map(|user| user.id.as_str()), |user_id| {
+                with_retries(&self.polling, "user policies", move || {
+                    self.access_policies_of(user_id, false)
+                })
+            })
+            .await
+            .results;
+        let user_policies: Vec<_> = user_policies
+            .into_iter()
+            .map(|(_, result)| result.expect("batch isn't cancelled"))
+            .collect::<UnifiResult<_>>()?;
+        for (user, policies) in users.iter_mut().zip(user_policies) {
+            user.access_policies = Some(policies);
+        }
+
+        report(AuditPhase::NfcCards, 0, None);
+        let nfc_cards =
+            with_retries(&self.polling, "NFC cards", || self.get_all_nfc_cards()).await?;
+        report(AuditPhase::NfcCards, nfc_cards.len(), Some(nfc_cards.len()));
+
+        report(AuditPhase::Policies, 0, None);
+        let policies = with_retries(&self.polling, "access policies", || {
+            self.get_all_access_policies()
+        })
+        .await?;
+        report(AuditPhase::Policies, policies.len(), Some(policies.len()));
+
+        report(AuditPhase::DoorGroups, 0, None);
+        let door_groups =
+            with_retries(&self.polling, "door groups", || self.get_all_door_groups()).await?;
+        report(
+            AuditPhase::DoorGroups,
+            door_groups.len(),
+            Some(door_groups.len()),
+        );
+
+        report(AuditPhase::Linking, 0, None);
+        let mut cards_by_user: HashMap<String, Vec<String>> = HashMap::new();
+        for card in &nfc_cards {
+            if let Some(user_id) = card_owner(card) {
+                cards_by_user
+                    .entry(user_id.to_string())
+                    .or_default()
+                    .push(card.display_id.clone());
+            }
+        }
+        let users_by_policy = invert_assignments(&users, true);
+        let inconsistencies = find_inconsistencies(&users, &nfc_cards, &policies);
+        if !inconsistencies.is_empty() {
+            warn!("Audit found {} inconsistencies", inconsistencies.len());
+        }
+        report(AuditPhase::Linking, 1, Some(1));
+
+        Ok(AuditSnapshot {
+            users,
+            nfc_cards,
+            policies,
+            door_groups,
+            cards_by_user,
+            users_by_policy,
+            inconsistencies,
+        })
+    }
+}
@@ -0,0 +1,226 @@
+//! Working out whether a user's credentials would open a door, and why, for "my card doesn't open the door" tickets.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use log::*;
+use serde::Serialize;
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::schedules::{Schedule, ScheduleDecision};
+use crate::{ApiErrorKind, UnifiClient, UnifiError, UnifiResult, UserStatus};
+
+/// The answer of [UnifiClient::evaluate_access]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct AccessEvaluation {
+    pub user_id: String,
+    pub door_id: String,
+    /// True if the user would be let through the door
+    pub granted: bool,
+    pub reason: AccessReason,
+    /// How each of the user's policies applies to the door, in the order the user holds them
+    pub policies: Vec<PolicyEvaluation>,
+    pub confidence: EvaluationConfidence,
+}
+
+/// What decided an [AccessEvaluation]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub enum AccessReason {
+    /// The policy grants access to the door at that time
+    PolicyMatched {
+        policy_id: String,
+        policy_name: String,
+    },
+    /// The user isn't active, so none of their credentials open anything
+    UserNotActive(UserStatus),
+    /// The door has a keep locked rule, see [crate::DoorLockRule::KeepLocked]
+    DoorKeptLocked,
+    /// The door is held unlocked by a temporary rule, anyone can open it
+    DoorUnlocked,
+    /// None of the user's policies grant access to the door at that time, see [AccessEvaluation::policies]
+    NoPolicyMatched,
+}
+
+/// How one of the user's policies applies to the door
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct PolicyEvaluation {
+    pub policy_id: String,
+    pub policy_name: String,
+    pub outcome: PolicyOutcome,
+}
+
+/// Whether a policy grants access to the door, or why not
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub enum PolicyOutcome {
+    /// Includes the door and its schedule is open at that time
+    Grants,
+    /// Doesn't include the door, neither directly nor through a door group
+    WrongDoor,
+    /// Includes the door, but its schedule is closed at that time
+    OutsideSchedule,
+    /// Includes the door, but the schedule is closed for the named holiday
+    HolidayExclusion(String),
+    /// Includes the door, but its schedule couldn't be read so it is assumed to be open
+    ScheduleUnknown,
+}
+
+/// How far an [AccessEvaluation] can be relied on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub enum EvaluationConfidence {
+    /// Everything the API exposes was checked. Schedule overrides set on an individual user in the UI
+    /// aren't exposed by the developer API, so for such users the controller may still decide otherwise.
+    MayHaveOverrides,
+    /// As [EvaluationConfidence::MayHaveOverrides], and access was granted by a policy whose schedule
+    /// couldn't be read, see [PolicyOutcome::ScheduleUnknown]
+    ScheduleUnknown,
+}
+
+impl UnifiClient {
+    /// Whether the user's credentials would open the door at `at`, or now if None, and why.
+    ///
+    /// Checks the user's status, which of their policies include the door, directly or through a door group,
+    /// and whether the schedules of those policies are open at that time, holidays included. Schedules are
+    /// read in the site's local time, see [UnifiClient::with_site_time_zone]. When evaluating now, a
+    /// temporary lock rule on the door (see [UnifiClient::set_door_lock_rule]) takes precedence, rules
+    /// can't be known for other times. Doors unlocked by their own unlock schedule, see
+    /// [UnifiClient::get_door_unlock_schedule], aren't taken into account.
+    ///
+    /// The result is never fully certain, per-user schedule overrides aren't visible through the API,
+    /// see [EvaluationConfidence]. Fails with [UnifiError::UserNotFound] for an unknown user, and
+    /// [UnifiError::Other] for a door that isn't in the building topology.
+    pub async fn evaluate_access(
+        &self,
+        user_id: &str,
+        door_id: &str,
+        at: Option<SystemTime>,
+    ) -> UnifiResult<AccessEvaluation> {
+        let (user, user_policies, all_policies, directory) = futures::try_join!(
+            self.get_user_by_id(user_id),
+            self.access_policies_of(user_id, false),
+            self.get_all_access_policies(),
+            self.door_directory(),
+        )
+        .map_err(|e| match e.kind() {
+            Some(ApiErrorKind::NotFound)
+                if e.code().is_some_and(|c| c.starts_with("CODE_USER_")) =>
+            {
+                UnifiError::UserNotFound {
+                    user: format!("id {user_id}"),
+                }
+            }
+            _ => e,
+        })?;
+        if !directory.doors.contains_key(door_id) {
+            return Err(UnifiError::Other(format!(
+                "No door with id {door_id} exists"
+            )));
+        }
+        let lock_rule = match at {
+            None => Some(self.door_lock_rule_type(door_id).await?),
+            Some(_) => None,
+        };
+        let at = at.unwrap_or_else(|| self.timestamp_now());
+
+        let mut schedules: HashMap<String, Option<Schedule>> = HashMap::new();
+        let mut policies = vec![];
+        for held in &user_policies {
+            // Policies expanded inside a user may lack their resources and schedule
+            let policy = all_policies
+                .iter()
+                .find(|p| p.id == held.id)
+                .unwrap_or(held);
+            let outcome = if !directory.resolve(policy).covers_door(door_id) {
+                PolicyOutcome::WrongDoor
+            } else {
+                // Policies often share a schedule
+                let schedule = match schedules.get(&policy.schedule_id) {
+                    Some(schedule) => schedule.clone(),
+                    None => {
+                        let schedule = self.readable_schedule(&policy.schedule_id).await?;
+                        schedules.insert(policy.schedule_id.clone(), schedule.clone());
+                        schedule
+                    }
+                };
+                match schedule {
+                    None => PolicyOutcome::ScheduleUnknown,
+                    Some(schedule) => match schedule.check(at, self.site_time_zone) {
+                        ScheduleDecision::Open | ScheduleDecision::OpenForHoliday(_) => {
+                            PolicyOutcome::Grants
+                        }
+                        ScheduleDecision::Closed => PolicyOutcome::OutsideSchedule,
+                        ScheduleDecision::ClosedForHoliday(holiday) => {
+                            PolicyOutcome::HolidayExclusion(holiday)
+                        }
+                    },
+                }
+            };
+            policies.push(PolicyEvaluation {
+                policy_id: policy.id.clone(),
+                policy_name: policy.name.clone(),
+                outcome,
+            });
+        }
+
+        // A policy known to grant access is a better answer than one assumed to
+        let matched = policies
+            .iter()
+            .find(|p| p.outcome == PolicyOutcome::Grants)
+            .or_else(|| {
+                policies
+                    .iter()
+                    .find(|p| p.outcome == PolicyOutcome::ScheduleUnknown)
+            });
+        let mut confidence = EvaluationConfidence::MayHaveOverrides;
+        let (granted, reason) = match lock_rule.as_deref() {
+            Some("keep_lock") => (false, AccessReason::DoorKeptLocked),
+            Some("keep_unlock" | "custom") => (true, AccessReason::DoorUnlocked),
+            _ if user.status != UserStatus::Active => {
+                (false, AccessReason::UserNotActive(user.status.clone()))
+            }
+            _ => match matched {
+                Some(policy) => {
+                    if policy.outcome == PolicyOutcome::ScheduleUnknown {
+                        confidence = EvaluationConfidence::ScheduleUnknown;
+                    }
+                    (
+                        true,
+                        AccessReason::PolicyMatched {
+                            policy_id: policy.policy_id.clone(),
+                            policy_name: policy.policy_name.clone(),
+                        },
+                    )
+                }
+                None => (false, AccessReason::NoPolicyMatched),
+            },
+        };
+        Ok(AccessEvaluation {
+            user_id: user_id.to_string(),
+            door_id: door_id.to_string(),
+            granted,
+            reason,
+            policies,
+            confidence,
+        })
+    }
+
+    /// The schedule with the id, None if the policy named none or it doesn't exist
+    async fn readable_schedule(&self, schedule_id: &str) -> UnifiResult<Option<Schedule>> {
+        if schedule_id.is_empty() {
+            return Ok(None);
+        }
+        match self.get_schedule(schedule_id).await {
+            Ok(schedule) => Ok(Some(schedule)),
+            Err(e) if e.kind() == Some(ApiErrorKind::NotFound) => {
+                warn!("Schedule {schedule_id} couldn't be read, assuming it is open: {e}");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
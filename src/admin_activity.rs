@@ -0,0 +1,197 @@
+//! Changes made by admins, through the UI or otherwise, from the admin activity log topic.
+//!
+//! The client's own changes are recorded by an [crate::AuditSink]; these events also cover what other
+//! admins did, so the two together account for every change when reconciling drift.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use log::*;
+
+use crate::clock::parse_rfc3339;
+use crate::{SystemLogEventWrapper, SystemLogOptions, SystemLogTopic, UnifiClient, UnifiResult};
+
+/// What an admin did, from the `event.type` of an admin activity event.
+///
+/// Types are matched on their object (`user`, `policy`...) and their last segment, the verb, so
+/// `access.user.delete` and `access.data.user.remove` are both [AdminActionKind::UserDeleted].
+/// Anything else is kept as [AdminActionKind::Unknown].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AdminActionKind {
+    UserCreated,
+    UserUpdated,
+    UserDeleted,
+    PolicyCreated,
+    PolicyUpdated,
+    PolicyDeleted,
+    /// A schedule or holiday group was created, changed or deleted
+    ScheduleChanged,
+    /// A door group was created, changed or deleted
+    DoorGroupChanged,
+    /// A card, PIN or touch pass was assigned or removed
+    CredentialChanged,
+    /// A device or door was adopted, renamed or reconfigured
+    DeviceChanged,
+    /// A door was unlocked from the UI or app
+    DoorUnlocked,
+    /// Controller settings were changed
+    SettingsChanged,
+    /// An admin signed in
+    Login,
+    /// An event type not matched, as the controller sent it. Empty if the event had no type.
+    Unknown(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verb {
+    Create,
+    Update,
+    Delete,
+}
+
+/// Verbs as they end event types
+const VERBS: &[(&str, Verb)] = &[
+    ("create", Verb::Create),
+    ("add", Verb::Create),
+    ("update", Verb::Update),
+    ("edit", Verb::Update),
+    ("modify", Verb::Update),
+    ("assign", Verb::Update),
+    ("unassign", Verb::Update),
+    ("delete", Verb::Delete),
+    ("remove", Verb::Delete),
+];
+
+impl AdminActionKind {
+    /// Maps an event type as the controller spells it, never fails
+    pub fn parse(event_type: &str) -> AdminActionKind {
+        let normalized = event_type
+            .trim()
+            .to_ascii_lowercase()
+            .replace(['-', ' '], "_");
+        let segments: Vec<&str> = normalized.split('.').collect();
+        let has = |object: &str| segments.iter().any(|s| *s == object);
+        let verb = segments
+            .last()
+            .and_then(|last| VERBS.iter().find(|(spelling, _)| spelling == last))
+            .map(|(_, verb)| *verb);
+        let kind = if has("login") || has("signin") || has("sign_in") {
+            Some(AdminActionKind::Login)
+        } else if has("unlock") || has("remote_unlock") {
+            Some(AdminActionKind::DoorUnlocked)
+        } else if has("user") {
+            verb.map(|verb| match verb {
+                Verb::Create => AdminActionKind::UserCreated,
+                Verb::Update => AdminActionKind::UserUpdated,
+                Verb::Delete => AdminActionKind::UserDeleted,
+            })
+        } else if has("policy") || has("access_policy") {
+            verb.map(|verb| match verb {
+                Verb::Create => AdminActionKind::PolicyCreated,
+                Verb::Update => AdminActionKind::PolicyUpdated,
+                Verb::Delete => AdminActionKind::PolicyDeleted,
+            })
+        } else if has("schedule") || has("holiday_group") {
+            verb.map(|_| AdminActionKind::ScheduleChanged)
+        } else if has("door_group") {
+            verb.map(|_| AdminActionKind::DoorGroupChanged)
+        } else if ["credential", "nfc_card", "pin_code", "touch_pass"]
+            .iter()
+            .any(|object| has(object))
+        {
+            verb.map(|_| AdminActionKind::CredentialChanged)
+        } else if has("device") || has("door") {
+            verb.map(|_| AdminActionKind::DeviceChanged)
+        } else if has("setting") || has("settings") {
+            verb.map(|_| AdminActionKind::SettingsChanged)
+        } else {
+            None
+        };
+        kind.unwrap_or_else(|| AdminActionKind::Unknown(event_type.to_string()))
+    }
+}
+
+impl fmt::Display for AdminActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminActionKind::Unknown(raw) => write!(f, "unknown ({raw})"),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
+
+/// A change made by an admin, see [UnifiClient::fetch_admin_activity]
+#[derive(Debug, Clone)]
+pub struct AdminAction {
+    /// Empty if the event didn't name the admin
+    pub admin_name: String,
+    /// Empty if the event didn't identify the admin
+    pub admin_id: String,
+    pub action_kind: AdminActionKind,
+    /// Type of the first target of the event (`user`, `access_policy`...), empty if it had none
+    pub target_type: String,
+    /// Display name of the first target of the event, empty if it had none
+    pub target_name: String,
+    pub timestamp: SystemTime,
+    /// The `event` block of the log event as sent, for fields not parsed here
+    pub details: serde_json::Value,
+}
+
+impl AdminAction {
+    /// The action an admin activity event records, None if its timestamp can't be parsed
+    pub fn from_event(event: &SystemLogEventWrapper) -> Option<AdminAction> {
+        let text = |value: &serde_json::Value, field: &str| {
+            value
+                .get(field)
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let source = &event.source;
+        let target = source
+            .target
+            .as_array()
+            .and_then(|targets| targets.first())
+            .unwrap_or(&serde_json::Value::Null);
+        Some(AdminAction {
+            admin_name: text(&source.actor, "display_name"),
+            admin_id: text(&source.actor, "id"),
+            action_kind: AdminActionKind::parse(&text(&source.event, "type")),
+            target_type: text(target, "type"),
+            target_name: text(target, "display_name"),
+            timestamp: parse_rfc3339(&event.timestamp)?,
+            details: source.event.clone(),
+        })
+    }
+}
+
+impl UnifiClient {
+    /// Changes made by admins between `since` and `until`, newest first.
+    /// Events whose timestamp can't be parsed are skipped with a warning.
+    pub async fn fetch_admin_activity(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> UnifiResult<Vec<AdminAction>> {
+        let events = self
+            .fetch_system_log_all(
+                SystemLogOptions::new(SystemLogTopic::AdminActivity)
+                    .since(since)
+                    .until(until),
+            )
+            .await?;
+        Ok(events
+            .iter()
+            .filter_map(|event| {
+                let action = AdminAction::from_event(event);
+                if action.is_none() {
+                    warn!(
+                        "Skipping admin activity event {} with timestamp {:?}",
+                        event.id, event.timestamp
+                    );
+                }
+                action
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,128 @@
+//! Which version of the developer API each endpoint is called on.
+
+use std::collections::HashMap;
+
+use crate::{UnifiClient, UnifiError};
+
+/// A version of the developer API, `/api/v1/developer` or `/api/v2/developer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ApiVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// The path every endpoint of this version starts with
+    pub fn base_path(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "/api/v1/developer",
+            ApiVersion::V2 => "/api/v2/developer",
+        }
+    }
+
+    /// The version after this one, None for the newest
+    pub fn next(self) -> Option<ApiVersion> {
+        match self {
+            ApiVersion::V1 => Some(ApiVersion::V2),
+            ApiVersion::V2 => None,
+        }
+    }
+}
+
+/// Where to call an endpoint that answered as moved
+pub(crate) struct EndpointMove {
+    endpoint: String,
+    pub(crate) resource: String,
+    pub(crate) from: ApiVersion,
+    pub(crate) to: ApiVersion,
+    /// The same request path on the next version
+    pub(crate) retry_path: String,
+}
+
+impl EndpointMove {
+    /// None if `api_path` isn't on a known version or there's no newer one
+    pub(crate) fn of(api_path: &str) -> Option<EndpointMove> {
+        let from = [ApiVersion::V1, ApiVersion::V2]
+            .into_iter()
+            .find(|v| api_path.starts_with(&format!("{}/", v.base_path())))?;
+        let to = from.next()?;
+        let endpoint = &api_path[from.base_path().len() + 1..];
+        Some(EndpointMove {
+            endpoint: api_path.to_string(),
+            resource: endpoint
+                .split(['/', '?'])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            from,
+            to,
+            retry_path: format!("{}/{endpoint}", to.base_path()),
+        })
+    }
+
+    pub(crate) fn into_error(self) -> UnifiError {
+        UnifiError::EndpointMoved {
+            endpoint: self.endpoint,
+            resource: self.resource,
+            from: self.from,
+            to: self.to,
+        }
+    }
+}
+
+/// The version used for each resource, see [UnifiClient::with_api_version]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ApiVersions {
+    default: ApiVersion,
+    /// By the first segment of the endpoint path, e.g. `users` or `credentials`
+    overrides: HashMap<String, ApiVersion>,
+    /// Retry requests answered as moved on the next version, see [UnifiClient::with_moved_endpoint_retry]
+    pub(crate) retry_moved: bool,
+}
+
+impl ApiVersions {
+    fn version_for(&self, endpoint: &str) -> ApiVersion {
+        let resource = endpoint.split(['/', '?']).next().unwrap_or_default();
+        self.overrides
+            .get(resource)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+impl UnifiClient {
+    /// Calls every endpoint on the given API version, defaults to [ApiVersion::V1]
+    pub fn with_api_version(mut self, version: ApiVersion) -> UnifiClient {
+        self.api_versions.default = version;
+        self
+    }
+
+    /// Calls the endpoints of one resource on a different version than the rest, for resources that
+    /// move to a new version before others.
+    /// `resource` is the first segment after the base path, e.g. `users`, `devices`, `credentials`
+    /// or `system`.
+    pub fn with_resource_api_version(mut self, resource: &str, version: ApiVersion) -> UnifiClient {
+        self.api_versions
+            .overrides
+            .insert(resource.to_string(), version);
+        self
+    }
+
+    /// Retries a request on the next API version when the controller answers that the endpoint is
+    /// deprecated or moved, off by default. With it off such requests fail with [UnifiError::EndpointMoved].
+    /// Every retried request costs a round trip, switch the resource with
+    /// [UnifiClient::with_resource_api_version] once the move is known.
+    pub fn with_moved_endpoint_retry(mut self, enabled: bool) -> UnifiClient {
+        self.api_versions.retry_moved = enabled;
+        self
+    }
+
+    /// The full path of an endpoint, e.g. `users/{id}` becomes `/api/v1/developer/users/{id}`
+    pub(crate) fn api_path(&self, endpoint: &str) -> String {
+        format!(
+            "{}/{endpoint}",
+            self.api_versions.version_for(endpoint).base_path()
+        )
+    }
+}
@@ -0,0 +1,199 @@
+//! Which users hold each access policy, and assigning policies with checks.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{AccessPolicy, UnifiClient, UnifiError, UnifiResult, User, UserStatus};
+
+impl UnifiClient {
+    /// Maps each policy id to the ids of the users holding it.
+    /// Policies nobody holds are absent from the map.
+    /// Deactivated users are skipped unless `include_deactivated` is set.
+    ///
+    /// Lists the users with their policies expanded, so a single paged request on firmware that supports it.
+    /// Fails if any user's policies can't be fetched, since a partial index would under report.
+    pub async fn get_policy_assignments(
+        &self,
+        include_deactivated: bool,
+    ) -> UnifiResult<HashMap<String, Vec<String>>> {
+        let users = self.get_all_users_expanded().await?;
+        Ok(invert_assignments(&users, include_deactivated))
+    }
+
+    /// Policies that no user holds, see [UnifiClient::get_policy_assignments]
+    pub async fn get_unused_policies(
+        &self,
+        include_deactivated: bool,
+    ) -> UnifiResult<Vec<AccessPolicy>> {
+        let assignments = self.get_policy_assignments(include_deactivated).await?;
+        Ok(self
+            .get_all_access_policies()
+            .await?
+            .into_iter()
+            .filter(|policy| !assignments.contains_key(&policy.id))
+            .collect())
+    }
+}
+
+/// Builds the policy id to user ids index from users with their policies filled in
+pub(crate) fn invert_assignments(
+    users: &[User],
+    include_deactivated: bool,
+) -> HashMap<String, Vec<String>> {
+    let mut assignments: HashMap<String, Vec<String>> = HashMap::new();
+    for user in users {
+        if !include_deactivated && user.status == UserStatus::Deactivated {
+            continue;
+        }
+        for policy in user.access_policies.iter().flatten() {
+            assignments
+                .entry(policy.id.clone())
+                .or_default()
+                .push(user.id.clone());
+        }
+    }
+    assignments
+}
+
+/// What [UnifiClient::assign_access_policies_checked] does with policy ids that don't exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownPolicies {
+    /// Return [crate::UnifiError::Validation] without changing anything
+    #[default]
+    Fail,
+    /// Assign the known policies and log a warning about the rest
+    Skip,
+}
+
+/// The outcome of [UnifiClient::assign_access_policies_checked]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssignmentResult {
+    /// Policy ids the user held before
+    pub previous: Vec<String>,
+    /// Policy ids the user holds now, as read back from the controller
+    pub current: Vec<String>,
+    /// Requested ids that didn't match any policy
+    pub unknown_ids: Vec<String>,
+}
+
+impl AssignmentResult {
+    /// False if the assignment left the user's policies as they were
+    pub fn changed(&self) -> bool {
+        let previous: BTreeSet<&String> = self.previous.iter().collect();
+        let current: BTreeSet<&String> = self.current.iter().collect();
+        previous != current
+    }
+}
+
+impl UnifiClient {
+    /// Like [UnifiClient::assign_access_policies], but checks the ids against the policy list first and
+    /// reads the user's policies back afterwards, reporting what changed.
+    /// Reading back matters because some firmware silently drops ids it doesn't know instead of failing.
+    pub async fn assign_access_policies_checked(
+        &self,
+        user_id: &str,
+        policy_ids: Vec<String>,
+        unknown: UnknownPolicies,
+    ) -> UnifiResult<AssignmentResult> {
+        let known = self.get_all_access_policies().await?;
+        self.assign_access_policies_against(user_id, policy_ids, &known, unknown)
+            .await
+    }
+
+    /// [UnifiClient::assign_access_policies_checked] with the policy list already fetched
+    pub(crate) async fn assign_access_policies_against(
+        &self,
+        user_id: &str,
+        policy_ids: Vec<String>,
+        known: &[AccessPolicy],
+        unknown: UnknownPolicies,
+    ) -> UnifiResult<AssignmentResult> {
+        let known_ids: HashSet<&str> = known.iter().map(|p| p.id.as_str()).collect();
+        let (policy_ids, unknown_ids): (Vec<String>, Vec<String>) = policy_ids
+            .into_iter()
+            .partition(|id| known_ids.contains(id.as_str()));
+        if !unknown_ids.is_empty() {
+            if unknown == UnknownPolicies::Fail {
+                return Err(UnifiError::Validation {
+                    field: "policy_ids".to_string(),
+                    reason: format!("no policies with ids {unknown_ids:?}"),
+                });
+            }
+            warn!("Not assigning unknown policies {unknown_ids:?} to user {user_id}");
+        }
+        let previous = policy_ids_of(self.get_access_policies_for_user(user_id).await?);
+        self.assign_access_policies(user_id, policy_ids.clone())
+            .await?;
+        // Nothing was sent, so reading back would only show the previous policies
+        let current = if self.dry_run {
+            policy_ids
+        } else {
+            policy_ids_of(self.get_access_policies_for_user(user_id).await?)
+        };
+        Ok(AssignmentResult {
+            previous,
+            current,
+            unknown_ids,
+        })
+    }
+}
+
+fn policy_ids_of(policies: Vec<AccessPolicy>) -> Vec<String> {
+    policies.into_iter().map(|p| p.id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn user(id: &str, status: &str, policy_ids: Option<&[&str]>) -> User {
+        let policies = policy_ids.map(|ids| {
+            ids.iter()
+                .map(|id| json!({"id": id, "name": format!("Policy {id}")}))
+                .collect::<Vec<_>>()
+        });
+        serde_json::from_value(json!({
+            "id": id,
+            "first_name": "",
+            "last_name": "",
+            "user_email": "",
+            "status": status,
+            "access_policies": policies,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn inverts_users_to_policies() {
+        let users = [
+            user("u1", "ACTIVE", Some(&["p1", "p2"])),
+            user("u2", "PENDING", Some(&["p1"])),
+            user("u3", "DEACTIVATED", Some(&["p3"])),
+            user("u4", "ACTIVE", None),
+        ];
+        let expected = HashMap::from([
+            ("p1".to_string(), vec!["u1".to_string(), "u2".to_string()]),
+            ("p2".to_string(), vec!["u1".to_string()]),
+        ]);
+        assert_eq!(invert_assignments(&users, false), expected);
+        let mut with_deactivated = expected;
+        with_deactivated.insert("p3".to_string(), vec!["u3".to_string()]);
+        assert_eq!(invert_assignments(&users, true), with_deactivated);
+    }
+
+    #[test]
+    fn changed_ignores_order() {
+        let result = |previous: &[&str], current: &[&str]| AssignmentResult {
+            previous: previous.iter().map(|id| id.to_string()).collect(),
+            current: current.iter().map(|id| id.to_string()).collect(),
+            unknown_ids: vec![],
+        };
+        assert!(!result(&["p1", "p2"], &["p2", "p1"]).changed());
+        assert!(result(&["p1"], &["p1", "p2"]).changed());
+        assert!(result(&["p1"], &[]).changed());
+    }
+}
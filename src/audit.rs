@@ -0,0 +1,166 @@
+//! Audit trail of the changes made through the client.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::UnifiResult;
+
+/// Receives an entry for every mutating operation performed through a [crate::UnifiClient].
+///
+/// Entries are emitted per public operation (e.g. "assign_access_policies to user X"),
+/// not per HTTP request, and read only operations don't produce entries.
+/// Install one with [crate::UnifiClient::with_audit_sink].
+pub trait AuditSink: Send + Sync {
+    /// Called once the operation has completed, successfully or not
+    fn record(&self, entry: AuditEntry);
+}
+
+/// A single change made (or attempted) through the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch when the operation completed
+    pub timestamp: u64,
+    /// Name of the client method, e.g. `assign_access_policies`
+    pub operation: String,
+    /// Ids of the objects affected, e.g. the user id
+    pub targets: Vec<String>,
+    /// What was sent, with credential material redacted
+    pub payload: serde_json::Value,
+    pub outcome: AuditOutcome,
+    pub duration: Duration,
+    /// True if the client was in dry run mode, so nothing was actually changed
+    pub dry_run: bool,
+}
+
+/// Whether an audited operation succeeded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditOutcome {
+    Success,
+    Failure { error: String },
+}
+
+impl AuditEntry {
+    pub(crate) fn new<T>(
+        operation: &str,
+        targets: &[&str],
+        payload: serde_json::Value,
+        result: &UnifiResult<T>,
+        duration: Duration,
+        dry_run: bool,
+    ) -> AuditEntry {
+        AuditEntry {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            operation: operation.to_string(),
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            payload: redact_json(payload),
+            outcome: match result {
+                Ok(_) => AuditOutcome::Success,
+                Err(e) => AuditOutcome::Failure {
+                    error: e.to_string(),
+                },
+            },
+            duration,
+            dry_run,
+        }
+    }
+}
+
+/// Keeps entries in memory, mostly useful for tests
+#[derive(Debug, Default)]
+pub struct InMemoryAuditSink {
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl InMemoryAuditSink {
+    /// All entries recorded so far
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+impl AuditSink for InMemoryAuditSink {
+    fn record(&self, entry: AuditEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+}
+
+/// Appends each entry as a line of JSON to a file
+#[derive(Debug)]
+pub struct JsonlAuditSink {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl JsonlAuditSink {
+    /// Appends to the file at `path`, creating it if needed
+    pub fn new(path: impl Into<PathBuf>) -> JsonlAuditSink {
+        JsonlAuditSink {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> UnifiResult<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
+
+impl AuditSink for JsonlAuditSink {
+    fn record(&self, entry: AuditEntry) {
+        // There is no caller to hand the error to, so the best we can do is make noise
+        if let Err(e) = self.append(&entry) {
+            error!(
+                "Failed to write audit entry {entry:?} to {:?}: {e}",
+                self.path
+            );
+        }
+    }
+}
+
+/// Field names whose values are credentials and must never be recorded
+const REDACTED_FIELDS: &[&str] = &["token", "pin_code", "pin"];
+
+/// Replaces the value of any credential field, at any depth, with `"[redacted]"`
+pub(crate) fn redact_json(mut value: serde_json::Value) -> serde_json::Value {
+    redact_in_place(&mut value);
+    value
+}
+
+/// A response body as it can be logged: JSON with credential fields redacted, or only the length of
+/// anything else, since it can't be inspected for tokens
+pub(crate) fn redact_body(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(value) => redact_json(value).to_string(),
+        Err(_) => format!("<{} bytes, not JSON>", body.len()),
+    }
+}
+
+fn redact_in_place(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = serde_json::Value::String("[redacted]".to_string());
+                } else {
+                    redact_in_place(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_in_place),
+        _ => {}
+    }
+}
@@ -0,0 +1,46 @@
+//! Writes the TypeScript bindings of the crate's data types into a directory.
+//!
+//! `cargo run --bin ts-export -- [--check] [DIR]`, DIR defaults to `bindings`.
+//! With `--check` nothing is written, and the exit code is non-zero if DIR is out of date, for use in CI.
+
+use std::process::ExitCode;
+
+use unifi_access::{export_typescript, stale_typescript};
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut dir = "bindings".to_string();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("Usage: ts-export [--check] [DIR]");
+                return ExitCode::SUCCESS;
+            }
+            _ => dir = arg,
+        }
+    }
+    if !check {
+        return match export_typescript(&dir) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Failed to export TypeScript bindings to {dir}: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    match stale_typescript(&dir) {
+        Ok(stale) if stale.is_empty() => ExitCode::SUCCESS,
+        Ok(stale) => {
+            eprintln!("TypeScript bindings in {dir} are out of date, rerun ts-export:");
+            for file in stale {
+                eprintln!("  {file}");
+            }
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Failed to check TypeScript bindings in {dir}: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
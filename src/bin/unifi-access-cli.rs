@@ -0,0 +1,368 @@
+//! Small admin tool built on top of the crate, doubles as a smoke test against real hardware.
+//!
+//! Reads the controller address and token from `UNIFI_HOST` and `UNIFI_TOKEN`.
+//! Build with `cargo run --features cli --bin unifi-access-cli -- --help`
+
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use unifi_access::{EnrollmentOptions, NfcCard, SystemLogOptions, SystemLogTopic, UnifiClient};
+
+#[derive(Parser)]
+#[command(about = "Administer a Unifi Access controller")]
+struct Cli {
+    /// Address of the controller, without scheme or port
+    #[arg(long, env = "UNIFI_HOST")]
+    host: String,
+    /// Developer API token created in the Unifi Access settings
+    #[arg(long, env = "UNIFI_TOKEN", hide_env_values = true)]
+    token: String,
+    /// Print results as JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+    /// Print card tokens in full instead of only their last four digits
+    #[arg(long, global = true)]
+    show_secrets: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage users
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// List access policies
+    Policies,
+    /// List devices
+    Devices,
+    /// Manage NFC cards
+    #[command(subcommand)]
+    Cards(CardsCommand),
+    /// Read the system log
+    #[command(subcommand)]
+    Log(LogCommand),
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// List all users
+    List {
+        /// Also fetch the access policies of every user (one request per user)
+        #[arg(long)]
+        with_policies: bool,
+    },
+    /// Register a new user
+    Create {
+        first_name: String,
+        last_name: String,
+        email: String,
+        employee_number: String,
+    },
+    /// Replace the access policies of a user
+    AssignPolicy {
+        user_id: String,
+        /// Policy ids to assign, pass none to remove all policies
+        policy_ids: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CardsCommand {
+    /// Enroll a card on a reader, optionally assigning it to a user
+    Enroll {
+        /// Name of the reader to enroll on
+        #[arg(long)]
+        device: String,
+        /// User to assign the card to once scanned
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Unassign and delete a card
+    Remove {
+        #[arg(long)]
+        token: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogCommand {
+    /// Print the log once
+    Show {
+        /// Topic to read, e.g. door_openings
+        #[arg(long, default_value = "all")]
+        topic: String,
+    },
+    /// Poll the log and print new events as they arrive
+    Tail {
+        #[arg(long, default_value = "door_openings")]
+        topic: String,
+        /// Seconds between polls
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+}
+
+type CliResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// How results are printed
+#[derive(Clone, Copy)]
+struct Output {
+    json: bool,
+    show_secrets: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> CliResult {
+    let client = UnifiClient::try_new(&cli.host, &cli.token)?;
+    let output = Output {
+        json: cli.json,
+        show_secrets: cli.show_secrets,
+    };
+    match cli.command {
+        Command::Users(UsersCommand::List { with_policies }) => {
+            let users = if with_policies {
+                client.get_all_users_with_access_information().await?
+            } else {
+                client.get_all_users().await?
+            };
+            if output.json {
+                let users = output.mask_tokens(serde_json::to_value(&users)?);
+                println!("{}", serde_json::to_string_pretty(&users)?);
+            } else {
+                print_table(
+                    &["ID", "NAME", "EMAIL", "EMPLOYEE #", "CARDS", "POLICIES"],
+                    users
+                        .iter()
+                        .map(|u| {
+                            vec![
+                                u.id.clone(),
+                                format!("{} {}", u.first_name, u.last_name),
+                                u.user_email.clone(),
+                                u.employee_number.clone(),
+                                u.nfc_cards.len().to_string(),
+                                u.access_policies
+                                    .as_ref()
+                                    .map(|p| {
+                                        p.iter()
+                                            .map(|p| p.name.as_str())
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    })
+                                    .unwrap_or_default(),
+                            ]
+                        })
+                        .collect(),
+                );
+            }
+        }
+        Command::Users(UsersCommand::Create {
+            first_name,
+            last_name,
+            email,
+            employee_number,
+        }) => {
+            let id = client
+                .register_user(first_name, last_name, email, employee_number)
+                .await?;
+            print_value(output.json, json!({ "id": id }), &id);
+        }
+        Command::Users(UsersCommand::AssignPolicy {
+            user_id,
+            policy_ids,
+        }) => {
+            client.assign_access_policies(&user_id, policy_ids).await?;
+        }
+        Command::Policies => {
+            let policies = client.get_all_access_policies().await?;
+            if output.json {
+                println!("{}", serde_json::to_string_pretty(&policies)?);
+            } else {
+                print_table(
+                    &["ID", "NAME"],
+                    policies
+                        .iter()
+                        .map(|p| vec![p.id.clone(), p.name.clone()])
+                        .collect(),
+                );
+            }
+        }
+        Command::Devices => {
+            let devices = client.get_devices().await?;
+            if output.json {
+                let devices: Vec<_> = devices
+                    .iter()
+                    .map(|d| json!({ "id": d.id, "name": d.name, "type": d.device_type }))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&devices)?);
+            } else {
+                print_table(
+                    &["ID", "NAME", "TYPE"],
+                    devices
+                        .iter()
+                        .map(|d| vec![d.id.clone(), d.name.clone(), d.device_type.clone()])
+                        .collect(),
+                );
+            }
+        }
+        Command::Cards(CardsCommand::Enroll { device, user }) => {
+            let devices = client.get_devices().await?;
+            let Some(device) = devices.iter().find(|d| d.name == device) else {
+                return Err(format!("No device named {device}").into());
+            };
+            eprintln!("Scan a card on {}...", device.name);
+            let enrollment = client
+                .start_enrollment(&device.id, EnrollmentOptions::default())
+                .await?;
+            let card = enrollment.wait_for_card().await?;
+            if let Some(user) = user {
+                client.assign_nfc_card(&user, &card).await?;
+            }
+            let token = output.token(card.token.expose());
+            print_value(
+                output.json,
+                json!({ "id": card.id, "token": token }),
+                &format!("{} {token}", card.id),
+            );
+        }
+        Command::Cards(CardsCommand::Remove { token }) => {
+            let card = NfcCard {
+                id: String::new(),
+                token: token.into(),
+            };
+            client.remove_nfc_card(&card).await?;
+        }
+        Command::Log(LogCommand::Show { topic }) => {
+            let events = client.fetch_system_log(parse_topic(&topic)?).await?;
+            for event in events {
+                print_event(output, &event);
+            }
+        }
+        Command::Log(LogCommand::Tail { topic, interval }) => {
+            let mut since = std::time::SystemTime::now();
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+                let now = std::time::SystemTime::now();
+                let events = client
+                    .fetch_system_log(SystemLogOptions::new(parse_topic(&topic)?).since(since))
+                    .await?;
+                for event in events {
+                    print_event(output, &event);
+                }
+                since = now;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_topic(topic: &str) -> Result<SystemLogTopic, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(topic.parse()?)
+}
+
+impl Output {
+    /// The token, or only its last four digits unless secrets are shown
+    fn token(self, token: &str) -> String {
+        if self.show_secrets {
+            return token.to_string();
+        }
+        let shown = token.len().saturating_sub(4);
+        match token.get(shown..) {
+            Some(tail) => format!("{}{tail}", "*".repeat(shown)),
+            None => "*".repeat(token.len()),
+        }
+    }
+
+    /// `value` with every `token` field, at any depth, masked as by [Output::token]
+    fn mask_tokens(self, mut value: serde_json::Value) -> serde_json::Value {
+        match &mut value {
+            serde_json::Value::Object(map) => {
+                for (key, field) in map.iter_mut() {
+                    *field = match field.as_str() {
+                        Some(token) if key == "token" => json!(self.token(token)),
+                        _ => self.mask_tokens(field.take()),
+                    };
+                }
+            }
+            serde_json::Value::Array(values) => {
+                for field in values.iter_mut() {
+                    *field = self.mask_tokens(field.take());
+                }
+            }
+            _ => {}
+        }
+        value
+    }
+}
+
+fn print_event(output: Output, event: &unifi_access::SystemLogEventWrapper) {
+    // The details of card events can include the card's token
+    let value = output.mask_tokens(json!({
+        "id": event.id,
+        "timestamp": event.timestamp,
+        "actor": event.source.actor,
+        "authentication": event.source.authentication,
+        "event": event.source.event,
+        "target": event.source.target,
+    }));
+    if output.json {
+        println!("{value}");
+    } else {
+        let message = event
+            .source
+            .event
+            .get("display_message")
+            .and_then(|m| m.as_str())
+            .unwrap_or_default();
+        let actor = event
+            .source
+            .actor
+            .get("display_name")
+            .and_then(|a| a.as_str())
+            .unwrap_or_default();
+        println!("{}  {actor}  {message}", event.timestamp);
+    }
+}
+
+fn print_value(as_json: bool, value: serde_json::Value, text: &str) {
+    if as_json {
+        println!("{value}");
+    } else {
+        println!("{text}");
+    }
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let render = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}", width = *width))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    println!("{}", render(headers.to_vec()).trim_end());
+    for row in &rows {
+        println!(
+            "{}",
+            render(row.iter().map(|c| c.as_str()).collect()).trim_end()
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Runs a simulated controller for integration tests of projects built on the developer API.
+//!
+//! Serves HTTPS with a self-signed certificate, so clients must accept invalid certificates.
+//! Build with `cargo run --features sim --bin unifi-access-sim -- --help`
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use unifi_access::{SimSeed, Simulator, DEFAULT_SIM_TOKEN};
+
+#[derive(Parser)]
+#[command(about = "Serve a simulated Unifi Access controller")]
+struct Cli {
+    /// Address to listen on, port 0 picks a free port
+    #[arg(long, default_value = "127.0.0.1:12445")]
+    addr: SocketAddr,
+    /// JSON file with the users, policies, cards etc. to start with
+    #[arg(long)]
+    seed: Option<PathBuf>,
+    /// API token clients must send
+    #[arg(long, env = "UNIFI_TOKEN", default_value = DEFAULT_SIM_TOKEN, hide_env_values = true)]
+    token: String,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let seed = match cli.seed.as_deref().map(SimSeed::from_file).transpose() {
+        Ok(seed) => seed.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("error: couldn't read seed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let handle = match Simulator::new(seed)
+        .with_token(&cli.token)
+        .start(cli.addr)
+        .await
+    {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "Simulated controller listening on https://{}",
+        handle.addr()
+    );
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("error: {e}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,194 @@
+//! Warnings for requests that are slower or larger than expected, an early sign of controller trouble.
+//!
+//! Budgets are set per endpoint with [UnifiClient::warn_if_slower_than] and
+//! [UnifiClient::warn_if_larger_than]. Every request is checked against the most specific budget of each
+//! kind matching its endpoint, and a request over budget is logged under the `unifi_access::budget` target
+//! and reported to the [crate::MetricsRecorder] if one is installed. The defaults flag any request over
+//! 10 seconds or any response over 5 MB, which a healthy controller shouldn't come near.
+
+use std::time::Duration;
+
+use log::*;
+
+use crate::UnifiClient;
+
+/// Latency budget applied to every endpoint unless a more specific one matches
+pub const DEFAULT_LATENCY_BUDGET: Duration = Duration::from_secs(10);
+
+/// Response size budget, in bytes, applied to every endpoint unless a more specific one matches
+pub const DEFAULT_SIZE_BUDGET: usize = 5 * 1024 * 1024;
+
+/// What a [BudgetExceeded] is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetKind {
+    /// Time from sending the request until the full body was received
+    Latency(Duration),
+    /// Size of the response body in bytes
+    Size(usize),
+}
+
+/// A request that went over budget, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct BudgetExceeded<'a> {
+    pub method: &'a reqwest::Method,
+    /// The templated endpoint, as in [crate::RequestMetrics::endpoint]
+    pub endpoint: &'a str,
+    /// The pattern of the budget that was exceeded
+    pub pattern: &'a str,
+    pub actual: BudgetKind,
+    pub budget: BudgetKind,
+}
+
+#[derive(Debug, Clone)]
+struct Budget {
+    pattern: String,
+    limit: BudgetKind,
+}
+
+/// The budgets of a client, the defaults first
+#[derive(Debug, Clone)]
+pub(crate) struct RequestBudgets {
+    budgets: Vec<Budget>,
+}
+
+impl Default for RequestBudgets {
+    fn default() -> Self {
+        RequestBudgets {
+            budgets: vec![
+                Budget {
+                    pattern: "*".to_string(),
+                    limit: BudgetKind::Latency(DEFAULT_LATENCY_BUDGET),
+                },
+                Budget {
+                    pattern: "*".to_string(),
+                    limit: BudgetKind::Size(DEFAULT_SIZE_BUDGET),
+                },
+            ],
+        }
+    }
+}
+
+/// How specifically `pattern` matches the templated `endpoint`, None if it doesn't.
+///
+/// Patterns are matched against the end of the endpoint a segment at a time, so `users/{id}` matches
+/// `/api/v1/developer/users/{id}`. A `*` segment matches any one segment and `{id}` matches an id.
+/// More segments, and fewer of them wildcards, are more specific.
+fn specificity(pattern: &str, endpoint: &str) -> Option<(usize, usize)> {
+    let pattern: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+    let endpoint: Vec<&str> = endpoint.trim_matches('/').split('/').collect();
+    if pattern.len() > endpoint.len() {
+        return None;
+    }
+    let tail = &endpoint[endpoint.len() - pattern.len()..];
+    let mut exact = 0;
+    for (wanted, segment) in pattern.iter().zip(tail) {
+        match *wanted {
+            "*" => {}
+            wanted if wanted == *segment => exact += 1,
+            _ => return None,
+        }
+    }
+    Some((exact, pattern.len()))
+}
+
+impl RequestBudgets {
+    fn add(&mut self, pattern: &str, limit: BudgetKind) {
+        let pattern = pattern.trim_matches('/').to_string();
+        // A later budget for the same pattern and kind replaces the earlier one
+        self.budgets.retain(|b| {
+            b.pattern != pattern
+                || std::mem::discriminant(&b.limit) != std::mem::discriminant(&limit)
+        });
+        self.budgets.push(Budget { pattern, limit });
+    }
+
+    /// The most specific budget of the same kind as `actual` for `endpoint`
+    fn budget_for(&self, endpoint: &str, actual: &BudgetKind) -> Option<&Budget> {
+        self.budgets
+            .iter()
+            .filter(|b| std::mem::discriminant(&b.limit) == std::mem::discriminant(actual))
+            .filter_map(|b| Some((specificity(&b.pattern, endpoint)?, b)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, b)| b)
+    }
+
+    /// The budgets `latency` and `size` exceed for `endpoint`
+    fn exceeded(
+        &self,
+        endpoint: &str,
+        latency: Duration,
+        size: Option<usize>,
+    ) -> Vec<(&Budget, BudgetKind)> {
+        let mut actuals = vec![BudgetKind::Latency(latency)];
+        actuals.extend(size.map(BudgetKind::Size));
+        actuals
+            .into_iter()
+            .filter_map(|actual| {
+                let budget = self.budget_for(endpoint, &actual)?;
+                let over = match (actual, budget.limit) {
+                    (BudgetKind::Latency(actual), BudgetKind::Latency(limit)) => actual > limit,
+                    (BudgetKind::Size(actual), BudgetKind::Size(limit)) => actual > limit,
+                    _ => false,
+                };
+                over.then_some((budget, actual))
+            })
+            .collect()
+    }
+}
+
+impl UnifiClient {
+    /// Warns about requests to endpoints matching `endpoint_pattern` that take longer than `budget`.
+    ///
+    /// Patterns match the end of the templated endpoint a segment at a time, e.g. `users/{id}/access_policies`,
+    /// `system/logs` or `users/*` where `*` is any one segment. The most specific matching pattern applies,
+    /// so this can also raise the default of [DEFAULT_LATENCY_BUDGET] for a slow endpoint. Requests over
+    /// budget are logged under the `unifi_access::budget` target and reported to the [crate::MetricsRecorder].
+    pub fn warn_if_slower_than(mut self, endpoint_pattern: &str, budget: Duration) -> UnifiClient {
+        self.budgets
+            .add(endpoint_pattern, BudgetKind::Latency(budget));
+        self
+    }
+
+    /// Warns about responses from endpoints matching `endpoint_pattern` larger than `bytes`,
+    /// see [UnifiClient::warn_if_slower_than] for the patterns
+    pub fn warn_if_larger_than(mut self, endpoint_pattern: &str, bytes: usize) -> UnifiClient {
+        self.budgets.add(endpoint_pattern, BudgetKind::Size(bytes));
+        self
+    }
+
+    /// Checks a completed request against the budgets, `size` is None if no response was received
+    pub(crate) fn check_budgets(
+        &self,
+        method: &reqwest::Method,
+        endpoint: &str,
+        latency: Duration,
+        size: Option<usize>,
+    ) {
+        for (budget, actual) in self.budgets.exceeded(endpoint, latency, size) {
+            match (actual, budget.limit) {
+                (BudgetKind::Latency(actual), BudgetKind::Latency(limit)) => warn!(
+                    target: "unifi_access::budget",
+                    "Slow request: method={method} endpoint={endpoint} duration_ms={} budget_ms={} pattern={}",
+                    actual.as_millis(),
+                    limit.as_millis(),
+                    budget.pattern
+                ),
+                (BudgetKind::Size(actual), BudgetKind::Size(limit)) => warn!(
+                    target: "unifi_access::budget",
+                    "Large response: method={method} endpoint={endpoint} bytes={actual} budget_bytes={limit} pattern={}",
+                    budget.pattern
+                ),
+                _ => {}
+            }
+            if let Some(recorder) = &self.metrics {
+                recorder.record_budget_exceeded(&BudgetExceeded {
+                    method,
+                    endpoint,
+                    pattern: &budget.pattern,
+                    actual,
+                    budget: budget.limit,
+                });
+            }
+        }
+    }
+}
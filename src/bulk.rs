@@ -0,0 +1,311 @@
+//! Running batches of client calls, and deactivating many users at once.
+//!
+//! [BulkExecutor] is what every bulk helper of the crate runs on, and can run a batch of any client calls:
+//! it limits how many run at once and optionally how fast they start, reports progress, stops starting
+//! new operations once cancelled, and returns each operation's result in input order.
+//!
+//! The exception is [QueuedUnifiClient::replay_pending](crate::QueuedUnifiClient::replay_pending), which
+//! must apply mutations one at a time and in order. [UnifiClient::audit_policy_drift] only reads a single
+//! list, so it has no batch to run.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::*;
+
+use crate::{
+    UnifiClient, UnifiError, UnifiResult, User, UserStatus, UserUpdate, MAX_CONCURRENT_REQUESTS,
+};
+
+/// What [UnifiClient::bulk_deactivate_users] does to each user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeactivateMode {
+    /// Set the user's status to [UserStatus::Deactivated]
+    SetStatus,
+    /// Remove all of the user's access policies, leaving the account active
+    StripPolicies,
+    /// Remove the policies, then deactivate
+    #[default]
+    Both,
+}
+
+/// Stops a batch run by a [BulkExecutor] from starting more operations, clones share the same state.
+/// Operations already running finish.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Passed to the [BulkExecutor::on_progress] callback each time an operation finishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkProgress {
+    /// Operations finished, successfully or not
+    pub done: usize,
+    /// Operations in the batch
+    pub total: usize,
+    /// Operations that failed so far
+    pub failures: usize,
+}
+
+/// Result of a batch, every operation with its outcome in the order they were given
+#[derive(Debug)]
+pub struct BulkResult<Op, Outcome> {
+    /// The outcome is None for operations that weren't started because the batch was cancelled
+    pub results: Vec<(Op, Option<UnifiResult<Outcome>>)>,
+}
+
+impl<Op, Outcome> BulkResult<Op, Outcome> {
+    pub fn succeeded(&self) -> impl Iterator<Item = (&Op, &Outcome)> {
+        self.results
+            .iter()
+            .filter_map(|(op, outcome)| match outcome {
+                Some(Ok(outcome)) => Some((op, outcome)),
+                _ => None,
+            })
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&Op, &UnifiError)> {
+        self.results
+            .iter()
+            .filter_map(|(op, outcome)| match outcome {
+                Some(Err(e)) => Some((op, e)),
+                _ => None,
+            })
+    }
+
+    /// Operations skipped because the batch was cancelled
+    pub fn not_started(&self) -> impl Iterator<Item = &Op> {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| outcome.is_none())
+            .map(|(op, _)| op)
+    }
+
+    /// True if every operation ran and succeeded
+    pub fn all_succeeded(&self) -> bool {
+        self.results
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Some(Ok(_))))
+    }
+}
+
+/// Runs a batch of operations, see the [module docs](self).
+/// By default up to [MAX_CONCURRENT_REQUESTS] run at once, without a rate limit.
+pub struct BulkExecutor {
+    concurrency: usize,
+    start_interval: Option<Duration>,
+    progress: Option<Box<dyn Fn(BulkProgress) + Send + Sync>>,
+    cancellation: CancellationToken,
+}
+
+impl fmt::Debug for BulkExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BulkExecutor")
+            .field("concurrency", &self.concurrency)
+            .field("start_interval", &self.start_interval)
+            .field("cancellation", &self.cancellation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for BulkExecutor {
+    fn default() -> BulkExecutor {
+        BulkExecutor {
+            concurrency: MAX_CONCURRENT_REQUESTS,
+            start_interval: None,
+            progress: None,
+            cancellation: CancellationToken::default(),
+        }
+    }
+}
+
+impl BulkExecutor {
+    pub fn new() -> BulkExecutor {
+        BulkExecutor::default()
+    }
+
+    /// Runs at most `limit` operations at once, at least 1
+    pub fn concurrency(mut self, limit: usize) -> BulkExecutor {
+        self.concurrency = limit.max(1);
+        self
+    }
+
+    /// Starts at most `per_second` operations per second, on top of the concurrency limit
+    pub fn rate_limit(mut self, per_second: f64) -> BulkExecutor {
+        self.start_interval = (per_second > 0.0).then(|| Duration::from_secs_f64(1.0 / per_second));
+        self
+    }
+
+    /// Calls `progress` each time an operation finishes
+    pub fn on_progress(
+        mut self,
+        progress: impl Fn(BulkProgress) + Send + Sync + 'static,
+    ) -> BulkExecutor {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Stops starting operations once `token` is cancelled
+    pub fn cancellation(mut self, token: CancellationToken) -> BulkExecutor {
+        self.cancellation = token;
+        self
+    }
+
+    /// Runs `operation` for each of `ops`. A failing operation doesn't stop the others.
+    pub async fn run<Op, Outcome, F, Fut>(
+        &self,
+        ops: impl IntoIterator<Item = Op>,
+        operation: F,
+    ) -> BulkResult<Op, Outcome>
+    where
+        Op: Clone,
+        F: Fn(Op) -> Fut,
+        Fut: Future<Output = UnifiResult<Outcome>>,
+    {
+        let ops: Vec<Op> = ops.into_iter().collect();
+        let total = ops.len();
+        let mut outcomes: Vec<Option<UnifiResult<Outcome>>> = (0..total).map(|_| None).collect();
+        let mut pending = ops.iter().cloned().enumerate();
+        let mut in_flight = FuturesUnordered::new();
+        let mut next_start = tokio::time::Instant::now();
+        let mut progress = BulkProgress {
+            done: 0,
+            total,
+            failures: 0,
+        };
+        loop {
+            while in_flight.len() < self.concurrency && !self.cancellation.is_cancelled() {
+                let Some((index, op)) = pending.next() else {
+                    break;
+                };
+                // Each operation waits for its start slot, so waiting doesn't hold up the running ones
+                let start_at = next_start;
+                if let Some(interval) = self.start_interval {
+                    next_start = next_start.max(tokio::time::Instant::now()) + interval;
+                }
+                let cancellation = &self.cancellation;
+                let operation = &operation;
+                in_flight.push(async move {
+                    tokio::time::sleep_until(start_at).await;
+                    if cancellation.is_cancelled() {
+                        return (index, None);
+                    }
+                    (index, Some(operation(op).await))
+                });
+            }
+            let Some((index, outcome)) = in_flight.next().await else {
+                break;
+            };
+            if let Some(outcome) = &outcome {
+                progress.done += 1;
+                if outcome.is_err() {
+                    progress.failures += 1;
+                }
+                if let Some(report) = &self.progress {
+                    report(progress);
+                }
+            }
+            outcomes[index] = outcome;
+        }
+        if self.cancellation.is_cancelled() {
+            info!(
+                "Bulk operation cancelled after {} of {total} operations",
+                progress.done
+            );
+        }
+        BulkResult {
+            results: ops.into_iter().zip(outcomes).collect(),
+        }
+    }
+}
+
+impl UnifiClient {
+    /// Deactivates the given users, up to [MAX_CONCURRENT_REQUESTS] at a time.
+    /// A failure for one user doesn't stop the others, see [BulkResult].
+    ///
+    /// With dry run enabled nothing is changed, the users are reported as succeeded
+    /// and the requests can be read back with [UnifiClient::planned_requests].
+    pub async fn bulk_deactivate_users(
+        &self,
+        user_ids: &[&str],
+        mode: DeactivateMode,
+    ) -> UnifiResult<BulkResult<String, ()>> {
+        self.bulk_deactivate_users_with(user_ids, mode, &BulkExecutor::default())
+            .await
+    }
+
+    /// Like [UnifiClient::bulk_deactivate_users], with the concurrency, progress reporting and
+    /// cancellation of `executor`
+    pub async fn bulk_deactivate_users_with(
+        &self,
+        user_ids: &[&str],
+        mode: DeactivateMode,
+        executor: &BulkExecutor,
+    ) -> UnifiResult<BulkResult<String, ()>> {
+        info!("Deactivating {} users ({mode:?})", user_ids.len());
+        let result = executor
+            .run(
+                user_ids.iter().map(|id| id.to_string()),
+                move |user_id| async move { self.deactivate_user(&user_id, mode).await },
+            )
+            .await;
+        for (user_id, e) in result.failed() {
+            warn!("Failed to deactivate user {user_id}: {e}");
+        }
+        Ok(result)
+    }
+
+    /// Fetches every user and deactivates those matching `predicate`, see [UnifiClient::bulk_deactivate_users].
+    /// Users that are already deactivated are skipped when only the status would change.
+    pub async fn bulk_deactivate_where(
+        &self,
+        predicate: impl Fn(&User) -> bool,
+        mode: DeactivateMode,
+    ) -> UnifiResult<BulkResult<String, ()>> {
+        let users = self.get_all_users().await?;
+        let user_ids: Vec<&str> = users
+            .iter()
+            .filter(|user| {
+                !(mode == DeactivateMode::SetStatus && user.status == UserStatus::Deactivated)
+            })
+            .filter(|user| predicate(user))
+            .map(|user| user.id.as_str())
+            .collect();
+        self.bulk_deactivate_users(&user_ids, mode).await
+    }
+
+    async fn deactivate_user(&self, user_id: &str, mode: DeactivateMode) -> UnifiResult<()> {
+        if mode != DeactivateMode::SetStatus {
+            self.remove_all_access_policies_from_user(user_id).await?;
+        }
+        if mode != DeactivateMode::StripPolicies {
+            self.update_user(
+                user_id,
+                &UserUpdate {
+                    status: Some(UserStatus::Deactivated),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
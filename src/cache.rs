@@ -0,0 +1,240 @@
+//! Opt-in caching layer for the read heavy parts of the API.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Tokio's clock rather than std's, so it follows time paused in tests
+use tokio::time::Instant;
+
+use crate::{
+    AccessPolicy, AssignmentResult, Device, NfcCard, UnifiClient, UnifiResult, UnknownPolicies,
+    User,
+};
+
+/// How long each kind of resource is served from the cache before being re-fetched
+#[derive(Debug, Clone)]
+pub struct CacheTtls {
+    /// Applies to the users list and individual user lookups
+    pub users: Duration,
+    /// Applies to the policy list and each user's policies
+    pub policies: Duration,
+    pub devices: Duration,
+}
+
+impl Default for CacheTtls {
+    fn default() -> Self {
+        CacheTtls {
+            users: Duration::from_secs(60),
+            policies: Duration::from_secs(60 * 60),
+            devices: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+struct CacheEntry<T> {
+    value: T,
+    fetched: Instant,
+}
+
+/// The lock is held for the duration of a fetch, so concurrent requests for a cold entry
+/// wait for the first one instead of all hitting the controller
+type Slot<T> = tokio::sync::Mutex<Option<CacheEntry<T>>>;
+
+/// Wraps a [UnifiClient] and serves reads from an in memory cache.
+///
+/// Mutations made through this wrapper invalidate the entries they affect.
+/// Changes made by anything else (the Unifi UI, other clients) are only seen once the TTL expires,
+/// use [CachedUnifiClient::invalidate_all] to force a refresh.
+pub struct CachedUnifiClient {
+    client: UnifiClient,
+    ttls: CacheTtls,
+    users: Slot<Vec<User>>,
+    policies: Slot<Vec<AccessPolicy>>,
+    devices: Slot<Vec<Device>>,
+    user_by_id: std::sync::Mutex<HashMap<String, Arc<Slot<User>>>>,
+    user_policies: std::sync::Mutex<HashMap<String, Arc<Slot<Vec<AccessPolicy>>>>>,
+}
+
+impl CachedUnifiClient {
+    /// Wraps an existing client with the given TTLs
+    pub fn new(client: UnifiClient, ttls: CacheTtls) -> CachedUnifiClient {
+        CachedUnifiClient {
+            client,
+            ttls,
+            users: Slot::default(),
+            policies: Slot::default(),
+            devices: Slot::default(),
+            user_by_id: Default::default(),
+            user_policies: Default::default(),
+        }
+    }
+
+    /// Access to the underlying client for operations that aren't cached.
+    /// Mutations made directly on the client do not invalidate the cache.
+    pub fn client(&self) -> &UnifiClient {
+        &self.client
+    }
+
+    /// Drops every cached entry, the next read of each resource goes to the controller
+    pub async fn invalidate_all(&self) {
+        *self.users.lock().await = None;
+        *self.policies.lock().await = None;
+        *self.devices.lock().await = None;
+        self.user_by_id.lock().unwrap().clear();
+        self.user_policies.lock().unwrap().clear();
+    }
+
+    /// Cached version of [UnifiClient::get_all_users]
+    pub async fn get_all_users(&self) -> UnifiResult<Vec<User>> {
+        cached(&self.users, self.ttls.users, || self.client.get_all_users()).await
+    }
+
+    /// Cached version of [UnifiClient::get_all_access_policies]
+    pub async fn get_all_access_policies(&self) -> UnifiResult<Vec<AccessPolicy>> {
+        cached(&self.policies, self.ttls.policies, || {
+            self.client.get_all_access_policies()
+        })
+        .await
+    }
+
+    /// Cached version of [UnifiClient::get_devices]
+    pub async fn get_devices(&self) -> UnifiResult<Vec<Device>> {
+        cached(&self.devices, self.ttls.devices, || {
+            self.client.get_devices()
+        })
+        .await
+    }
+
+    /// Cached version of [UnifiClient::get_user_by_id]
+    pub async fn get_user_by_id(&self, user_id: &str) -> UnifiResult<User> {
+        let slot = slot_for(&self.user_by_id, user_id);
+        cached(&*slot, self.ttls.users, || {
+            self.client.get_user_by_id(user_id)
+        })
+        .await
+    }
+
+    /// Cached version of [UnifiClient::get_access_policies_for_user]
+    pub async fn get_access_policies_for_user(
+        &self,
+        user_id: &str,
+    ) -> UnifiResult<Vec<AccessPolicy>> {
+        let slot = slot_for(&self.user_policies, user_id);
+        cached(&*slot, self.ttls.policies, || {
+            self.client.get_access_policies_for_user(user_id)
+        })
+        .await
+    }
+
+    /// Calls [UnifiClient::register_user] and invalidates the users list
+    pub async fn register_user(
+        &self,
+        first_name: String,
+        last_name: String,
+        email: String,
+        employee_number: String,
+    ) -> UnifiResult<String> {
+        let result = self
+            .client
+            .register_user(first_name, last_name, email, employee_number)
+            .await;
+        *self.users.lock().await = None;
+        result
+    }
+
+    /// Calls [UnifiClient::assign_access_policies] and invalidates that user's entries
+    pub async fn assign_access_policies(
+        &self,
+        user_id: &str,
+        policy_ids: Vec<String>,
+    ) -> UnifiResult<()> {
+        let result = self
+            .client
+            .assign_access_policies(user_id, policy_ids)
+            .await;
+        self.invalidate_user(user_id).await;
+        result
+    }
+
+    /// [UnifiClient::assign_access_policies_checked] using the cached policy list, invalidates that user's entries
+    pub async fn assign_access_policies_checked(
+        &self,
+        user_id: &str,
+        policy_ids: Vec<String>,
+        unknown: UnknownPolicies,
+    ) -> UnifiResult<AssignmentResult> {
+        let known = self.get_all_access_policies().await?;
+        let result = self
+            .client
+            .assign_access_policies_against(user_id, policy_ids, &known, unknown)
+            .await;
+        self.invalidate_user(user_id).await;
+        result
+    }
+
+    /// Calls [UnifiClient::remove_all_access_policies_from_user] and invalidates that user's entries
+    pub async fn remove_all_access_policies_from_user(&self, user_id: &str) -> UnifiResult<()> {
+        let result = self
+            .client
+            .remove_all_access_policies_from_user(user_id)
+            .await;
+        self.invalidate_user(user_id).await;
+        result
+    }
+
+    /// Calls [UnifiClient::assign_nfc_card] and invalidates that user's entries
+    pub async fn assign_nfc_card(&self, user_id: &str, card: &NfcCard) -> UnifiResult<()> {
+        let result = self.client.assign_nfc_card(user_id, card).await;
+        self.invalidate_user(user_id).await;
+        result
+    }
+
+    /// Calls [UnifiClient::remove_nfc_card] and invalidates all user entries,
+    /// since we don't know locally who the card belonged to
+    pub async fn remove_nfc_card(&self, card: &NfcCard) -> UnifiResult<()> {
+        let result = self.client.remove_nfc_card(card).await;
+        *self.users.lock().await = None;
+        self.user_by_id.lock().unwrap().clear();
+        result
+    }
+
+    /// Invalidation is done even if the mutation failed, as a failed request may still have been applied
+    async fn invalidate_user(&self, user_id: &str) {
+        *self.users.lock().await = None;
+        self.user_by_id.lock().unwrap().remove(user_id);
+        self.user_policies.lock().unwrap().remove(user_id);
+    }
+}
+
+/// Gets or creates the slot for a key, the map lock is only held long enough to clone the Arc
+fn slot_for<T>(map: &std::sync::Mutex<HashMap<String, Arc<Slot<T>>>>, key: &str) -> Arc<Slot<T>> {
+    map.lock()
+        .unwrap()
+        .entry(key.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Returns the cached value if still fresh, otherwise fetches and stores a new one.
+/// Errors are not cached.
+async fn cached<T, F, Fut>(slot: &Slot<T>, ttl: Duration, fetch: F) -> UnifiResult<T>
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = UnifiResult<T>>,
+{
+    let mut entry = slot.lock().await;
+    if let Some(entry) = entry.as_ref() {
+        if entry.fetched.elapsed() < ttl {
+            return Ok(entry.value.clone());
+        }
+    }
+    let value = fetch().await?;
+    *entry = Some(CacheEntry {
+        value: value.clone(),
+        fetched: Instant::now(),
+    });
+    Ok(value)
+}
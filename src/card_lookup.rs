@@ -0,0 +1,136 @@
+//! Finding a card from what a person holding it can see, and who it belongs to.
+//!
+//! A card has two identifiers. The display id is the number printed on it and shown in the UI
+//! ([NfcCard::id], [NfcCardRecord::display_id]); the token is what the card sends to readers and what
+//! the controller's card endpoints are keyed by. Someone handing in a found fob can only read the
+//! display id, so lookups here start from that and fall back to treating the input as a token.
+
+use log::*;
+use serde::Deserialize;
+
+use crate::card_token::normalized_or_raw;
+use crate::{
+    encode_path_segment, sort_by_name_then_id, ApiErrorKind, CardToken, NfcCard,
+    NfcCardListOptions, NfcCardRecord, Secret, UnifiClient, UnifiError, UnifiResult, User,
+};
+
+impl NfcCardRecord {
+    /// The card in the form the assignment and removal methods take
+    pub fn card(&self) -> NfcCard {
+        NfcCard {
+            id: self.display_id.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+impl UnifiClient {
+    /// Cards whose display id or token contains `keyword`, sorted by display id.
+    ///
+    /// The keyword is passed to the controller's card list filter. Firmware that ignores the filter
+    /// returns every card, so the results are also filtered here and are the same either way.
+    pub async fn search_nfc_cards(&self, keyword: &str) -> UnifiResult<Vec<NfcCardRecord>> {
+        let keyword = keyword.trim();
+        if keyword.is_empty() {
+            return Err(UnifiError::Validation {
+                field: "keyword".to_string(),
+                reason: "must not be empty".to_string(),
+            });
+        }
+        let token_keyword = normalized_or_raw(keyword);
+        let mut cards: Vec<NfcCardRecord> = self
+            .generic_request_all_pages(
+                &self.api_path("credentials/nfc_cards/tokens"),
+                &NfcCardListOptions::new().keyword(keyword),
+            )
+            .await?;
+        cards.retain(|card| {
+            card.display_id.contains(keyword)
+                || normalized_or_raw(card.token.expose()).contains(&token_keyword)
+        });
+        sort_by_name_then_id(&mut cards, |c| (&c.display_id, c.token.expose()));
+        Ok(cards)
+    }
+
+    /// The card with the display id or token `display_id_or_token` and the user it is assigned to,
+    /// None if no card matches.
+    ///
+    /// Cards with exactly that display id are searched for first. Failing that, and if the input is a
+    /// valid token, the card is fetched by token. The user is None for an unassigned card, or one
+    /// assigned to a user that no longer exists.
+    pub async fn identify_card_owner(
+        &self,
+        display_id_or_token: &str,
+    ) -> UnifiResult<Option<(NfcCardRecord, Option<User>)>> {
+        let input = display_id_or_token.trim();
+        let by_display_id = self
+            .search_nfc_cards(input)
+            .await?
+            .into_iter()
+            .find(|card| card.display_id == input);
+        let card = match by_display_id {
+            Some(card) => card,
+            None => match self.nfc_card_by_token(input).await? {
+                Some(card) => card,
+                None => return Ok(None),
+            },
+        };
+        let user = match &card.user_id {
+            Some(user_id) => match self.get_user_by_id(user_id).await {
+                Ok(user) => Some(user),
+                Err(e) if e.kind() == Some(ApiErrorKind::NotFound) => {
+                    warn!(
+                        "Card {} is assigned to missing user {user_id}",
+                        card.display_id
+                    );
+                    None
+                }
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+        Ok(Some((card, user)))
+    }
+
+    /// The card with the token, None if the input isn't a valid token or no card has it
+    async fn nfc_card_by_token(&self, token: &str) -> UnifiResult<Option<NfcCardRecord>> {
+        // Only the user id is sent by all firmware, the rest is filled in from the request
+        #[derive(Debug, Deserialize)]
+        struct TokenDetail {
+            #[serde(default)]
+            display_id: Option<String>,
+            #[serde(default)]
+            user_id: Option<String>,
+        }
+        let Ok(token) = CardToken::parse(token) else {
+            return Ok(None);
+        };
+        let token = token.normalized().expose().to_string();
+        let detail: UnifiResult<TokenDetail> = self
+            .generic_request(
+                reqwest::Method::GET,
+                self.api_path(&format!(
+                    "credentials/nfc_cards/tokens/{}",
+                    encode_path_segment(&token)
+                )),
+                None,
+            )
+            .await;
+        match detail {
+            Ok(detail) => Ok(Some(NfcCardRecord {
+                display_id: detail.display_id.unwrap_or_default(),
+                token: Secret::new(&token),
+                user_id: detail.user_id.filter(|id| !id.is_empty()),
+            })),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    Some(ApiErrorKind::NotFound | ApiErrorKind::InvalidParams)
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Normalizing NFC card tokens, so a card is found however its token was written down.
+//!
+//! Tokens are hex strings, but arrive in different shapes: enrollment and imports differ in casing,
+//! and tokens typed in by hand often have `:`, `-` or spaces between bytes. The controller only
+//! matches its own form, lowercase hex without separators, so every card method normalizes first.
+//!
+//! Known token lengths, in hex digits, are 8, 14 and 20 for the 4, 7 and 10 byte UIDs of third party
+//! cards, and 64 for UniFi cards. Other even lengths in between are accepted, as new card types
+//! appear, but odd lengths can't be whole bytes and are rejected as ambiguous.
+
+use crate::{Secret, UnifiError, UnifiResult};
+
+/// Shortest token accepted, a 4 byte UID
+const MIN_TOKEN_DIGITS: usize = 8;
+/// Longest token accepted, a UniFi card
+const MAX_TOKEN_DIGITS: usize = 64;
+
+fn invalid(reason: &str) -> UnifiError {
+    UnifiError::Validation {
+        field: "token".to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// A card token in the controller's form, with the form it was given in kept for display
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CardToken {
+    normalized: Secret,
+    original: Secret,
+}
+
+impl CardToken {
+    /// Normalizes `token`, failing with [UnifiError::Validation] if it isn't a plausible card token.
+    ///
+    /// A leading `0x` and any `:`, `-`, `.` or whitespace separators are removed and letters lowercased,
+    /// e.g. `04:A2:3B:C1` becomes `04a23bc1`.
+    pub fn parse(token: &str) -> UnifiResult<CardToken> {
+        let trimmed = token.trim();
+        let digits = trimmed
+            .strip_prefix("0x")
+            .or_else(|| trimmed.strip_prefix("0X"))
+            .unwrap_or(trimmed);
+        let normalized: String = digits
+            .chars()
+            .filter(|c| !matches!(c, ':' | '-' | '.') && !c.is_whitespace())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if normalized.is_empty() {
+            return Err(invalid("must not be empty"));
+        }
+        if !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid("must be hexadecimal"));
+        }
+        if normalized.len() % 2 != 0 {
+            return Err(invalid(
+                "has an odd number of hex digits, a digit is missing or extra",
+            ));
+        }
+        if !(MIN_TOKEN_DIGITS..=MAX_TOKEN_DIGITS).contains(&normalized.len()) {
+            return Err(invalid(&format!(
+                "must have between {MIN_TOKEN_DIGITS} and {MAX_TOKEN_DIGITS} hex digits, has {}",
+                normalized.len()
+            )));
+        }
+        Ok(CardToken {
+            normalized: Secret::new(normalized),
+            original: Secret::new(token),
+        })
+    }
+
+    /// The token as the controller stores it
+    pub fn normalized(&self) -> &Secret {
+        &self.normalized
+    }
+
+    /// The token exactly as it was given
+    pub fn original(&self) -> &Secret {
+        &self.original
+    }
+}
+
+/// The normalized form of `token`, or `token` itself if it can't be normalized.
+/// For comparing tokens where an invalid one shouldn't be an error, e.g. tokens read back from the controller.
+pub(crate) fn normalized_or_raw(token: &str) -> String {
+    CardToken::parse(token)
+        .map(|t| t.normalized.expose().to_string())
+        .unwrap_or_else(|_| token.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(token: &str) -> String {
+        CardToken::parse(token)
+            .unwrap()
+            .normalized()
+            .expose()
+            .to_string()
+    }
+
+    fn rejection(token: &str) -> String {
+        match CardToken::parse(token) {
+            Err(UnifiError::Validation { field, reason }) => {
+                assert_eq!(field, "token");
+                reason
+            }
+            other => panic!("{token:?} wasn't rejected: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn separators_prefix_and_case_are_normalized() {
+        for token in [
+            "04a23bc1",
+            "04:A2:3B:C1",
+            "04-a2-3b-c1",
+            "04.A2.3B.C1",
+            " 04 a2\t3b c1\n",
+            "0x04A23BC1",
+            "0X04:a2:3b:c1",
+        ] {
+            assert_eq!(normalized(token), "04a23bc1", "{token:?}");
+        }
+        let token = CardToken::parse("04:A2:3B:C1").unwrap();
+        assert_eq!(token.original().expose(), "04:A2:3B:C1");
+    }
+
+    #[test]
+    fn odd_lengths_are_rejected() {
+        assert!(rejection("04a23bc").contains("odd number"));
+        assert!(rejection("04:a2:3b:c1:5").contains("odd number"));
+    }
+
+    #[test]
+    fn non_hex_characters_are_rejected() {
+        for token in [
+            "04a23bcg",
+            "ab/cd/ef/01",
+            "a b+c1234",
+            "04a2é3bc",
+            "../../users",
+            "04_a2_3b_c1",
+        ] {
+            assert_eq!(rejection(token), "must be hexadecimal", "{token:?}");
+        }
+        for token in ["", "  ", "0x", "::--"] {
+            assert_eq!(rejection(token), "must not be empty", "{token:?}");
+        }
+    }
+
+    #[test]
+    fn lengths_are_bounded() {
+        assert!(rejection("04a23b").contains("has 6"));
+        assert_eq!(normalized("04a23bc1"), "04a23bc1");
+        assert_eq!(normalized(&"ab".repeat(32)), "ab".repeat(32));
+        assert!(rejection(&"ab".repeat(33)).contains("has 66"));
+    }
+
+    #[test]
+    fn invalid_tokens_are_compared_raw() {
+        assert_eq!(normalized_or_raw("04:A2:3B:C1"), "04a23bc1");
+        assert_eq!(normalized_or_raw("not a token"), "not a token");
+    }
+}
@@ -0,0 +1,60 @@
+//! Detecting which users changed between two polls, without comparing whole user lists by hand.
+
+use std::collections::HashMap;
+
+use crate::{UnifiClient, UnifiResult, User};
+
+/// Content hashes of users by id, as returned in [UserChanges::hashes]
+pub type UserHashes = HashMap<String, u64>;
+
+/// Result of [UnifiClient::get_users_changed_since]
+#[derive(Debug, Clone, Default)]
+pub struct UserChanges {
+    /// Users that are new or differ from the previous poll
+    pub changed: Vec<User>,
+    /// Ids of users that existed at the previous poll and don't anymore
+    pub removed: Vec<String>,
+    /// Hashes of every current user, pass these to the next call
+    pub hashes: UserHashes,
+}
+
+/// Hashes the user as the list endpoint returns it.
+/// FNV-1a rather than the std hasher so stored hashes stay valid across Rust versions.
+fn user_hash(user: &User) -> u64 {
+    let bytes = serde_json::to_vec(user).unwrap_or_default();
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Compares the current users against the hashes from the previous poll
+fn compare_users(users: Vec<User>, previous: &UserHashes) -> UserChanges {
+    let mut changes = UserChanges::default();
+    for user in users {
+        let hash = user_hash(&user);
+        changes.hashes.insert(user.id.clone(), hash);
+        if previous.get(&user.id) != Some(&hash) {
+            changes.changed.push(user);
+        }
+    }
+    changes.removed = previous
+        .keys()
+        .filter(|id| !changes.hashes.contains_key(*id))
+        .cloned()
+        .collect();
+    changes
+}
+
+impl UnifiClient {
+    /// The users that were added, edited or removed since the poll that produced `previous`.
+    /// Pass an empty map the first time, every user is then reported as changed.
+    ///
+    /// The controller has no updated-at filter, so every page of users is still fetched,
+    /// but callers only need to process what changed. A change is never missed, but a user may be
+    /// reported as changed without a visible difference, e.g. after a crate upgrade adds a field.
+    /// Access policies aren't part of the user list, so policy changes aren't detected.
+    pub async fn get_users_changed_since(&self, previous: &UserHashes) -> UnifiResult<UserChanges> {
+        let users = self.get_all_users().await?;
+        Ok(compare_users(users, previous))
+    }
+}
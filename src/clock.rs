@@ -0,0 +1,299 @@
+//! Measuring how far the local clock is from the controller's.
+//!
+//! Every response with a `Date` header updates the measurement, so it stays current without extra requests.
+//! The header has a resolution of one second and is stamped before the response travels back,
+//! so a measurement is only accurate to a second or two.
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+
+use crate::list_options::list_path;
+use crate::{UnifiClient, UnifiError, UnifiResult, UserListOptions};
+
+/// Skew above which a warning is logged, schedules and onboard times are noticeably off beyond this
+pub const CLOCK_SKEW_WARNING_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Difference between the controller's clock and the local one, see [UnifiClient::check_clock_skew]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew {
+    /// Controller time minus local time, in seconds
+    offset_secs: i64,
+}
+
+impl ClockSkew {
+    /// Controller time minus local time in seconds, positive if the controller is ahead
+    pub fn offset_secs(&self) -> i64 {
+        self.offset_secs
+    }
+
+    /// Size of the skew regardless of direction
+    pub fn magnitude(&self) -> Duration {
+        Duration::from_secs(self.offset_secs.unsigned_abs())
+    }
+
+    /// True if the controller's clock is ahead of the local one
+    pub fn controller_ahead(&self) -> bool {
+        self.offset_secs > 0
+    }
+
+    /// What the controller's clock reads at local time `local`
+    pub fn to_controller_time(&self, local: SystemTime) -> SystemTime {
+        if self.controller_ahead() {
+            local + self.magnitude()
+        } else {
+            local - self.magnitude()
+        }
+    }
+}
+
+/// The latest measurement, shared between clones of a client
+#[derive(Debug, Default)]
+pub(crate) struct SkewTracker {
+    last: Mutex<Option<ClockSkew>>,
+}
+
+impl SkewTracker {
+    /// Records the skew from a response's `Date` header, received at local time `received`
+    pub(crate) fn observe(&self, controller_time: SystemTime, received: SystemTime) {
+        let offset_secs = match controller_time.duration_since(received) {
+            Ok(ahead) => ahead.as_secs() as i64,
+            Err(behind) => -(behind.duration().as_secs() as i64),
+        };
+        let skew = ClockSkew { offset_secs };
+        let previous = self.last.lock().unwrap().replace(skew);
+        let over = |s: &ClockSkew| s.magnitude() > CLOCK_SKEW_WARNING_THRESHOLD;
+        // Only warn when the skew first crosses the threshold, not on every response
+        if over(&skew) && !previous.as_ref().is_some_and(over) {
+            warn!(
+                "Controller clock is {}s {} the local clock, check NTP on both",
+                skew.magnitude().as_secs(),
+                if skew.controller_ahead() {
+                    "ahead of"
+                } else {
+                    "behind"
+                }
+            );
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<ClockSkew> {
+        *self.last.lock().unwrap()
+    }
+}
+
+/// Parses an HTTP date in the only format servers may send, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub(crate) fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let [_weekday, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|m| *m == month)? as u64
+        + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|n| n.parse::<u64>().ok());
+    let (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) =
+        (hms.next(), hms.next(), hms.next(), hms.next())
+    else {
+        return None;
+    };
+    utc_time(year, month, day, h, m, s)
+}
+
+/// The time of a UTC civil date and time, None if a field is out of range or it is before 1970
+pub(crate) fn utc_time(
+    year: u64,
+    month: u64,
+    day: u64,
+    h: u64,
+    m: u64,
+    s: u64,
+) -> Option<SystemTime> {
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month as u32, day as u32) as u64;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + h * 3600 + m * 60 + s))
+}
+
+/// The number of days since 1970-01-01 of a civil date, negative before it
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Counting years from March so leap days come last, in 400 year eras
+    let (y, m_from_march) = if month > 2 {
+        (year, i64::from(month) - 3)
+    } else {
+        (year - 1, i64::from(month) + 9)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y.rem_euclid(400);
+    let day_of_year = (153 * m_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = 365 * year_of_era + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Whole seconds since the epoch, negative before it
+pub(crate) fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+/// The year, month and day of a number of days since 1970-01-01
+#[cfg(feature = "schedules")]
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Counting years from March so leap days come last, in 400 year eras
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let m_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * m_from_march + 2) / 5 + 1) as u32;
+    let month = if m_from_march < 10 {
+        m_from_march as u32 + 3
+    } else {
+        m_from_march as u32 - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC, e.g. `2024-05-03T12:34:56Z`. Fractions of a second are dropped.
+#[cfg(feature = "reports")]
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let secs = unix_secs(time);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let secs_of_day = secs.rem_euclid(86400);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an RFC 3339 timestamp as used in the system log, e.g. `2024-05-03T12:34:56.789Z`
+/// or with an offset like `+02:00`. Fractions of a second are dropped.
+#[cfg(any(feature = "logs", feature = "sim"))]
+pub(crate) fn parse_rfc3339(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+    let mut ymd = date.split('-').map(|n| n.parse::<u64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day)), None) =
+        (ymd.next(), ymd.next(), ymd.next(), ymd.next())
+    else {
+        return None;
+    };
+    // The offset is Z or starts at the first sign after the seconds
+    let (clock, offset_secs) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(sign_at);
+        let (oh, om) = offset[1..].split_once(':')?;
+        let secs = oh.parse::<i64>().ok()? * 3600 + om.parse::<i64>().ok()? * 60;
+        (clock, if offset.starts_with('-') { -secs } else { secs })
+    };
+    let clock = clock.split('.').next()?;
+    let mut hms = clock.split(':').map(|n| n.parse::<u64>().ok());
+    let (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) =
+        (hms.next(), hms.next(), hms.next(), hms.next())
+    else {
+        return None;
+    };
+    let local = utc_time(year, month, day, h, m, s)?;
+    // The local time minus its offset is UTC
+    if offset_secs >= 0 {
+        local.checked_sub(Duration::from_secs(offset_secs as u64))
+    } else {
+        local.checked_add(Duration::from_secs(offset_secs.unsigned_abs()))
+    }
+}
+
+impl UnifiClient {
+    /// Stamps onboard times and other controller-side timestamps using the controller's clock,
+    /// as last measured, instead of the local one. Off by default.
+    pub fn with_clock_skew_correction(mut self, enabled: bool) -> UnifiClient {
+        self.correct_clock_skew = enabled;
+        self
+    }
+
+    /// The skew measured from the most recent response with a `Date` header, None before any response
+    pub fn clock_skew(&self) -> Option<ClockSkew> {
+        self.clock.last()
+    }
+
+    /// Makes a small request to measure the skew between the local clock and the controller's now.
+    /// A warning is logged if it exceeds [CLOCK_SKEW_WARNING_THRESHOLD].
+    pub async fn check_clock_skew(&self) -> UnifiResult<ClockSkew> {
+        let response = self
+            .generic_request_full(
+                reqwest::Method::GET,
+                list_path(&self.api_path("users"), 1, &UserListOptions::from(1)),
+                None,
+            )
+            .await?;
+        if response.date.is_none() {
+            return Err(UnifiError::Other(
+                "Controller didn't send a Date header, can't measure clock skew".to_string(),
+            ));
+        }
+        // The response was measured as it arrived
+        self.clock_skew()
+            .ok_or(UnifiError::Other("Clock skew wasn't recorded".to_string()))
+    }
+
+    /// The current time, on the controller's clock if skew correction is enabled
+    pub(crate) fn timestamp_now(&self) -> SystemTime {
+        let now = SystemTime::now();
+        match self.clock_skew() {
+            Some(skew) if self.correct_clock_skew => skew.to_controller_time(now),
+            _ => now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "schedules")]
+    #[test]
+    fn civil_dates_round_trip_through_days() {
+        for (days, date) in [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (59, (1970, 3, 1)),
+            (11016, (2000, 2, 29)),
+            (11017, (2000, 3, 1)),
+            (19722, (2023, 12, 31)),
+            (19782, (2024, 2, 29)),
+        ] {
+            assert_eq!(civil_from_days(days), date, "{days}");
+            assert_eq!(days_from_civil(date.0, date.1, date.2), days, "{date:?}");
+        }
+    }
+
+    #[cfg(feature = "reports")]
+    #[test]
+    fn rfc3339_formats_and_parses_back() {
+        for timestamp in [
+            "1970-01-01T00:00:00Z",
+            "2000-02-29T23:59:59Z",
+            "2024-01-01T12:00:00Z",
+        ] {
+            let time = parse_rfc3339(timestamp).unwrap();
+            assert_eq!(format_rfc3339(time), timestamp);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Conversions between the user types: what should exist ([NewUser]), what exists ([User])
+//! and what to change ([UserUpdate]).
+//!
+//! Empty strings are values like any other: an empty email in a [NewUser] means the user should have
+//! no email, so [UserUpdate::diff] produces `Some("")` to clear it, and [User::apply] sets it to empty.
+//! `None` in a [UserUpdate] always means "leave as is".
+
+use crate::{validation, NewUser, UnifiError, User, UserUpdate};
+
+impl TryFrom<&User> for NewUser {
+    type Error = UnifiError;
+
+    /// The details needed to recreate the user, e.g. on another controller.
+    /// Fails with [UnifiError::Validation] if the user's names are blank, as registration would reject them.
+    fn try_from(user: &User) -> Result<NewUser, UnifiError> {
+        validation::validate_name("first_name", &user.first_name)?;
+        validation::validate_name("last_name", &user.last_name)?;
+        Ok(NewUser {
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            email: user.user_email.clone(),
+            employee_number: user.employee_number.clone(),
+            allow_duplicate_email: false,
+        })
+    }
+}
+
+/// `Some(desired)` if it differs from `current`
+fn changed(current: &str, desired: &str) -> Option<String> {
+    (current != desired).then(|| desired.to_string())
+}
+
+impl UserUpdate {
+    /// The update that brings `current` in line with `desired`, with only the differing fields set.
+    /// Status isn't part of [NewUser], so it is never changed.
+    pub fn diff(current: &User, desired: &NewUser) -> UserUpdate {
+        UserUpdate {
+            first_name: changed(&current.first_name, &desired.first_name),
+            last_name: changed(&current.last_name, &desired.last_name),
+            email: changed(&current.user_email, &desired.email),
+            employee_number: changed(&current.employee_number, &desired.employee_number),
+            status: None,
+        }
+    }
+
+    /// True if the update wouldn't change anything
+    pub fn is_empty(&self) -> bool {
+        self.first_name.is_none()
+            && self.last_name.is_none()
+            && self.email.is_none()
+            && self.employee_number.is_none()
+            && self.status.is_none()
+    }
+}
+
+impl User {
+    /// Applies the update to this local copy, e.g. to keep a cache in step after [crate::UnifiClient::update_user]
+    pub fn apply(&mut self, update: &UserUpdate) {
+        if let Some(first_name) = &update.first_name {
+            self.first_name = first_name.clone();
+        }
+        if let Some(last_name) = &update.last_name {
+            self.last_name = last_name.clone();
+        }
+        if let Some(email) = &update.email {
+            self.user_email = email.clone();
+        }
+        if let Some(employee_number) = &update.employee_number {
+            self.employee_number = employee_number.clone();
+        }
+        if let Some(status) = &update.status {
+            self.status = status.clone();
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! How a door was opened, from the `authentication.credential_provider` field of log events.
+
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::SystemLogEvent;
+
+/// The credential or mechanism that opened a door.
+///
+/// Firmware versions spell the providers differently ("NFC", "nfc_card", "PIN_CODE"...), they are matched
+/// ignoring case and `-`/space separators. A value not in the table is kept as [CredentialProvider::Unknown]
+/// rather than failing, add it to the table once its meaning is known.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CredentialProvider {
+    Nfc,
+    Pin,
+    /// A phone, through Apple/Google wallet or the Access app
+    TouchPass,
+    /// Unlocked from the UI, the app or the developer API rather than at the door
+    RemoteUnlock,
+    /// The request-to-exit button or sensor on the inside of the door (REX)
+    ExitButton,
+    /// A provider not in the table, as the controller sent it. Empty if the event didn't name one.
+    Unknown(String),
+}
+
+/// Known spellings, normalized to lowercase with `_` separators
+const PROVIDERS: &[(&str, CredentialProvider)] = &[
+    ("nfc", CredentialProvider::Nfc),
+    ("nfc_card", CredentialProvider::Nfc),
+    ("pin", CredentialProvider::Pin),
+    ("pin_code", CredentialProvider::Pin),
+    ("touch_pass", CredentialProvider::TouchPass),
+    ("touchpass", CredentialProvider::TouchPass),
+    ("remote_unlock", CredentialProvider::RemoteUnlock),
+    ("remote", CredentialProvider::RemoteUnlock),
+    ("rex", CredentialProvider::ExitButton),
+    ("exit_button", CredentialProvider::ExitButton),
+    ("request_to_exit", CredentialProvider::ExitButton),
+];
+
+impl CredentialProvider {
+    /// Maps a provider as the controller spells it, never fails
+    pub fn parse(provider: &str) -> CredentialProvider {
+        let normalized: String = provider
+            .trim()
+            .chars()
+            .map(|c| match c {
+                '-' | ' ' => '_',
+                c => c.to_ascii_lowercase(),
+            })
+            .collect();
+        PROVIDERS
+            .iter()
+            .find(|(spelling, _)| *spelling == normalized)
+            .map(|(_, known)| known.clone())
+            .unwrap_or_else(|| CredentialProvider::Unknown(provider.to_string()))
+    }
+
+    /// Name used when grouping and serializing, the raw value for [CredentialProvider::Unknown]
+    pub fn as_str(&self) -> &str {
+        match self {
+            CredentialProvider::Nfc => "nfc",
+            CredentialProvider::Pin => "pin",
+            CredentialProvider::TouchPass => "touch_pass",
+            CredentialProvider::RemoteUnlock => "remote_unlock",
+            CredentialProvider::ExitButton => "exit_button",
+            CredentialProvider::Unknown(raw) => raw,
+        }
+    }
+}
+
+impl fmt::Display for CredentialProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Serialized as a plain string so it can key maps in JSON reports
+impl Serialize for CredentialProvider {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for CredentialProvider {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(CredentialProvider::parse(&String::deserialize(
+            deserializer,
+        )?))
+    }
+}
+
+impl SystemLogEvent {
+    /// How the door was opened, None if the event has no credential provider (e.g. it isn't a door opening)
+    pub fn credential_provider(&self) -> Option<CredentialProvider> {
+        let provider = self.authentication.get("credential_provider")?.as_str()?;
+        Some(CredentialProvider::parse(provider))
+    }
+}
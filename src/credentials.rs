@@ -0,0 +1,82 @@
+//! Giving users a credential in one call, whichever kind the site supports.
+//!
+//! Sites with only keypads have no device that can enroll NFC cards, so the NFC flow reports
+//! [UnifiError::NoEnrollmentDeviceAvailable] up front, and [UnifiClient::issue_pin_to_user] is the
+//! parallel flow for them. Visitors aren't wrapped by this crate, so these work on users only.
+
+use log::*;
+use serde_json::json;
+
+use crate::{
+    encode_path_segment, Device, EnrollmentOptions, NfcCard, Secret, UnifiClient, UnifiError,
+    UnifiResult,
+};
+
+impl UnifiClient {
+    /// The devices that can run an NFC enrollment session, empty on sites with only keypads or hubs
+    pub async fn get_enrollment_devices(&self) -> UnifiResult<Vec<Device>> {
+        Ok(self
+            .get_devices()
+            .await?
+            .into_iter()
+            .filter(Device::can_enroll_cards)
+            .collect())
+    }
+
+    /// Enrolls a new card on `device_id`, or on the first device able to enroll if None,
+    /// waits for it to be scanned and assigns it to the user.
+    ///
+    /// Fails with [UnifiError::NoEnrollmentDeviceAvailable] before starting anything if no device was
+    /// given and none can enroll. If assigning fails the enrolled card is left unassigned.
+    pub async fn enroll_and_assign_nfc_card(
+        &self,
+        user_id: &str,
+        device_id: Option<&str>,
+        options: EnrollmentOptions,
+    ) -> UnifiResult<NfcCard> {
+        let device_id = match device_id {
+            Some(device_id) => device_id.to_string(),
+            None => {
+                self.get_enrollment_devices()
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or(UnifiError::NoEnrollmentDeviceAvailable)?
+                    .id
+            }
+        };
+        let handle = self.start_enrollment(&device_id, options).await?;
+        let card = handle.wait_for_card().await?;
+        self.assign_nfc_card(user_id, &card).await?;
+        Ok(card)
+    }
+
+    /// Has the controller generate a PIN code and assigns it to the user, replacing any PIN they had.
+    ///
+    /// The PIN is only returned, for handing to the user. It is redacted in audit entries and
+    /// HTTP recordings, and like card tokens only appears in logs at trace level.
+    pub async fn issue_pin_to_user(&self, user_id: &str) -> UnifiResult<Secret> {
+        let pin: Secret = self
+            .generic_request(
+                reqwest::Method::POST,
+                self.api_path("credentials/pin_codes"),
+                None,
+            )
+            .await?;
+        let body = json!({
+            "pin_code": pin,
+        });
+        self.audited("issue_pin_to_user", &[user_id], body.clone(), async {
+            self.generic_request_no_parse(
+                reqwest::Method::PUT,
+                self.api_path(&format!("users/{}/pin_codes", encode_path_segment(user_id))),
+                Some(body),
+            )
+            .await?;
+            Ok(())
+        })
+        .await?;
+        info!("Issued a new PIN to user {user_id}");
+        Ok(pin)
+    }
+}
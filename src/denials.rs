@@ -0,0 +1,326 @@
+//! Alerting on repeated access denials at a door, e.g. "the same card was denied 3 times within 5 minutes".
+//!
+//! [AccessDeniedMonitor] is a pure sliding window over denials, fed with explicit times so it can be
+//! driven from any event source. [UnifiClient::poll_access_denials] feeds it from the door openings log.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::parse_rfc3339;
+use crate::state_store::{load_state, save_state, VersionedState};
+use crate::{
+    Secret, StateStore, SystemLogEventWrapper, SystemLogOptions, SystemLogTopic, UnifiClient,
+    UnifiResult,
+};
+
+/// Who was denied
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeniedActor {
+    /// A known user, by id
+    User(String),
+    /// A credential not assigned to any user, e.g. an unknown NFC card
+    Credential(Secret),
+}
+
+/// One denial within a [DeniedBurst]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeniedAttempt {
+    /// Id of the system log event, empty if the denial didn't come from the log
+    pub event_id: String,
+    pub at: SystemTime,
+}
+
+/// Denials of one actor at one door that crossed the threshold, see [AccessDeniedMonitor]
+#[derive(Debug, Clone)]
+pub struct DeniedBurst {
+    pub actor: DeniedActor,
+    /// Id of the door
+    pub door: String,
+    /// Number of denials within the window
+    pub count: usize,
+    /// The configured window the denials fell within
+    pub window: Duration,
+    /// The denials, oldest first
+    pub events: Vec<DeniedAttempt>,
+}
+
+type DenialKey = (DeniedActor, String);
+
+/// Format of an [AccessDeniedMonitor]'s poll position in a [StateStore]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DenialCursor {
+    /// Milliseconds since the unix epoch of the newest event polled
+    polled_until_ms: Option<u64>,
+    /// Log events already counted, for the overlap of the next poll
+    #[serde(default)]
+    seen_events: Vec<String>,
+}
+
+impl VersionedState for DenialCursor {
+    const VERSION: u8 = 1;
+}
+
+/// Where a monitor keeps its cursor, see [AccessDeniedMonitor::with_state_store]
+struct CursorStore {
+    store: Arc<dyn StateStore>,
+    key: String,
+}
+
+impl fmt::Debug for CursorStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CursorStore")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tracks denials per actor and door, reporting a [DeniedBurst] once `threshold` of them fall within `window`.
+///
+/// After a burst is reported the same actor and door stay quiet for the cooldown, by default the window,
+/// so one incident is reported once rather than on every further denial.
+#[derive(Debug)]
+pub struct AccessDeniedMonitor {
+    threshold: usize,
+    window: Duration,
+    cooldown: Duration,
+    denials: HashMap<DenialKey, VecDeque<DeniedAttempt>>,
+    last_burst: HashMap<DenialKey, SystemTime>,
+    /// Log events already counted, polls overlap by up to a second
+    seen_events: HashSet<String>,
+    /// Time of the newest log event fed in by [UnifiClient::poll_access_denials]
+    polled_until: Option<SystemTime>,
+    cursor_store: Option<CursorStore>,
+}
+
+impl AccessDeniedMonitor {
+    /// A threshold of 0 is treated as 1
+    pub fn new(threshold: usize, window: Duration) -> AccessDeniedMonitor {
+        AccessDeniedMonitor {
+            threshold: threshold.max(1),
+            window,
+            cooldown: window,
+            denials: HashMap::new(),
+            last_burst: HashMap::new(),
+            seen_events: HashSet::new(),
+            polled_until: None,
+            cursor_store: None,
+        }
+    }
+
+    /// Keeps the position of [UnifiClient::poll_access_denials] in `store` under `key`, so after a restart
+    /// polling continues where it stopped instead of reporting the same denials again.
+    /// Denials inside a window that hasn't crossed the threshold yet aren't kept.
+    pub fn with_state_store(
+        mut self,
+        store: Arc<dyn StateStore>,
+        key: &str,
+    ) -> AccessDeniedMonitor {
+        self.cursor_store = Some(CursorStore {
+            store,
+            key: key.to_string(),
+        });
+        self
+    }
+
+    /// How long the same actor and door stay quiet after a burst is reported
+    pub fn with_cooldown(mut self, cooldown: Duration) -> AccessDeniedMonitor {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Counts a denial at time `at`, returning the burst if this denial crosses the threshold.
+    /// Denials should be recorded in time order, older ones only count against the newest window.
+    pub fn record_denial(
+        &mut self,
+        actor: DeniedActor,
+        door: &str,
+        attempt: DeniedAttempt,
+    ) -> Option<DeniedBurst> {
+        let at = attempt.at;
+        let key = (actor, door.to_string());
+        let window = self.window;
+        let attempts = self.denials.entry(key.clone()).or_default();
+        attempts.push_back(attempt);
+        while attempts
+            .front()
+            .is_some_and(|oldest| oldest.at + window < at)
+        {
+            attempts.pop_front();
+        }
+        if attempts.len() < self.threshold {
+            return None;
+        }
+        let cooling_down = self
+            .last_burst
+            .get(&key)
+            .is_some_and(|last| at < *last + self.cooldown);
+        if cooling_down {
+            return None;
+        }
+        let events: Vec<DeniedAttempt> = attempts.iter().cloned().collect();
+        self.last_burst.insert(key.clone(), at);
+        Some(DeniedBurst {
+            actor: key.0,
+            door: key.1,
+            count: events.len(),
+            window,
+            events,
+        })
+    }
+
+    /// Counts the event if it is a denial at a door, see [AccessDeniedMonitor::record_denial].
+    /// Other events, and events seen before, are ignored.
+    pub fn record_event(&mut self, event: &SystemLogEventWrapper) -> Option<DeniedBurst> {
+        let (actor, door) = denial_of(event)?;
+        let Some(at) = parse_rfc3339(&event.timestamp) else {
+            warn!(
+                "Ignoring denial {} with unparseable timestamp {:?}",
+                event.id, event.timestamp
+            );
+            return None;
+        };
+        if !self.seen_events.insert(event.id.clone()) {
+            return None;
+        }
+        self.record_denial(
+            actor,
+            &door,
+            DeniedAttempt {
+                event_id: event.id.clone(),
+                at,
+            },
+        )
+    }
+
+    /// Forgets denials and cooldowns that can no longer affect a burst at or after `now`
+    pub fn prune(&mut self, now: SystemTime) {
+        let (window, cooldown) = (self.window, self.cooldown);
+        let mut forgotten = vec![];
+        self.denials.retain(|_, attempts| {
+            while attempts
+                .front()
+                .is_some_and(|oldest| oldest.at + window < now)
+            {
+                forgotten.extend(attempts.pop_front().map(|a| a.event_id));
+            }
+            !attempts.is_empty()
+        });
+        self.last_burst.retain(|_, last| *last + cooldown > now);
+        for event_id in forgotten {
+            self.seen_events.remove(&event_id);
+        }
+    }
+}
+
+/// Whether a door opening event let the person through, None for events without a result
+pub(crate) fn access_granted(event: &SystemLogEventWrapper) -> Option<bool> {
+    let result = event.source.event.get("result")?.as_str()?;
+    Some(result.eq_ignore_ascii_case("ACCESS"))
+}
+
+/// Id of the door an event happened at, None if it didn't involve a door
+pub(crate) fn event_door_id(event: &SystemLogEventWrapper) -> Option<String> {
+    Some(
+        event
+            .source
+            .target
+            .as_array()?
+            .iter()
+            .find(|t| t.get("type").and_then(|t| t.as_str()) == Some("door"))?
+            .get("id")?
+            .as_str()?
+            .to_string(),
+    )
+}
+
+/// The actor and door of a denied door opening, None for anything else
+fn denial_of(event: &SystemLogEventWrapper) -> Option<(DeniedActor, String)> {
+    if access_granted(event)? {
+        return None;
+    }
+    let door = event_door_id(event)?;
+    let source = &event.source;
+    let user_id = source
+        .actor
+        .get("id")
+        .and_then(|id| id.as_str())
+        .filter(|id| !id.is_empty());
+    let actor = match user_id {
+        Some(id) => DeniedActor::User(id.to_string()),
+        None => {
+            DeniedActor::Credential(Secret::new(source.authentication.get("issuer")?.as_str()?))
+        }
+    };
+    Some((actor, door))
+}
+
+impl UnifiClient {
+    /// Feeds the door openings logged since the previous poll into `monitor` and calls `on_burst` for each burst.
+    /// The first poll starts from `monitor`'s window before now. Call it regularly, e.g. every minute.
+    pub async fn poll_access_denials(
+        &self,
+        monitor: &mut AccessDeniedMonitor,
+        mut on_burst: impl FnMut(DeniedBurst),
+    ) -> UnifiResult<()> {
+        let now = SystemTime::now();
+        if monitor.polled_until.is_none() {
+            load_cursor(monitor).await?;
+        }
+        let since = monitor.polled_until.unwrap_or(now - monitor.window);
+        let mut events = self
+            .fetch_system_log_all(SystemLogOptions::new(SystemLogTopic::DoorOpenings).since(since))
+            .await?;
+        // The log lists the newest first, the monitor needs them in time order
+        events.sort_by_key(|e| parse_rfc3339(&e.timestamp));
+        for event in &events {
+            if let Some(burst) = monitor.record_event(event) {
+                on_burst(burst);
+            }
+        }
+        monitor.polled_until = events
+            .iter()
+            .filter_map(|e| parse_rfc3339(&e.timestamp))
+            .max()
+            .or(monitor.polled_until)
+            .or(Some(since));
+        monitor.prune(now);
+        save_cursor(monitor).await
+    }
+}
+
+/// Restores the poll position of a monitor with a state store, if one was saved
+async fn load_cursor(monitor: &mut AccessDeniedMonitor) -> UnifiResult<()> {
+    let Some(cursor_store) = &monitor.cursor_store else {
+        return Ok(());
+    };
+    let Some(cursor) =
+        load_state::<DenialCursor>(cursor_store.store.as_ref(), &cursor_store.key).await?
+    else {
+        return Ok(());
+    };
+    monitor.polled_until = cursor
+        .polled_until_ms
+        .map(|ms| UNIX_EPOCH + Duration::from_millis(ms));
+    monitor.seen_events.extend(cursor.seen_events);
+    Ok(())
+}
+
+/// Saves the poll position of a monitor with a state store
+async fn save_cursor(monitor: &AccessDeniedMonitor) -> UnifiResult<()> {
+    let Some(cursor_store) = &monitor.cursor_store else {
+        return Ok(());
+    };
+    let cursor = DenialCursor {
+        polled_until_ms: monitor
+            .polled_until
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64),
+        seen_events: monitor.seen_events.iter().cloned().collect(),
+    };
+    save_state(cursor_store.store.as_ref(), &cursor_store.key, &cursor).await
+}
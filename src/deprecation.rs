@@ -0,0 +1,72 @@
+//! Runtime warnings for deprecated methods, pointing at what replaces them.
+//!
+//! Deprecated methods stay for at least one minor version with `#[deprecated]`, which only warns when
+//! the calling crate is compiled. They also log a warning naming the replacement the first time one is
+//! called in the process, under the `unifi_access::deprecated` target, unless the client was built with
+//! [UnifiClient::suppress_deprecation_warnings]. Every entry in [DEPRECATIONS] has a section in
+//! MIGRATION.md.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use log::*;
+
+use crate::UnifiClient;
+
+/// A deprecated method and what replaces it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deprecation {
+    /// Path of the deprecated method, e.g. `UnifiClient::enroll_nfc_card`
+    pub symbol: &'static str,
+    /// Version of the crate it was deprecated in
+    pub since: &'static str,
+    /// Paths of the methods or types to use instead
+    pub replacements: &'static [&'static str],
+    /// How to migrate, one sentence
+    pub note: &'static str,
+}
+
+/// Every deprecated method still in the crate
+pub const DEPRECATIONS: &[Deprecation] = &[Deprecation {
+    symbol: "UnifiClient::enroll_nfc_card",
+    since: "0.2.0",
+    replacements: &[
+        "UnifiClient::start_enrollment",
+        "EnrollmentHandle::wait_for_card",
+    ],
+    note: "the handle returned by start_enrollment replaces the session id Mutex and can cancel the session",
+}];
+
+/// Deprecated methods already warned about in this process
+static WARNED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// The registry entry of a deprecated method
+pub fn deprecation(symbol: &str) -> Option<&'static Deprecation> {
+    DEPRECATIONS.iter().find(|d| d.symbol == symbol)
+}
+
+impl UnifiClient {
+    /// Stops deprecated methods called through this client from logging a warning
+    pub fn suppress_deprecation_warnings(mut self) -> UnifiClient {
+        self.deprecation_warnings = false;
+        self
+    }
+
+    /// Warns that `symbol` is deprecated, once per process
+    pub(crate) fn warn_deprecated(&self, symbol: &'static str) {
+        if !self.deprecation_warnings || !WARNED.lock().unwrap().insert(symbol) {
+            return;
+        }
+        match deprecation(symbol) {
+            Some(d) => warn!(
+                target: "unifi_access::deprecated",
+                "{} is deprecated since {}, use {} instead: {}",
+                d.symbol,
+                d.since,
+                d.replacements.join(" and "),
+                d.note
+            ),
+            None => warn!(target: "unifi_access::deprecated", "{symbol} is deprecated"),
+        }
+    }
+}
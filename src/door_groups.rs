@@ -0,0 +1,122 @@
+//! Door groups and keeping their membership in line with a desired list.
+
+use std::collections::BTreeSet;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::{encode_path_segment, PolicyResource, UnifiClient, UnifiError, UnifiResult};
+
+/// A named group of doors, which access policies can grant access to as a whole
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct DoorGroup {
+    pub id: String,
+    #[serde(rename = "group_name", alias = "name")]
+    pub name: String,
+    #[serde(default)]
+    pub resources: Vec<PolicyResource>,
+}
+
+impl DoorGroup {
+    /// Ids of the doors in the group
+    pub fn door_ids(&self) -> BTreeSet<String> {
+        self.resources
+            .iter()
+            .filter(|r| r.resource_type == "door")
+            .map(|r| r.id.clone())
+            .collect()
+    }
+}
+
+/// The change made by [UnifiClient::reconcile_door_group]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DoorGroupDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl DoorGroupDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// What needs to change to get from `current` to `desired`
+fn diff_doors(current: &BTreeSet<String>, desired: &BTreeSet<String>) -> DoorGroupDiff {
+    DoorGroupDiff {
+        added: desired.difference(current).cloned().collect(),
+        removed: current.difference(desired).cloned().collect(),
+    }
+}
+
+impl UnifiClient {
+    /// Retrieves every door group, sorted by name
+    pub async fn get_all_door_groups(&self) -> UnifiResult<Vec<DoorGroup>> {
+        let groups: Option<Vec<DoorGroup>> = self
+            .generic_request_optional(reqwest::Method::GET, self.api_path("door_groups"), None)
+            .await?;
+        let mut groups = groups.unwrap_or_default();
+        groups.sort_by(|a, b| (&a.name, &a.id).cmp(&(&b.name, &b.id)));
+        Ok(groups)
+    }
+
+    /// Fetches a door group by id
+    pub async fn get_door_group(&self, group_id: &str) -> UnifiResult<DoorGroup> {
+        self.generic_request(
+            reqwest::Method::GET,
+            self.api_path(&format!("door_groups/{}", encode_path_segment(group_id))),
+            None,
+        )
+        .await
+    }
+
+    /// Makes the group contain exactly `desired_door_ids`, returning what was added and removed.
+    ///
+    /// The update endpoint replaces the whole member list, so the group is read back after writing.
+    /// If it doesn't hold the desired doors someone else edited it in between, and
+    /// [UnifiError::ConcurrentModification] is returned with both lists so the caller can retry.
+    /// Nothing is written if the group already matches.
+    pub async fn reconcile_door_group(
+        &self,
+        group_id: &str,
+        desired_door_ids: &[&str],
+    ) -> UnifiResult<DoorGroupDiff> {
+        let desired: BTreeSet<String> = desired_door_ids.iter().map(|id| id.to_string()).collect();
+        let current = self.get_door_group(group_id).await?;
+        let diff = diff_doors(&current.door_ids(), &desired);
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+        info!(
+            "Updating door group {group_id}: adding {:?}, removing {:?}",
+            diff.added, diff.removed
+        );
+        let body = json!({ "group_name": current.name, "resources": desired });
+        self.audited("update_door_group", &[group_id], body.clone(), async {
+            self.generic_request_no_parse(
+                reqwest::Method::PUT,
+                self.api_path(&format!("door_groups/{}", encode_path_segment(group_id))),
+                Some(body),
+            )
+            .await?;
+            Ok(())
+        })
+        .await?;
+        if self.dry_run {
+            return Ok(diff);
+        }
+        let actual = self.get_door_group(group_id).await?.door_ids();
+        if actual != desired {
+            return Err(UnifiError::ConcurrentModification {
+                object: format!("door group {group_id}"),
+                expected: desired.into_iter().collect(),
+                actual: actual.into_iter().collect(),
+            });
+        }
+        Ok(diff)
+    }
+}
@@ -0,0 +1,104 @@
+//! A door's own unlock schedule: the weekly windows it stays unlocked in, for doors like a shop entrance
+//! that open for business rather than per person.
+
+use std::time::SystemTime;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::{encode_path_segment, SiteTimeZone, UnifiClient, UnifiResult, WeekSchedule};
+
+/// When a door unlocks by itself, see [UnifiClient::get_door_unlock_schedule]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct DoorUnlockSchedule {
+    /// The windows the door is unlocked in, in the site's local time
+    #[serde(default)]
+    pub week_schedule: WeekSchedule,
+    /// Each window only unlocks the door once someone with access has come through it,
+    /// until then the door stays locked
+    #[serde(default)]
+    pub first_person_in: bool,
+}
+
+impl DoorUnlockSchedule {
+    /// True if `at` falls in a window of the schedule, on the clock of a site in `time_zone`.
+    /// With [DoorUnlockSchedule::first_person_in] the door may still be locked, waiting for the first person.
+    pub fn unlocks_at(&self, at: SystemTime, time_zone: impl Into<SiteTimeZone>) -> bool {
+        self.week_schedule.is_open_at(at, time_zone)
+    }
+}
+
+impl UnifiClient {
+    /// Fetches a door's unlock schedule, None if it has none and stays locked outside lock rules
+    pub async fn get_door_unlock_schedule(
+        &self,
+        door_id: &str,
+    ) -> UnifiResult<Option<DoorUnlockSchedule>> {
+        debug!("Sending get_door_unlock_schedule_request: {door_id}");
+        self.generic_request_optional(
+            reqwest::Method::GET,
+            self.api_path(&format!(
+                "doors/{}/unlock_schedule",
+                encode_path_segment(door_id)
+            )),
+            None,
+        )
+        .await
+    }
+
+    /// Replaces a door's unlock schedule. Windows are read in the site's local time.
+    /// See [UnifiClient::clear_door_unlock_schedule] to go back to always locked.
+    pub async fn set_door_unlock_schedule(
+        &self,
+        door_id: &str,
+        schedule: DoorUnlockSchedule,
+    ) -> UnifiResult<()> {
+        let body = serde_json::to_value(&schedule)?;
+        debug!("Setting unlock schedule of door {door_id} to {body}");
+        self.audited(
+            "set_door_unlock_schedule",
+            &[door_id],
+            body.clone(),
+            async {
+                self.generic_request_no_parse(
+                    reqwest::Method::PUT,
+                    self.api_path(&format!(
+                        "doors/{}/unlock_schedule",
+                        encode_path_segment(door_id)
+                    )),
+                    Some(body),
+                )
+                .await?;
+                Ok(())
+            },
+        )
+        .await
+    }
+
+    /// Removes a door's unlock schedule, so it stays locked unless a lock rule says otherwise.
+    /// Setting a schedule without windows has the same effect.
+    pub async fn clear_door_unlock_schedule(&self, door_id: &str) -> UnifiResult<()> {
+        debug!("Clearing unlock schedule of door {door_id}");
+        self.audited(
+            "clear_door_unlock_schedule",
+            &[door_id],
+            serde_json::Value::Null,
+            async {
+                self.generic_request_no_parse(
+                    reqwest::Method::DELETE,
+                    self.api_path(&format!(
+                        "doors/{}/unlock_schedule",
+                        encode_path_segment(door_id)
+                    )),
+                    None,
+                )
+                .await?;
+                Ok(())
+            },
+        )
+        .await
+    }
+}
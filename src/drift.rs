@@ -0,0 +1,219 @@
+//! Least privilege checks, comparing the policies users hold against the policies they should hold.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{UnifiClient, UnifiResult, User};
+
+/// A user whose policies differ from what was expected
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UserDrift {
+    /// The email or employee number the user was matched by
+    pub key: String,
+    pub user_id: String,
+    /// Names of policies the user holds but isn't expected to, sorted
+    pub unexpected: Vec<String>,
+    /// Names of policies the user is expected to hold but doesn't, sorted
+    pub missing: Vec<String>,
+}
+
+/// Result of [UnifiClient::audit_policy_drift], every list is sorted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DriftReport {
+    /// Users whose policies don't match their expectation
+    pub drifted: Vec<UserDrift>,
+    /// Keys of users on the controller that have no expectation
+    pub unlisted: Vec<String>,
+    /// Expectation keys that didn't match any user on the controller
+    pub unmatched: Vec<String>,
+}
+
+impl DriftReport {
+    /// True if every listed user holds exactly their expected policies
+    pub fn is_clean(&self) -> bool {
+        self.drifted.is_empty()
+    }
+}
+
+impl UnifiClient {
+    /// Reports users whose access policies differ from `expected`, a map from email or employee number
+    /// to the names of the policies that user should hold. Emails are matched case insensitively.
+    ///
+    /// Lists the users with their policies expanded, so a single paged request on firmware that supports it.
+    pub async fn audit_policy_drift(
+        &self,
+        expected: &HashMap<String, Vec<String>>,
+    ) -> UnifiResult<DriftReport> {
+        let users = self.get_all_users_expanded().await?;
+        Ok(compare_policies(&users, expected))
+    }
+}
+
+/// Compares the policies held by `users` against `expected`, see [UnifiClient::audit_policy_drift]
+fn compare_policies(users: &[User], expected: &HashMap<String, Vec<String>>) -> DriftReport {
+    let expected: HashMap<String, BTreeSet<&str>> = expected
+        .iter()
+        .map(|(key, policies)| {
+            (
+                key.to_lowercase(),
+                policies.iter().map(String::as_str).collect(),
+            )
+        })
+        .collect();
+    let mut matched = BTreeSet::new();
+    let mut report = DriftReport::default();
+
+    for user in users {
+        let email = user.user_email.to_lowercase();
+        let employee_number = user.employee_number.to_lowercase();
+        let (key, wanted) = match (expected.get(&email), expected.get(&employee_number)) {
+            (Some(wanted), _) if !email.is_empty() => (email, wanted),
+            (_, Some(wanted)) if !employee_number.is_empty() => (employee_number, wanted),
+            _ => {
+                report.unlisted.push(if email.is_empty() {
+                    employee_number
+                } else {
+                    email
+                });
+                continue;
+            }
+        };
+        let held: BTreeSet<&str> = user
+            .access_policies
+            .iter()
+            .flatten()
+            .map(|p| p.name.as_str())
+            .collect();
+        let unexpected: Vec<String> = held.difference(wanted).map(|p| p.to_string()).collect();
+        let missing: Vec<String> = wanted.difference(&held).map(|p| p.to_string()).collect();
+        if !unexpected.is_empty() || !missing.is_empty() {
+            report.drifted.push(UserDrift {
+                key: key.clone(),
+                user_id: user.id.clone(),
+                unexpected,
+                missing,
+            });
+        }
+        matched.insert(key);
+    }
+
+    report.unmatched = expected
+        .keys()
+        .filter(|key| !matched.contains(*key))
+        .cloned()
+        .collect();
+    report.drifted.sort();
+    report.unlisted.sort();
+    report.unmatched.sort();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn user(id: &str, email: &str, employee_number: &str, policies: Option<&[&str]>) -> User {
+        let policies = policies.map(|names| {
+            names
+                .iter()
+                .map(|name| json!({"id": format!("p-{name}"), "name": name}))
+                .collect::<Vec<_>>()
+        });
+        serde_json::from_value(json!({
+            "id": id,
+            "first_name": "",
+            "last_name": "",
+            "user_email": email,
+            "employee_number": employee_number,
+            "access_policies": policies,
+        }))
+        .unwrap()
+    }
+
+    fn expected(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        entries
+            .iter()
+            .map(|(key, names)| {
+                (
+                    key.to_string(),
+                    names.iter().map(|n| n.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn drift(key: &str, user_id: &str, unexpected: &[&str], missing: &[&str]) -> UserDrift {
+        UserDrift {
+            key: key.to_string(),
+            user_id: user_id.to_string(),
+            unexpected: unexpected.iter().map(|p| p.to_string()).collect(),
+            missing: missing.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn reports_unexpected_and_missing_policies() {
+        let users = [
+            user(
+                "u1",
+                "Alice@Example.com",
+                "",
+                Some(&["Staff", "Lab", "Annex"]),
+            ),
+            user("u2", "", "E2", Some(&["Staff"])),
+            user("u3", "carol@example.com", "E3", Some(&["Staff"])),
+        ];
+        let report = compare_policies(
+            &users,
+            &expected(&[
+                ("alice@example.com", &["Staff"]),
+                ("e2", &["Lab", "Staff"]),
+                ("CAROL@example.com", &["Staff"]),
+            ]),
+        );
+        assert_eq!(
+            report.drifted,
+            vec![
+                drift("alice@example.com", "u1", &["Annex", "Lab"], &[]),
+                drift("e2", "u2", &[], &["Lab"]),
+            ]
+        );
+        assert!(report.unlisted.is_empty() && report.unmatched.is_empty());
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn email_is_matched_before_employee_number() {
+        let users = [user("u1", "a@example.com", "E1", Some(&["Staff"]))];
+        let report = compare_policies(
+            &users,
+            &expected(&[("a@example.com", &["Staff"]), ("E1", &["Lab"])]),
+        );
+        assert!(report.is_clean());
+        // The employee number expectation matched nobody since the email was used
+        assert_eq!(report.unmatched, vec!["e1".to_string()]);
+    }
+
+    #[test]
+    fn lists_users_and_expectations_without_a_counterpart() {
+        let users = [
+            user("u1", "dave@example.com", "E9", Some(&[])),
+            user("u2", "", "E4", None),
+            user("u3", "erin@example.com", "", None),
+        ];
+        let report = compare_policies(
+            &users,
+            &expected(&[("zed@example.com", &[]), ("erin@example.com", &["Staff"])]),
+        );
+        assert_eq!(report.unlisted, vec!["dave@example.com", "e4"]);
+        assert_eq!(report.unmatched, vec!["zed@example.com"]);
+        // Users whose policies weren't fetched hold none
+        assert_eq!(
+            report.drifted,
+            vec![drift("erin@example.com", "u3", &[], &["Staff"])]
+        );
+    }
+}
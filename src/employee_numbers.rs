@@ -0,0 +1,101 @@
+//! Finding users by employee number, which integrations often fill with their own member id.
+//!
+//! The controller doesn't require employee numbers to be unique, and its keyword search also matches
+//! names and emails containing the number, so search results are checked for an exact match here.
+
+use std::collections::BTreeMap;
+
+use log::*;
+
+use crate::{encode_path_segment, UnifiClient, UnifiError, UnifiResult, User, LIST_PAGE_SIZE};
+
+impl UnifiClient {
+    /// The user whose employee number is `number`, None if nobody has it.
+    ///
+    /// Uses the controller's keyword search, reading every page of results, and keeps only exact matches
+    /// (ignoring surrounding whitespace). Firmware that ignores the keyword returns every user, which
+    /// gives the same answer more slowly. Fails with [UnifiError::Validation] if several users have the
+    /// number, see [UnifiClient::find_duplicate_employee_numbers].
+    pub async fn find_user_by_employee_number(&self, number: &str) -> UnifiResult<Option<User>> {
+        let number = number.trim();
+        if number.is_empty() {
+            return Err(UnifiError::Validation {
+                field: "employee_number".to_string(),
+                reason: "must not be empty".to_string(),
+            });
+        }
+        let mut matches: Vec<User> = self
+            .search_users(number)
+            .await?
+            .into_iter()
+            .filter(|user| user.employee_number.trim() == number)
+            .collect();
+        match matches.len() {
+            0 | 1 => Ok(matches.pop()),
+            count => Err(UnifiError::Validation {
+                field: "employee_number".to_string(),
+                reason: format!("{count} users have employee number {number}"),
+            }),
+        }
+    }
+
+    /// Employee numbers held by more than one user, with those users.
+    /// Sorted by employee number, users by id. Users without an employee number are ignored.
+    pub async fn find_duplicate_employee_numbers(&self) -> UnifiResult<Vec<(String, Vec<User>)>> {
+        let mut by_number: BTreeMap<String, Vec<User>> = BTreeMap::new();
+        for user in self.get_all_users().await? {
+            let number = user.employee_number.trim().to_string();
+            if !number.is_empty() {
+                by_number.entry(number).or_default().push(user);
+            }
+        }
+        Ok(by_number
+            .into_iter()
+            .filter(|(_, users)| users.len() > 1)
+            .map(|(number, mut users)| {
+                users.sort_by(|a, b| a.id.cmp(&b.id));
+                (number, users)
+            })
+            .collect())
+    }
+
+    /// Every page of the users the controller's keyword search matches
+    async fn search_users(&self, keyword: &str) -> UnifiResult<Vec<User>> {
+        let api_path = self.api_path("users");
+        let keyword = encode_path_segment(keyword);
+        let mut users = vec![];
+        let mut page_num = 1;
+        loop {
+            let envelope = self
+                .generic_request_envelope(
+                    reqwest::Method::GET,
+                    format!(
+                        "{api_path}?keyword={keyword}&page_num={page_num}&page_size={LIST_PAGE_SIZE}"
+                    ),
+                    None,
+                )
+                .await?;
+            let Some(envelope) = envelope else {
+                return Ok(users);
+            };
+            let page: Vec<User> = match envelope.data {
+                None | Some(serde_json::Value::Null) => vec![],
+                Some(data) => serde_json::from_value(data)?,
+            };
+            let empty = page.is_empty();
+            users.extend(page);
+            match envelope.pagination {
+                Some(pagination) if !empty && users.len() < pagination.total as usize => {
+                    page_num += 1
+                }
+                _ => {
+                    debug!(
+                        "Keyword search for {keyword} returned {} users",
+                        users.len()
+                    );
+                    return Ok(users);
+                }
+            }
+        }
+    }
+}
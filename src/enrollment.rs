@@ -0,0 +1,323 @@
+//! Handle based NFC card enrollment.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::*;
+
+use crate::{NfcCard, PollingConfig, UnifiClient, UnifiError, UnifiResult};
+
+/// Number of consecutive polls that may fail transiently before [EnrollmentHandle::wait_for_card] gives up
+pub const MAX_POLL_RETRIES: u32 = 5;
+
+/// True for failures where polling again may succeed: no response, or a server error
+pub(crate) fn is_transient(e: &UnifiError) -> bool {
+    match e {
+        UnifiError::Http(_) | UnifiError::ControllerUnavailable { .. } => true,
+        UnifiError::UnexpectedResponse { status, .. } => *status >= 500,
+        _ => false,
+    }
+}
+
+/// Sessions started through a client and its clones, by device id.
+/// The session id is empty while the session is still being started.
+pub(crate) type ActiveEnrollments = Mutex<HashMap<String, String>>;
+
+/// Removes the device's entry, unless it has since been taken over by another session
+fn release(active: &ActiveEnrollments, device_id: &str, session_id: &str) {
+    let mut active = active.lock().unwrap();
+    if active.get(device_id).map(String::as_str) == Some(session_id) {
+        active.remove(device_id);
+    }
+}
+
+/// A device claimed for a session that is still being started.
+/// Dropping it frees the device, so a failed or dropped [UnifiClient::start_enrollment] doesn't leave it taken.
+struct Reservation<'a> {
+    active: &'a ActiveEnrollments,
+    device_id: String,
+}
+
+impl Reservation<'_> {
+    /// Hands the device over to the started session
+    fn fulfil(mut self, session_id: String) {
+        let device_id = std::mem::take(&mut self.device_id);
+        self.active.lock().unwrap().insert(device_id, session_id);
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        // Empty once fulfilled
+        if !self.device_id.is_empty() {
+            release(self.active, &self.device_id, "");
+        }
+    }
+}
+
+/// What [UnifiClient::start_enrollment] does when the client, or a clone of it,
+/// already has an enrollment session running on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConcurrentEnrollment {
+    /// Return [UnifiError::EnrollmentInProgress]
+    #[default]
+    Fail,
+    /// Wait for the other session to finish, then start
+    Wait,
+    /// End the other session and start a new one, whoever is waiting on the other session gets an error
+    Supersede,
+}
+
+/// Confirmation that a card previously provisioned by Unifi (e.g. at another site) may be wiped
+/// during enrollment, see [EnrollmentOptions::with_reset].
+/// Resetting can't be undone, so it takes a reason, which is logged and sent to the audit sink.
+#[derive(Debug, Clone)]
+pub struct ResetUaCard {
+    reason: String,
+}
+
+impl ResetUaCard {
+    pub fn confirmed(reason: impl Into<String>) -> ResetUaCard {
+        ResetUaCard {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Settings for [UnifiClient::start_enrollment]
+#[derive(Debug, Clone)]
+pub struct EnrollmentOptions {
+    /// Asks the reader to reset cards that were previously provisioned by Unifi, defaults to None (no reset)
+    pub reset_ua_card: Option<ResetUaCard>,
+    /// How often the session is checked for a scanned card,
+    /// defaults to the client's [PollingConfig::base_interval]
+    pub poll_interval: Option<Duration>,
+    /// What to do if the device is already enrolling, defaults to [ConcurrentEnrollment::Fail]
+    pub on_conflict: ConcurrentEnrollment,
+}
+
+impl Default for EnrollmentOptions {
+    fn default() -> Self {
+        EnrollmentOptions {
+            reset_ua_card: None,
+            poll_interval: None,
+            on_conflict: ConcurrentEnrollment::default(),
+        }
+    }
+}
+
+impl EnrollmentOptions {
+    /// Lets the reader reset a card previously provisioned by Unifi, wiping it
+    pub fn with_reset(mut self, reset: ResetUaCard) -> EnrollmentOptions {
+        self.reset_ua_card = Some(reset);
+        self
+    }
+}
+
+/// An enrollment session running on a reader, created with [UnifiClient::start_enrollment].
+///
+/// Clones refer to the same session, so a clone can be handed to another task to cancel
+/// while this one waits for a card.
+/// If every clone is dropped before a card is scanned or the session is cancelled,
+/// the session is ended in the background on a best effort basis.
+#[derive(Clone)]
+pub struct EnrollmentHandle {
+    inner: Arc<EnrollmentInner>,
+}
+
+struct EnrollmentInner {
+    client: UnifiClient,
+    device_id: String,
+    session_id: String,
+    polling: PollingConfig,
+    /// Set once a card has been scanned or the session was cancelled
+    finished: AtomicBool,
+}
+
+impl EnrollmentInner {
+    /// Marks the session finished and frees up the device, returns false if it had already finished
+    fn finish(&self) -> bool {
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        release(&self.client.enrollments, &self.device_id, &self.session_id);
+        true
+    }
+}
+
+impl EnrollmentHandle {
+    /// Id of the session on the controller
+    pub fn session_id(&self) -> &str {
+        &self.inner.session_id
+    }
+
+    /// Polls the session until a card is scanned.
+    /// Errors if the session is cancelled, through [EnrollmentHandle::cancel] or on the controller.
+    ///
+    /// Up to [MAX_POLL_RETRIES] polls in a row may fail with a transport error or a 5xx response
+    /// (e.g. while the controller is busy) before giving up.
+    /// When giving up the session is ended, so the reader is free for the next enrollment.
+    pub async fn wait_for_card(&self) -> UnifiResult<NfcCard> {
+        let mut failed_polls = 0;
+        loop {
+            if self.inner.finished.load(Ordering::SeqCst) {
+                return Err(UnifiError::Other(
+                    "Enrollment session has already finished".to_string(),
+                ));
+            }
+            match self
+                .inner
+                .client
+                .get_nfc_enrollment_session_status(&self.inner.session_id)
+                .await
+            {
+                Ok(Some(card)) => {
+                    self.inner.finish();
+                    return Ok(card);
+                }
+                Ok(None) => failed_polls = 0,
+                Err(e) if is_transient(&e) && failed_polls < MAX_POLL_RETRIES => {
+                    failed_polls += 1;
+                    warn!(
+                        "Polling enrollment session {} failed, retrying: {e}",
+                        self.inner.session_id
+                    );
+                }
+                Err(e) => {
+                    self.abandon().await;
+                    return Err(e);
+                }
+            }
+            self.inner.polling.wait(failed_polls).await;
+        }
+    }
+
+    /// Ends the session on the controller after a failure, errors are only logged
+    async fn abandon(&self) {
+        if !self.inner.finish() {
+            return;
+        }
+        if let Err(e) = self
+            .inner
+            .client
+            .end_enrollment_session(&self.inner.session_id)
+            .await
+        {
+            debug!(
+                "Failed to end enrollment session {}: {e}",
+                self.inner.session_id
+            );
+        }
+    }
+
+    /// Ends the session on the controller, any task waiting on a clone of this handle will return an error
+    pub async fn cancel(self) -> UnifiResult<()> {
+        self.inner.finish();
+        self.inner
+            .client
+            .end_enrollment_session(&self.inner.session_id)
+            .await
+    }
+}
+
+impl Drop for EnrollmentInner {
+    fn drop(&mut self) {
+        if !self.finish() {
+            return;
+        }
+        // Can't wait here, so the session is ended in the background if there's a runtime to do it on
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(
+                "Enrollment session {} was abandoned and couldn't be ended",
+                self.session_id
+            );
+            return;
+        };
+        let client = self.client.clone();
+        let session_id = std::mem::take(&mut self.session_id);
+        runtime.spawn(async move {
+            if let Err(e) = client.end_enrollment_session(&session_id).await {
+                debug!("Failed to end abandoned enrollment session {session_id}: {e}");
+            }
+        });
+    }
+}
+
+impl UnifiClient {
+    /// Starts an enrollment session on the reader with the given device id.
+    /// Use the returned handle to wait for a card to be scanned, or to cancel the session.
+    ///
+    /// Only one session per device is allowed at a time across this client and its clones,
+    /// see [EnrollmentOptions::on_conflict]. Sessions started some other way aren't known about.
+    pub async fn start_enrollment(
+        &self,
+        device_id: &str,
+        options: EnrollmentOptions,
+    ) -> UnifiResult<EnrollmentHandle> {
+        let reservation = self.reserve_device(device_id, &options).await?;
+        let session_id = self
+            .start_nfc_enrollment_session_with(device_id, options.reset_ua_card.as_ref())
+            .await?;
+        reservation.fulfil(session_id.clone());
+        Ok(EnrollmentHandle {
+            inner: Arc::new(EnrollmentInner {
+                client: self.clone(),
+                device_id: device_id.to_string(),
+                session_id,
+                polling: self.enrollment_polling(&options),
+                finished: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// The client's polling settings with the interval from `options`, if it has one
+    fn enrollment_polling(&self, options: &EnrollmentOptions) -> PollingConfig {
+        PollingConfig {
+            base_interval: options.poll_interval.unwrap_or(self.polling.base_interval),
+            ..self.polling.clone()
+        }
+    }
+
+    /// Claims the device for a new session, dealing with an existing one according to `options`
+    async fn reserve_device(
+        &self,
+        device_id: &str,
+        options: &EnrollmentOptions,
+    ) -> UnifiResult<Reservation<'_>> {
+        loop {
+            let existing = {
+                let mut active = self.enrollments.lock().unwrap();
+                match active.get(device_id) {
+                    Some(session_id) => session_id.clone(),
+                    None => {
+                        active.insert(device_id.to_string(), String::new());
+                        return Ok(Reservation {
+                            active: &self.enrollments,
+                            device_id: device_id.to_string(),
+                        });
+                    }
+                }
+            };
+            match options.on_conflict {
+                ConcurrentEnrollment::Wait => self.enrollment_polling(options).wait(0).await,
+                // A session that is still starting has no id to end yet
+                ConcurrentEnrollment::Supersede if !existing.is_empty() => {
+                    info!("Superseding enrollment session {existing} on device {device_id}");
+                    release(&self.enrollments, device_id, &existing);
+                    self.end_enrollment_session(&existing).await?;
+                }
+                _ => {
+                    return Err(UnifiError::EnrollmentInProgress {
+                        device_id: device_id.to_string(),
+                    })
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Error type shared by every operation in the crate.
+
+use std::fmt;
+
+use crate::{PlannedRequest, RawResponse};
+
+/// How much of an unexpected response body is kept in [UnifiError::UnexpectedResponse]
+const BODY_SNIPPET_LEN: usize = 300;
+
+/// The error type for this crate
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UnifiError {
+    /// The request couldn't be sent or the response couldn't be read
+    Http(reqwest::Error),
+    /// A response from the controller couldn't be parsed into the expected type
+    Json(serde_json::Error),
+    /// Reading or writing local state (journals, snapshots on disk) failed
+    Io(std::io::Error),
+    /// The controller responded with a code other than `SUCCESS`
+    Api {
+        /// Path of the request that failed
+        endpoint: String,
+        /// The raw code returned by the controller, e.g. `CODE_PARAMS_INVALID`
+        code: String,
+        /// Broad category of `code`, see [ApiErrorKind::from_code]
+        kind: ApiErrorKind,
+        /// The message returned by the controller
+        msg: String,
+    },
+    /// The controller (or something in front of it, like a reverse proxy) responded with something
+    /// other than the usual JSON envelope, e.g. an HTML error page while the controller reboots
+    UnexpectedResponse {
+        /// HTTP status of the response
+        status: u16,
+        content_type: Option<String>,
+        /// The start of the response body
+        body_snippet: String,
+    },
+    /// No device with this id exists on the controller
+    DeviceNotFound { device_id: String },
+    /// The device exists but has no card reader, e.g. it's a hub
+    DeviceCannotEnroll {
+        device_id: String,
+        device_type: String,
+    },
+    /// No device on the controller can enroll NFC cards, e.g. a site with only keypads
+    NoEnrollmentDeviceAvailable,
+    /// A `_confirmed` operation found the object in a different state than the caller expected,
+    /// so nothing was changed
+    ConfirmationMismatch {
+        /// What was checked, e.g. `user 1234`
+        object: String,
+        expected: String,
+        actual: String,
+    },
+    /// Input was rejected locally before anything was sent, see [crate::UnifiClient::with_validation]
+    Validation {
+        /// Name of the offending field, e.g. `email`
+        field: String,
+        reason: String,
+    },
+    /// An enrollment session is already running on the device, see [crate::EnrollmentOptions::on_conflict]
+    EnrollmentInProgress { device_id: String },
+    /// No card was scanned on the device before the timeout, the session has been ended
+    EnrollmentTimedOut {
+        device_id: String,
+        timeout: std::time::Duration,
+    },
+    /// Another user already has the email of a user being registered,
+    /// see [crate::UnifiClient::with_duplicate_email_check]
+    EmailAlreadyExists {
+        /// None if the user having it isn't known
+        existing_user_id: Option<String>,
+    },
+    /// No user matches a lookup that isn't by id, e.g. by email, or the user of a request turned out
+    /// not to exist, see [crate::UnifiClient::get_access_policies_for_user]
+    UserNotFound {
+        /// What was looked up, e.g. `email jane@example.com`
+        user: String,
+    },
+    /// The controller answered 503, usually because it is restarting or updating,
+    /// see [crate::UnifiClient::with_maintenance_window]
+    ControllerUnavailable {
+        /// Path of the request that failed
+        endpoint: String,
+    },
+    /// A change was written but reading it back showed something else, most likely because it was
+    /// edited concurrently (e.g. in the UI). The change can be retried.
+    ConcurrentModification {
+        /// What was changed, e.g. `door group 1234`
+        object: String,
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+    /// The endpoint is deprecated or has moved on the API version it was called on. Switch the resource
+    /// with [crate::UnifiClient::with_resource_api_version], or let the client retry on the next version
+    /// with [crate::UnifiClient::with_moved_endpoint_retry].
+    EndpointMoved {
+        /// Path of the request that failed
+        endpoint: String,
+        /// The first segment of the endpoint, what to pass to `with_resource_api_version`
+        resource: String,
+        from: crate::ApiVersion,
+        to: crate::ApiVersion,
+    },
+    /// Persisted state was written in a format this version of the crate can't read,
+    /// usually by a newer version, see [crate::StateStore]
+    UnsupportedStateVersion {
+        /// Key the state is stored under
+        key: String,
+        version: u8,
+    },
+    /// The client is in dry run mode and the operation needed a response from a request that wasn't sent.
+    /// Contains the request that would have been made.
+    DryRun(PlannedRequest),
+    /// Anything else, described by the message
+    Other(String),
+}
+
+/// Broad category of an error code returned by the controller, so callers can react to
+/// a class of failure without matching code strings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApiErrorKind {
+    /// The request was malformed or a value was rejected
+    InvalidParams,
+    /// The referenced object (user, device, session, ...) doesn't exist
+    NotFound,
+    /// An object with the same identity already exists
+    AlreadyExists,
+    /// The credential is in a state that prevents the operation, e.g. the card is bound to another user
+    CredentialConflict,
+    /// The controller asked us to slow down
+    RateLimited,
+    /// The token is invalid or expired
+    Forbidden,
+    /// The token is valid but isn't allowed to do this, e.g. a read-only token used to modify a user
+    InsufficientScope,
+    /// The endpoint is deprecated or moved to another API version, see [UnifiError::EndpointMoved]
+    Moved,
+    /// A code not in the table, check the raw code
+    Unknown,
+}
+
+/// Known controller error codes, add new codes here as they are encountered.
+/// No rate limiting code has been observed yet, [ApiErrorKind::RateLimited] is reserved for it.
+const API_ERROR_CODES: &[(&str, ApiErrorKind)] = &[
+    ("CODE_PARAMS_INVALID", ApiErrorKind::InvalidParams),
+    ("CODE_USER_EMAIL_ERROR", ApiErrorKind::InvalidParams),
+    ("CODE_CREDS_NFC_CARD_INVALID", ApiErrorKind::InvalidParams),
+    (
+        "CODE_CREDS_NFC_READ_POLL_TOKEN_EMPTY",
+        ApiErrorKind::InvalidParams,
+    ),
+    (
+        "CODE_CREDS_PIN_CODE_CREDS_LENGTH_INVALID",
+        ApiErrorKind::InvalidParams,
+    ),
+    ("CODE_RESOURCE_NOT_FOUND", ApiErrorKind::NotFound),
+    ("CODE_NOT_EXISTS", ApiErrorKind::NotFound),
+    ("CODE_USER_NOT_EXISTS", ApiErrorKind::NotFound),
+    ("CODE_USER_ACCOUNT_NOT_EXIST", ApiErrorKind::NotFound),
+    ("CODE_USER_WORKER_NOT_EXISTS", ApiErrorKind::NotFound),
+    ("CODE_DEVICE_DEVICE_NOT_FOUND", ApiErrorKind::NotFound),
+    (
+        "CODE_CREDS_NFC_READ_SESSION_NOT_FOUND",
+        ApiErrorKind::NotFound,
+    ),
+    (
+        "CODE_ACCESS_POLICY_SCHEDULE_NOT_FOUND",
+        ApiErrorKind::NotFound,
+    ),
+    ("CODE_USER_NAME_DUPLICATED", ApiErrorKind::AlreadyExists),
+    (
+        "CODE_CREDS_PIN_CODE_CREDS_ALREADY_EXIST",
+        ApiErrorKind::AlreadyExists,
+    ),
+    (
+        "CODE_CREDS_NFC_HAS_BIND_USER",
+        ApiErrorKind::CredentialConflict,
+    ),
+    (
+        "CODE_CREDS_NFC_CARD_IS_PROVISION",
+        ApiErrorKind::CredentialConflict,
+    ),
+    (
+        "CODE_CREDS_NFC_CARD_PROVISION_FAILED",
+        ApiErrorKind::CredentialConflict,
+    ),
+    (
+        "CODE_CREDS_NFC_CARD_CANNOT_BE_DELETE",
+        ApiErrorKind::CredentialConflict,
+    ),
+    ("CODE_OPERATION_FORBIDDEN", ApiErrorKind::InsufficientScope),
+    ("CODE_AUTH_FAILED", ApiErrorKind::Forbidden),
+    ("CODE_ACCESS_TOKEN_INVALID", ApiErrorKind::Forbidden),
+    ("CODE_UNAUTHORIZED", ApiErrorKind::Forbidden),
+    // Not observed yet, the spellings firmware announcing v2 endpoints is expected to use
+    ("CODE_API_VERSION_DEPRECATED", ApiErrorKind::Moved),
+    ("CODE_API_MOVED", ApiErrorKind::Moved),
+];
+
+impl ApiErrorKind {
+    /// Looks up the category of a raw controller code, [ApiErrorKind::Unknown] if it isn't known
+    pub fn from_code(code: &str) -> ApiErrorKind {
+        API_ERROR_CODES
+            .iter()
+            .find(|(known, _)| *known == code)
+            .map(|(_, kind)| *kind)
+            .unwrap_or(ApiErrorKind::Unknown)
+    }
+}
+
+/// The result type for this crate
+pub type UnifiResult<T> = Result<T, UnifiError>;
+
+impl fmt::Display for UnifiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnifiError::Http(e) => write!(f, "Request to controller failed: {e}"),
+            UnifiError::Json(e) => write!(f, "Failed to parse response: {e}"),
+            UnifiError::Io(e) => write!(f, "IO error: {e}"),
+            UnifiError::Api {
+                endpoint,
+                code,
+                msg,
+                kind: ApiErrorKind::InsufficientScope,
+            } => write!(
+                f,
+                "Failed request to {endpoint}: {code} {msg} (the API token's permissions don't allow this operation)"
+            ),
+            UnifiError::Api {
+                endpoint,
+                code,
+                msg,
+                ..
+            } => write!(f, "Failed request to {endpoint}: {code} {msg}"),
+            UnifiError::UnexpectedResponse {
+                status,
+                content_type,
+                body_snippet,
+            } => write!(
+                f,
+                "Unexpected response from controller (HTTP {status}, content-type {}): {body_snippet}",
+                content_type.as_deref().unwrap_or("missing")
+            ),
+            UnifiError::DeviceNotFound { device_id } => {
+                write!(f, "No device with id {device_id} exists")
+            }
+            UnifiError::DeviceCannotEnroll {
+                device_id,
+                device_type,
+            } => write!(
+                f,
+                "Device {device_id} is a {device_type}, which can't enroll cards"
+            ),
+            UnifiError::NoEnrollmentDeviceAvailable => write!(
+                f,
+                "No device on the controller can enroll NFC cards, issue a PIN instead"
+            ),
+            UnifiError::ConfirmationMismatch {
+                object,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Refusing to modify {object}: expected {expected} but found {actual}"
+            ),
+            UnifiError::Validation { field, reason } => write!(f, "Invalid {field}: {reason}"),
+            UnifiError::EnrollmentInProgress { device_id } => {
+                write!(f, "An enrollment session is already running on device {device_id}")
+            }
+            UnifiError::EnrollmentTimedOut { device_id, timeout } => write!(
+                f,
+                "No card was scanned on device {device_id} within {}s",
+                timeout.as_secs()
+            ),
+            UnifiError::EmailAlreadyExists {
+                existing_user_id: Some(id),
+            } => write!(f, "User {id} already has this email"),
+            UnifiError::EmailAlreadyExists {
+                existing_user_id: None,
+            } => write!(f, "Another user already has this email"),
+            UnifiError::UserNotFound { user } => write!(f, "No user with {user} exists"),
+            UnifiError::ControllerUnavailable { endpoint } => write!(
+                f,
+                "Controller unavailable (HTTP 503) for request to {endpoint}"
+            ),
+            UnifiError::ConcurrentModification {
+                object,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{object} was modified concurrently: expected {expected:?} but found {actual:?}"
+            ),
+            UnifiError::EndpointMoved {
+                endpoint,
+                resource,
+                from,
+                to,
+            } => write!(
+                f,
+                "{endpoint} is deprecated on API {from:?}, call {resource} on {to:?} with with_resource_api_version(\"{resource}\", ApiVersion::{to:?})"
+            ),
+            UnifiError::UnsupportedStateVersion { key, version } => write!(
+                f,
+                "State {key} has format version {version}, which this version of unifi_access can't read"
+            ),
+            UnifiError::DryRun(request) => write!(
+                f,
+                "Dry run enabled, {} {} was not sent",
+                request.method, request.path
+            ),
+            UnifiError::Other(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl UnifiError {
+    /// The category of the controller's error code, None if the error didn't come from the controller
+    pub fn kind(&self) -> Option<ApiErrorKind> {
+        match self {
+            UnifiError::Api { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// The raw code returned by the controller, None if the error didn't come from the controller
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            UnifiError::Api { code, .. } => Some(code),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn unexpected_response(response: &RawResponse) -> UnifiError {
+        let mut end = response.body.len().min(BODY_SNIPPET_LEN);
+        while !response.body.is_char_boundary(end) {
+            end -= 1;
+        }
+        UnifiError::UnexpectedResponse {
+            status: response.status,
+            content_type: response.content_type.clone(),
+            body_snippet: response.body[..end].to_string(),
+        }
+    }
+}
+
+impl std::error::Error for UnifiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UnifiError::Http(e) => Some(e),
+            UnifiError::Json(e) => Some(e),
+            UnifiError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for UnifiError {
+    fn from(e: reqwest::Error) -> Self {
+        UnifiError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for UnifiError {
+    fn from(e: serde_json::Error) -> Self {
+        UnifiError::Json(e)
+    }
+}
+
+impl From<std::io::Error> for UnifiError {
+    fn from(e: std::io::Error) -> Self {
+        UnifiError::Io(e)
+    }
+}
+
+impl From<std::time::SystemTimeError> for UnifiError {
+    fn from(e: std::time::SystemTimeError) -> Self {
+        UnifiError::Other(format!("System clock is before the unix epoch: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn every_known_code_maps_to_its_kind() {
+        let mut seen = HashSet::new();
+        for (code, kind) in API_ERROR_CODES {
+            assert!(seen.insert(*code), "{code} is listed twice");
+            assert!(code.starts_with("CODE_"), "{code}");
+            assert_ne!(*kind, ApiErrorKind::Unknown, "{code}");
+            assert_eq!(ApiErrorKind::from_code(code), *kind, "{code}");
+        }
+    }
+
+    #[test]
+    fn unknown_codes_are_unknown() {
+        for code in ["", "SUCCESS", "CODE_SOMETHING_NEW", "code_params_invalid"] {
+            assert_eq!(
+                ApiErrorKind::from_code(code),
+                ApiErrorKind::Unknown,
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn api_errors_expose_their_code_and_kind() {
+        let error = UnifiError::Api {
+            endpoint: "/api/v1/developer/users/u1".to_string(),
+            code: "CODE_USER_NOT_EXISTS".to_string(),
+            kind: ApiErrorKind::from_code("CODE_USER_NOT_EXISTS"),
+            msg: "user not exists".to_string(),
+        };
+        assert_eq!(error.kind(), Some(ApiErrorKind::NotFound));
+        assert_eq!(error.code(), Some("CODE_USER_NOT_EXISTS"));
+        let error = UnifiError::Other("not from the controller".to_string());
+        assert_eq!(error.kind(), None);
+        assert_eq!(error.code(), None);
+    }
+
+    fn snippet(body: &str) -> String {
+        let response = RawResponse {
+            status: 502,
+            content_type: Some("text/html".to_string()),
+            body: body.to_string(),
+            date: None,
+        };
+        match UnifiError::unexpected_response(&response) {
+            UnifiError::UnexpectedResponse {
+                status,
+                content_type,
+                body_snippet,
+            } => {
+                assert_eq!(status, 502);
+                assert_eq!(content_type.as_deref(), Some("text/html"));
+                body_snippet
+            }
+            other => panic!("expected UnexpectedResponse, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unexpected_response_keeps_short_bodies() {
+        assert_eq!(snippet(""), "");
+        assert_eq!(
+            snippet("<html>Bad Gateway</html>"),
+            "<html>Bad Gateway</html>"
+        );
+        let exact = "a".repeat(BODY_SNIPPET_LEN);
+        assert_eq!(snippet(&exact), exact);
+    }
+
+    #[test]
+    fn unexpected_response_truncates_long_bodies() {
+        let body = "a".repeat(BODY_SNIPPET_LEN + 50);
+        assert_eq!(snippet(&body), body[..BODY_SNIPPET_LEN]);
+    }
+
+    #[test]
+    fn unexpected_response_truncates_on_a_char_boundary() {
+        // A two byte character straddling the limit is dropped rather than split
+        let body = format!("{}é and more", "a".repeat(BODY_SNIPPET_LEN - 1));
+        let snippet = snippet(&body);
+        assert_eq!(snippet.len(), BODY_SNIPPET_LEN - 1);
+        assert!(body.starts_with(&snippet));
+    }
+}
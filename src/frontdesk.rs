@@ -0,0 +1,548 @@
+//! The workflows of a front desk kiosk: onboarding a member with a fob, replacing a lost fob and offboarding.
+//!
+//! Each workflow is a sequence of existing client calls. What [FrontDesk] adds is the handling of a
+//! failure part way through: onboarding undoes what it already did, so a failed attempt can simply be
+//! retried, and every outcome says which step failed and what was undone.
+//!
+//! The developer API can't delete users, so undoing or offboarding a user deactivates them.
+//! Enabled with the `frontdesk` feature.
+
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use log::*;
+
+use crate::{
+    EnrollmentOptions, NewUser, NfcCard, UnifiClient, UnifiError, UnifiResult, User, UserStatus,
+    UserUpdate,
+};
+
+/// How a member is identified at the desk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemberRef {
+    /// The user's id on the controller
+    Id(String),
+    /// The user's email, matched case-insensitively
+    Email(String),
+}
+
+/// The calls [FrontDesk] is built from, implemented by [UnifiClient].
+/// Implement it to run the workflows against something else, e.g. a test double.
+pub trait FrontDeskBackend: Send + Sync {
+    /// The user `member` refers to, [UnifiError::UserNotFound] if nobody has the email
+    fn find_user<'a>(&'a self, member: &'a MemberRef) -> BoxFuture<'a, UnifiResult<User>>;
+    fn create_user<'a>(&'a self, user: &'a NewUser) -> BoxFuture<'a, UnifiResult<User>>;
+    /// Waits for a card to be scanned on the device, failing with [UnifiError::EnrollmentTimedOut]
+    /// once `timeout` passes
+    fn enroll_card<'a>(
+        &'a self,
+        device_id: &'a str,
+        options: EnrollmentOptions,
+        timeout: Duration,
+    ) -> BoxFuture<'a, UnifiResult<NfcCard>>;
+    fn assign_card<'a>(
+        &'a self,
+        user_id: &'a str,
+        card: &'a NfcCard,
+    ) -> BoxFuture<'a, UnifiResult<()>>;
+    /// Unassigns the card and deletes it from the controller
+    fn remove_card<'a>(&'a self, card: &'a NfcCard) -> BoxFuture<'a, UnifiResult<()>>;
+    fn assign_policies<'a>(
+        &'a self,
+        user_id: &'a str,
+        policy_ids: Vec<String>,
+    ) -> BoxFuture<'a, UnifiResult<()>>;
+    fn remove_policies<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>>;
+    fn deactivate_user<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>>;
+}
+
+impl FrontDeskBackend for UnifiClient {
+    fn find_user<'a>(&'a self, member: &'a MemberRef) -> BoxFuture<'a, UnifiResult<User>> {
+        Box::pin(async move {
+            match member {
+                MemberRef::Id(id) => self.get_user_by_id(id).await,
+                MemberRef::Email(email) => self
+                    .get_all_users()
+                    .await?
+                    .into_iter()
+                    .find(|u| u.user_email.eq_ignore_ascii_case(email.trim()))
+                    .ok_or_else(|| UnifiError::UserNotFound {
+                        user: format!("email {email}"),
+                    }),
+            }
+        })
+    }
+
+    fn create_user<'a>(&'a self, user: &'a NewUser) -> BoxFuture<'a, UnifiResult<User>> {
+        Box::pin(self.create_user_full(user))
+    }
+
+    fn enroll_card<'a>(
+        &'a self,
+        device_id: &'a str,
+        options: EnrollmentOptions,
+        timeout: Duration,
+    ) -> BoxFuture<'a, UnifiResult<NfcCard>> {
+        Box::pin(async move {
+            let handle = self.start_enrollment(device_id, options).await?;
+            let scanned = tokio::time::timeout(timeout, handle.wait_for_card()).await;
+            match scanned {
+                Ok(result) => result,
+                Err(_) => {
+                    if let Err(e) = handle.cancel().await {
+                        debug!("Failed to end timed out enrollment on device {device_id}: {e}");
+                    }
+                    Err(UnifiError::EnrollmentTimedOut {
+                        device_id: device_id.to_string(),
+                        timeout,
+                    })
+                }
+            }
+        })
+    }
+
+    fn assign_card<'a>(
+        &'a self,
+        user_id: &'a str,
+        card: &'a NfcCard,
+    ) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.assign_nfc_card(user_id, card))
+    }
+
+    fn remove_card<'a>(&'a self, card: &'a NfcCard) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.remove_nfc_card(card))
+    }
+
+    fn assign_policies<'a>(
+        &'a self,
+        user_id: &'a str,
+        policy_ids: Vec<String>,
+    ) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.assign_access_policies(user_id, policy_ids))
+    }
+
+    fn remove_policies<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.remove_all_access_policies_from_user(user_id))
+    }
+
+    fn deactivate_user<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move {
+            self.update_user(
+                user_id,
+                &UserUpdate {
+                    status: Some(UserStatus::Deactivated),
+                    ..Default::default()
+                },
+            )
+            .await
+        })
+    }
+}
+
+/// Something a failed workflow undid, or tried to
+#[derive(Debug, Clone)]
+pub enum Undo {
+    /// The card was unassigned and deleted
+    RemoveCard(NfcCard),
+    /// The user was deactivated, by id
+    DeactivateUser(String),
+}
+
+/// An [Undo] and whether it worked, None if it did
+#[derive(Debug)]
+pub struct UndoResult {
+    pub undo: Undo,
+    pub error: Option<UnifiError>,
+}
+
+/// The step a workflow failed at, the error, and what was undone
+#[derive(Debug)]
+pub struct WorkflowFailure<S> {
+    pub step: S,
+    pub error: UnifiError,
+    /// In the order they were attempted, an undo that failed leaves its change in place
+    pub undone: Vec<UndoResult>,
+}
+
+impl<S> WorkflowFailure<S> {
+    /// True if everything the workflow changed before failing was undone
+    pub fn fully_undone(&self) -> bool {
+        self.undone.iter().all(|u| u.error.is_none())
+    }
+}
+
+/// Steps of [FrontDesk::onboard_member]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnboardStep {
+    /// Includes checking that nobody already has the email
+    CreateUser,
+    EnrollCard,
+    AssignCard,
+    AssignPolicies,
+}
+
+/// Result of [FrontDesk::onboard_member]
+#[derive(Debug)]
+pub enum OnboardOutcome {
+    Completed {
+        user: User,
+        card: NfcCard,
+        policy_ids: Vec<String>,
+    },
+    Failed(WorkflowFailure<OnboardStep>),
+}
+
+/// Steps of [FrontDesk::replace_lost_card]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaceStep {
+    FindUser,
+    /// Removing the user's current cards, nothing new is enrolled while a lost card still works
+    RemoveOldCards,
+    EnrollCard,
+    AssignCard,
+}
+
+/// Result of [FrontDesk::replace_lost_card]
+#[derive(Debug)]
+pub enum ReplaceOutcome {
+    Completed {
+        user_id: String,
+        /// The cards that were removed
+        removed: Vec<NfcCard>,
+        card: NfcCard,
+    },
+    Failed {
+        failure: WorkflowFailure<ReplaceStep>,
+        /// Old cards removed before the failure, these aren't restored
+        removed: Vec<NfcCard>,
+    },
+}
+
+/// Result of [FrontDesk::offboard_member]
+#[derive(Debug)]
+pub struct OffboardReport {
+    pub user_id: String,
+    /// Cards deleted, empty when the record was kept
+    pub removed_cards: Vec<NfcCard>,
+    /// Cards that couldn't be deleted, they no longer open anything once the user is deactivated
+    pub failed_cards: Vec<(NfcCard, UnifiError)>,
+}
+
+/// The kiosk workflows, configured once for the desk's reader and the policies new members get.
+/// See the [module docs](self).
+pub struct FrontDesk<B = UnifiClient> {
+    backend: B,
+    device_id: String,
+    policy_ids: Vec<String>,
+    enrollment_options: EnrollmentOptions,
+    enrollment_timeout: Duration,
+    normalize_names: bool,
+}
+
+impl<B: FrontDeskBackend> FrontDesk<B> {
+    /// Enrolls cards on `device_id`, the desk's reader. New members get no policies until
+    /// [FrontDesk::with_policies] is set, and have 60s to scan their fob.
+    pub fn new(backend: B, device_id: &str) -> FrontDesk<B> {
+        FrontDesk {
+            backend,
+            device_id: device_id.to_string(),
+            policy_ids: vec![],
+            enrollment_options: EnrollmentOptions::default(),
+            enrollment_timeout: Duration::from_secs(60),
+            normalize_names: true,
+        }
+    }
+
+    /// The policies every new member is given
+    pub fn with_policies(mut self, policy_ids: Vec<String>) -> FrontDesk<B> {
+        self.policy_ids = policy_ids;
+        self
+    }
+
+    /// How long a member has to scan their fob before enrollment gives up
+    pub fn with_enrollment_timeout(mut self, timeout: Duration) -> FrontDesk<B> {
+        self.enrollment_timeout = timeout;
+        self
+    }
+
+    /// Options for enrolling on the desk's reader, e.g. to reset previously provisioned fobs
+    pub fn with_enrollment_options(mut self, options: EnrollmentOptions) -> FrontDesk<B> {
+        self.enrollment_options = options;
+        self
+    }
+
+    /// Whether names and emails of new members are tidied before creating them, on by default.
+    /// Whitespace is trimmed, emails lowercased, and names typed in all lower or upper case are
+    /// capitalized, e.g. `ada LOVELACE` becomes `Ada Lovelace` but `McAdams` is kept.
+    pub fn with_name_normalization(mut self, enabled: bool) -> FrontDesk<B> {
+        self.normalize_names = enabled;
+        self
+    }
+
+    /// Access to the backend, usually the client
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Creates the member, enrolls a fob on the desk's reader, assigns it and gives them the desk's policies.
+    ///
+    /// If a step fails, the steps before it are undone: the fob is removed and the new user deactivated.
+    /// Onboarding an email that already belongs to a user fails at [OnboardStep::CreateUser] without changes,
+    /// unless the member has [NewUser::allow_duplicate_email] set.
+    pub async fn onboard_member(&self, member: NewUser) -> OnboardOutcome {
+        let member = if self.normalize_names {
+            normalize_member(member)
+        } else {
+            member
+        };
+        let mut undo = vec![];
+
+        if !member.allow_duplicate_email {
+            if let Err(error) = self.check_email_unused(&member.email).await {
+                return self
+                    .onboard_failed(OnboardStep::CreateUser, error, undo)
+                    .await;
+            }
+        }
+        let user = match self.backend.create_user(&member).await {
+            Ok(user) => user,
+            Err(error) => {
+                return self
+                    .onboard_failed(OnboardStep::CreateUser, error, undo)
+                    .await
+            }
+        };
+        undo.push(Undo::DeactivateUser(user.id.clone()));
+
+        let card = match self.enroll().await {
+            Ok(card) => card,
+            Err(error) => {
+                return self
+                    .onboard_failed(OnboardStep::EnrollCard, error, undo)
+                    .await
+            }
+        };
+        // Enrolled cards exist on the controller even before they are assigned
+        undo.push(Undo::RemoveCard(card.clone()));
+
+        if let Err(error) = self.backend.assign_card(&user.id, &card).await {
+            return self
+                .onboard_failed(OnboardStep::AssignCard, error, undo)
+                .await;
+        }
+        if !self.policy_ids.is_empty() {
+            if let Err(error) = self
+                .backend
+                .assign_policies(&user.id, self.policy_ids.clone())
+                .await
+            {
+                return self
+                    .onboard_failed(OnboardStep::AssignPolicies, error, undo)
+                    .await;
+            }
+        }
+        info!("Onboarded user {} with card {}", user.id, card.id);
+        OnboardOutcome::Completed {
+            user,
+            card,
+            policy_ids: self.policy_ids.clone(),
+        }
+    }
+
+    async fn onboard_failed(
+        &self,
+        step: OnboardStep,
+        error: UnifiError,
+        undo: Vec<Undo>,
+    ) -> OnboardOutcome {
+        warn!("Onboarding failed at {step:?}: {error}");
+        OnboardOutcome::Failed(WorkflowFailure {
+            step,
+            error,
+            undone: self.undo_all(undo).await,
+        })
+    }
+
+    /// Removes the member's current cards, then enrolls a new fob on the desk's reader and assigns it.
+    ///
+    /// Removed cards aren't restored if a later step fails, since they are presumed lost. If the new fob
+    /// can't be assigned it is removed again, leaving the member without a card until the next attempt.
+    pub async fn replace_lost_card(&self, member: &MemberRef) -> ReplaceOutcome {
+        let mut removed = vec![];
+        let user = match self.backend.find_user(member).await {
+            Ok(user) => user,
+            Err(error) => {
+                return self
+                    .replace_failed(ReplaceStep::FindUser, error, vec![], removed)
+                    .await
+            }
+        };
+        for card in &user.nfc_cards {
+            match self.backend.remove_card(card).await {
+                Ok(()) => removed.push(card.clone()),
+                Err(error) => {
+                    return self
+                        .replace_failed(ReplaceStep::RemoveOldCards, error, vec![], removed)
+                        .await
+                }
+            }
+        }
+
+        let card = match self.enroll().await {
+            Ok(card) => card,
+            Err(error) => {
+                return self
+                    .replace_failed(ReplaceStep::EnrollCard, error, vec![], removed)
+                    .await
+            }
+        };
+        if let Err(error) = self.backend.assign_card(&user.id, &card).await {
+            let undo = vec![Undo::RemoveCard(card)];
+            return self
+                .replace_failed(ReplaceStep::AssignCard, error, undo, removed)
+                .await;
+        }
+        info!(
+            "Replaced {} cards of user {} with card {}",
+            removed.len(),
+            user.id,
+            card.id
+        );
+        ReplaceOutcome::Completed {
+            user_id: user.id,
+            removed,
+            card,
+        }
+    }
+
+    async fn replace_failed(
+        &self,
+        step: ReplaceStep,
+        error: UnifiError,
+        undo: Vec<Undo>,
+        removed: Vec<NfcCard>,
+    ) -> ReplaceOutcome {
+        warn!("Replacing card failed at {step:?}: {error}");
+        ReplaceOutcome::Failed {
+            failure: WorkflowFailure {
+                step,
+                error,
+                undone: self.undo_all(undo).await,
+            },
+            removed,
+        }
+    }
+
+    /// Removes the member's policies and deactivates them, so none of their credentials open anything.
+    ///
+    /// With `keep_record` their cards stay assigned, as a record of what they held. Without it the cards
+    /// are also deleted, freeing the fobs for re-enrollment. The user itself is kept either way, since
+    /// the API can't delete users. Failing to delete a card is reported rather than failing the call.
+    pub async fn offboard_member(
+        &self,
+        member: &MemberRef,
+        keep_record: bool,
+    ) -> UnifiResult<OffboardReport> {
+        let user = self.backend.find_user(member).await?;
+        self.backend.remove_policies(&user.id).await?;
+        self.backend.deactivate_user(&user.id).await?;
+        let mut report = OffboardReport {
+            user_id: user.id.clone(),
+            removed_cards: vec![],
+            failed_cards: vec![],
+        };
+        if !keep_record {
+            for card in user.nfc_cards {
+                match self.backend.remove_card(&card).await {
+                    Ok(()) => report.removed_cards.push(card),
+                    Err(e) => {
+                        warn!("Failed to remove card {} of user {}: {e}", card.id, user.id);
+                        report.failed_cards.push((card, e));
+                    }
+                }
+            }
+        }
+        info!("Offboarded user {}", user.id);
+        Ok(report)
+    }
+
+    /// Enrolls a card on the desk's reader
+    async fn enroll(&self) -> UnifiResult<NfcCard> {
+        self.backend
+            .enroll_card(
+                &self.device_id,
+                self.enrollment_options.clone(),
+                self.enrollment_timeout,
+            )
+            .await
+    }
+
+    /// Fails if a user already has `email`, an empty email is never taken
+    async fn check_email_unused(&self, email: &str) -> UnifiResult<()> {
+        if email.is_empty() {
+            return Ok(());
+        }
+        match self
+            .backend
+            .find_user(&MemberRef::Email(email.to_string()))
+            .await
+        {
+            Ok(user) => Err(UnifiError::EmailAlreadyExists {
+                existing_user_id: Some(user.id),
+            }),
+            Err(UnifiError::UserNotFound { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Undoes the changes, newest first, carrying on past failures
+    async fn undo_all(&self, mut undo: Vec<Undo>) -> Vec<UndoResult> {
+        let mut results = vec![];
+        while let Some(undo) = undo.pop() {
+            let result = match &undo {
+                Undo::RemoveCard(card) => self.backend.remove_card(card).await,
+                Undo::DeactivateUser(user_id) => self.backend.deactivate_user(user_id).await,
+            };
+            if let Err(e) = &result {
+                warn!("Failed to undo {undo:?}: {e}");
+            }
+            results.push(UndoResult {
+                undo,
+                error: result.err(),
+            });
+        }
+        results
+    }
+}
+
+/// Tidies the member's details, see [FrontDesk::with_name_normalization]
+fn normalize_member(member: NewUser) -> NewUser {
+    NewUser {
+        first_name: normalize_name(&member.first_name),
+        last_name: normalize_name(&member.last_name),
+        email: member.email.trim().to_lowercase(),
+        employee_number: member.employee_number.trim().to_string(),
+        allow_duplicate_email: member.allow_duplicate_email,
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| {
+            let mixed_case =
+                word.chars().any(char::is_lowercase) && word.chars().any(char::is_uppercase);
+            if mixed_case {
+                return word.to_string();
+            }
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,265 @@
+//! Access policies granted for a limited time and revoked once they expire.
+//!
+//! The developer API has no time bounded policy assignment for users (visitors have their own
+//! schedules, which this crate doesn't wrap), so expiry is enforced by calling
+//! [UnifiClient::revoke_expired_grants] regularly, e.g. from `spawn_periodic` when the
+//! `periodic` feature is on. Until it runs, an expired grant still opens doors.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::future::BoxFuture;
+use log::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::state_store::{load_state, save_state, VersionedState};
+use crate::{StateStore, UnifiClient, UnifiError, UnifiResult};
+
+/// Key [StoredGrantRegistry] keeps its grants under
+const GRANTS_KEY: &str = "unifi_access/grants";
+
+/// Policies given to a user until a point in time, see [UnifiClient::grant_temporary_access]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct TemporaryGrant {
+    /// Unique id of the grant in the registry
+    pub id: String,
+    pub user_id: String,
+    /// Policies covered by the grant: those it added, and those the user already held through another
+    /// grant. Policies the user held otherwise aren't included.
+    pub policy_ids: Vec<String>,
+    /// Seconds since the unix epoch when the grant was made
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub granted_at: u64,
+    /// Seconds since the unix epoch after which the grant is revoked
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub expires_at: u64,
+}
+
+/// Storage for active grants, install a durable one with [UnifiClient::with_grant_registry]
+/// so grants outlive the process
+pub trait GrantRegistry: Send + Sync {
+    /// Stores a new grant
+    fn record<'a>(&'a self, grant: &'a TemporaryGrant) -> BoxFuture<'a, UnifiResult<()>>;
+    /// All grants that haven't been revoked yet
+    fn grants(&self) -> BoxFuture<'_, UnifiResult<Vec<TemporaryGrant>>>;
+    /// Forgets a revoked grant, removing an unknown id is not an error
+    fn remove<'a>(&'a self, grant_id: &'a str) -> BoxFuture<'a, UnifiResult<()>>;
+}
+
+/// Registry kept in memory, lost when the process exits. The default for a new client.
+#[derive(Debug, Default)]
+pub struct InMemoryGrantRegistry {
+    grants: Mutex<Vec<TemporaryGrant>>,
+}
+
+impl GrantRegistry for InMemoryGrantRegistry {
+    fn record<'a>(&'a self, grant: &'a TemporaryGrant) -> BoxFuture<'a, UnifiResult<()>> {
+        self.grants.lock().unwrap().push(grant.clone());
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn grants(&self) -> BoxFuture<'_, UnifiResult<Vec<TemporaryGrant>>> {
+        let grants = self.grants.lock().unwrap().clone();
+        Box::pin(std::future::ready(Ok(grants)))
+    }
+
+    fn remove<'a>(&'a self, grant_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        self.grants.lock().unwrap().retain(|g| g.id != grant_id);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Format of the grants in a [StateStore]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredGrants {
+    grants: Vec<TemporaryGrant>,
+}
+
+impl VersionedState for StoredGrants {
+    const VERSION: u8 = 1;
+}
+
+/// Registry kept in a [StateStore], so grants survive restarts. Install it with [UnifiClient::with_grant_store].
+pub struct StoredGrantRegistry {
+    store: Arc<dyn StateStore>,
+    // Serializes read-modify-write of the stored grants within this process
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl StoredGrantRegistry {
+    pub fn new(store: Arc<dyn StateStore>) -> StoredGrantRegistry {
+        StoredGrantRegistry {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn load(&self) -> UnifiResult<StoredGrants> {
+        Ok(load_state(self.store.as_ref(), GRANTS_KEY)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn update(&self, change: impl FnOnce(&mut StoredGrants)) -> UnifiResult<()> {
+        let _guard = self.lock.lock().await;
+        let mut state = self.load().await?;
+        change(&mut state);
+        save_state(self.store.as_ref(), GRANTS_KEY, &state).await
+    }
+}
+
+impl GrantRegistry for StoredGrantRegistry {
+    fn record<'a>(&'a self, grant: &'a TemporaryGrant) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.update(|state| state.grants.push(grant.clone())))
+    }
+
+    fn grants(&self) -> BoxFuture<'_, UnifiResult<Vec<TemporaryGrant>>> {
+        Box::pin(async { Ok(self.load().await?.grants) })
+    }
+
+    fn remove<'a>(&'a self, grant_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(self.update(move |state| state.grants.retain(|g| g.id != grant_id)))
+    }
+}
+
+fn unix_secs(time: SystemTime) -> UnifiResult<u64> {
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Policies the user's grants that haven't expired at `now` cover
+fn still_granted(grants: &[TemporaryGrant], user_id: &str, now: u64) -> HashSet<String> {
+    grants
+        .iter()
+        .filter(|grant| grant.user_id == user_id && grant.expires_at > now)
+        .flat_map(|grant| grant.policy_ids.iter().cloned())
+        .collect()
+}
+
+impl UnifiClient {
+    /// Stores temporary grants in `registry` instead of in memory
+    pub fn with_grant_registry(mut self, registry: Arc<dyn GrantRegistry>) -> UnifiClient {
+        self.grants = registry;
+        self
+    }
+
+    /// Stores temporary grants in `store`, see [StoredGrantRegistry]
+    pub fn with_grant_store(self, store: Arc<dyn StateStore>) -> UnifiClient {
+        self.with_grant_registry(Arc::new(StoredGrantRegistry::new(store)))
+    }
+
+    /// Adds the policies to the user's current ones and records a grant that expires at `until`.
+    /// The controller doesn't expire the policies itself, call [UnifiClient::revoke_expired_grants] regularly.
+    ///
+    /// Grants of the same policy overlap: it is only revoked once every grant covering it has expired.
+    /// Policies the user held before, not through a grant, are never revoked.
+    pub async fn grant_temporary_access(
+        &self,
+        user_id: &str,
+        policy_ids: &[&str],
+        until: SystemTime,
+    ) -> UnifiResult<TemporaryGrant> {
+        let now = SystemTime::now();
+        let granted = still_granted(&self.grants.grants().await?, user_id, unix_secs(now)?);
+        let mut held: Vec<String> = self
+            .get_access_policies_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|p| p.id)
+            .collect();
+        let mut covered: Vec<String> = vec![];
+        let mut added: Vec<String> = vec![];
+        for id in policy_ids.iter().map(|id| id.to_string()) {
+            let is_held = held.contains(&id);
+            // A policy held through another grant is covered by this one too, one held otherwise is left alone
+            if covered.contains(&id) || (is_held && !granted.contains(&id)) {
+                continue;
+            }
+            if !is_held {
+                added.push(id.clone());
+            }
+            covered.push(id);
+        }
+        held.extend(added);
+        self.assign_access_policies(user_id, held).await?;
+        let grant = TemporaryGrant {
+            id: format!("{user_id}-{}", now.duration_since(UNIX_EPOCH)?.as_nanos()),
+            user_id: user_id.to_string(),
+            policy_ids: covered,
+            granted_at: unix_secs(now)?,
+            expires_at: unix_secs(until)?,
+        };
+        info!(
+            "Granted {:?} to user {user_id} until {}",
+            grant.policy_ids, grant.expires_at
+        );
+        self.grants.record(&grant).await?;
+        Ok(grant)
+    }
+
+    /// Removes the policies of every grant past its expiry and forgets the grant, returning the revoked grants.
+    ///
+    /// Only the policies the grant covers are removed, anything assigned since is kept, and so is any
+    /// policy another grant that hasn't expired still covers.
+    /// Safe to run repeatedly: policies the user no longer holds, or a deleted user, count as revoked.
+    /// A grant that fails to revoke stays in the registry to be retried on the next run.
+    pub async fn revoke_expired_grants(&self) -> UnifiResult<Vec<TemporaryGrant>> {
+        let now = unix_secs(SystemTime::now())?;
+        let grants = self.grants.grants().await?;
+        let mut revoked = vec![];
+        for grant in &grants {
+            if grant.expires_at > now {
+                continue;
+            }
+            let kept = still_granted(&grants, &grant.user_id, now);
+            match self.revoke_grant(grant, &kept).await {
+                Ok(()) => {
+                    self.grants.remove(&grant.id).await?;
+                    revoked.push(grant.clone());
+                }
+                Err(e) => warn!(
+                    "Failed to revoke grant {} for user {}, will retry: {e}",
+                    grant.id, grant.user_id
+                ),
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// Removes the policies of `grant` from its user, except those in `kept`
+    async fn revoke_grant(
+        &self,
+        grant: &TemporaryGrant,
+        kept: &HashSet<String>,
+    ) -> UnifiResult<()> {
+        let held = match self.get_access_policies_for_user(&grant.user_id).await {
+            Ok(held) => held,
+            Err(UnifiError::UserNotFound { .. }) => {
+                info!(
+                    "User {} of grant {} no longer exists",
+                    grant.user_id, grant.id
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let granted: HashSet<&str> = grant
+            .policy_ids
+            .iter()
+            .filter(|id| !kept.contains(*id))
+            .map(String::as_str)
+            .collect();
+        let remaining: Vec<String> = held
+            .iter()
+            .filter(|p| !granted.contains(p.id.as_str()))
+            .map(|p| p.id.clone())
+            .collect();
+        if remaining.len() == held.len() {
+            return Ok(());
+        }
+        self.assign_access_policies(&grant.user_id, remaining).await
+    }
+}
@@ -29,45 +29,352 @@
 //!
 //! The API is fully async and technically relies on `tokio`, but tokio could be removed if folks want a different runtime.
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "sync")]
+mod access_audit;
+#[cfg(feature = "sync")]
+pub use access_audit::{AuditPhase, AuditProgress, AuditSnapshot, Inconsistency};
+#[cfg(feature = "schedules")]
+mod access_evaluation;
+#[cfg(feature = "schedules")]
+pub use access_evaluation::{
+    AccessEvaluation, AccessReason, EvaluationConfidence, PolicyEvaluation, PolicyOutcome,
+};
+#[cfg(feature = "logs")]
+mod admin_activity;
+#[cfg(feature = "logs")]
+pub use admin_activity::{AdminAction, AdminActionKind};
+mod api_version;
+pub use api_version::ApiVersion;
+mod assignments;
+pub use assignments::{AssignmentResult, UnknownPolicies};
+mod audit;
+pub use audit::{AuditEntry, AuditOutcome, AuditSink, InMemoryAuditSink, JsonlAuditSink};
+mod budgets;
+pub use budgets::{BudgetExceeded, BudgetKind, DEFAULT_LATENCY_BUDGET, DEFAULT_SIZE_BUDGET};
+mod bulk;
+pub use bulk::{BulkExecutor, BulkProgress, BulkResult, CancellationToken, DeactivateMode};
+mod cache;
+pub use cache::{CacheTtls, CachedUnifiClient};
+mod card_lookup;
+mod card_token;
+pub use card_token::CardToken;
+#[cfg(feature = "sync")]
+mod changes;
+#[cfg(feature = "sync")]
+pub use changes::{UserChanges, UserHashes};
+mod clock;
+pub use clock::{ClockSkew, CLOCK_SKEW_WARNING_THRESHOLD};
+mod convert;
+mod credential_provider;
+pub use credential_provider::CredentialProvider;
+mod credentials;
+#[cfg(feature = "logs")]
+mod denials;
+#[cfg(feature = "logs")]
+pub use denials::{AccessDeniedMonitor, DeniedActor, DeniedAttempt, DeniedBurst};
+mod deprecation;
+pub use deprecation::{deprecation, Deprecation, DEPRECATIONS};
+mod door_groups;
+pub use door_groups::{DoorGroup, DoorGroupDiff};
+#[cfg(feature = "schedules")]
+mod door_unlock_schedules;
+#[cfg(feature = "schedules")]
+pub use door_unlock_schedules::DoorUnlockSchedule;
+#[cfg(feature = "sync")]
+mod drift;
+#[cfg(feature = "sync")]
+pub use drift::{DriftReport, UserDrift};
+mod employee_numbers;
+mod enrollment;
+pub use enrollment::{
+    ConcurrentEnrollment, EnrollmentHandle, EnrollmentOptions, ResetUaCard, MAX_POLL_RETRIES,
+};
+mod error;
+pub use error::{ApiErrorKind, UnifiError, UnifiResult};
+#[cfg(feature = "frontdesk")]
+mod frontdesk;
+#[cfg(feature = "frontdesk")]
+pub use frontdesk::{
+    FrontDesk, FrontDeskBackend, MemberRef, OffboardReport, OnboardOutcome, OnboardStep,
+    ReplaceOutcome, ReplaceStep, Undo, UndoResult, WorkflowFailure,
+};
+mod grants;
+pub use grants::{GrantRegistry, InMemoryGrantRegistry, StoredGrantRegistry, TemporaryGrant};
+mod list_options;
+use list_options::ListOptions;
+pub use list_options::{
+    AccessPolicyListOptions, NfcCardListOptions, SystemLogOptions, UserListOptions,
+};
+mod lock_rules;
+pub use lock_rules::DoorLockRule;
+mod metrics;
+pub use metrics::{MetricsRecorder, RequestMetrics, RequestOutcome};
+#[cfg(feature = "reports")]
+mod occupancy;
+#[cfg(feature = "reports")]
+pub use occupancy::{
+    estimate_occupancy, AreaOccupancy, DoorOpening, HourlyOccupancy, OccupancyConfig,
+    OccupancyReport,
+};
+#[cfg(feature = "periodic")]
+mod periodic;
+#[cfg(feature = "periodic")]
+pub use periodic::{PeriodicHandle, PeriodicStatus};
+mod policy_resources;
+pub use policy_resources::{ResolvedPolicy, ResolvedResource};
+mod polling;
+pub use polling::PollingConfig;
+mod pool;
+pub mod prelude;
+pub use pool::{SiteConfig, UnifiClientPool};
+mod queue;
+pub use queue::{
+    Delivery, InMemoryJournal, JsonFileJournal, MutationJournal, QueuedMutation, QueuedUnifiClient,
+    ReplayOutcome, ReplayReport, StoredJournal,
+};
+mod recording;
+pub use recording::scrub_recording;
+#[cfg(feature = "reports")]
+mod reports;
+#[cfg(feature = "reports")]
+pub use reports::{
+    build_access_report, AccessEntry, AccessReport, ReportGrouping, ReportRow, ReportSpec,
+};
+mod scheduled_unlocks;
+pub use scheduled_unlocks::{ScheduledUnlock, ScheduledUnlockReport, TimeRange};
+#[cfg(feature = "schedules")]
+mod schedules;
+#[cfg(feature = "schedules")]
+pub use schedules::{
+    Holiday, HolidayGroup, Schedule, ScheduleDecision, ScheduleWindow, WeekSchedule,
+};
+mod secret;
+pub use secret::Secret;
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "sim")]
+pub use sim::{SimFault, SimHandle, SimRequest, SimSeed, Simulator, DEFAULT_SIM_TOKEN};
+#[cfg(feature = "sync")]
+mod snapshot;
+#[cfg(feature = "sync")]
+pub use snapshot::{
+    RestoreAction, RestoreEntry, RestoreObject, RestoreOptions, RestoreReport, Snapshot,
+};
+#[cfg(feature = "sync")]
+mod snapshot_diff;
+#[cfg(feature = "sync")]
+pub use snapshot_diff::{FieldChange, PolicyChange, SnapshotDiff, UserChange};
+mod stable;
+pub use stable::STABLE_SCHEMA_VERSION;
+mod state_store;
+pub use state_store::{InMemoryStateStore, JsonFileStateStore, StateStore};
+mod summary;
+pub use summary::CredentialSummary;
+#[cfg(feature = "schedules")]
+mod time_zone;
+#[cfg(feature = "schedules")]
+pub use time_zone::SiteTimeZone;
+mod topology;
+pub use topology::{doors_on_floor, find_floor_for_door, Building, DoorRef, Floor};
+#[cfg(feature = "ts")]
+mod ts_export;
+#[cfg(feature = "ts")]
+pub use ts_export::{export_typescript, stale_typescript, typescript_bindings};
+mod validation;
 
 use log::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
-use simple_error::bail;
+#[cfg(feature = "ts")]
 use ts_rs::TS;
 
 /// The base client object that operations are provided on.
+///
+/// Cloning is cheap, clones share the connection pool, installed hooks and the dry run plan.
+#[derive(Clone)]
 pub struct UnifiClient {
     client: reqwest::Client,
     auth_token: String,
     host: String,
+    port: u16,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    budgets: budgets::RequestBudgets,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    dry_run: bool,
+    validate: bool,
+    check_duplicate_email: bool,
+    check_user_exists: bool,
+    deprecation_warnings: bool,
+    maintenance_window: Option<std::time::Duration>,
+    api_versions: api_version::ApiVersions,
+    polling: PollingConfig,
+    grants: Arc<dyn GrantRegistry>,
+    scheduled_unlocks: Arc<scheduled_unlocks::UnlockSchedule>,
+    http_recorder: Option<Arc<recording::HttpRecorder>>,
+    clock: Arc<clock::SkewTracker>,
+    correct_clock_skew: bool,
+    #[cfg(feature = "schedules")]
+    site_time_zone: SiteTimeZone,
+    enrollments: Arc<enrollment::ActiveEnrollments>,
+    planned_requests: Arc<Mutex<Vec<PlannedRequest>>>,
 }
 
 /// Represents a user in the unifi system.
 /// This is used with serde_json to serialize and deserialize the JSON responses from the API.
-#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+///
+/// Schedule overrides set on an individual user in the UI aren't exposed by the developer API, neither on
+/// the user nor on a sub-endpoint, so a user's access policies don't tell the whole story for such users.
+/// [UnifiClient::evaluate_access] marks its answers with [EvaluationConfidence::MayHaveOverrides] for this reason.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(TS))]
 pub struct User {
     /// ID is in the form of a uuid
     pub id: String,
     pub first_name: String,
     pub last_name: String,
+    /// Some firmware sends null instead of an empty list
+    #[serde(default, deserialize_with = "null_as_default")]
     pub nfc_cards: Vec<NfcCard>,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub employee_number: String,
     pub user_email: String,
+    #[serde(default)]
+    pub status: UserStatus,
     /// Doing a bit of a hack here
     /// access_policies isn't provided in the main users API by unifi
     /// But we need for our use case so we're including it here
+    /// Policies that can't be parsed are skipped with a warning rather than failing the user
+    #[serde(default, deserialize_with = "skip_invalid_policies")]
     pub access_policies: Option<Vec<AccessPolicy>>,
 }
 
+/// Whether a user account is in use
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UserStatus {
+    Active,
+    /// Invited but not yet set up
+    Pending,
+    Deactivated,
+    /// Missing, or a status this crate doesn't know about
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+/// The elements of a list response that could be parsed, and the ones that couldn't.
+/// Returned by the `_lenient` list methods so one malformed record doesn't fail the whole list.
+#[derive(Debug)]
+pub struct PartialList<T> {
+    pub items: Vec<T>,
+    /// Each element that failed to parse, with the reason
+    pub failures: Vec<(serde_json::Value, serde_json::Error)>,
+}
+
+/// Whether a list has pages after one that was `empty`, with `fetched` items collected so far.
+/// Stops once the reported total has been collected, at an empty page (in case the total is overstated),
+/// or after the first page if the endpoint doesn't paginate.
+fn has_more_pages(pagination: Option<&Pagination>, empty: bool, fetched: usize) -> bool {
+    pagination.is_some_and(|pagination| !empty && fetched < pagination.total as usize)
+}
+
+/// Deserializes a null or missing field as the type's default value
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Deserializes a list of policies, dropping the elements that aren't a recognizable policy
+fn skip_invalid_policies<'de, D>(deserializer: D) -> Result<Option<Vec<AccessPolicy>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(values) = Option::<Vec<serde_json::Value>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        values
+            .into_iter()
+            .filter_map(|value| match serde_json::from_value(value.clone()) {
+                Ok(policy) => Some(policy),
+                Err(e) => {
+                    warn!("Skipping access policy that couldn't be parsed ({e}): {value}");
+                    None
+                }
+            })
+            .collect(),
+    ))
+}
+
+/// The details needed to register a user, see [UnifiClient::create_user]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct NewUser {
+    pub first_name: String,
+    pub last_name: String,
+    /// May be left empty
+    pub email: String,
+    pub employee_number: String,
+    /// Skips [UnifiClient::with_duplicate_email_check] for this user, for the rare legitimate case of
+    /// two people sharing an address, e.g. family members. Firmware that rejects duplicates still does.
+    #[serde(default)]
+    pub allow_duplicate_email: bool,
+}
+
+impl NewUser {
+    /// Sets [NewUser::allow_duplicate_email]
+    pub fn with_duplicate_email_allowed(mut self, allowed: bool) -> NewUser {
+        self.allow_duplicate_email = allowed;
+        self
+    }
+}
+
+/// Changes to an existing user, see [UnifiClient::update_user].
+/// Fields left as None are not sent and keep their current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct UserUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(rename = "user_email", skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub employee_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+}
+
 /// Represents an NFC card in the unifi system.
-#[derive(Debug, Serialize, Deserialize, Clone, TS)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(TS))]
 pub struct NfcCard {
-    /// Display name of the card in UI
+    /// The display id, the number printed on the card and shown in the UI. Not the token.
     pub id: String,
-    /// Actual NFC token
-    pub token: String,
+    /// The token the card sends to readers, which is what the controller identifies cards by
+    #[cfg_attr(feature = "ts", ts(type = "string"))]
+    pub token: Secret,
+}
+
+/// An NFC card as listed by the controller, whether or not it is assigned, see [UnifiClient::get_all_nfc_cards]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct NfcCardRecord {
+    /// The number printed on the card, the same as [NfcCard::id]
+    #[serde(default)]
+    pub display_id: String,
+    #[cfg_attr(feature = "ts", ts(type = "string"))]
+    pub token: Secret,
+    /// Id of the user the card is assigned to, None if it is unassigned
+    #[serde(default)]
+    pub user_id: Option<String>,
 }
 
 /// The response format for a list of users
@@ -84,22 +391,121 @@ struct GenericResponse {
     pub data: Option<serde_json::Value>,
     pub msg: String,
     pub code: String,
+    /// Only sent by list endpoints
+    #[serde(default)]
+    pub pagination: Option<Pagination>,
+    /// Anything else the controller sent alongside, some endpoints put warnings here
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl GenericResponse {
+    /// Logs the message and any extra fields of a successful response, which can carry warnings
+    /// (e.g. a policy referencing an offline door) that would otherwise be lost
+    fn warn_if_noteworthy(&self, api_path: &str) {
+        let msg = self.msg.trim();
+        let plain_success = msg.is_empty() || msg.eq_ignore_ascii_case("success");
+        if plain_success && self.extra.is_empty() {
+            return;
+        }
+        let endpoint = metrics::endpoint_template(api_path);
+        if self.extra.is_empty() {
+            warn!("Controller succeeded with a message for {endpoint}: {msg}");
+        } else {
+            warn!(
+                "Controller succeeded with a message for {endpoint}: {msg} {:?}",
+                self.extra
+            );
+        }
+    }
+}
+
+/// Paging information sent with list responses
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct Pagination {
+    /// The page returned, starting from 1
+    pub page_num: u32,
+    pub page_size: u32,
+    /// Number of items across all pages
+    pub total: u32,
+}
+
+/// One page of a list endpoint
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// None if the controller didn't send paging information, in which case `items` is the whole list
+    pub pagination: Option<Pagination>,
+}
+
+/// Result of [UnifiClient::get_all_users_with_policies]
+#[derive(Debug)]
+pub struct UsersWithPolicies {
+    /// Every user, those listed in `failures` have `access_policies` set to None
+    pub users: Vec<User>,
+    /// Id of each user whose policies couldn't be fetched, with the reason
+    pub failures: Vec<(String, UnifiError)>,
+}
+
+/// An unprocessed response from the controller, see [UnifiClient::raw_request_full]
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    /// HTTP status code of the response
+    pub status: u16,
+    /// Value of the content-type header, if the response had one
+    pub content_type: Option<String>,
+    /// Body of the response exactly as received
+    pub body: String,
+    /// Time from the Date header, if the response had a valid one
+    pub date: Option<std::time::SystemTime>,
+}
+
+/// A request that wasn't sent because the client is in dry run mode, see [UnifiClient::with_dry_run]
+#[derive(Debug, Clone)]
+pub struct PlannedRequest {
+    pub method: reqwest::Method,
+    /// Path of the endpoint, e.g. `/api/v1/developer/users`
+    pub path: String,
+    pub body: Option<serde_json::Value>,
 }
 
 /// Represents an access policy in the unifi system
-#[derive(Debug, Deserialize, Serialize, Clone, TS)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[cfg_attr(feature = "ts", derive(TS))]
 pub struct AccessPolicy {
     // UUID of the policy
+    // Some firmware names these policy_id and policy_name when expanded inside a user
+    #[serde(alias = "policy_id")]
     pub id: String,
+    #[serde(alias = "policy_name")]
     pub name: String,
-    // Ignoring this for now as I don't need it
-    // pub resources: Vec<String>,
+    /// The doors and door groups the policy grants access to
+    #[serde(default)]
+    pub resources: Vec<PolicyResource>,
+    /// The schedule the policy grants access in, see [UnifiClient::get_schedule].
+    /// Empty if the controller didn't send one, e.g. for policies expanded inside a user.
+    #[serde(
+        default,
+        deserialize_with = "null_as_default",
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub schedule_id: String,
     // type
-    // schedule_id
+}
+
+/// Something an access policy grants access to
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct PolicyResource {
+    pub id: String,
+    /// e.g. `door` or `door_group`
+    #[serde(rename = "type")]
+    pub resource_type: String,
 }
 
 /// Represents a physical device within the building
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct Device {
     // Oddly device ids are not uuids...🤷
     pub id: String,
@@ -108,8 +514,17 @@ pub struct Device {
     pub device_type: String,
 }
 
+impl Device {
+    /// False for device types known to have no card reader (hubs), which can't run an enrollment session.
+    /// Unknown types are assumed to be capable so new hardware isn't rejected.
+    pub fn can_enroll_cards(&self) -> bool {
+        !(self.device_type.starts_with("UAH") || self.device_type.starts_with("UA-Hub"))
+    }
+}
+
 /// The available system log topics within unifi
-#[derive(Debug, Deserialize, Serialize, TS)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
 #[serde(rename_all = "snake_case")]
 pub enum SystemLogTopic {
     All,
@@ -119,6 +534,57 @@ pub enum SystemLogTopic {
     DeviceEvents,
     AdminActivity,
     Visitor,
+    /// A topic without a variant here, e.g. from newer firmware, sent and received as the raw string.
+    /// Prefer parsing with [str::parse], which maps known names to their variant.
+    #[serde(untagged)]
+    Custom(String),
+}
+
+impl SystemLogTopic {
+    /// The name the controller uses for the topic
+    pub fn as_str(&self) -> &str {
+        match self {
+            SystemLogTopic::All => "all",
+            SystemLogTopic::DoorOpenings => "door_openings",
+            SystemLogTopic::Critical => "critical",
+            SystemLogTopic::Updates => "updates",
+            SystemLogTopic::DeviceEvents => "device_events",
+            SystemLogTopic::AdminActivity => "admin_activity",
+            SystemLogTopic::Visitor => "visitor",
+            SystemLogTopic::Custom(topic) => topic,
+        }
+    }
+}
+
+impl std::fmt::Display for SystemLogTopic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SystemLogTopic {
+    type Err = UnifiError;
+
+    /// Known names become their variant, anything else [SystemLogTopic::Custom]. Only blank names are rejected.
+    fn from_str(topic: &str) -> Result<SystemLogTopic, UnifiError> {
+        let topic = topic.trim();
+        Ok(match topic {
+            "" => {
+                return Err(UnifiError::Validation {
+                    field: "topic".to_string(),
+                    reason: "must not be empty".to_string(),
+                })
+            }
+            "all" => SystemLogTopic::All,
+            "door_openings" => SystemLogTopic::DoorOpenings,
+            "critical" => SystemLogTopic::Critical,
+            "updates" => SystemLogTopic::Updates,
+            "device_events" => SystemLogTopic::DeviceEvents,
+            "admin_activity" => SystemLogTopic::AdminActivity,
+            "visitor" => SystemLogTopic::Visitor,
+            custom => SystemLogTopic::Custom(custom.to_string()),
+        })
+    }
 }
 
 /// An individual entry in the unifi system log
@@ -149,15 +615,134 @@ pub struct SystemLogEventWrapper {
 #[derive(Debug, Deserialize)]
 pub struct SystemLogResponse {
     hits: Vec<SystemLogEventWrapper>,
-    // pages: u32,
-    // total: u32,
+    // Not sent by all firmware versions
+    pages: Option<u32>,
+    total: Option<u32>,
+}
+
+/// One page of the system log, see [UnifiClient::fetch_system_log_page]
+#[derive(Debug)]
+pub struct SystemLogPage {
+    pub events: Vec<SystemLogEventWrapper>,
+    /// The page that was requested, starting from 1
+    pub page: u32,
+    /// Number of pages available, None if the controller didn't say
+    pub total_pages: Option<u32>,
+    /// Number of events available across all pages, None if the controller didn't say
+    pub total_hits: Option<u32>,
+}
+
+/// Number of items requested per page when fetching a whole list
+const LIST_PAGE_SIZE: u32 = 100;
+
+/// Number of events requested per page when paging through the system log
+const SYSTEM_LOG_PAGE_SIZE: u32 = 100;
+
+/// Above this many distinct actors, resolve_log_actors fetches the whole user list instead of each user
+const ACTOR_LOOKUP_LIST_THRESHOLD: usize = 50;
+
+/// The port Unifi Access serves the developer API on
+pub const DEFAULT_PORT: u16 = 12445;
+
+/// The maximum number of requests helpers that fan out over many objects will have in flight at once
+pub const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Creates the HTTP client builder with the TLS policy used to talk to the controller.
+/// All certificate handling lives here so that any other transport opened against the
+/// controller (e.g. a future notification socket) makes the same trust decisions.
+fn tls_client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        // The SSL cert is self-signed and untrusted
+        // We have to disable cert checking to get around this
+        .danger_accept_invalid_certs(true)
+}
+
+/// The `id` field of a create user response
+fn user_id_from_response(response: &serde_json::Value) -> UnifiResult<String> {
+    let id = response
+        .get("id")
+        .ok_or(UnifiError::Other("id not found in response".to_string()))?
+        .as_str()
+        .ok_or(UnifiError::Other("id not a string".to_string()))?;
+    Ok(id.to_string())
+}
+
+/// Code of a registration rejected for its email, whether malformed or already taken
+const EMAIL_REJECTED_CODE: &str = "CODE_USER_EMAIL_ERROR";
+
+/// Sorts by name, then id for equal names. The controller's order varies between calls,
+/// so list methods sort to give the same output for the same data.
+fn sort_by_name_then_id<T>(items: &mut [T], key: impl Fn(&T) -> (&str, &str)) {
+    items.sort_by(|a, b| key(a).cmp(&key(b)));
+}
+
+/// Percent-encodes a caller provided value (id, card token) so it is always sent as a single path segment
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte))
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
 }
 
-/// The error type for this crate
-type UnifiError = Box<dyn std::error::Error + Send + Sync>;
+/// True if the path has a `.` or `..` segment, which URL parsing resolves away. Encoding can't prevent that,
+/// an id of `..` is still `..` once encoded, so such requests would reach a different endpoint.
+fn has_dot_segment(api_path: &str) -> bool {
+    let path = api_path.split('?').next().unwrap_or_default();
+    path.split('/').any(|segment| {
+        matches!(
+            segment.to_ascii_lowercase().replace("%2e", ".").as_str(),
+            "." | ".."
+        )
+    })
+}
 
-/// The result type for this crate
-type UnifiResult<T> = Result<T, UnifiError>;
+/// True for requests that don't modify anything on the controller
+fn is_read_only(method: &reqwest::Method, api_path: &str) -> bool {
+    // The system log is read with a POST
+    *method == reqwest::Method::GET
+        || (*method == reqwest::Method::POST && api_path.contains("/developer/system/logs"))
+}
+
+/// Sends a request and reads the full body
+async fn send_request(request: reqwest::RequestBuilder) -> reqwest::Result<RawResponse> {
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(clock::parse_http_date);
+    let body = response.text().await?;
+    Ok(RawResponse {
+        status,
+        content_type,
+        body,
+        date,
+    })
+}
+
+/// Classifies a response for metrics, only parses the body if the HTTP layer succeeded
+fn request_outcome(result: &reqwest::Result<RawResponse>) -> RequestOutcome {
+    match result {
+        Err(_) => RequestOutcome::TransportError,
+        Ok(response) if !(200..300).contains(&response.status) => RequestOutcome::HttpError,
+        Ok(response) => match serde_json::from_str::<GenericResponse>(&response.body) {
+            Ok(parsed) if parsed.code != "SUCCESS" => RequestOutcome::ApiError,
+            _ => RequestOutcome::Success,
+        },
+    }
+}
 
 impl UnifiClient {
     /// Creates a new client against the given address with the given auth token
@@ -170,41 +755,292 @@ impl UnifiClient {
     /// For full documentation of the API see:
     ///
     /// <https://core-config-gfoz.uid.alpha.ui.com/configs/unifi-access/api_reference.pdf>
+    ///
+    /// Panics if `hostname` isn't a valid address, use [UnifiClient::try_new] to handle that as an error.
     pub fn new(hostname: &str, key: &str) -> UnifiClient {
-        let client = reqwest::Client::builder()
-            // The SSL cert is self-signed and untrusted
-            // We have to disable cert checking to get around this
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap();
-        UnifiClient {
+        UnifiClient::try_new(hostname, key)
+            .unwrap_or_else(|e| panic!("Couldn't create UnifiClient for {hostname:?}: {e}"))
+    }
+
+    /// Creates a client, checking the settings first.
+    ///
+    /// `hostname` must be a bare hostname or IP address, optionally followed by `:port`,
+    /// e.g. `192.168.1.1` or `access.example.com:12445`, otherwise [UnifiError::Validation] is returned.
+    /// Surrounding whitespace is trimmed from both the hostname and the key.
+    pub fn try_new(hostname: &str, key: &str) -> UnifiResult<UnifiClient> {
+        let (host, port) = validation::parse_host("hostname", hostname)?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(UnifiError::Validation {
+                field: "key".to_string(),
+                reason: "must not be empty".to_string(),
+            });
+        }
+        let client = tls_client_builder().build()?;
+        Ok(UnifiClient {
             client,
             auth_token: key.to_string(),
-            host: hostname.to_string(),
+            host,
+            port: port.unwrap_or(DEFAULT_PORT),
+            metrics: None,
+            budgets: Default::default(),
+            audit_sink: None,
+            dry_run: false,
+            validate: true,
+            check_duplicate_email: false,
+            check_user_exists: true,
+            deprecation_warnings: true,
+            maintenance_window: None,
+            api_versions: Default::default(),
+            polling: Default::default(),
+            grants: Arc::new(InMemoryGrantRegistry::default()),
+            scheduled_unlocks: Arc::new(scheduled_unlocks::UnlockSchedule::new(Arc::new(
+                InMemoryStateStore::default(),
+            ))),
+            http_recorder: None,
+            clock: Default::default(),
+            correct_clock_skew: false,
+            #[cfg(feature = "schedules")]
+            site_time_zone: SiteTimeZone::UTC,
+            enrollments: Default::default(),
+            planned_requests: Default::default(),
+        })
+    }
+
+    /// Connects to the controller on a port other than the default of 12445
+    pub fn with_port(mut self, port: u16) -> UnifiClient {
+        self.port = port;
+        self
+    }
+
+    /// Enables or disables dry run mode.
+    ///
+    /// While enabled no mutating request is sent to the controller, reads still go through.
+    /// Each request that would have been sent is recorded, see [UnifiClient::planned_requests].
+    /// Operations that only need the controller to accept the request report success,
+    /// operations that need data back (e.g. the id of a newly registered user) fail with [UnifiError::DryRun].
+    pub fn with_dry_run(mut self, enabled: bool) -> UnifiClient {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Turns the local checks of user input (names, emails, card tokens) on or off, they are on by default.
+    /// The checks only reject input the controller is known to reject, see [UnifiError::Validation].
+    pub fn with_validation(mut self, enabled: bool) -> UnifiClient {
+        self.validate = enabled;
+        self
+    }
+
+    /// Searches for a user with the same email before registering one, off by default.
+    ///
+    /// Some firmware rejects a second user with an existing email, others silently create it. With the check
+    /// on, both fail with [UnifiError::EmailAlreadyExists], at the cost of fetching every user per registration.
+    /// Users with [NewUser::allow_duplicate_email] set skip the check.
+    pub fn with_duplicate_email_check(mut self, enabled: bool) -> UnifiClient {
+        self.check_duplicate_email = enabled;
+        self
+    }
+
+    /// Makes [UnifiClient::get_access_policies_for_user] confirm a user exists when it gets no policies, on by default.
+    ///
+    /// Some firmware answers with an empty list for a user id that doesn't exist. With the check on, an empty
+    /// answer costs a second request to fetch the user, and a missing user fails with [UnifiError::UserNotFound]
+    /// on every firmware. Turn it off when the ids are known to be valid, e.g. just listed, and a missing user
+    /// may show up as having no policies.
+    pub fn with_user_existence_check(mut self, enabled: bool) -> UnifiClient {
+        self.check_user_exists = enabled;
+        self
+    }
+
+    /// Rides out controller restarts and firmware updates: requests that fail because the controller
+    /// refused the connection or answered 503 are retried with backoff for up to `window` before the error is returned.
+    /// The waits between retries follow [UnifiClient::with_polling_config].
+    /// Such requests never reached the controller, so retrying mutations is safe.
+    /// Without this a 503 fails immediately with [UnifiError::ControllerUnavailable].
+    pub fn with_maintenance_window(mut self, window: std::time::Duration) -> UnifiClient {
+        self.maintenance_window = Some(window);
+        self
+    }
+
+    /// Sets how long helpers wait between polls and retries, e.g. enrollment polling and
+    /// [UnifiClient::with_maintenance_window] retries. See [PollingConfig] for the defaults.
+    pub fn with_polling_config(mut self, polling: PollingConfig) -> UnifiClient {
+        self.polling = polling;
+        self
+    }
+
+    /// The requests that were not sent because dry run mode is enabled, in the order they were made
+    pub fn planned_requests(&self) -> Vec<PlannedRequest> {
+        self.planned_requests.lock().unwrap().clone()
+    }
+
+    /// Returns and clears the recorded dry run requests
+    pub fn take_planned_requests(&self) -> Vec<PlannedRequest> {
+        std::mem::take(&mut *self.planned_requests.lock().unwrap())
+    }
+
+    /// If dry run is enabled and this request would modify the controller, records it and returns the plan
+    fn plan_if_dry_run(
+        &self,
+        method: &reqwest::Method,
+        api_path: &str,
+        body: &Option<serde_json::Value>,
+    ) -> Option<PlannedRequest> {
+        if !self.dry_run || is_read_only(method, api_path) {
+            return None;
         }
+        let planned = PlannedRequest {
+            method: method.clone(),
+            path: api_path.to_string(),
+            body: body.clone(),
+        };
+        info!(
+            "Dry run, not sending: {method} {} {:?}",
+            metrics::endpoint_template(api_path),
+            body.clone().map(audit::redact_json)
+        );
+        self.planned_requests.lock().unwrap().push(planned.clone());
+        Some(planned)
+    }
+
+    /// Reports every change made through this client to the given sink, see [AuditSink]
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> UnifiClient {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Runs a mutating operation, reporting it to the audit sink if one is installed
+    async fn audited<T>(
+        &self,
+        operation: &str,
+        targets: &[&str],
+        payload: serde_json::Value,
+        operation_future: impl std::future::Future<Output = UnifiResult<T>>,
+    ) -> UnifiResult<T> {
+        let Some(sink) = &self.audit_sink else {
+            return operation_future.await;
+        };
+        let started = std::time::Instant::now();
+        let result = operation_future.await;
+        sink.record(AuditEntry::new(
+            operation,
+            targets,
+            payload,
+            &result,
+            started.elapsed(),
+            self.dry_run,
+        ));
+        result
+    }
+
+    /// Reports every request made by this client to the given recorder, see [MetricsRecorder]
+    pub fn with_metrics_recorder(mut self, recorder: Arc<dyn MetricsRecorder>) -> UnifiClient {
+        self.metrics = Some(recorder);
+        self
     }
 
     /// Internal function that wraps all requests
-    async fn generic_request_raw(
+    async fn generic_request_full(
         &self,
         method: reqwest::Method,
         api_path: String,
         body: Option<serde_json::Value>,
-    ) -> UnifiResult<String> {
-        let url = format!("https://{}:12445{}", self.host, api_path);
-        debug!("Sending request: {method} {url} {body:?}");
+    ) -> UnifiResult<RawResponse> {
+        if has_dot_segment(&api_path) {
+            return Err(UnifiError::Validation {
+                field: "path".to_string(),
+                reason: format!("{api_path} has a . or .. segment, an id can't be . or .."),
+            });
+        }
+        if self.plan_if_dry_run(&method, &api_path, &body).is_some() {
+            return Ok(RawResponse {
+                status: 200,
+                content_type: Some("application/json".to_string()),
+                body: json!({"code": "SUCCESS", "msg": "dry run", "data": null}).to_string(),
+                date: None,
+            });
+        }
+        let url = format!("https://{}:{}{}", self.host, self.port, api_path);
+        // Paths and bodies can contain card tokens, only their redacted forms are ever logged
+        let endpoint = metrics::endpoint_template(&api_path);
+        debug!(
+            "Sending request: {method} {endpoint} {:?}",
+            body.clone().map(audit::redact_json)
+        );
+        let body = body.map(|b| b.to_string());
+        let deadline = self
+            .maintenance_window
+            .map(|window| std::time::Instant::now() + window);
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .send_once(&method, &url, &api_path, body.as_deref())
+                .await;
+            let unavailable = match &result {
+                Err(e) => e.is_connect(),
+                Ok(response) => response.status == 503,
+            };
+            let retry_delay = self.polling.delay(attempt);
+            let retry = unavailable
+                && deadline.is_some_and(|d| std::time::Instant::now() + retry_delay < d);
+            if !retry {
+                let response = result?;
+                trace!(
+                    "Got raw response: {} {}",
+                    response.status,
+                    audit::redact_body(&response.body)
+                );
+                return Ok(response);
+            }
+            warn!("Controller unavailable for {method} {endpoint}, retrying in {retry_delay:?}");
+            self.polling.wait(attempt).await;
+            attempt += 1;
+        }
+    }
+
+    /// Makes a single attempt at a request, reporting it to the metrics recorder
+    async fn send_once(
+        &self,
+        method: &reqwest::Method,
+        url: &str,
+        api_path: &str,
+        body: Option<&str>,
+    ) -> reqwest::Result<RawResponse> {
         let mut request = self
             .client
-            .request(method, url)
+            .request(method.clone(), url)
             .bearer_auth(&self.auth_token);
         if let Some(body) = body {
             request = request
                 .header("content-type", "application/json")
                 .body(body.to_string());
         }
-        let response = request.send().await?.text().await?;
-        trace!("Got raw response: {response}");
-        Ok(response)
+        let start = std::time::Instant::now();
+        let result = send_request(request).await;
+        if let Some(date) = result.as_ref().ok().and_then(|r| r.date) {
+            self.clock.observe(date, std::time::SystemTime::now());
+        }
+        if let Some(recorder) = &self.http_recorder {
+            recorder.record(method, api_path, body, &result);
+        }
+        let duration = start.elapsed();
+        let endpoint = metrics::endpoint_template(api_path);
+        if let Some(recorder) = &self.metrics {
+            recorder.record_request(&RequestMetrics {
+                method,
+                endpoint: &endpoint,
+                outcome: request_outcome(&result),
+                status: result.as_ref().ok().map(|r| r.status),
+                duration,
+            });
+        }
+        self.check_budgets(
+            method,
+            &endpoint,
+            duration,
+            result.as_ref().ok().map(|r| r.body.len()),
+        );
+        result
     }
 
     /// Generically hits an endpoint and handles the response code without deserializing the "data" field
@@ -214,42 +1050,282 @@ impl UnifiClient {
         api_path: String,
         body: Option<serde_json::Value>,
     ) -> UnifiResult<Option<serde_json::Value>> {
+        Ok(self
+            .generic_request_envelope(method, api_path, body)
+            .await?
+            .and_then(|envelope| envelope.data))
+    }
+
+    /// Hits an endpoint, checks the response code and returns the whole response envelope.
+    /// Returns None for an empty response.
+    async fn generic_request_envelope(
+        &self,
+        method: reqwest::Method,
+        api_path: String,
+        body: Option<serde_json::Value>,
+    ) -> UnifiResult<Option<GenericResponse>> {
+        let result = self
+            .generic_request_envelope_once(method.clone(), api_path.clone(), body.clone())
+            .await;
+        match result {
+            Err(e) if e.kind() == Some(ApiErrorKind::Moved) => {
+                let Some(moved) = api_version::EndpointMove::of(&api_path) else {
+                    return Err(e);
+                };
+                if !self.api_versions.retry_moved {
+                    return Err(moved.into_error());
+                }
+                warn!(
+                    "{} moved from {:?} to {:?}, retrying",
+                    moved.resource, moved.from, moved.to
+                );
+                self.generic_request_envelope_once(method, moved.retry_path, body)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// A single attempt of [UnifiClient::generic_request_envelope], on the version the path names
+    async fn generic_request_envelope_once(
+        &self,
+        method: reqwest::Method,
+        api_path: String,
+        body: Option<serde_json::Value>,
+    ) -> UnifiResult<Option<GenericResponse>> {
         let response = self
-            .generic_request_raw(method, api_path.clone(), body)
+            .generic_request_full(method, api_path.clone(), body)
             .await?;
-        trace!("Got response from unifi: {response}");
-        let parsed: GenericResponse = serde_json::from_str(&response)?;
+        trace!(
+            "Got response from unifi: {}",
+            audit::redact_body(&response.body)
+        );
+        if response.status == 503 {
+            return Err(UnifiError::ControllerUnavailable { endpoint: api_path });
+        }
+        // Some endpoints answer with an empty body instead of an envelope when there is nothing to return
+        if response.body.trim().is_empty() && (200..300).contains(&response.status) {
+            return Ok(None);
+        }
+        // Anything that isn't the JSON envelope (proxy error pages, truncated bodies) is reported with the
+        // body attached, a bare parse error doesn't tell the operator anything
+        let parsed: GenericResponse = serde_json::from_str(&response.body)
+            .map_err(|_| UnifiError::unexpected_response(&response))?;
         if parsed.code != "SUCCESS" {
-            bail!("Failed request to {api_path}: {}", parsed.msg);
+            return Err(UnifiError::Api {
+                endpoint: api_path,
+                kind: ApiErrorKind::from_code(&parsed.code),
+                code: parsed.code,
+                msg: parsed.msg,
+            });
+        }
+        parsed.warn_if_noteworthy(&api_path);
+        Ok(Some(parsed))
+    }
+
+    /// Fetches one page of a list endpoint, `api_path` must not already have a query string
+    async fn generic_request_page<T: DeserializeOwned>(
+        &self,
+        api_path: &str,
+        page_num: u32,
+        options: &impl ListOptions,
+    ) -> UnifiResult<Page<T>> {
+        let envelope = self
+            .generic_request_envelope(
+                reqwest::Method::GET,
+                list_options::list_path(api_path, page_num, options),
+                None,
+            )
+            .await?;
+        let Some(envelope) = envelope else {
+            return Ok(Page {
+                items: vec![],
+                pagination: None,
+            });
+        };
+        let items = match envelope.data {
+            // An empty list can come back as null data
+            None | Some(serde_json::Value::Null) => vec![],
+            Some(data) => serde_json::from_value(data)?,
+        };
+        Ok(Page {
+            items,
+            pagination: envelope.pagination,
+        })
+    }
+
+    /// Fetches every page of a list endpoint, see [has_more_pages] for when it stops
+    async fn generic_request_all_pages<T: DeserializeOwned>(
+        &self,
+        api_path: &str,
+        options: &impl ListOptions,
+    ) -> UnifiResult<Vec<T>> {
+        let mut items = vec![];
+        let mut page_num = 1;
+        loop {
+            let page: Page<T> = self
+                .generic_request_page(api_path, page_num, options)
+                .await?;
+            let empty = page.items.is_empty();
+            items.extend(page.items);
+            if !has_more_pages(page.pagination.as_ref(), empty, items.len()) {
+                return Ok(items);
+            }
+            page_num += 1;
         }
-        Ok(parsed.data)
     }
 
-    /// Generically hits and endpoint, handles the response code, and tries to deserialize the "data" field
+    /// Like generic_request_all_pages, but parses each element on its own as its page arrives,
+    /// collecting the ones that fail instead of failing the whole request
+    async fn generic_request_all_pages_lenient<T: DeserializeOwned>(
+        &self,
+        api_path: &str,
+        options: &impl ListOptions,
+    ) -> UnifiResult<PartialList<T>> {
+        let mut list = PartialList {
+            items: vec![],
+            failures: vec![],
+        };
+        let mut fetched = 0;
+        let mut page_num = 1;
+        loop {
+            let page: Page<serde_json::Value> = self
+                .generic_request_page(api_path, page_num, options)
+                .await?;
+            let empty = page.items.is_empty();
+            fetched += page.items.len();
+            for element in page.items {
+                match T::deserialize(&element) {
+                    Ok(item) => list.items.push(item),
+                    Err(e) => {
+                        warn!("Skipping malformed element from {api_path}: {e}");
+                        list.failures.push((element, e));
+                    }
+                }
+            }
+            if !has_more_pages(page.pagination.as_ref(), empty, fetched) {
+                return Ok(list);
+            }
+            page_num += 1;
+        }
+    }
+
+    /// Generically hits and endpoint, handles the response code, and tries to deserialize the "data" field.
+    /// Errors if the controller didn't send any data, see [UnifiClient::generic_request_optional] for endpoints where that's fine.
     async fn generic_request<T: DeserializeOwned>(
         &self,
         method: reqwest::Method,
         api_path: String,
         body: Option<serde_json::Value>,
     ) -> UnifiResult<T> {
+        self.generic_request_optional(method, api_path, body)
+            .await?
+            .ok_or(UnifiError::Other("No data found in response".to_string()))
+    }
+
+    /// The same as generic_request, but a successful response with missing or null "data" returns None
+    async fn generic_request_optional<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        api_path: String,
+        body: Option<serde_json::Value>,
+    ) -> UnifiResult<Option<T>> {
+        // A mutation that needs data back can't be faked, so dry runs stop here
+        if let Some(planned) = self.plan_if_dry_run(&method, &api_path, &body) {
+            return Err(UnifiError::DryRun(planned));
+        }
         let raw = self
             .generic_request_no_parse(method, api_path.clone(), body)
             .await?;
-        Ok(serde_json::from_value(raw.ok_or(
-            simple_error::SimpleError::new(format!("No data found in response")),
-        )?)?)
+        match raw {
+            Some(serde_json::Value::Null) | None => Ok(None),
+            Some(data) => Ok(Some(serde_json::from_value(data)?)),
+        }
     }
 
-    /// Gets a list of all users.
-    /// Endpoint supports partial fetches and pagination, not using those yet.
-    /// Endpoint supports optionally getting access policy info, not implementing that yet.
+    /// Escape hatch for endpoints this crate doesn't wrap yet.
+    /// Sends a request to `path` (e.g. `/api/v1/developer/doors`), checks the response code the same
+    /// way every other method does, and returns the "data" field of the response.
+    /// Returns `serde_json::Value::Null` if the controller sent no data.
+    ///
+    /// This is not considered a stable part of the API surface of the crate,
+    /// prefer the typed methods where they exist.
+    pub async fn raw_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> UnifiResult<serde_json::Value> {
+        Ok(self
+            .generic_request_no_parse(method, path.to_string(), body)
+            .await?
+            .unwrap_or(serde_json::Value::Null))
+    }
+
+    /// Escape hatch for endpoints that don't follow the standard `{code, msg, data}` response format.
+    /// Returns the HTTP status and untouched body, no checking of the response is performed.
+    /// Transport errors are still returned as errors.
+    ///
+    /// This is not considered a stable part of the API surface of the crate,
+    /// prefer the typed methods where they exist.
+    pub async fn raw_request_full(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> UnifiResult<RawResponse> {
+        self.generic_request_full(method, path.to_string(), body)
+            .await
+    }
+
+    /// Gets a list of all users, fetching every page, sorted by id
     pub async fn get_all_users(&self) -> UnifiResult<Vec<User>> {
-        self.generic_request(
-            reqwest::Method::GET,
-            "/api/v1/developer/users".to_string(),
-            None,
-        )
-        .await
+        self.get_all_users_with(UserListOptions::default()).await
+    }
+
+    /// Gets a list of all users, fetching every page, with the given page size, expansion etc.
+    /// Users are sorted by id, and expanded policies by name.
+    pub async fn get_all_users_with(
+        &self,
+        options: impl Into<UserListOptions>,
+    ) -> UnifiResult<Vec<User>> {
+        let mut users: Vec<User> = self
+            .generic_request_all_pages(&self.api_path("users"), &options.into())
+            .await?;
+        users.sort_by(|a, b| a.id.cmp(&b.id));
+        for policies in users.iter_mut().filter_map(|u| u.access_policies.as_mut()) {
+            sort_by_name_then_id(policies, |p| (&p.name, &p.id));
+        }
+        Ok(users)
+    }
+
+    /// Gets a single page of users, pages start from 1.
+    /// `options` can be just the page size, or a [UserListOptions].
+    pub async fn get_users_page(
+        &self,
+        page_num: u32,
+        options: impl Into<UserListOptions>,
+    ) -> UnifiResult<Page<User>> {
+        self.generic_request_page(&self.api_path("users"), page_num, &options.into())
+            .await
+    }
+
+    /// The same as get_all_users, but users that fail to parse are returned separately instead of failing the call
+    pub async fn get_all_users_lenient(&self) -> UnifiResult<PartialList<User>> {
+        self.get_all_users_lenient_with(UserListOptions::default())
+            .await
+    }
+
+    /// The same as get_all_users_with, but users that fail to parse are returned separately instead of failing the call
+    pub async fn get_all_users_lenient_with(
+        &self,
+        options: impl Into<UserListOptions>,
+    ) -> UnifiResult<PartialList<User>> {
+        let mut list: PartialList<User> = self
+            .generic_request_all_pages_lenient(&self.api_path("users"), &options.into())
+            .await?;
+        list.items.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(list)
     }
 
     /// The same as get_all_users but also collects the access policies for each user.
@@ -257,7 +1333,62 @@ impl UnifiClient {
     pub async fn get_all_users_with_access_information(&self) -> UnifiResult<Vec<User>> {
         let mut users = self.get_all_users().await?;
         for user in users.iter_mut() {
-            user.access_policies = Some(self.get_access_policies_for_user(&user.id).await?);
+            user.access_policies = Some(self.access_policies_of(&user.id, false).await?);
+        }
+        Ok(users)
+    }
+
+    /// Like get_all_users_with_access_information, but a failure to fetch one user's policies doesn't fail the whole call.
+    /// Policies are fetched for up to [MAX_CONCURRENT_REQUESTS] users at once.
+    /// Users whose policies couldn't be fetched are still returned, with `access_policies` set to None,
+    /// and are listed in [UsersWithPolicies::failures] so the caller can decide whether that's acceptable.
+    pub async fn get_all_users_with_policies(&self) -> UnifiResult<UsersWithPolicies> {
+        let mut users = self.get_all_users().await?;
+        let policies: Vec<_> = BulkExecutor::default()
+            .run(users.iter().map(|user| user.id.as_str()), |user_id| {
+                self.access_policies_of(user_id, false)
+            })
+            .await
+            .results
+            .into_iter()
+            .map(|(_, result)| result.expect("batch isn't cancelled"))
+            .collect();
+        let mut failures = vec![];
+        for (user, result) in users.iter_mut().zip(policies) {
+            match result {
+                Ok(policies) => user.access_policies = Some(policies),
+                Err(e) => {
+                    warn!("Failed to fetch access policies for user {}: {e}", user.id);
+                    failures.push((user.id.clone(), e));
+                }
+            }
+        }
+        Ok(UsersWithPolicies { users, failures })
+    }
+
+    /// Every user with their access policies, from one users list with `expand[]=access_policy`.
+    /// Users the list came back without policies for, e.g. on firmware that ignores the expansion, are
+    /// fetched one by one, [MAX_CONCURRENT_REQUESTS] at a time. Fails if any of them can't be fetched.
+    pub(crate) async fn get_all_users_expanded(&self) -> UnifiResult<Vec<User>> {
+        let mut users = self
+            .get_all_users_with(UserListOptions::new().expand_access_policies())
+            .await?;
+        let missing: Vec<usize> = (0..users.len())
+            .filter(|i| users[*i].access_policies.is_none())
+            .collect();
+        if missing.is_empty() {
+            return Ok(users);
+        }
+        debug!(
+            "The users list didn't include policies for {} users, fetching them separately",
+            missing.len()
+        );
+        let fetched = BulkExecutor::default()
+            .run(missing, |i| self.access_policies_of(&users[i].id, false))
+            .await
+            .results;
+        for (i, result) in fetched {
+            users[i].access_policies = Some(result.expect("batch isn't cancelled")?);
         }
         Ok(users)
     }
@@ -271,38 +1402,137 @@ impl UnifiClient {
         email: String,
         employee_number: String,
     ) -> UnifiResult<String> {
-        debug!("Sending register_user_request: {first_name} {last_name} {email} {employee_number}");
-        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
-        let register_user_response: serde_json::Value = self
-            .generic_request(
-                reqwest::Method::POST,
-                "/api/v1/developer/users".to_string(),
-                Some(json!({
-                    "first_name": first_name,
-                    "last_name": last_name,
-                    "user_email": email,
-                    "employee_number": employee_number,
-                    "onboard_time": now.as_secs(),
-                })),
-            )
-            .await?;
-        let id = register_user_response
-            .get("id")
-            .ok_or(simple_error::SimpleError::new("id not found in response"))?
-            .as_str()
-            .ok_or(simple_error::SimpleError::new("id not a string"))?;
-        Ok(id.to_string())
+        self.create_user(&NewUser {
+            first_name,
+            last_name,
+            email,
+            employee_number,
+            allow_duplicate_email: false,
+        })
+        .await
+    }
+
+    /// Registers a new user, returning the UUID of the created user.
+    /// Unless disabled with [UnifiClient::with_validation] the fields are checked before anything is sent,
+    /// returning [UnifiError::Validation] for a blank name or malformed email.
+    /// A controller rejecting the email because another user has it fails with [UnifiError::EmailAlreadyExists],
+    /// see [UnifiClient::with_duplicate_email_check] for firmware that accepts duplicates.
+    /// Use [UnifiClient::create_user_full] to get the whole created record.
+    pub async fn create_user(&self, user: &NewUser) -> UnifiResult<String> {
+        let response = self.send_create_user(user).await?;
+        user_id_from_response(&response)
+    }
+
+    /// Registers a new user like [UnifiClient::create_user], returning the created user as stored by the controller
+    /// (e.g. with its email normalized).
+    /// Some firmware only returns the id on creation, in which case the user is fetched afterwards.
+    pub async fn create_user_full(&self, user: &NewUser) -> UnifiResult<User> {
+        let response = self.send_create_user(user).await?;
+        match serde_json::from_value::<User>(response.clone()) {
+            Ok(created) => Ok(created),
+            Err(e) => {
+                debug!("Create user response isn't a full user ({e}), fetching it");
+                self.get_user_by_id(&user_id_from_response(&response)?)
+                    .await
+            }
+        }
+    }
+
+    /// Validates and sends the create user request, returning the data of the response
+    async fn send_create_user(&self, user: &NewUser) -> UnifiResult<serde_json::Value> {
+        if self.validate {
+            validation::validate_name("first_name", &user.first_name)?;
+            validation::validate_name("last_name", &user.last_name)?;
+            validation::validate_email("email", &user.email)?;
+        }
+        if self.check_duplicate_email && !user.allow_duplicate_email && !user.email.is_empty() {
+            if let Some(existing) = self.user_id_with_email(&user.email).await? {
+                return Err(UnifiError::EmailAlreadyExists {
+                    existing_user_id: Some(existing),
+                });
+            }
+        }
+        debug!("Sending register_user_request: {user:?}");
+        let now = self.timestamp_now().duration_since(std::time::UNIX_EPOCH)?;
+        let body = json!({
+            "first_name": user.first_name,
+            "last_name": user.last_name,
+            "user_email": user.email,
+            "employee_number": user.employee_number,
+            "onboard_time": now.as_secs(),
+        });
+        let result = self
+            .audited("create_user", &[user.email.as_str()], body.clone(), async {
+                self.generic_request(reqwest::Method::POST, self.api_path("users"), Some(body))
+                    .await
+            })
+            .await;
+        match result {
+            Err(e) if e.code() == Some(EMAIL_REJECTED_CODE) && !user.email.is_empty() => {
+                Err(self.email_rejection(&user.email, e).await)
+            }
+            result => result,
+        }
+    }
+
+    /// The error for a registration rejected with [EMAIL_REJECTED_CODE], which firmware uses both for a
+    /// malformed email and for one another user has. Looks for that user to tell the two apart, the
+    /// controller's error is returned as is if there is none or the lookup fails.
+    async fn email_rejection(&self, email: &str, error: UnifiError) -> UnifiError {
+        match self.user_id_with_email(email).await {
+            Ok(Some(existing)) => UnifiError::EmailAlreadyExists {
+                existing_user_id: Some(existing),
+            },
+            Ok(None) => error,
+            Err(e) => {
+                warn!(
+                    "Couldn't look up the user with email {email} to tell why it was rejected: {e}"
+                );
+                error
+            }
+        }
     }
 
-    /// Retrieves the list of access policies
+    /// Id of a user with the email, compared case insensitively
+    async fn user_id_with_email(&self, email: &str) -> UnifiResult<Option<String>> {
+        let email = email.trim();
+        Ok(self
+            .get_all_users()
+            .await?
+            .into_iter()
+            .find(|u| u.user_email.trim().eq_ignore_ascii_case(email))
+            .map(|u| u.id))
+    }
+
+    /// Retrieves the list of access policies, fetching every page, sorted by name
     pub async fn get_all_access_policies(&self) -> UnifiResult<Vec<AccessPolicy>> {
+        self.get_all_access_policies_with(AccessPolicyListOptions::default())
+            .await
+    }
+
+    /// Retrieves the list of access policies, fetching every page, with the given page size, expansion etc.
+    /// Policies are sorted by name.
+    pub async fn get_all_access_policies_with(
+        &self,
+        options: impl Into<AccessPolicyListOptions>,
+    ) -> UnifiResult<Vec<AccessPolicy>> {
         debug!("Sending get_all_access_policies_request");
-        self.generic_request(
-            reqwest::Method::GET,
-            "/api/v1/developer/access_policies".to_string(),
-            None,
-        )
-        .await
+        let mut policies: Vec<AccessPolicy> = self
+            .generic_request_all_pages(&self.api_path("access_policies"), &options.into())
+            .await?;
+        sort_by_name_then_id(&mut policies, |p| (&p.name, &p.id));
+        Ok(policies)
+    }
+
+    /// Gets a single page of access policies, pages start from 1.
+    /// `options` can be just the page size, or an [AccessPolicyListOptions].
+    pub async fn get_access_policies_page(
+        &self,
+        page_num: u32,
+        options: impl Into<AccessPolicyListOptions>,
+    ) -> UnifiResult<Page<AccessPolicy>> {
+        self.generic_request_page(&self.api_path("access_policies"), page_num, &options.into())
+            .await
     }
 
     /// Returns the details of an individual user by their uuid
@@ -310,97 +1540,287 @@ impl UnifiClient {
         debug!("Sending get_user_by_id_request: {user_id}");
         self.generic_request(
             reqwest::Method::GET,
-            format!("/api/v1/developer/users/{}", user_id),
+            self.api_path(&format!("users/{}", encode_path_segment(user_id))),
             None,
         )
         .await
     }
 
+    /// Updates the given fields of a user, see [UserUpdate].
+    /// Unless disabled with [UnifiClient::with_validation] the fields being changed are checked first.
+    pub async fn update_user(&self, user_id: &str, update: &UserUpdate) -> UnifiResult<()> {
+        if self.validate {
+            if let Some(first_name) = &update.first_name {
+                validation::validate_name("first_name", first_name)?;
+            }
+            if let Some(last_name) = &update.last_name {
+                validation::validate_name("last_name", last_name)?;
+            }
+            if let Some(email) = &update.email {
+                validation::validate_email("email", email)?;
+            }
+        }
+        let api = self.api_path(&format!("users/{}", encode_path_segment(user_id)));
+        debug!("Sending update_user_request: {user_id} {update:?}");
+        let body = serde_json::to_value(update)?;
+        self.audited("update_user", &[user_id], body.clone(), async {
+            self.generic_request_no_parse(reqwest::Method::PUT, api, Some(body))
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Fetches several users by id, running up to [MAX_CONCURRENT_REQUESTS] lookups at once.
+    /// Results are returned in the same order as `ids`, with a separate result per id
+    /// so that a single deleted user doesn't fail the whole batch.
+    pub async fn get_users_by_ids(&self, ids: &[&str]) -> UnifiResult<Vec<UnifiResult<User>>> {
+        debug!("Fetching {} users by id", ids.len());
+        let result = BulkExecutor::default()
+            .run(ids.iter().copied(), |id| self.get_user_by_id(id))
+            .await;
+        Ok(result
+            .results
+            .into_iter()
+            .map(|(_, user)| user.expect("batch isn't cancelled"))
+            .collect())
+    }
+
     /// Assigns an access policy to a user
     pub async fn assign_access_policies(
         &self,
         user_id: &str,
         policy_ids: Vec<String>,
     ) -> UnifiResult<()> {
-        let api = format!("/api/v1/developer/users/{}/access_policies", user_id);
+        let api = self.api_path(&format!(
+            "users/{}/access_policies",
+            encode_path_segment(user_id)
+        ));
         debug!("Sending assign_access_policy_request: {user_id} {policy_ids:?} to {api}");
-        let _ = self
-            .generic_request_no_parse(
-                reqwest::Method::PUT,
-                api,
-                Some(json!({
-                    "access_policy_ids": policy_ids,
-                })),
-            )
-            .await?;
-        Ok(())
+        let body = json!({
+            "access_policy_ids": policy_ids,
+        });
+        self.audited("assign_access_policies", &[user_id], body.clone(), async {
+            self.generic_request_no_parse(reqwest::Method::PUT, api, Some(body))
+                .await?;
+            Ok(())
+        })
+        .await
     }
 
     /// Removes all access policies from a user making them effectively inactive, but retaining the NFC card information
     pub async fn remove_all_access_policies_from_user(&self, user_id: &str) -> UnifiResult<()> {
-        let api = format!("/api/v1/developer/users/{}/access_policies", user_id);
+        let api = self.api_path(&format!(
+            "users/{}/access_policies",
+            encode_path_segment(user_id)
+        ));
         debug!("Sending assign_access_policy_request to remove access: {user_id} to {api}");
-        let _ = self
-            .generic_request_no_parse(
-                reqwest::Method::PUT,
-                api,
-                Some(json!({
-                    "access_policy_ids": [],
-                })),
-            )
-            .await?;
-        Ok(())
+        let body = json!({
+            "access_policy_ids": [],
+        });
+        self.audited(
+            "remove_all_access_policies_from_user",
+            &[user_id],
+            body.clone(),
+            async {
+                self.generic_request_no_parse(reqwest::Method::PUT, api, Some(body))
+                    .await?;
+                Ok(())
+            },
+        )
+        .await
     }
 
-    /// Retrieves the list of access policies for a given user
+    /// Same as remove_all_access_policies_from_user, but first fetches the user and checks their email
+    /// matches `expected_email` (ignoring case), returning [UnifiError::ConfirmationMismatch] if it doesn't.
+    /// Guards against revoking the wrong person's access because of a mistyped id.
+    pub async fn remove_all_access_policies_from_user_confirmed(
+        &self,
+        user_id: &str,
+        expected_email: &str,
+    ) -> UnifiResult<()> {
+        let user = self.get_user_by_id(user_id).await?;
+        if !user.user_email.eq_ignore_ascii_case(expected_email) {
+            return Err(UnifiError::ConfirmationMismatch {
+                object: format!("user {user_id}"),
+                expected: expected_email.to_string(),
+                actual: user.user_email,
+            });
+        }
+        self.remove_all_access_policies_from_user(user_id).await
+    }
+
+    /// Retrieves the list of access policies for a given user, sorted by name.
+    ///
+    /// Fails with [UnifiError::UserNotFound] if the user doesn't exist. Some firmware answers with an empty
+    /// list for a missing user, so an empty answer is checked with an extra request for the user,
+    /// see [UnifiClient::with_user_existence_check].
     pub async fn get_access_policies_for_user(
         &self,
         user_id: &str,
     ) -> UnifiResult<Vec<AccessPolicy>> {
-        let api = format!("/api/v1/developer/users/{}/access_policies", user_id);
+        self.access_policies_of(user_id, self.check_user_exists)
+            .await
+    }
+
+    /// [UnifiClient::get_access_policies_for_user], checking an empty answer only if `check_exists`.
+    /// Callers that just listed the users pass false.
+    pub(crate) async fn access_policies_of(
+        &self,
+        user_id: &str,
+        check_exists: bool,
+    ) -> UnifiResult<Vec<AccessPolicy>> {
+        let not_found = || UnifiError::UserNotFound {
+            user: format!("id {user_id}"),
+        };
+        let api = self.api_path(&format!(
+            "users/{}/access_policies",
+            encode_path_segment(user_id)
+        ));
         debug!("Sending get_access_policies_for_user_request: {user_id} to {api}");
-        let response = self
-            .generic_request(reqwest::Method::GET, api, None)
-            .await?;
-        Ok(response)
+        // A user without policies can come back as null data
+        let response: Option<Vec<AccessPolicy>> = match self
+            .generic_request_optional(reqwest::Method::GET, api, None)
+            .await
+        {
+            Ok(response) => response,
+            // Firmware that says why names the user in the code
+            Err(e)
+                if e.kind() == Some(ApiErrorKind::NotFound)
+                    && e.code().is_some_and(|code| code.starts_with("CODE_USER_")) =>
+            {
+                return Err(not_found())
+            }
+            Err(e) => return Err(e),
+        };
+        let mut policies = response.unwrap_or_default();
+        if policies.is_empty() && check_exists {
+            match self.get_user_by_id(user_id).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == Some(ApiErrorKind::NotFound) => return Err(not_found()),
+                Err(e) => return Err(e),
+            }
+        }
+        sort_by_name_then_id(&mut policies, |p| (&p.name, &p.id));
+        Ok(policies)
     }
 
-    /// Retrieves a list of all devices
+    /// Retrieves a list of all devices.
+    /// The controller lists a device once per group it is in (e.g. a hub serving two doors),
+    /// so devices are deduplicated by id, keeping the first occurrence. Sorted by name.
     pub async fn get_devices(&self) -> UnifiResult<Vec<Device>> {
-        // Weirdly this endpoint returns a list of lists of devices for no reason
-        let response: Vec<Vec<Device>> = self
-            .generic_request(
-                reqwest::Method::GET,
-                "/api/v1/developer/devices".to_string(),
-                None,
-            )
+        let mut seen = std::collections::HashSet::new();
+        let mut devices: Vec<Device> = self
+            .get_devices_grouped()
+            .await?
+            .into_iter()
+            .flatten()
+            .filter(|device| seen.insert(device.id.clone()))
+            .collect();
+        sort_by_name_then_id(&mut devices, |d| (&d.name, &d.id));
+        Ok(devices)
+    }
+
+    /// Retrieves all devices grouped the way the controller returns them, which can contain duplicates
+    pub async fn get_devices_grouped(&self) -> UnifiResult<Vec<Vec<Device>>> {
+        let endpoint = self.api_path("devices");
+        // Weirdly this endpoint returns a list of lists of devices
+        let response: Option<serde_json::Value> = self
+            .generic_request_optional(reqwest::Method::GET, endpoint.clone(), None)
             .await?;
-        Ok(response.into_iter().flatten().collect())
+        let groups = match response {
+            None => return Ok(vec![]),
+            Some(serde_json::Value::Array(groups)) => groups,
+            Some(other) => {
+                return Err(UnifiError::Other(format!(
+                    "Expected a list of device lists from {endpoint}, got {other}"
+                )))
+            }
+        };
+        groups
+            .into_iter()
+            .map(|group| -> UnifiResult<Vec<Device>> {
+                match group {
+                    serde_json::Value::Array(_) => Ok(serde_json::from_value(group)?),
+                    other => Err(UnifiError::Other(format!(
+                        "Expected a list of devices from {endpoint}, got {other}"
+                    ))),
+                }
+            })
+            .collect()
     }
 
     /// Starts a session on a specific reader device to enroll a new card
     /// Returns the created session id if successful
     /// The reader will now poll for a card
+    /// Cards already provisioned by Unifi are not reset, see [ResetUaCard] to allow that.
     pub async fn start_nfc_enrollment_session(&self, device_id: &str) -> UnifiResult<String> {
-        let enroll_response: serde_json::Value = self
-            .generic_request(
-                reqwest::Method::POST,
-                "/api/v1/developer/credentials/nfc_cards/sessions".to_string(),
-                Some(json!({
-                    "device_id": device_id,
-                    // Setting this as default for now
-                    "reset_ua_card": true
-                })),
-            )
-            .await?;
-        let session_id = enroll_response
-            .get("session_id")
-            .ok_or(simple_error::SimpleError::new(
-                "session_id not found in response",
-            ))?
-            .as_str()
-            .ok_or(simple_error::SimpleError::new("session_id not a string"))?;
-        Ok(session_id.to_string())
+        self.start_nfc_enrollment_session_with(device_id, None)
+            .await
+    }
+
+    /// Same as start_nfc_enrollment_session, but first looks the device up and returns
+    /// [UnifiError::DeviceNotFound] or [UnifiError::DeviceCannotEnroll] rather than the controller's own error.
+    /// Costs an extra request to list the devices.
+    pub async fn start_nfc_enrollment_session_checked(
+        &self,
+        device_id: &str,
+    ) -> UnifiResult<String> {
+        let devices = self.get_devices().await?;
+        let device = devices.iter().find(|d| d.id == device_id).ok_or_else(|| {
+            UnifiError::DeviceNotFound {
+                device_id: device_id.to_string(),
+            }
+        })?;
+        if !device.can_enroll_cards() {
+            return Err(UnifiError::DeviceCannotEnroll {
+                device_id: device_id.to_string(),
+                device_type: device.device_type.clone(),
+            });
+        }
+        self.start_nfc_enrollment_session(device_id).await
+    }
+
+    async fn start_nfc_enrollment_session_with(
+        &self,
+        device_id: &str,
+        reset: Option<&ResetUaCard>,
+    ) -> UnifiResult<String> {
+        let body = json!({
+            "device_id": device_id,
+            "reset_ua_card": reset.is_some()
+        });
+        let mut audit_payload = body.clone();
+        if let Some(reset) = reset {
+            warn!(
+                "Enrollment on device {device_id} may reset a provisioned card: {}",
+                reset.reason()
+            );
+            audit_payload["reset_reason"] = json!(reset.reason());
+        }
+        self.audited(
+            "start_nfc_enrollment_session",
+            &[device_id],
+            audit_payload,
+            async {
+                let enroll_response: serde_json::Value = self
+                    .generic_request(
+                        reqwest::Method::POST,
+                        self.api_path("credentials/nfc_cards/sessions"),
+                        Some(body),
+                    )
+                    .await?;
+                let session_id = enroll_response
+                    .get("session_id")
+                    .ok_or(UnifiError::Other(
+                        "session_id not found in response".to_string(),
+                    ))?
+                    .as_str()
+                    .ok_or(UnifiError::Other("session_id not a string".to_string()))?;
+                Ok(session_id.to_string())
+            },
+        )
+        .await
     }
 
     /// Hits the session status endpoint a single time
@@ -412,32 +1832,31 @@ impl UnifiClient {
         session_id: &str,
     ) -> UnifiResult<Option<NfcCard>> {
         let response = self
-            .generic_request_raw(
+            .generic_request_full(
                 reqwest::Method::GET,
-                format!(
-                    "/api/v1/developer/credentials/nfc_cards/sessions/{}",
-                    session_id
-                ),
+                self.api_path(&format!(
+                    "credentials/nfc_cards/sessions/{}",
+                    encode_path_segment(session_id)
+                )),
                 None,
             )
             .await?;
 
         // Check if we got the "SESSION_NOT_FOUND" meaning it has been cancelled
-        if response.to_string().contains("SESSION_NOT_FOUND") {
-            return Err(Box::new(simple_error::SimpleError::new(
-                "Session has been canceled",
-            )));
+        if response.body.contains("SESSION_NOT_FOUND") {
+            return Err(UnifiError::Other("Session has been canceled".to_string()));
         }
-        if response.to_string().contains("TOKEN_EMPTY") {
+        if response.body.contains("TOKEN_EMPTY") {
             // We don't have a card yet
             return Ok(None);
         }
         // Parse as JSON, strip the code and parse body
-        let parsed: GenericResponse = serde_json::from_str(&response)?;
+        let parsed: GenericResponse = serde_json::from_str(&response.body)
+            .map_err(|_| UnifiError::unexpected_response(&response))?;
 
         let body = parsed
             .data
-            .ok_or(simple_error::SimpleError::new("data not found in response"))?;
+            .ok_or(UnifiError::Other("data not found in response".to_string()))?;
 
         // Otherwise try to parse response as card and return it
         let x: Option<NfcCard> = serde_json::from_value(body)?;
@@ -446,39 +1865,52 @@ impl UnifiClient {
 
     /// Complete a single card enrollment on the device
     /// Will start an enrollment session, and poll until the card is scanned
+    /// The session id is written to `session_state` so it can be ended from elsewhere
+    #[deprecated(note = "use start_enrollment and EnrollmentHandle::wait_for_card instead")]
     pub async fn enroll_nfc_card(
         &self,
         device_id: &str,
         session_state: &Mutex<Option<String>>,
     ) -> UnifiResult<NfcCard> {
-        let session = self.start_nfc_enrollment_session(device_id).await?;
-        *session_state.lock().unwrap() = Some(session.clone());
-        loop {
-            let result = self.get_nfc_enrollment_session_status(&session).await;
-            match result {
-                Ok(Some(card)) => return Ok(card),
-                Ok(None) => {
-                    // Wait and read again
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-            }
-        }
+        self.warn_deprecated("UnifiClient::enroll_nfc_card");
+        let handle = self
+            .start_enrollment(device_id, EnrollmentOptions::default())
+            .await?;
+        *session_state.lock().unwrap() = Some(handle.session_id().to_string());
+        handle.wait_for_card().await
     }
 
     /// Assigns a card to a user
     pub async fn assign_nfc_card(&self, user_id: &str, card: &NfcCard) -> UnifiResult<()> {
-        self.generic_request_no_parse(
-            reqwest::Method::PUT,
-            format!("/api/v1/developer/users/{}/nfc_cards", user_id),
-            Some(json!({
-                "token": card.token,
-            })),
+        let token = self.card_token(card)?;
+        let body = json!({
+            "token": token,
+        });
+        self.audited(
+            "assign_nfc_card",
+            &[user_id, card.id.as_str()],
+            body.clone(),
+            async {
+                self.generic_request_no_parse(
+                    reqwest::Method::PUT,
+                    self.api_path(&format!("users/{}/nfc_cards", encode_path_segment(user_id))),
+                    Some(body),
+                )
+                .await?;
+                Ok(())
+            },
         )
-        .await?;
-        Ok(())
+        .await
+    }
+
+    /// The token of `card` in the controller's form, see [CardToken::parse].
+    /// A token that can't be normalized is an error, unless validation is disabled and it is sent as given.
+    fn card_token(&self, card: &NfcCard) -> UnifiResult<Secret> {
+        match CardToken::parse(card.token.expose()) {
+            Ok(token) => Ok(token.normalized().clone()),
+            Err(e) if self.validate => Err(e),
+            Err(_) => Ok(card.token.clone()),
+        }
     }
 
     /// Fetches the user id of the user the card is assigned to if any
@@ -488,84 +1920,289 @@ impl UnifiClient {
         struct CardUser {
             user_id: Option<String>,
         }
+        let token = self.card_token(card)?;
         let x: CardUser = self
             .generic_request(
                 reqwest::Method::GET,
-                format!(
-                    "/api/v1/developer/credentials/nfc_cards/tokens/{}",
-                    card.token
-                ),
+                self.api_path(&format!(
+                    "credentials/nfc_cards/tokens/{}",
+                    encode_path_segment(token.expose())
+                )),
                 None,
             )
             .await?;
         Ok(x.user_id)
     }
 
+    /// Retrieves every NFC card known to the controller, fetching every page, sorted by display id
+    pub async fn get_all_nfc_cards(&self) -> UnifiResult<Vec<NfcCardRecord>> {
+        debug!("Sending get_all_nfc_cards_request");
+        let mut cards: Vec<NfcCardRecord> = self
+            .generic_request_all_pages(
+                &self.api_path("credentials/nfc_cards/tokens"),
+                &NfcCardListOptions::default(),
+            )
+            .await?;
+        sort_by_name_then_id(&mut cards, |c| (&c.display_id, c.token.expose()));
+        Ok(cards)
+    }
+
+    /// Gets a single page of NFC cards, pages start from 1.
+    /// `options` can be just the page size, or an [NfcCardListOptions].
+    pub async fn get_nfc_cards_page(
+        &self,
+        page_num: u32,
+        options: impl Into<NfcCardListOptions>,
+    ) -> UnifiResult<Page<NfcCardRecord>> {
+        self.generic_request_page(
+            &self.api_path("credentials/nfc_cards/tokens"),
+            page_num,
+            &options.into(),
+        )
+        .await
+    }
+
+    /// Same as remove_nfc_card, but first checks the card is assigned to `expected_user_id`
+    /// (or to nobody if None), returning [UnifiError::ConfirmationMismatch] if it isn't.
+    pub async fn remove_nfc_card_confirmed(
+        &self,
+        card: &NfcCard,
+        expected_user_id: Option<&str>,
+    ) -> UnifiResult<()> {
+        let user_id = self.fetch_nfc_card_user(card).await?;
+        if user_id.as_deref() != expected_user_id {
+            return Err(UnifiError::ConfirmationMismatch {
+                object: format!("card {}", card.id),
+                expected: expected_user_id.unwrap_or("unassigned").to_string(),
+                actual: user_id.unwrap_or_else(|| "unassigned".to_string()),
+            });
+        }
+        self.remove_nfc_card(card).await
+    }
+
     /// Removes an NFC card from the system entirely
     /// This will find any users the card is enrolled to and unassign the card from them
     /// Card will need to be re-enrolled to be used again
     pub async fn remove_nfc_card(&self, card: &NfcCard) -> UnifiResult<()> {
-        // Fetch the card data to see if it assigned to anyone
-        let user = self.fetch_nfc_card_user(card).await?;
-        if let Some(user_id) = user {
-            info!("Unassigning card {card:?} from user {user_id}");
-            // Unassign the card from the user
-            self.generic_request_no_parse(
-                reqwest::Method::PUT,
-                format!("/api/v1/developer/users/{}/nfc_cards/delete", user_id),
-                Some(json!({
-                    "token": card.token,
-                })),
-            )
-            .await?;
-        }
+        let token = self.card_token(card)?;
+        let body = json!({
+            "token": token,
+        });
+        self.audited(
+            "remove_nfc_card",
+            &[card.id.as_str()],
+            body.clone(),
+            async {
+                // Fetch the card data to see if it assigned to anyone
+                let user = self.fetch_nfc_card_user(card).await?;
+                if let Some(user_id) = user {
+                    info!("Unassigning card {card:?} from user {user_id}");
+                    // Unassign the card from the user
+                    self.generic_request_no_parse(
+                        reqwest::Method::PUT,
+                        self.api_path(&format!(
+                            "users/{}/nfc_cards/delete",
+                            encode_path_segment(&user_id)
+                        )),
+                        Some(body),
+                    )
+                    .await?;
+                }
 
-        // Actually delete the card
-        info!("Deleting card {card:?}");
-        let endpoint = format!(
-            "/api/v1/developer/credentials/nfc_cards/tokens/{}",
-            card.token
-        );
-        self.generic_request_no_parse(reqwest::Method::DELETE, endpoint, None)
-            .await?;
-        info!("Card deleted successfully");
-        Ok(())
+                // Actually delete the card
+                info!("Deleting card {card:?}");
+                let endpoint = self.api_path(&format!(
+                    "credentials/nfc_cards/tokens/{}",
+                    encode_path_segment(token.expose())
+                ));
+                self.generic_request_no_parse(reqwest::Method::DELETE, endpoint, None)
+                    .await?;
+                info!("Card deleted successfully");
+                Ok(())
+            },
+        )
+        .await
     }
 
     /// Ends an ongoing enrollment session
     pub async fn end_enrollment_session(&self, session_id: &str) -> UnifiResult<()> {
-        self.generic_request_no_parse(
-            reqwest::Method::DELETE,
-            format!(
-                "/api/v1/developer/credentials/nfc_cards/sessions/{}",
-                session_id
-            ),
-            None,
+        self.audited(
+            "end_enrollment_session",
+            &[session_id],
+            serde_json::Value::Null,
+            async {
+                self.generic_request_no_parse(
+                    reqwest::Method::DELETE,
+                    self.api_path(&format!(
+                        "credentials/nfc_cards/sessions/{}",
+                        encode_path_segment(session_id)
+                    )),
+                    None,
+                )
+                .await?;
+                Ok(())
+            },
         )
-        .await?;
-        Ok(())
+        .await
     }
 
     /// Accesses the system log for the device. The system log contains a variety of useful
     /// information about the system, but can be overwhelming and requires pagination.
-    // TODO optional parameters: pagination, start and end times,
+    /// Only returns the first page the controller sends, see [UnifiClient::fetch_system_log_all].
+    /// `options` can be just a [SystemLogTopic], or [SystemLogOptions] to filter by time or actor.
     // TODO this function likely not recommended for use until we get it cleaned up more
     pub async fn fetch_system_log(
         &self,
-        topic: SystemLogTopic,
-        start_time: Option<std::time::SystemTime>,
+        options: impl Into<SystemLogOptions>,
     ) -> UnifiResult<Vec<SystemLogEventWrapper>> {
-        let body = json!({
-            "topic": topic,
-            "since": start_time.map(|t| t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()),
-        });
-        let full_response: SystemLogResponse = self
-            .generic_request(
+        let full_response: Option<SystemLogResponse> = self
+            .generic_request_optional(
                 reqwest::Method::POST, // Unifi... why is this a post?
-                "/api/v1/developer/system/logs".to_string(),
-                Some(body),
+                self.api_path("system/logs"),
+                Some(options.into().body()),
             )
             .await?;
-        Ok(full_response.hits)
+        Ok(full_response.map(|r| r.hits).unwrap_or_default())
+    }
+
+    /// Looks up the current user record for each distinct actor id in `events`.
+    /// Actors that are not users on the controller (deleted users, visitors, the system) map to None.
+    /// Fetches users individually, up to [MAX_CONCURRENT_REQUESTS] at once, or lists all users
+    /// if there are many distinct actors.
+    pub async fn resolve_log_actors(
+        &self,
+        events: &[SystemLogEventWrapper],
+    ) -> UnifiResult<std::collections::HashMap<String, Option<User>>> {
+        let ids: std::collections::BTreeSet<&str> = events
+            .iter()
+            .filter_map(|e| e.source.actor.get("id")?.as_str())
+            .filter(|id| !id.is_empty())
+            .collect();
+        if ids.len() > ACTOR_LOOKUP_LIST_THRESHOLD {
+            let mut users: std::collections::HashMap<String, User> = self
+                .get_all_users()
+                .await?
+                .into_iter()
+                .map(|u| (u.id.clone(), u))
+                .collect();
+            return Ok(ids
+                .into_iter()
+                .map(|id| (id.to_string(), users.remove(id)))
+                .collect());
+        }
+        let ids: Vec<&str> = ids.into_iter().collect();
+        let mut actors = std::collections::HashMap::new();
+        for (id, result) in ids.iter().zip(self.get_users_by_ids(&ids).await?) {
+            let user = match result {
+                Ok(user) => Some(user),
+                // The code for an unknown user varies, any answer from the controller means it isn't there
+                Err(UnifiError::Api { .. }) => None,
+                Err(e) => return Err(e),
+            };
+            actors.insert(id.to_string(), user);
+        }
+        Ok(actors)
+    }
+
+    /// Fetches a single page of the system log, pages start from 1
+    pub async fn fetch_system_log_page(
+        &self,
+        page: u32,
+        options: impl Into<SystemLogOptions>,
+    ) -> UnifiResult<SystemLogPage> {
+        let options = options.into();
+        let response: Option<SystemLogResponse> = self
+            .generic_request_optional(
+                reqwest::Method::POST,
+                list_options::list_path(&self.api_path("system/logs"), page, &options),
+                Some(options.body()),
+            )
+            .await?;
+        Ok(match response {
+            Some(response) => SystemLogPage {
+                events: response.hits,
+                page,
+                total_pages: response.pages,
+                total_hits: response.total,
+            },
+            None => SystemLogPage {
+                events: vec![],
+                page,
+                total_pages: None,
+                total_hits: None,
+            },
+        })
+    }
+
+    /// Fetches every page of the system log.
+    /// Stops at the last page the controller reports, or at the first page that isn't full if it doesn't report totals.
+    pub async fn fetch_system_log_all(
+        &self,
+        options: impl Into<SystemLogOptions>,
+    ) -> UnifiResult<Vec<SystemLogEventWrapper>> {
+        let options = options.into();
+        let mut events = vec![];
+        let mut page = 1;
+        loop {
+            let result = self.fetch_system_log_page(page, options.clone()).await?;
+            let short_page = result.events.len() < options.items_per_page() as usize;
+            events.extend(result.events);
+            let last_page = match result.total_pages {
+                Some(total_pages) => page >= total_pages,
+                None => short_page,
+            };
+            if last_page {
+                return Ok(events);
+            }
+            page += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_segments_are_percent_encoded() {
+        assert_eq!(encode_path_segment("abc-123_x.y~z"), "abc-123_x.y~z");
+        assert_eq!(encode_path_segment("ab/cd"), "ab%2Fcd");
+        assert_eq!(encode_path_segment("a b+c"), "a%20b%2Bc");
+        assert_eq!(encode_path_segment("50%?#"), "50%25%3F%23");
+        assert_eq!(encode_path_segment("é"), "%C3%A9");
+        assert_eq!(encode_path_segment(""), "");
+    }
+
+    #[test]
+    fn traversal_stays_in_one_segment() {
+        let path = format!(
+            "/api/v1/developer/users/{}",
+            encode_path_segment("../doors")
+        );
+        assert_eq!(path, "/api/v1/developer/users/..%2Fdoors");
+        assert!(!has_dot_segment(&path));
+        let path = format!(
+            "/api/v1/developer/users/{}",
+            encode_path_segment("..\\doors")
+        );
+        assert!(!has_dot_segment(&path));
+    }
+
+    #[test]
+    fn dot_segments_are_detected() {
+        for id in [".", "..", "%2e", ".%2E", "%2E%2e"] {
+            let path = format!(
+                "/api/v1/developer/users/{}/nfc_cards",
+                encode_path_segment(id)
+            );
+            // Encoding turns the % of the last three into %25, only bare dots get through
+            assert_eq!(has_dot_segment(&path), !id.contains('%'), "{id}");
+            assert!(
+                has_dot_segment(&format!("/api/v1/developer/users/{id}")),
+                "{id}"
+            );
+        }
+        assert!(!has_dot_segment("/api/v1/developer/users/...?keyword=.."));
+        assert!(!has_dot_segment("/api/v1/developer/users/a.b"));
     }
 }
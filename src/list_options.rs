@@ -0,0 +1,353 @@
+//! Options for the list endpoints.
+//!
+//! Every list has its own options type built with chained setters, e.g.
+//! `UserListOptions::new().page_size(50).expand_access_policies()`. The default options behave like
+//! the plain list methods, and a `u32` converts into any of them as the page size, so
+//! `client.get_users_page(1, 100)` keeps working and new filters don't change method signatures.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::{encode_path_segment, SystemLogTopic, LIST_PAGE_SIZE, SYSTEM_LOG_PAGE_SIZE};
+
+/// Implemented by the options of every list endpoint, so the request layer renders them the same way
+pub(crate) trait ListOptions {
+    /// Items requested per page
+    fn items_per_page(&self) -> u32;
+
+    /// Query parameters besides paging, as (name, value) pairs. Values are encoded when rendered.
+    fn query_params(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+}
+
+/// `api_path` with the page and the query parameters of `options` appended.
+/// `api_path` must not already have a query string.
+pub(crate) fn list_path(api_path: &str, page_num: u32, options: &impl ListOptions) -> String {
+    let mut path = format!(
+        "{api_path}?page_num={page_num}&page_size={}",
+        options.items_per_page()
+    );
+    for (name, value) in options.query_params() {
+        path.push_str(&format!("&{name}={}", encode_path_segment(&value)));
+    }
+    path
+}
+
+/// Options for listing users, see [crate::UnifiClient::get_all_users_with]
+#[derive(Debug, Clone, Default)]
+pub struct UserListOptions {
+    page_size: Option<u32>,
+    expand_access_policies: bool,
+}
+
+impl UserListOptions {
+    pub fn new() -> UserListOptions {
+        UserListOptions::default()
+    }
+
+    /// Users requested per page, 100 by default
+    pub fn page_size(mut self, page_size: u32) -> UserListOptions {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Has the controller include each user's access policies in the list,
+    /// filling [crate::User::access_policies] without a request per user
+    pub fn expand_access_policies(mut self) -> UserListOptions {
+        self.expand_access_policies = true;
+        self
+    }
+}
+
+impl From<u32> for UserListOptions {
+    fn from(page_size: u32) -> UserListOptions {
+        UserListOptions::new().page_size(page_size)
+    }
+}
+
+impl ListOptions for UserListOptions {
+    fn items_per_page(&self) -> u32 {
+        self.page_size.unwrap_or(LIST_PAGE_SIZE)
+    }
+
+    fn query_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if self.expand_access_policies {
+            params.push(("expand[]", "access_policy".to_string()));
+        }
+        params
+    }
+}
+
+/// Options for listing access policies, see [crate::UnifiClient::get_access_policies_page]
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicyListOptions {
+    page_size: Option<u32>,
+    expand_resources: bool,
+}
+
+impl AccessPolicyListOptions {
+    pub fn new() -> AccessPolicyListOptions {
+        AccessPolicyListOptions::default()
+    }
+
+    /// Policies requested per page, 100 by default
+    pub fn page_size(mut self, page_size: u32) -> AccessPolicyListOptions {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Has the controller inline the details of each policy's resources, not just their ids and types.
+    /// Controllers that already inline them ignore it.
+    pub fn expand_resources(mut self) -> AccessPolicyListOptions {
+        self.expand_resources = true;
+        self
+    }
+}
+
+impl From<u32> for AccessPolicyListOptions {
+    fn from(page_size: u32) -> AccessPolicyListOptions {
+        AccessPolicyListOptions::new().page_size(page_size)
+    }
+}
+
+impl ListOptions for AccessPolicyListOptions {
+    fn items_per_page(&self) -> u32 {
+        self.page_size.unwrap_or(LIST_PAGE_SIZE)
+    }
+
+    fn query_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if self.expand_resources {
+            params.push(("expand[]", "resource".to_string()));
+        }
+        params
+    }
+}
+
+/// Options for listing NFC cards, see [crate::UnifiClient::get_nfc_cards_page]
+#[derive(Debug, Clone, Default)]
+pub struct NfcCardListOptions {
+    page_size: Option<u32>,
+    keyword: Option<String>,
+}
+
+impl NfcCardListOptions {
+    pub fn new() -> NfcCardListOptions {
+        NfcCardListOptions::default()
+    }
+
+    /// Cards requested per page, 100 by default
+    pub fn page_size(mut self, page_size: u32) -> NfcCardListOptions {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Only cards whose display id (the number printed on the card) or token contains `keyword`
+    pub fn keyword(mut self, keyword: &str) -> NfcCardListOptions {
+        self.keyword = Some(keyword.to_string());
+        self
+    }
+}
+
+impl From<u32> for NfcCardListOptions {
+    fn from(page_size: u32) -> NfcCardListOptions {
+        NfcCardListOptions::new().page_size(page_size)
+    }
+}
+
+impl ListOptions for NfcCardListOptions {
+    fn items_per_page(&self) -> u32 {
+        self.page_size.unwrap_or(LIST_PAGE_SIZE)
+    }
+
+    fn query_params(&self) -> Vec<(&'static str, String)> {
+        self.keyword
+            .iter()
+            .map(|keyword| ("keyword", keyword.clone()))
+            .collect()
+    }
+}
+
+/// Options for reading the system log, see [crate::UnifiClient::fetch_system_log_all].
+/// A [SystemLogTopic] converts into options for the whole history of that topic.
+#[derive(Debug, Clone)]
+pub struct SystemLogOptions {
+    topic: SystemLogTopic,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    actor_id: Option<String>,
+    page_size: Option<u32>,
+}
+
+impl SystemLogOptions {
+    pub fn new(topic: SystemLogTopic) -> SystemLogOptions {
+        SystemLogOptions {
+            topic,
+            since: None,
+            until: None,
+            actor_id: None,
+            page_size: None,
+        }
+    }
+
+    /// Only events at or after `time`
+    pub fn since(mut self, time: SystemTime) -> SystemLogOptions {
+        self.since = Some(time);
+        self
+    }
+
+    /// Only events at or before `time`
+    pub fn until(mut self, time: SystemTime) -> SystemLogOptions {
+        self.until = Some(time);
+        self
+    }
+
+    /// Only events caused by the actor (usually a user) with this id
+    pub fn actor_id(mut self, actor_id: &str) -> SystemLogOptions {
+        self.actor_id = Some(actor_id.to_string());
+        self
+    }
+
+    /// Events requested per page, 100 by default
+    pub fn page_size(mut self, page_size: u32) -> SystemLogOptions {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// The system log takes its filters in the body of a POST rather than the query string
+    pub(crate) fn body(&self) -> serde_json::Value {
+        let secs = |t: SystemTime| {
+            t.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        };
+        let mut body = json!({
+            "topic": self.topic,
+            "since": self.since.map(secs),
+        });
+        if let Some(until) = self.until {
+            body["until"] = json!(secs(until));
+        }
+        if let Some(actor_id) = &self.actor_id {
+            body["actor_id"] = json!(actor_id);
+        }
+        body
+    }
+}
+
+impl From<SystemLogTopic> for SystemLogOptions {
+    fn from(topic: SystemLogTopic) -> SystemLogOptions {
+        SystemLogOptions::new(topic)
+    }
+}
+
+impl ListOptions for SystemLogOptions {
+    fn items_per_page(&self) -> u32 {
+        self.page_size.unwrap_or(SYSTEM_LOG_PAGE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const USERS: &str = "/api/v1/developer/users";
+
+    #[test]
+    fn defaults_only_page() {
+        assert_eq!(
+            list_path(USERS, 1, &UserListOptions::default()),
+            "/api/v1/developer/users?page_num=1&page_size=100"
+        );
+        assert_eq!(
+            list_path("/p", 3, &AccessPolicyListOptions::default()),
+            "/p?page_num=3&page_size=100"
+        );
+        assert_eq!(
+            list_path("/c", 2, &NfcCardListOptions::default()),
+            "/c?page_num=2&page_size=100"
+        );
+        assert_eq!(
+            list_path("/l", 1, &SystemLogOptions::from(SystemLogTopic::All)),
+            "/l?page_num=1&page_size=100"
+        );
+    }
+
+    #[test]
+    fn page_size_converts_from_a_number() {
+        assert_eq!(
+            list_path(USERS, 2, &UserListOptions::from(25)),
+            "/api/v1/developer/users?page_num=2&page_size=25"
+        );
+        assert_eq!(AccessPolicyListOptions::from(7).items_per_page(), 7);
+        assert_eq!(NfcCardListOptions::from(8).items_per_page(), 8);
+        assert_eq!(
+            SystemLogOptions::from(SystemLogTopic::All)
+                .page_size(9)
+                .items_per_page(),
+            9
+        );
+    }
+
+    #[test]
+    fn expansions_are_rendered() {
+        assert_eq!(
+            list_path(
+                USERS,
+                1,
+                &UserListOptions::new()
+                    .page_size(50)
+                    .expand_access_policies()
+            ),
+            "/api/v1/developer/users?page_num=1&page_size=50&expand[]=access_policy"
+        );
+        assert_eq!(
+            list_path("/p", 1, &AccessPolicyListOptions::new().expand_resources()),
+            "/p?page_num=1&page_size=100&expand[]=resource"
+        );
+    }
+
+    #[test]
+    fn keywords_are_encoded() {
+        assert_eq!(
+            list_path(
+                "/c",
+                1,
+                &NfcCardListOptions::new().keyword("04 a2&page_num=9")
+            ),
+            "/c?page_num=1&page_size=100&keyword=04%20a2%26page_num%3D9"
+        );
+        assert_eq!(
+            list_path("/c", 1, &NfcCardListOptions::new().keyword("ü#")),
+            "/c?page_num=1&page_size=100&keyword=%C3%BC%23"
+        );
+    }
+
+    #[test]
+    fn system_log_filters_go_in_the_body() {
+        let since = UNIX_EPOCH + Duration::from_secs(1_704_110_000);
+        let options = SystemLogOptions::new(SystemLogTopic::DoorOpenings)
+            .since(since)
+            .until(since + Duration::from_secs(60))
+            .actor_id("u1");
+        assert!(options.query_params().is_empty());
+        assert_eq!(
+            options.body(),
+            json!({
+                "topic": "door_openings",
+                "since": 1_704_110_000,
+                "until": 1_704_110_060,
+                "actor_id": "u1",
+            })
+        );
+        assert_eq!(
+            SystemLogOptions::new(SystemLogTopic::Critical).body(),
+            json!({"topic": "critical", "since": null})
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Hooks for observing the requests the client makes to the controller.
+
+use std::time::Duration;
+
+use crate::BudgetExceeded;
+
+/// Receives a callback for every HTTP request made to the controller.
+///
+/// Install one with [crate::UnifiClient::with_metrics_recorder] and forward the values to
+/// whatever metrics system you use (e.g. a `unifi_requests_total{method,endpoint,outcome}` counter
+/// and a `unifi_request_duration_seconds` histogram).
+///
+/// Helpers that make multiple requests (enrollment polling, per user lookups) report each request individually.
+pub trait MetricsRecorder: Send + Sync {
+    /// Called after each request completes, successfully or not
+    fn record_request(&self, request: &RequestMetrics);
+
+    /// Called when a request is slower or its response larger than its budget,
+    /// see [crate::UnifiClient::warn_if_slower_than]. Does nothing by default.
+    fn record_budget_exceeded(&self, _exceeded: &BudgetExceeded) {}
+}
+
+/// Information about a single completed request
+#[derive(Debug, Clone)]
+pub struct RequestMetrics<'a> {
+    pub method: &'a reqwest::Method,
+    /// The path with ids replaced by `{id}`, e.g. `/api/v1/developer/users/{id}`,
+    /// so it can be used as a metric label without unbounded cardinality
+    pub endpoint: &'a str,
+    pub outcome: RequestOutcome,
+    /// HTTP status if a response was received
+    pub status: Option<u16>,
+    /// Time from sending the request until the full body was received
+    pub duration: Duration,
+}
+
+/// Coarse classification of how a request went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOutcome {
+    /// Controller responded with a `SUCCESS` code
+    Success,
+    /// Controller responded, but with an error code in the response body
+    ApiError,
+    /// Controller responded with a non 2xx HTTP status
+    HttpError,
+    /// No response was received (connection, TLS, timeout errors)
+    TransportError,
+}
+
+impl RequestOutcome {
+    /// Short lowercase name suitable for a metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestOutcome::Success => "success",
+            RequestOutcome::ApiError => "api_error",
+            RequestOutcome::HttpError => "http_error",
+            RequestOutcome::TransportError => "transport_error",
+        }
+    }
+}
+
+/// Replaces the dynamic segments of an api path (ids, tokens) with `{id}` and drops any query string.
+/// Resource names in the developer API are all lowercase words, so anything else is treated as an id.
+/// The segment after `tokens` is always a card token, even when it happens to be all letters.
+pub(crate) fn endpoint_template(api_path: &str) -> String {
+    let path = api_path.split('?').next().unwrap_or_default();
+    let mut after_tokens = false;
+    path.split('/')
+        .map(|segment| {
+            let is_secret = std::mem::replace(&mut after_tokens, segment == "tokens");
+            let is_resource =
+                !is_secret && segment.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+            let is_version = segment.len() >= 2
+                && segment.starts_with('v')
+                && segment[1..].chars().all(|c| c.is_ascii_digit());
+            if is_resource || is_version {
+                segment
+            } else {
+                "{id}"
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,263 @@
+//! Estimating how many people are in an area, hour by hour, from door openings.
+//!
+//! The model is deliberately simple, and the numbers are estimates for planning rather than a headcount:
+//!
+//! - Every granted opening of an entrance door is one person entering, tailgating and doors held open
+//!   aren't seen.
+//! - Every granted opening of an exit door is one person leaving. Most sites don't badge out, so people
+//!   also leave without a trace; the estimate decays by half every `half_life` to account for them.
+//! - A door that is both an entrance and an exit of an area (one reader, both directions) can't tell the
+//!   two apart, so it only counts as an entrance.
+//! - The estimate never goes below zero, and starts at zero at the beginning of the range.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::clock::parse_rfc3339;
+use crate::denials::{access_granted, event_door_id};
+use crate::{
+    CredentialProvider, SystemLogEventWrapper, SystemLogOptions, SystemLogTopic, UnifiClient,
+    UnifiError, UnifiResult,
+};
+
+const HOUR_SECS: u64 = 3600;
+
+/// A door opening that let someone through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoorOpening {
+    pub door_id: String,
+    pub at: SystemTime,
+    /// How the door was opened, [CredentialProvider::Unknown] with an empty string if the event didn't say
+    pub provider: CredentialProvider,
+}
+
+impl DoorOpening {
+    /// The opening an event records, None for denials and events that aren't door openings
+    pub fn from_event(event: &SystemLogEventWrapper) -> Option<DoorOpening> {
+        if !access_granted(event)? {
+            return None;
+        }
+        Some(DoorOpening {
+            door_id: event_door_id(event)?,
+            at: parse_rfc3339(&event.timestamp)?,
+            provider: event
+                .source
+                .credential_provider()
+                .unwrap_or(CredentialProvider::Unknown(String::new())),
+        })
+    }
+}
+
+/// What opening a door means for an area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DoorRole {
+    Entrance,
+    Exit,
+}
+
+/// The areas to estimate and their doors, see the [module docs](self) for the model
+#[derive(Debug, Clone)]
+pub struct OccupancyConfig {
+    half_life: Duration,
+    areas: BTreeMap<String, HashMap<String, DoorRole>>,
+}
+
+impl OccupancyConfig {
+    /// `half_life` is how long it takes for half the people to leave without using an exit door
+    pub fn new(half_life: Duration) -> OccupancyConfig {
+        OccupancyConfig {
+            half_life,
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Counts openings of the door as people entering `area`
+    pub fn entrance(mut self, area: &str, door_id: &str) -> OccupancyConfig {
+        self.areas
+            .entry(area.to_string())
+            .or_default()
+            .insert(door_id.to_string(), DoorRole::Entrance);
+        self
+    }
+
+    /// Counts openings of the door as people leaving `area`, unless it is also an entrance of the area
+    pub fn exit(mut self, area: &str, door_id: &str) -> OccupancyConfig {
+        self.areas
+            .entry(area.to_string())
+            .or_default()
+            .entry(door_id.to_string())
+            .or_insert(DoorRole::Exit);
+        self
+    }
+}
+
+/// One hour of an [AreaOccupancy]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HourlyOccupancy {
+    /// Start of the hour, in seconds since the unix epoch
+    pub hour_start: u64,
+    pub entries: u32,
+    pub exits: u32,
+    /// The hour's counted entries and exits, by how the door was opened
+    pub by_provider: BTreeMap<CredentialProvider, u32>,
+    /// Highest estimate during the hour
+    pub peak: f64,
+    /// Estimate at the end of the hour
+    pub end: f64,
+}
+
+/// The estimate for one area
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AreaOccupancy {
+    /// Every hour of the range, in order, including quiet ones
+    pub hours: Vec<HourlyOccupancy>,
+    /// Seconds since the unix epoch of the highest estimate, None if nobody entered
+    pub peak_at: Option<u64>,
+    pub peak: f64,
+}
+
+/// Result of [estimate_occupancy], by area name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OccupancyReport {
+    /// Start of the first hour, in seconds since the unix epoch
+    pub start: u64,
+    pub areas: BTreeMap<String, AreaOccupancy>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Estimates the occupancy of every area in `config` for each hour overlapping `range`.
+/// Openings outside the range, or of doors no area lists, are ignored. Hours are whole UTC hours.
+pub fn estimate_occupancy(
+    openings: &[DoorOpening],
+    config: &OccupancyConfig,
+    range: Range<SystemTime>,
+) -> OccupancyReport {
+    let start = unix_secs(range.start) / HOUR_SECS * HOUR_SECS;
+    let end = unix_secs(range.end).max(start + 1);
+    let hour_count = (end - start).div_ceil(HOUR_SECS) as usize;
+    let mut openings: Vec<&DoorOpening> =
+        openings.iter().filter(|o| range.contains(&o.at)).collect();
+    openings.sort_by_key(|o| o.at);
+    let half_life = config.half_life.as_secs_f64().max(f64::MIN_POSITIVE);
+
+    let areas = config
+        .areas
+        .iter()
+        .map(|(area, doors)| {
+            let mut hours: Vec<HourlyOccupancy> = (0..hour_count)
+                .map(|i| HourlyOccupancy {
+                    hour_start: start + i as u64 * HOUR_SECS,
+                    entries: 0,
+                    exits: 0,
+                    by_provider: BTreeMap::new(),
+                    peak: 0.0,
+                    end: 0.0,
+                })
+                .collect();
+            let mut estimate = 0.0;
+            // Time of `estimate` in seconds since the unix epoch, with sub-second precision
+            let mut estimate_at = start as f64;
+            let decay_to = |estimate: f64, from: f64, to: f64| {
+                estimate * 0.5f64.powf((to - from).max(0.0) / half_life)
+            };
+            let (mut peak, mut peak_at) = (0.0, None);
+            let mut hour = 0;
+            for opening in &openings {
+                let Some(role) = doors.get(&opening.door_id) else {
+                    continue;
+                };
+                let at = opening
+                    .at
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or_default();
+                // Close the hours that ended before this opening
+                while hour + 1 < hour_count && at >= hours[hour + 1].hour_start as f64 {
+                    let hour_end = hours[hour + 1].hour_start as f64;
+                    estimate = decay_to(estimate, estimate_at, hour_end);
+                    estimate_at = hour_end;
+                    hours[hour].end = estimate;
+                    hour += 1;
+                    hours[hour].peak = estimate;
+                }
+                estimate = decay_to(estimate, estimate_at, at);
+                estimate_at = at;
+                match role {
+                    DoorRole::Entrance => {
+                        estimate += 1.0;
+                        hours[hour].entries += 1;
+                    }
+                    DoorRole::Exit => {
+                        estimate = (estimate - 1.0f64).max(0.0);
+                        hours[hour].exits += 1;
+                    }
+                }
+                *hours[hour]
+                    .by_provider
+                    .entry(opening.provider.clone())
+                    .or_default() += 1;
+                if estimate > hours[hour].peak {
+                    hours[hour].peak = estimate;
+                }
+                if estimate > peak {
+                    peak = estimate;
+                    peak_at = Some(at as u64);
+                }
+            }
+            // Decay through the rest of the range
+            for i in hour..hour_count {
+                let hour_end = (hours[i].hour_start + HOUR_SECS) as f64;
+                estimate = decay_to(estimate, estimate_at, hour_end);
+                estimate_at = hour_end;
+                hours[i].end = estimate;
+                if i + 1 < hour_count {
+                    hours[i + 1].peak = hours[i + 1].peak.max(estimate);
+                }
+            }
+            (
+                area.clone(),
+                AreaOccupancy {
+                    hours,
+                    peak_at,
+                    peak,
+                },
+            )
+        })
+        .collect();
+    OccupancyReport { start, areas }
+}
+
+impl UnifiClient {
+    /// Estimates occupancy for `range` from the door openings in the system log, see [estimate_occupancy].
+    /// Fetches the whole range of the log, which can be slow for long ranges on busy sites.
+    pub async fn occupancy_report(
+        &self,
+        range: Range<SystemTime>,
+        config: &OccupancyConfig,
+    ) -> UnifiResult<OccupancyReport> {
+        if range.end <= range.start {
+            return Err(UnifiError::Validation {
+                field: "range".to_string(),
+                reason: "must end after it starts".to_string(),
+            });
+        }
+        let events = self
+            .fetch_system_log_all(
+                SystemLogOptions::new(SystemLogTopic::DoorOpenings)
+                    .since(range.start)
+                    .until(range.end),
+            )
+            .await?;
+        let openings: Vec<DoorOpening> =
+            events.iter().filter_map(DoorOpening::from_event).collect();
+        Ok(estimate_occupancy(&openings, config, range))
+    }
+}
@@ -0,0 +1,232 @@
+//! Helper for running a reconciliation task against the controller on a schedule.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use log::*;
+use tokio::sync::Notify;
+
+use crate::polling::add_jitter;
+use crate::{UnifiClient, UnifiResult};
+
+/// After this many consecutive failures the delay between runs stops growing
+const MAX_BACKOFF_DOUBLINGS: u32 = 5;
+
+/// State of a task started with [UnifiClient::spawn_periodic]
+#[derive(Debug, Clone, Default)]
+pub struct PeriodicStatus {
+    /// When the most recent run finished
+    pub last_run: Option<SystemTime>,
+    /// Error from the most recent run, `None` if it succeeded
+    pub last_error: Option<String>,
+    /// Number of runs in a row that have failed, resets on success
+    pub consecutive_failures: u32,
+    /// Total number of runs completed
+    pub runs: u64,
+}
+
+/// Controls a task started with [UnifiClient::spawn_periodic].
+///
+/// Dropping the handle leaves the task running, call [PeriodicHandle::shutdown] to stop it.
+pub struct PeriodicHandle {
+    trigger: Arc<Notify>,
+    stop: Arc<Notify>,
+    status: Arc<Mutex<PeriodicStatus>>,
+    join: tokio::task::JoinHandle<()>,
+}
+
+impl PeriodicHandle {
+    /// Starts a run as soon as possible instead of waiting for the next tick.
+    /// If a run is in progress another one starts right after it finishes.
+    pub fn trigger_now(&self) {
+        self.trigger.notify_one();
+    }
+
+    /// A snapshot of how the task has been doing
+    pub fn status(&self) -> PeriodicStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Stops the task, waiting for a run that is in progress to finish
+    pub async fn shutdown(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.join.await {
+            error!("Periodic task ended abnormally: {e}");
+        }
+    }
+}
+
+impl UnifiClient {
+    /// Runs `task` every `interval` on the tokio runtime until [PeriodicHandle::shutdown] is called.
+    ///
+    /// - Up to 10% of random jitter is added to each wait, so multiple processes don't hit the controller in lockstep.
+    /// - Runs never overlap, ticks that pass while a run is still going are skipped rather than queued.
+    /// - After consecutive failures the wait doubles each time (up to 32x `interval`) and returns to normal after a success.
+    ///
+    /// The task is given its own clone of the client, which shares the connection pool and configuration.
+    /// Must be called from within a tokio runtime. Requires the `periodic` feature.
+    pub fn spawn_periodic<F, Fut>(&self, interval: Duration, task: F) -> PeriodicHandle
+    where
+        F: Fn(UnifiClient) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = UnifiResult<()>> + Send + 'static,
+    {
+        let trigger = Arc::new(Notify::new());
+        let stop = Arc::new(Notify::new());
+        let status = Arc::new(Mutex::new(PeriodicStatus::default()));
+        let join = tokio::spawn(run_periodic(
+            self.clone(),
+            interval,
+            task,
+            trigger.clone(),
+            stop.clone(),
+            status.clone(),
+        ));
+        PeriodicHandle {
+            trigger,
+            stop,
+            status,
+            join,
+        }
+    }
+}
+
+async fn run_periodic<F, Fut>(
+    client: UnifiClient,
+    interval: Duration,
+    task: F,
+    trigger: Arc<Notify>,
+    stop: Arc<Notify>,
+    status: Arc<Mutex<PeriodicStatus>>,
+) where
+    F: Fn(UnifiClient) -> Fut,
+    Fut: Future<Output = UnifiResult<()>>,
+{
+    loop {
+        let failures = status.lock().unwrap().consecutive_failures;
+        let delay = add_jitter(backoff(interval, failures), 0.1);
+        tokio::select! {
+            _ = stop.notified() => break,
+            _ = trigger.notified() => debug!("Periodic task triggered early"),
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        let result = task(client.clone()).await;
+        let mut status = status.lock().unwrap();
+        status.last_run = Some(SystemTime::now());
+        status.runs += 1;
+        match result {
+            Ok(()) => {
+                status.last_error = None;
+                status.consecutive_failures = 0;
+            }
+            Err(e) => {
+                status.consecutive_failures += 1;
+                warn!(
+                    "Periodic task failed ({} in a row): {e}",
+                    status.consecutive_failures
+                );
+                status.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// `interval` doubled once per consecutive failure, capped at [MAX_BACKOFF_DOUBLINGS]
+fn backoff(interval: Duration, consecutive_failures: u32) -> Duration {
+    interval * 2u32.pow(consecutive_failures.min(MAX_BACKOFF_DOUBLINGS))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::UnifiError;
+
+    /// Counts its runs, failing the first `failures` of them
+    fn counting_task(
+        failures: u32,
+    ) -> (
+        Arc<AtomicU32>,
+        impl Fn(UnifiClient) -> std::future::Ready<UnifiResult<()>> + Send + Sync + 'static,
+    ) {
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let task = move |_: UnifiClient| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            std::future::ready(if run < failures {
+                Err(UnifiError::Other(format!("run {run} failed")))
+            } else {
+                Ok(())
+            })
+        };
+        (runs, task)
+    }
+
+    fn client() -> UnifiClient {
+        UnifiClient::new("127.0.0.1", "token")
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(backoff(interval, 0), interval);
+        assert_eq!(backoff(interval, 1), Duration::from_secs(20));
+        assert_eq!(backoff(interval, 5), Duration::from_secs(320));
+        assert_eq!(backoff(interval, 50), Duration::from_secs(320));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_once_per_interval_with_jitter() {
+        let (runs, task) = counting_task(0);
+        let handle = client().spawn_periodic(Duration::from_secs(100), task);
+        tokio::time::sleep(Duration::from_secs(99)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        // Jitter adds at most 10%
+        tokio::time::sleep(Duration::from_secs(12)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(Duration::from_secs(110)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        let status = handle.status();
+        assert_eq!((status.runs, status.consecutive_failures), (2, 0));
+        assert!(status.last_run.is_some() && status.last_error.is_none());
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_back_off_and_success_resets() {
+        let (runs, task) = counting_task(2);
+        let handle = client().spawn_periodic(Duration::from_secs(100), task);
+        // First run at 100-110s fails, the next waits 200-220s
+        tokio::time::sleep(Duration::from_secs(111)).await;
+        let status = handle.status();
+        assert_eq!((status.runs, status.consecutive_failures), (1, 1));
+        assert_eq!(status.last_error.as_deref(), Some("run 0 failed"));
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        // Second run by 330s fails too, the third waits 400-440s and succeeds
+        tokio::time::sleep(Duration::from_secs(70)).await;
+        assert_eq!(handle.status().consecutive_failures, 2);
+        tokio::time::sleep(Duration::from_secs(440)).await;
+        let status = handle.status();
+        assert_eq!((status.runs, status.consecutive_failures), (3, 0));
+        assert_eq!(status.last_error, None);
+        // Back to the normal interval
+        tokio::time::sleep(Duration::from_secs(110)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trigger_runs_early_and_shutdown_stops() {
+        let (runs, task) = counting_task(0);
+        let handle = client().spawn_periodic(Duration::from_secs(3600), task);
+        handle.trigger_now();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        handle.shutdown().await;
+        tokio::time::sleep(Duration::from_secs(7200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
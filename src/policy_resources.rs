@@ -0,0 +1,151 @@
+//! Turning the resource ids in an access policy into named doors and door groups.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::{AccessPolicy, DoorGroup, DoorRef, UnifiClient, UnifiResult};
+
+/// A resource of a policy, resolved against the controller's doors and door groups
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub enum ResolvedResource {
+    Door(DoorRef),
+    DoorGroup {
+        id: String,
+        name: String,
+        /// Member doors that exist
+        doors: Vec<DoorRef>,
+        /// Ids of member doors that don't exist anymore
+        missing_doors: Vec<String>,
+    },
+    /// The policy references something that doesn't exist, e.g. a deleted door
+    Unresolved {
+        id: String,
+        resource_type: String,
+    },
+}
+
+/// A policy with its resources resolved, see [UnifiClient::resolve_policy_resources]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct ResolvedPolicy {
+    pub policy_id: String,
+    pub policy_name: String,
+    pub resources: Vec<ResolvedResource>,
+}
+
+impl ResolvedPolicy {
+    /// True if the policy, or one of its door groups, references something that doesn't exist
+    pub fn has_dangling_references(&self) -> bool {
+        self.resources.iter().any(|r| match r {
+            ResolvedResource::Unresolved { .. } => true,
+            ResolvedResource::DoorGroup { missing_doors, .. } => !missing_doors.is_empty(),
+            ResolvedResource::Door(_) => false,
+        })
+    }
+
+    /// True if the policy grants access to the door, directly or through one of its door groups
+    pub fn covers_door(&self, door_id: &str) -> bool {
+        self.resources.iter().any(|r| match r {
+            ResolvedResource::Door(door) => door.id == door_id,
+            ResolvedResource::DoorGroup { doors, .. } => doors.iter().any(|d| d.id == door_id),
+            ResolvedResource::Unresolved { .. } => false,
+        })
+    }
+}
+
+/// Every door and door group, fetched once and shared between policies
+pub(crate) struct DoorDirectory {
+    pub(crate) doors: HashMap<String, DoorRef>,
+    groups: HashMap<String, DoorGroup>,
+}
+
+impl DoorDirectory {
+    pub(crate) fn resolve(&self, policy: &AccessPolicy) -> ResolvedPolicy {
+        let resources = policy
+            .resources
+            .iter()
+            .map(|resource| {
+                let unresolved = || ResolvedResource::Unresolved {
+                    id: resource.id.clone(),
+                    resource_type: resource.resource_type.clone(),
+                };
+                match resource.resource_type.as_str() {
+                    "door" => self
+                        .doors
+                        .get(&resource.id)
+                        .map(|door| ResolvedResource::Door(door.clone()))
+                        .unwrap_or_else(unresolved),
+                    "door_group" => self
+                        .groups
+                        .get(&resource.id)
+                        .map(|group| {
+                            let (doors, missing_doors) = group.door_ids().into_iter().fold(
+                                (vec![], vec![]),
+                                |(mut doors, mut missing), id| {
+                                    match self.doors.get(&id) {
+                                        Some(door) => doors.push(door.clone()),
+                                        None => missing.push(id),
+                                    }
+                                    (doors, missing)
+                                },
+                            );
+                            ResolvedResource::DoorGroup {
+                                id: group.id.clone(),
+                                name: group.name.clone(),
+                                doors,
+                                missing_doors,
+                            }
+                        })
+                        .unwrap_or_else(unresolved),
+                    _ => unresolved(),
+                }
+            })
+            .collect();
+        ResolvedPolicy {
+            policy_id: policy.id.clone(),
+            policy_name: policy.name.clone(),
+            resources,
+        }
+    }
+}
+
+impl UnifiClient {
+    /// Fetches the doors (from the building topology) and door groups once
+    pub(crate) async fn door_directory(&self) -> UnifiResult<DoorDirectory> {
+        let (topology, groups) =
+            futures::try_join!(self.fetch_building_topology(), self.get_all_door_groups())?;
+        let doors = topology
+            .iter()
+            .flat_map(|building| {
+                building
+                    .floors
+                    .iter()
+                    .flat_map(|floor| &floor.doors)
+                    .chain(&building.unassigned_doors)
+            })
+            .map(|door| (door.id.clone(), door.clone()))
+            .collect();
+        let groups = groups.into_iter().map(|g| (g.id.clone(), g)).collect();
+        Ok(DoorDirectory { doors, groups })
+    }
+
+    /// Resolves the doors and door groups the policy grants access to, expanding groups into their doors.
+    /// References to doors or groups that no longer exist are kept as [ResolvedResource::Unresolved].
+    pub async fn resolve_policy_resources(
+        &self,
+        policy: &AccessPolicy,
+    ) -> UnifiResult<ResolvedPolicy> {
+        Ok(self.door_directory().await?.resolve(policy))
+    }
+
+    /// Resolves every access policy, fetching the doors and door groups only once
+    pub async fn resolve_all_policy_resources(&self) -> UnifiResult<Vec<ResolvedPolicy>> {
+        let (policies, directory) =
+            futures::try_join!(self.get_all_access_policies(), self.door_directory())?;
+        Ok(policies.iter().map(|p| directory.resolve(p)).collect())
+    }
+}
@@ -0,0 +1,55 @@
+//! How long helpers wait between polls and retries.
+
+use std::time::{Duration, SystemTime};
+
+/// Wait settings shared by every helper that polls or retries, see [crate::UnifiClient::with_polling_config].
+///
+/// The n-th consecutive wait (counting from 0) is `base_interval * backoff^n`, capped at `max_interval`,
+/// plus up to `jitter` of itself at random. Helpers reset the count once they make progress,
+/// e.g. enrollment polling stays at `base_interval` until a poll fails.
+#[derive(Debug, Clone)]
+pub struct PollingConfig {
+    /// Defaults to 100ms
+    pub base_interval: Duration,
+    /// Defaults to 30s
+    pub max_interval: Duration,
+    /// Factor applied per consecutive wait, defaults to 2
+    pub backoff: f64,
+    /// Fraction of random extra delay, defaults to 0.1
+    pub jitter: f64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            base_interval: Duration::from_millis(100),
+            max_interval: Duration::from_secs(30),
+            backoff: 2.0,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl PollingConfig {
+    /// The delay before the next attempt, after `attempt` consecutive waits, without jitter
+    pub fn delay(&self, attempt: u32) -> Duration {
+        // After 64 steps the cap has long been reached, limiting it keeps the factor finite
+        let factor = self.backoff.max(1.0).powi(attempt.min(64) as i32);
+        let delay = self.base_interval.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_interval.as_secs_f64()))
+    }
+
+    /// Sleeps for [PollingConfig::delay] plus jitter, every helper waits through this
+    pub(crate) async fn wait(&self, attempt: u32) {
+        tokio::time::sleep(add_jitter(self.delay(attempt), self.jitter)).await;
+    }
+}
+
+/// Adds up to `fraction` of `delay` to it, this only needs to spread processes apart so the clock is random enough
+pub(crate) fn add_jitter(delay: Duration, fraction: f64) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    delay + delay.mul_f64(fraction.max(0.0) * f64::from(nanos % 1000) / 1000.0)
+}
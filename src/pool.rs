@@ -0,0 +1,112 @@
+//! Support for managing several controllers (e.g. one per building) from one place.
+
+use std::collections::BTreeMap;
+
+use futures::future::join_all;
+use serde::Deserialize;
+
+use crate::{UnifiClient, UnifiError, UnifiResult, User};
+
+/// Connection details for a single controller
+#[derive(Debug, Clone, Deserialize)]
+pub struct SiteConfig {
+    /// Address of the controller, without scheme
+    pub host: String,
+    /// Developer API token for the controller
+    pub token: String,
+    /// Defaults to the port given in `host`, or [crate::DEFAULT_PORT]
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+/// A set of named clients, one per site.
+///
+/// Cross-site helpers query every site concurrently and return one result per site,
+/// so a site that is down doesn't hide the results of the others.
+/// Sites are always iterated in name order.
+#[derive(Default)]
+pub struct UnifiClientPool {
+    clients: BTreeMap<String, UnifiClient>,
+}
+
+impl UnifiClientPool {
+    /// Creates an empty pool
+    pub fn new() -> UnifiClientPool {
+        UnifiClientPool::default()
+    }
+
+    /// Creates a client for each configured site.
+    /// Fails if a site name, host, or token is empty, or a site name is repeated.
+    pub fn from_config(
+        sites: impl IntoIterator<Item = (String, SiteConfig)>,
+    ) -> UnifiResult<UnifiClientPool> {
+        let mut pool = UnifiClientPool::new();
+        for (name, config) in sites {
+            if config.host.trim().is_empty() {
+                return Err(UnifiError::Other(format!("Site {name} has no host")));
+            }
+            if config.token.trim().is_empty() {
+                return Err(UnifiError::Other(format!("Site {name} has no token")));
+            }
+            let mut client = UnifiClient::try_new(&config.host, &config.token)?;
+            // A port given in the host is kept unless the config sets one explicitly
+            if let Some(port) = config.port {
+                client = client.with_port(port);
+            }
+            pool.add_site(name, client)?;
+        }
+        Ok(pool)
+    }
+
+    /// Adds a client under the given site name, fails if the name is empty or already in use
+    pub fn add_site(&mut self, name: impl Into<String>, client: UnifiClient) -> UnifiResult<()> {
+        let name = name.into();
+        if name.trim().is_empty() {
+            return Err(UnifiError::Other("Site name can't be empty".to_string()));
+        }
+        if self.clients.contains_key(&name) {
+            return Err(UnifiError::Other(format!("Site {name} already exists")));
+        }
+        self.clients.insert(name, client);
+        Ok(())
+    }
+
+    /// The client for a site, if it exists
+    pub fn client(&self, site: &str) -> Option<&UnifiClient> {
+        self.clients.get(site)
+    }
+
+    /// Names of all sites in the pool
+    pub fn sites(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(|k| k.as_str())
+    }
+
+    /// Fetches the users of every site
+    pub async fn get_all_users_across_sites(&self) -> Vec<(String, UnifiResult<Vec<User>>)> {
+        join_all(
+            self.clients
+                .iter()
+                .map(|(site, client)| async move { (site.clone(), client.get_all_users().await) }),
+        )
+        .await
+    }
+
+    /// Looks for a user with the given email (case insensitive) on every site
+    pub async fn find_user_by_email_any_site(
+        &self,
+        email: &str,
+    ) -> Vec<(String, UnifiResult<Option<User>>)> {
+        self.get_all_users_across_sites()
+            .await
+            .into_iter()
+            .map(|(site, users)| {
+                let user = users.map(|users| {
+                    users
+                        .into_iter()
+                        .find(|u| u.user_email.eq_ignore_ascii_case(email))
+                });
+                (site, user)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,10 @@
+//! The types most programs driving doors need, for a single glob import:
+//!
+//! ```
+//! use unifi_access::prelude::*;
+//! ```
+
+pub use crate::{
+    AccessPolicy, ApiErrorKind, Device, EnrollmentHandle, EnrollmentOptions, NewUser, NfcCard,
+    Secret, UnifiClient, UnifiError, UnifiResult, User, UserStatus,
+};
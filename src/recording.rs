@@ -0,0 +1,137 @@
+//! Recording of raw HTTP exchanges to a file, to attach to bug reports about unexpected responses.
+//!
+//! Credentials are scrubbed before anything is written: the bearer token is never recorded, ids and
+//! card tokens in paths are replaced as in the debug log, and credential fields in JSON bodies are
+//! redacted with the same rules as the audit log.
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::redact_json;
+use crate::{metrics, RawResponse, UnifiClient, UnifiResult};
+
+/// One line of a recording
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedExchange {
+    /// Seconds since the unix epoch when the response arrived
+    timestamp: u64,
+    method: String,
+    /// The path with ids replaced, e.g. `/api/v1/developer/users/{id}`
+    path: String,
+    request_body: Option<serde_json::Value>,
+    /// None if no response was received
+    status: Option<u16>,
+    content_type: Option<String>,
+    /// JSON bodies are stored as JSON, anything else as a string
+    response_body: Option<serde_json::Value>,
+    /// Why no response was received
+    error: Option<String>,
+}
+
+/// A body as JSON with credentials redacted, or as a plain string if it isn't JSON
+fn scrubbed_body(body: &str) -> serde_json::Value {
+    match serde_json::from_str(body) {
+        Ok(json) => redact_json(json),
+        Err(_) => serde_json::Value::String(body.to_string()),
+    }
+}
+
+/// Appends exchanges to a JSON lines file, see [UnifiClient::with_http_recording]
+#[derive(Debug)]
+pub(crate) struct HttpRecorder {
+    path: PathBuf,
+    // Serializes appends between threads of this process
+    lock: Mutex<()>,
+}
+
+impl HttpRecorder {
+    pub(crate) fn record(
+        &self,
+        method: &reqwest::Method,
+        api_path: &str,
+        body: Option<&str>,
+        result: &reqwest::Result<RawResponse>,
+    ) {
+        let response = result.as_ref().ok();
+        let exchange = RecordedExchange {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            method: method.to_string(),
+            path: metrics::endpoint_template(api_path),
+            request_body: body.map(scrubbed_body),
+            status: response.map(|r| r.status),
+            content_type: response.and_then(|r| r.content_type.clone()),
+            response_body: response.map(|r| {
+                let mut body = scrubbed_body(&r.body);
+                // A generated PIN comes back as the bare data, with no field name to redact by
+                if api_path.ends_with("/credentials/pin_codes") {
+                    if let Some(data) = body.get_mut("data") {
+                        *data = serde_json::Value::String("[redacted]".to_string());
+                    }
+                }
+                body
+            }),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        // Recording is a diagnostic aid, failing to write must not fail the request
+        if let Err(e) = self.append(&exchange) {
+            warn!(
+                "Failed to record HTTP exchange to {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn append(&self, exchange: &RecordedExchange) -> UnifiResult<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(exchange)?)?;
+        Ok(())
+    }
+}
+
+impl UnifiClient {
+    /// Appends every request and response to the file at `path` as JSON lines, for sharing in bug reports.
+    /// The bearer token is never written and credentials in paths and bodies are scrubbed.
+    pub fn with_http_recording(mut self, path: impl Into<PathBuf>) -> UnifiClient {
+        self.http_recorder = Some(std::sync::Arc::new(HttpRecorder {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }));
+        self
+    }
+}
+
+/// Re-applies redaction to every JSON line of the file at `path`, in place.
+/// Useful for recordings or logs captured some other way before sharing them.
+/// Lines that aren't JSON are kept as they are.
+pub fn scrub_recording(path: impl AsRef<Path>) -> UnifiResult<()> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path)?;
+    let mut scrubbed = vec![];
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(json) => scrubbed.push(serde_json::to_string(&redact_json(json))?),
+            Err(_) => scrubbed.push(line),
+        }
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".scrubbing");
+    let mut temp = std::fs::File::create(&temp_path)?;
+    for line in &scrubbed {
+        writeln!(temp, "{line}")?;
+    }
+    temp.sync_data()?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
@@ -0,0 +1,456 @@
+//! Access reports: counts of door entries per user, door or policy over a time range, with after hours entries.
+//!
+//! Entries come from the door openings in the system log. Names are taken from the log rather than looked up,
+//! so users deleted since they opened a door keep the name they had at the time.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::clock::{format_rfc3339, parse_rfc3339};
+use crate::denials::{access_granted, event_door_id};
+use crate::{
+    ResolvedPolicy, Schedule, SiteTimeZone, SystemLogEventWrapper, SystemLogOptions,
+    SystemLogTopic, UnifiClient, UnifiError, UnifiResult, UserListOptions,
+};
+
+/// A door opening that let a person through, with the names the log gave them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessEntry {
+    /// Empty if the event didn't identify the user, e.g. an unassigned card
+    pub user_id: String,
+    /// Empty if the event didn't name the user
+    pub user_name: String,
+    pub door_id: String,
+    /// Empty if the event didn't name the door
+    pub door_name: String,
+    pub at: SystemTime,
+}
+
+impl AccessEntry {
+    /// The entry an event records, None for denials and events that aren't door openings
+    pub fn from_event(event: &SystemLogEventWrapper) -> Option<AccessEntry> {
+        if !access_granted(event)? {
+            return None;
+        }
+        let text = |value: &serde_json::Value, field: &str| {
+            value
+                .get(field)
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let door_id = event_door_id(event)?;
+        let door = event
+            .source
+            .target
+            .as_array()
+            .and_then(|targets| targets.iter().find(|t| text(t, "id") == door_id))
+            .unwrap_or(&serde_json::Value::Null);
+        Some(AccessEntry {
+            user_id: text(&event.source.actor, "id"),
+            user_name: text(&event.source.actor, "display_name"),
+            door_name: text(door, "display_name"),
+            door_id,
+            at: parse_rfc3339(&event.timestamp)?,
+        })
+    }
+}
+
+/// What the rows of an [AccessReport] are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReportGrouping {
+    /// One row per user, users the log didn't identify are grouped by their name
+    User,
+    Door,
+    /// One row per policy the user holds that includes the door, as of when the report is made.
+    /// Entries no such policy explains, e.g. by deleted users, go in a row with an empty key.
+    Policy,
+}
+
+/// What goes into an [AccessReport], see [UnifiClient::generate_access_report]
+#[derive(Debug, Clone)]
+pub struct ReportSpec {
+    range: Range<SystemTime>,
+    grouping: ReportGrouping,
+    doors: Option<BTreeSet<String>>,
+    staffed_hours: Option<Schedule>,
+}
+
+impl ReportSpec {
+    /// Entries from `range`, in rows by `grouping`, at every door
+    pub fn new(range: Range<SystemTime>, grouping: ReportGrouping) -> ReportSpec {
+        ReportSpec {
+            range,
+            grouping,
+            doors: None,
+            staffed_hours: None,
+        }
+    }
+
+    /// Only counts entries at these doors
+    pub fn doors<S: Into<String>>(mut self, door_ids: impl IntoIterator<Item = S>) -> ReportSpec {
+        self.doors = Some(door_ids.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Counts entries at times the schedule doesn't allow access as after hours.
+    /// A [Schedule] built by hand works as well as one fetched with [UnifiClient::get_schedule].
+    pub fn staffed_hours(mut self, schedule: Schedule) -> ReportSpec {
+        self.staffed_hours = Some(schedule);
+        self
+    }
+}
+
+/// One row of an [AccessReport]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportRow {
+    /// Id of the user, door or policy. For users the log didn't identify, their name.
+    pub key: String,
+    /// The most recent name the log gave the user or door, or the policy's name
+    pub name: String,
+    pub entries: u32,
+    /// Entries outside [ReportSpec::staffed_hours], 0 if none were given
+    pub after_hours_entries: u32,
+    /// Seconds since the unix epoch of the first entry
+    pub first_access: u64,
+    /// Seconds since the unix epoch of the last entry
+    pub last_access: u64,
+}
+
+/// Result of [UnifiClient::generate_access_report]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccessReport {
+    /// Start of the range, in seconds since the unix epoch
+    pub start: u64,
+    /// End of the range, excluded, in seconds since the unix epoch
+    pub end: u64,
+    pub grouping: ReportGrouping,
+    /// Sorted by name, then key
+    pub rows: Vec<ReportRow>,
+}
+
+impl AccessReport {
+    /// The rows as CSV with a header line, times in RFC 3339 UTC
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("key,name,entries,after_hours_entries,first_access,last_access\n");
+        let time = |secs: u64| format_rfc3339(UNIX_EPOCH + Duration::from_secs(secs));
+        for row in &self.rows {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                csv_field(&row.key),
+                csv_field(&row.name),
+                row.entries,
+                row.after_hours_entries,
+                time(row.first_access),
+                time(row.last_access)
+            ));
+        }
+        csv
+    }
+
+    /// The report as pretty printed JSON
+    pub fn to_json(&self) -> UnifiResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Quotes a field containing a separator, quote or line break, doubling its quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Aggregates `entries` into a report as `spec` asks, the pure part of [UnifiClient::generate_access_report].
+///
+/// `user_policies` maps user ids to the policies they hold, only used when grouping by policy.
+/// Staffed hours are read on the clock of a site in `time_zone`, which may be a fixed offset in seconds ahead of UTC.
+pub fn build_access_report(
+    entries: &[AccessEntry],
+    spec: &ReportSpec,
+    user_policies: &HashMap<String, Vec<ResolvedPolicy>>,
+    time_zone: impl Into<SiteTimeZone>,
+) -> AccessReport {
+    let time_zone = time_zone.into();
+    let mut entries: Vec<&AccessEntry> = entries
+        .iter()
+        .filter(|e| spec.range.contains(&e.at))
+        .filter(|e| match &spec.doors {
+            Some(doors) => doors.contains(&e.door_id),
+            None => true,
+        })
+        .collect();
+    entries.sort_by_key(|e| e.at);
+    let mut rows: BTreeMap<String, ReportRow> = BTreeMap::new();
+    for entry in entries {
+        let groups: Vec<(String, String)> = match spec.grouping {
+            ReportGrouping::User if entry.user_id.is_empty() => {
+                vec![(entry.user_name.clone(), entry.user_name.clone())]
+            }
+            ReportGrouping::User => vec![(entry.user_id.clone(), entry.user_name.clone())],
+            ReportGrouping::Door => vec![(entry.door_id.clone(), entry.door_name.clone())],
+            ReportGrouping::Policy => {
+                let policies: Vec<(String, String)> = user_policies
+                    .get(&entry.user_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|p| p.covers_door(&entry.door_id))
+                    .map(|p| (p.policy_id.clone(), p.policy_name.clone()))
+                    .collect();
+                if policies.is_empty() {
+                    vec![(String::new(), String::new())]
+                } else {
+                    policies
+                }
+            }
+        };
+        let after_hours = spec
+            .staffed_hours
+            .as_ref()
+            .is_some_and(|s| !s.check(entry.at, time_zone).allows_access());
+        let at = unix_secs(entry.at);
+        for (key, name) in groups {
+            let row = rows.entry(key.clone()).or_insert_with(|| ReportRow {
+                key,
+                name: String::new(),
+                entries: 0,
+                after_hours_entries: 0,
+                first_access: at,
+                last_access: at,
+            });
+            // Entries are in order, so the last name seen is the most recent
+            if !name.is_empty() {
+                row.name = name;
+            }
+            row.entries += 1;
+            row.after_hours_entries += u32::from(after_hours);
+            row.last_access = at;
+        }
+    }
+    let mut rows: Vec<ReportRow> = rows.into_values().collect();
+    rows.sort_by(|a, b| (&a.name, &a.key).cmp(&(&b.name, &b.key)));
+    AccessReport {
+        start: unix_secs(spec.range.start),
+        end: unix_secs(spec.range.end),
+        grouping: spec.grouping,
+        rows,
+    }
+}
+
+impl UnifiClient {
+    /// Counts the door entries in the system log as `spec` asks, see [build_access_report].
+    /// Fetches the whole range of the log, which can be slow for long ranges on busy sites. Grouping by
+    /// policy also lists the users with their policies, and fetches the doors and door groups.
+    /// Staffed hours are read in the site's local time, see [UnifiClient::with_site_time_zone].
+    pub async fn generate_access_report(&self, spec: &ReportSpec) -> UnifiResult<AccessReport> {
+        if spec.range.end <= spec.range.start {
+            return Err(UnifiError::Validation {
+                field: "range".to_string(),
+                reason: "must end after it starts".to_string(),
+            });
+        }
+        let events = self
+            .fetch_system_log_all(
+                SystemLogOptions::new(SystemLogTopic::DoorOpenings)
+                    .since(spec.range.start)
+                    .until(spec.range.end),
+            )
+            .await?;
+        let entries: Vec<AccessEntry> = events.iter().filter_map(AccessEntry::from_event).collect();
+        let mut user_policies = HashMap::new();
+        if spec.grouping == ReportGrouping::Policy {
+            let (users, policies) = futures::try_join!(
+                self.get_all_users_with(UserListOptions::new().expand_access_policies()),
+                self.resolve_all_policy_resources(),
+            )?;
+            for user in users {
+                let held: Vec<ResolvedPolicy> = user
+                    .access_policies
+                    .iter()
+                    .flatten()
+                    .filter_map(|held| policies.iter().find(|p| p.policy_id == held.id))
+                    .cloned()
+                    .collect();
+                user_policies.insert(user.id, held);
+            }
+        }
+        Ok(build_access_report(
+            &entries,
+            spec,
+            &user_policies,
+            self.site_time_zone,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::utc_time;
+    use crate::{DoorRef, ResolvedResource, ScheduleWindow, WeekSchedule};
+
+    /// 2024-01-01 was a Monday
+    fn monday(h: u64, m: u64) -> SystemTime {
+        utc_time(2024, 1, 1, h, m, 0).unwrap()
+    }
+
+    fn tuesday(h: u64, m: u64) -> SystemTime {
+        utc_time(2024, 1, 2, h, m, 0).unwrap()
+    }
+
+    fn entry(user: (&str, &str), door: (&str, &str), at: SystemTime) -> AccessEntry {
+        AccessEntry {
+            user_id: user.0.to_string(),
+            user_name: user.1.to_string(),
+            door_id: door.0.to_string(),
+            door_name: door.1.to_string(),
+            at,
+        }
+    }
+
+    fn entries() -> Vec<AccessEntry> {
+        vec![
+            entry(("u1", "Alice"), ("d1", "Front"), monday(10, 0)),
+            entry(("u1", "Alice Smith"), ("d1", "Front"), monday(20, 0)),
+            // Deleted since, only the log knows their name
+            entry(("u2", "Bob"), ("d2", "Back"), tuesday(9, 30)),
+            entry(("", "Visitor, \"VIP\""), ("d1", "Front"), tuesday(11, 0)),
+            // Outside the range
+            entry(
+                ("u1", "Alice Smith"),
+                ("d2", "Back"),
+                utc_time(2024, 1, 8, 10, 0, 0).unwrap(),
+            ),
+        ]
+    }
+
+    fn week() -> Range<SystemTime> {
+        monday(0, 0)..utc_time(2024, 1, 8, 0, 0, 0).unwrap()
+    }
+
+    fn staffed_weekdays() -> Schedule {
+        let day = vec![ScheduleWindow {
+            start_time: "09:00:00".to_string(),
+            end_time: "17:00:59".to_string(),
+        }];
+        Schedule {
+            id: "staffed".to_string(),
+            name: "Staffed".to_string(),
+            week_schedule: WeekSchedule {
+                monday: day.clone(),
+                tuesday: day.clone(),
+                wednesday: day.clone(),
+                thursday: day.clone(),
+                friday: day,
+                ..WeekSchedule::default()
+            },
+            holiday_group: None,
+            holiday_schedule: vec![],
+        }
+    }
+
+    fn summary(report: &AccessReport) -> Vec<(&str, &str, u32, u32)> {
+        report
+            .rows
+            .iter()
+            .map(|r| {
+                (
+                    r.key.as_str(),
+                    r.name.as_str(),
+                    r.entries,
+                    r.after_hours_entries,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_by_user_with_the_latest_log_name() {
+        let spec = ReportSpec::new(week(), ReportGrouping::User).staffed_hours(staffed_weekdays());
+        let report = build_access_report(&entries(), &spec, &HashMap::new(), 0);
+        assert_eq!(
+            summary(&report),
+            vec![
+                ("u1", "Alice Smith", 2, 1),
+                ("u2", "Bob", 1, 0),
+                ("Visitor, \"VIP\"", "Visitor, \"VIP\"", 1, 0),
+            ]
+        );
+        let alice = &report.rows[0];
+        assert_eq!(alice.first_access, unix_secs(monday(10, 0)));
+        assert_eq!(alice.last_access, unix_secs(monday(20, 0)));
+        assert_eq!((report.start, report.end), (1704067200, 1704672000));
+    }
+
+    #[test]
+    fn staffed_hours_follow_the_site_offset() {
+        let spec = ReportSpec::new(week(), ReportGrouping::Door).staffed_hours(staffed_weekdays());
+        // At UTC-10 Monday 10:00 UTC is Monday 00:00 locally and Tuesday 09:30 UTC is Monday 23:30
+        let report = build_access_report(&entries(), &spec, &HashMap::new(), -10 * 3600);
+        assert_eq!(
+            summary(&report),
+            vec![("d2", "Back", 1, 1), ("d1", "Front", 3, 2)]
+        );
+    }
+
+    #[test]
+    fn filters_doors_and_counts_nothing_after_hours_without_a_schedule() {
+        let spec = ReportSpec::new(week(), ReportGrouping::Door).doors(["d1"]);
+        let report = build_access_report(&entries(), &spec, &HashMap::new(), 0);
+        assert_eq!(summary(&report), vec![("d1", "Front", 3, 0)]);
+    }
+
+    #[test]
+    fn groups_by_the_policies_covering_the_door() {
+        let policy = |id: &str, door: &str| ResolvedPolicy {
+            policy_id: id.to_string(),
+            policy_name: format!("Policy {id}"),
+            resources: vec![ResolvedResource::Door(DoorRef {
+                id: door.to_string(),
+                name: String::new(),
+            })],
+        };
+        let user_policies = HashMap::from([(
+            "u1".to_string(),
+            vec![policy("p1", "d1"), policy("p2", "d2"), policy("p3", "d1")],
+        )]);
+        let spec = ReportSpec::new(week(), ReportGrouping::Policy);
+        let report = build_access_report(&entries(), &spec, &user_policies, 0);
+        // Bob was deleted and the visitor has no id, nothing explains their entries
+        assert_eq!(
+            summary(&report),
+            vec![
+                ("", "", 2, 0),
+                ("p1", "Policy p1", 2, 0),
+                ("p3", "Policy p3", 2, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_formats_times() {
+        let spec = ReportSpec::new(week(), ReportGrouping::User).doors(["d1"]);
+        let report = build_access_report(&entries(), &spec, &HashMap::new(), 0);
+        assert_eq!(
+            report.to_csv(),
+            "key,name,entries,after_hours_entries,first_access,last_access\n\
+             u1,Alice Smith,2,0,2024-01-01T10:00:00Z,2024-01-01T20:00:00Z\n\
+             \"Visitor, \"\"VIP\"\"\",\"Visitor, \"\"VIP\"\"\",1,0,2024-01-02T11:00:00Z,2024-01-02T11:00:00Z\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["grouping"], "User");
+        assert_eq!(json["rows"][0]["entries"], 2);
+    }
+}
@@ -0,0 +1,276 @@
+//! Unlocking a door for a one-off window, e.g. an evening event, without changing its schedule.
+//!
+//! The developer API has no one-off schedule exceptions, so windows are kept in a [StateStore] and
+//! applied by [UnifiClient::process_scheduled_unlocks], which sets a [DoorLockRule::Custom] unlock for
+//! the rest of the window once it has started and resets the door after it ends. Call it regularly,
+//! e.g. every minute from `spawn_periodic` when the `periodic` feature is on; a window only starts
+//! when it runs, so the interval is how late a door can unlock.
+//!
+//! If the process was down when a window started, the next run still unlocks the door for what is left
+//! of the window. A window that passed entirely while it was down is dropped and reported as missed.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::state_store::{load_state, save_state, VersionedState};
+use crate::{DoorLockRule, StateStore, UnifiClient, UnifiError, UnifiResult};
+
+/// Key scheduled unlocks are kept under in the store
+const SCHEDULED_UNLOCKS_KEY: &str = "unifi_access/scheduled_unlocks";
+
+/// A span of time, `start` inclusive and `end` exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: SystemTime,
+    pub end: SystemTime,
+}
+
+impl TimeRange {
+    /// Fails with [UnifiError::Validation] unless `end` is after `start`
+    pub fn new(start: SystemTime, end: SystemTime) -> UnifiResult<TimeRange> {
+        if end <= start {
+            return Err(UnifiError::Validation {
+                field: "window".to_string(),
+                reason: "must end after it starts".to_string(),
+            });
+        }
+        Ok(TimeRange { start, end })
+    }
+}
+
+/// A door unlock waiting for or inside its window, see [UnifiClient::schedule_one_time_unlock]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledUnlock {
+    pub id: String,
+    pub door_id: String,
+    /// Seconds since the unix epoch the door unlocks at
+    pub starts_at: u64,
+    /// Seconds since the unix epoch the door locks again at
+    pub ends_at: u64,
+    /// Whether the unlock has been set on the door
+    #[serde(default)]
+    pub applied: bool,
+}
+
+/// What a run of [UnifiClient::process_scheduled_unlocks] did
+#[derive(Debug, Default)]
+pub struct ScheduledUnlockReport {
+    /// Unlocks whose window started, now set on their door
+    pub applied: Vec<ScheduledUnlock>,
+    /// Unlocks whose window ended, their door reset to its schedule
+    pub cleared: Vec<ScheduledUnlock>,
+    /// Unlocks whose whole window passed before a run saw it, dropped without touching the door
+    pub missed: Vec<ScheduledUnlock>,
+    /// Unlocks that couldn't be applied or cleared, they are retried on the next run
+    pub failed: Vec<(ScheduledUnlock, UnifiError)>,
+}
+
+/// Format of the scheduled unlocks in a [StateStore]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredUnlocks {
+    unlocks: Vec<ScheduledUnlock>,
+}
+
+impl VersionedState for StoredUnlocks {
+    const VERSION: u8 = 1;
+}
+
+/// The store scheduled unlocks are kept in, shared by clones of a client
+pub(crate) struct UnlockSchedule {
+    store: Arc<dyn StateStore>,
+    // Serializes read-modify-write of the stored unlocks within this process
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl UnlockSchedule {
+    pub(crate) fn new(store: Arc<dyn StateStore>) -> UnlockSchedule {
+        UnlockSchedule {
+            store,
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    async fn load(&self) -> UnifiResult<StoredUnlocks> {
+        Ok(load_state(self.store.as_ref(), SCHEDULED_UNLOCKS_KEY)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn save(&self, state: &StoredUnlocks) -> UnifiResult<()> {
+        save_state(self.store.as_ref(), SCHEDULED_UNLOCKS_KEY, state).await
+    }
+}
+
+fn unix_secs(time: SystemTime) -> UnifiResult<u64> {
+    Ok(time.duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// The latest end of the windows open on the door at `now`. The door stays unlocked until then, so a window
+/// nested in a longer one doesn't cut the longer one short.
+fn open_until(unlocks: &[ScheduledUnlock], door_id: &str, now: u64) -> Option<u64> {
+    unlocks
+        .iter()
+        .filter(|other| other.door_id == door_id && other.starts_at <= now && now < other.ends_at)
+        .map(|other| other.ends_at)
+        .max()
+}
+
+/// Whether another window on the door is open at `now`, in which case the door isn't reset.
+/// An open window that isn't applied yet is by the end of the same run, whatever the order.
+fn still_unlocked_by_other(
+    unlocks: &[ScheduledUnlock],
+    unlock: &ScheduledUnlock,
+    now: u64,
+) -> bool {
+    unlocks.iter().any(|other| {
+        other.id != unlock.id
+            && other.door_id == unlock.door_id
+            && other.starts_at <= now
+            && now < other.ends_at
+    })
+}
+
+impl UnifiClient {
+    /// Keeps scheduled unlocks in `store` instead of in memory, so they survive restarts
+    pub fn with_scheduled_unlock_store(mut self, store: Arc<dyn StateStore>) -> UnifiClient {
+        self.scheduled_unlocks = Arc::new(UnlockSchedule::new(store));
+        self
+    }
+
+    /// Registers an unlock of the door for `window`, applied by [UnifiClient::process_scheduled_unlocks].
+    /// Nothing is sent to the controller until the window starts.
+    pub async fn schedule_one_time_unlock(
+        &self,
+        door_id: &str,
+        window: TimeRange,
+    ) -> UnifiResult<ScheduledUnlock> {
+        let unlock = ScheduledUnlock {
+            id: format!(
+                "{door_id}-{}",
+                window.start.duration_since(UNIX_EPOCH)?.as_nanos()
+            ),
+            door_id: door_id.to_string(),
+            starts_at: unix_secs(window.start)?,
+            ends_at: unix_secs(window.end)?,
+            applied: false,
+        };
+        let schedule = &self.scheduled_unlocks;
+        let _guard = schedule.lock.lock().await;
+        let mut state = schedule.load().await?;
+        if state.unlocks.iter().any(|u| u.id == unlock.id) {
+            return Err(UnifiError::Validation {
+                field: "window".to_string(),
+                reason: format!("door {door_id} already has an unlock starting then"),
+            });
+        }
+        state.unlocks.push(unlock.clone());
+        schedule.save(&state).await?;
+        info!(
+            "Scheduled unlock of door {door_id} from {} to {}",
+            unlock.starts_at, unlock.ends_at
+        );
+        Ok(unlock)
+    }
+
+    /// Unlocks that are waiting for their window or inside it, ordered by start
+    pub async fn list_scheduled_unlocks(&self) -> UnifiResult<Vec<ScheduledUnlock>> {
+        let mut unlocks = self.scheduled_unlocks.load().await?.unlocks;
+        unlocks.sort_by(|a, b| (a.starts_at, &a.id).cmp(&(b.starts_at, &b.id)));
+        Ok(unlocks)
+    }
+
+    /// Removes a scheduled unlock, resetting the door if its window is underway.
+    /// Returns false if there is no unlock with the id.
+    pub async fn cancel_scheduled_unlock(&self, unlock_id: &str) -> UnifiResult<bool> {
+        let now = unix_secs(SystemTime::now())?;
+        let schedule = &self.scheduled_unlocks;
+        let _guard = schedule.lock.lock().await;
+        let mut state = schedule.load().await?;
+        let Some(unlock) = state.unlocks.iter().find(|u| u.id == unlock_id).cloned() else {
+            return Ok(false);
+        };
+        state.unlocks.retain(|u| u.id != unlock_id);
+        if unlock.applied {
+            // Other windows open on the door keep it unlocked until the latest of them ends
+            let rule = match open_until(&state.unlocks, &unlock.door_id, now) {
+                Some(until) => DoorLockRule::Custom(Duration::from_secs(until - now)),
+                None => DoorLockRule::Reset,
+            };
+            self.set_door_lock_rule(&unlock.door_id, rule).await?;
+        }
+        schedule.save(&state).await?;
+        info!("Cancelled scheduled unlock {unlock_id}");
+        Ok(true)
+    }
+
+    /// Applies the unlocks whose window has started and clears those whose window has ended.
+    /// Call it regularly, see the [module docs](self). Failures are reported and retried on the next run.
+    pub async fn process_scheduled_unlocks(&self) -> UnifiResult<ScheduledUnlockReport> {
+        self.process_scheduled_unlocks_at(SystemTime::now()).await
+    }
+
+    /// [UnifiClient::process_scheduled_unlocks] as if the time were `now`
+    pub async fn process_scheduled_unlocks_at(
+        &self,
+        now: SystemTime,
+    ) -> UnifiResult<ScheduledUnlockReport> {
+        let now = unix_secs(now)?;
+        let schedule = &self.scheduled_unlocks;
+        let _guard = schedule.lock.lock().await;
+        let mut state = schedule.load().await?;
+        let mut report = ScheduledUnlockReport::default();
+        let mut remaining = vec![];
+        for mut unlock in state.unlocks.clone() {
+            if now >= unlock.ends_at {
+                if !unlock.applied {
+                    warn!(
+                        "Window of scheduled unlock {} passed before it was processed",
+                        unlock.id
+                    );
+                    report.missed.push(unlock);
+                    continue;
+                }
+                if still_unlocked_by_other(&state.unlocks, &unlock, now) {
+                    report.cleared.push(unlock);
+                    continue;
+                }
+                match self
+                    .set_door_lock_rule(&unlock.door_id, DoorLockRule::Reset)
+                    .await
+                {
+                    Ok(()) => report.cleared.push(unlock),
+                    Err(e) => {
+                        warn!("Failed to clear scheduled unlock {}: {e}", unlock.id);
+                        remaining.push(unlock.clone());
+                        report.failed.push((unlock, e));
+                    }
+                }
+            } else if now >= unlock.starts_at && !unlock.applied {
+                // Unlocks for what is left of the window, so a late run doesn't overrun it, or of a longer
+                // window open on the same door
+                let until =
+                    open_until(&state.unlocks, &unlock.door_id, now).unwrap_or(unlock.ends_at);
+                let rule = DoorLockRule::Custom(Duration::from_secs(until - now));
+                match self.set_door_lock_rule(&unlock.door_id, rule).await {
+                    Ok(()) => {
+                        unlock.applied = true;
+                        report.applied.push(unlock.clone());
+                    }
+                    Err(e) => {
+                        warn!("Failed to apply scheduled unlock {}: {e}", unlock.id);
+                        report.failed.push((unlock.clone(), e));
+                    }
+                }
+                remaining.push(unlock);
+            } else {
+                remaining.push(unlock);
+            }
+        }
+        state.unlocks = remaining;
+        schedule.save(&state).await?;
+        Ok(report)
+    }
+}
@@ -0,0 +1,1174 @@
+//! Access policy schedules: the weekly windows a policy grants access in, and the holidays that replace them.
+//!
+//! Schedules are defined in the site's local time. The API doesn't say what that is, set it with
+//! [UnifiClient::with_site_time_zone] before checking a schedule against a point in time.
+
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::clock::{civil_from_days, days_from_civil, unix_secs};
+use crate::{encode_path_segment, null_as_default, SiteTimeZone, UnifiClient, UnifiResult};
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// How far [Schedule::next_transition_after] looks ahead, a year and a week so every recurring holiday
+/// and daylight saving time change comes up
+const TRANSITION_SEARCH_DAYS: i64 = 373;
+
+/// A schedule of an access policy, see [UnifiClient::get_schedule]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct Schedule {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub week_schedule: WeekSchedule,
+    /// The holidays on which [Schedule::holiday_schedule] applies instead of the week schedule
+    #[serde(default)]
+    pub holiday_group: Option<HolidayGroup>,
+    /// Windows on holidays, empty if holidays are closed all day
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub holiday_schedule: Vec<ScheduleWindow>,
+}
+
+/// The windows of each day of the week, a day without windows is closed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+#[serde(default)]
+pub struct WeekSchedule {
+    #[serde(deserialize_with = "null_as_default")]
+    pub sunday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub monday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub tuesday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub wednesday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub thursday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub friday: Vec<ScheduleWindow>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub saturday: Vec<ScheduleWindow>,
+}
+
+impl WeekSchedule {
+    /// The windows of a day, counting from Sunday as 0
+    fn day(&self, weekday: u32) -> &[ScheduleWindow] {
+        match weekday {
+            0 => &self.sunday,
+            1 => &self.monday,
+            2 => &self.tuesday,
+            3 => &self.wednesday,
+            4 => &self.thursday,
+            5 => &self.friday,
+            _ => &self.saturday,
+        }
+    }
+
+    /// The windows of every day
+    fn windows(&self) -> impl Iterator<Item = &ScheduleWindow> {
+        (0..7).flat_map(move |weekday| self.day(weekday))
+    }
+
+    /// True if a window holds `at` on the clock of a site in `time_zone`, which may be a fixed offset
+    /// in seconds ahead of UTC. A window that crosses midnight counts on the next morning too.
+    pub fn is_open_at(&self, at: SystemTime, time_zone: impl Into<SiteTimeZone>) -> bool {
+        let time_zone = time_zone.into();
+        self.covers(&LocalTime::at(at, time_zone.utc_offset_at(at)))
+    }
+
+    /// True if a window of the day holds the local time, or one running on from the day before
+    fn covers(&self, local: &LocalTime) -> bool {
+        let yesterday = self.day((local.weekday + 6) % 7);
+        self.day(local.weekday)
+            .iter()
+            .any(|w| w.contains(local.secs))
+            || yesterday.iter().any(|w| w.spills_into(local.secs))
+    }
+}
+
+/// Part of a day, e.g. `09:00:00` to `17:00:59`. Both ends are included, to the second.
+/// A window that ends before it starts crosses midnight, e.g. `22:00:00` to `06:00:00` runs from 22:00 on its own
+/// day until 06:00 the next morning.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct ScheduleWindow {
+    pub start_time: String,
+    pub end_time: String,
+}
+
+impl ScheduleWindow {
+    /// Start and end in seconds since midnight, None if either can't be parsed
+    fn bounds(&self) -> Option<(u32, u32)> {
+        let bounds = parse_time_of_day(&self.start_time).zip(parse_time_of_day(&self.end_time));
+        if bounds.is_none() {
+            warn!(
+                "Ignoring schedule window with unreadable times {} to {}",
+                self.start_time, self.end_time
+            );
+        }
+        bounds
+    }
+
+    /// True if the second of the window's own day falls in the window, up to midnight if it crosses it
+    fn contains(&self, secs: u32) -> bool {
+        match self.bounds() {
+            Some((start, end)) if start <= end => start <= secs && secs <= end,
+            Some((start, _)) => start <= secs,
+            None => false,
+        }
+    }
+
+    /// True if the window crosses midnight and runs on to the second of the next day
+    fn spills_into(&self, secs: u32) -> bool {
+        matches!(self.bounds(), Some((start, end)) if start > end && secs <= end)
+    }
+}
+
+/// The holidays a schedule observes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct HolidayGroup {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub holidays: Vec<Holiday>,
+}
+
+/// A holiday, from `start_time` up to but not including `end_time`, both in the site's local time
+/// e.g. `2024-12-25 00:00:00`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct Holiday {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    /// The holiday falls on the same dates every year
+    #[serde(default)]
+    pub repeat: bool,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+impl Holiday {
+    /// True if the local time falls within the holiday, never if either end can't be parsed
+    fn covers(&self, local: &LocalTime) -> bool {
+        let (Some(start), Some(end)) = (
+            parse_local_datetime(&self.start_time),
+            parse_local_datetime(&self.end_time),
+        ) else {
+            warn!(
+                "Ignoring holiday {} with unreadable times {} to {}",
+                self.name, self.start_time, self.end_time
+            );
+            return false;
+        };
+        let now = local.datetime();
+        if !self.repeat {
+            return start <= now && now < end;
+        }
+        // Compared without the year, a holiday over new year wraps around
+        let (start, end, now) = (start.without_year(), end.without_year(), now.without_year());
+        if start <= end {
+            start <= now && now < end
+        } else {
+            now >= start || now < end
+        }
+    }
+}
+
+/// Whether a schedule allows access at a point in time, see [Schedule::check]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub enum ScheduleDecision {
+    /// Within a window of the day
+    Open,
+    /// Outside every window of the day
+    Closed,
+    /// On the named holiday, within a window of the holiday schedule
+    OpenForHoliday(String),
+    /// On the named holiday, outside every window of the holiday schedule
+    ClosedForHoliday(String),
+}
+
+impl ScheduleDecision {
+    /// True if the decision is to let the user in
+    pub fn allows_access(&self) -> bool {
+        matches!(
+            self,
+            ScheduleDecision::Open | ScheduleDecision::OpenForHoliday(_)
+        )
+    }
+}
+
+impl Schedule {
+    /// Whether the schedule allows access at `at`, on the clock of a site in `time_zone`, which may be
+    /// a fixed offset in seconds ahead of UTC. On a holiday of the schedule's holiday group the holiday
+    /// schedule applies instead of the week's.
+    pub fn check(&self, at: SystemTime, time_zone: impl Into<SiteTimeZone>) -> ScheduleDecision {
+        let time_zone = time_zone.into();
+        self.decide(&LocalTime::at(at, time_zone.utc_offset_at(at)), &[])
+    }
+
+    /// True if the schedule allows access at `at`, on the clock of a site in `time_zone`.
+    /// The holidays of `holiday_groups` are observed as well as those of [Schedule::holiday_group],
+    /// see [Schedule::check] for why access is allowed or not.
+    pub fn is_active_at(
+        &self,
+        at: SystemTime,
+        holiday_groups: &[HolidayGroup],
+        time_zone: impl Into<SiteTimeZone>,
+    ) -> bool {
+        let time_zone = time_zone.into();
+        self.decide(
+            &LocalTime::at(at, time_zone.utc_offset_at(at)),
+            holiday_groups,
+        )
+        .allows_access()
+    }
+
+    /// The first time after `at` the schedule starts or stops allowing access, as [Schedule::is_active_at]
+    /// with the same holiday groups and time zone reads it. Local times skipped when clocks go forward
+    /// never happen, so a window starting in the gap opens when the clocks change.
+    ///
+    /// None if nothing changes within a year, counting every upcoming holiday of the groups.
+    pub fn next_transition_after(
+        &self,
+        at: SystemTime,
+        holiday_groups: &[HolidayGroup],
+        time_zone: impl Into<SiteTimeZone>,
+    ) -> Option<SystemTime> {
+        let time_zone = time_zone.into();
+        let from = unix_secs(at);
+        let active = |unix: i64| {
+            self.decide(
+                &LocalTime::from_unix(unix, time_zone.offset_at(unix)),
+                holiday_groups,
+            )
+            .allows_access()
+        };
+        let now = active(from);
+        self.transition_candidates(from, holiday_groups, &time_zone)
+            .into_iter()
+            .find(|&unix| active(unix) != now)
+            .and_then(|unix| u64::try_from(unix).ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn decide(&self, local: &LocalTime, holiday_groups: &[HolidayGroup]) -> ScheduleDecision {
+        let holiday = self
+            .holiday_group
+            .iter()
+            .chain(holiday_groups)
+            .flat_map(|group| &group.holidays)
+            .find(|holiday| holiday.covers(local));
+        if let Some(holiday) = holiday {
+            // The holiday schedule applies every day of the holiday, so its windows also run on from the day before
+            let open = self
+                .holiday_schedule
+                .iter()
+                .any(|w| w.contains(local.secs) || w.spills_into(local.secs));
+            return if open {
+                ScheduleDecision::OpenForHoliday(holiday.name.clone())
+            } else {
+                ScheduleDecision::ClosedForHoliday(holiday.name.clone())
+            };
+        }
+        if self.week_schedule.covers(local) {
+            ScheduleDecision::Open
+        } else {
+            ScheduleDecision::Closed
+        }
+    }
+
+    /// Every instant after `from` at which the decision could change, in order: the local midnights, window
+    /// starts, the second after each window ends, the holiday boundaries, and the daylight saving time changes
+    fn transition_candidates(
+        &self,
+        from: i64,
+        holiday_groups: &[HolidayGroup],
+        time_zone: &SiteTimeZone,
+    ) -> Vec<i64> {
+        let offsets = time_zone.offsets();
+        let first_day = (from + i64::from(offsets.iter().max().copied().unwrap_or_default()))
+            .div_euclid(SECS_PER_DAY);
+        let mut days: BTreeSet<i64> =
+            (first_day - 1..=first_day + TRANSITION_SEARCH_DAYS).collect();
+        let holidays: Vec<(DateTime, DateTime, bool)> = self
+            .holiday_group
+            .iter()
+            .chain(holiday_groups)
+            .flat_map(|group| &group.holidays)
+            .filter_map(|holiday| {
+                let start = parse_local_datetime(&holiday.start_time)?;
+                let end = parse_local_datetime(&holiday.end_time)?;
+                Some((start, end, holiday.repeat))
+            })
+            .collect();
+        // Holidays that don't repeat may lie beyond the search, their windows matter all the same
+        for (start, end, _) in holidays.iter().filter(|(_, _, repeat)| !repeat) {
+            let start = days_from_civil(start.year, start.month, start.day);
+            let end = days_from_civil(end.year, end.month, end.day);
+            if end >= first_day {
+                days.extend(start.max(first_day)..=end.min(start + TRANSITION_SEARCH_DAYS));
+            }
+        }
+        let mut window_secs: BTreeSet<u32> = BTreeSet::from([0]);
+        for window in self.week_schedule.windows().chain(&self.holiday_schedule) {
+            if let Some((start, end)) = window.bounds() {
+                window_secs.insert(start);
+                if end + 1 < SECS_PER_DAY as u32 {
+                    window_secs.insert(end + 1);
+                }
+            }
+        }
+        let mut candidates = BTreeSet::new();
+        for day in days {
+            let (year, month, date) = civil_from_days(day);
+            let holiday_secs = holidays.iter().flat_map(|(start, end, repeat)| {
+                [start, end].into_iter().filter_map(move |boundary| {
+                    let same_date = boundary.month == month
+                        && boundary.day == date
+                        && (*repeat || boundary.year == year);
+                    same_date.then_some(boundary.secs)
+                })
+            });
+            for secs in window_secs.iter().copied().chain(holiday_secs) {
+                let local = day * SECS_PER_DAY + i64::from(secs);
+                // A local time exists under each offset that is in force at it, none in a skipped hour
+                for &offset in &offsets {
+                    let unix = local - i64::from(offset);
+                    if unix > from && time_zone.offset_at(unix) == offset {
+                        candidates.insert(unix);
+                    }
+                }
+            }
+        }
+        let (first_year, _, _) = civil_from_days(first_day);
+        let last = (first_day + TRANSITION_SEARCH_DAYS + 1) * SECS_PER_DAY;
+        for year in first_year..=first_year + 2 {
+            candidates.extend(
+                time_zone
+                    .transitions(year)
+                    .into_iter()
+                    .filter(|&unix| unix > from && unix <= last),
+            );
+        }
+        candidates.into_iter().collect()
+    }
+}
+
+/// A civil date and time of day, ordered chronologically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    secs: u32,
+}
+
+impl DateTime {
+    /// The same date and time in year 0, for comparing dates that recur every year
+    fn without_year(self) -> DateTime {
+        DateTime { year: 0, ..self }
+    }
+}
+
+/// A point in time as read on the site's clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LocalTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    /// Day of the week, Sunday is 0
+    weekday: u32,
+    /// Seconds since local midnight
+    secs: u32,
+}
+
+impl LocalTime {
+    fn at(at: SystemTime, utc_offset_secs: i32) -> LocalTime {
+        LocalTime::from_unix(unix_secs(at), utc_offset_secs)
+    }
+
+    fn from_unix(unix: i64, utc_offset_secs: i32) -> LocalTime {
+        let local = unix + i64::from(utc_offset_secs);
+        let days = local.div_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        LocalTime {
+            year,
+            month,
+            day,
+            // The epoch was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            secs: local.rem_euclid(SECS_PER_DAY) as u32,
+        }
+    }
+
+    fn datetime(&self) -> DateTime {
+        DateTime {
+            year: self.year,
+            month: self.month,
+            day: self.day,
+            secs: self.secs,
+        }
+    }
+}
+
+/// Seconds since midnight of `HH:MM:SS` or `HH:MM`
+fn parse_time_of_day(time: &str) -> Option<u32> {
+    let mut parts = time.trim().split(':').map(|n| n.parse::<u32>().ok());
+    let (Some(Some(h)), Some(Some(m)), s) = (parts.next(), parts.next(), parts.next()) else {
+        return None;
+    };
+    let s = match s {
+        None => 0,
+        Some(s) => s?,
+    };
+    if parts.next().is_some() || h > 23 || m > 59 || s > 59 {
+        return None;
+    }
+    Some(h * 3600 + m * 60 + s)
+}
+
+/// A local date and time like `2024-12-25 00:00:00` or `2024-12-25T00:00:00Z`.
+/// Holidays are entered as local dates, so a trailing zone is ignored rather than converted.
+fn parse_local_datetime(datetime: &str) -> Option<DateTime> {
+    let (date, time) = datetime.trim().split_once(['T', 't', ' '])?;
+    let mut ymd = date.split('-').map(|n| n.parse::<u32>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day)), None) =
+        (ymd.next(), ymd.next(), ymd.next(), ymd.next())
+    else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let time = time.trim_end_matches(['Z', 'z']);
+    let time = time.split(['+', '-', '.']).next()?;
+    Some(DateTime {
+        year: i64::from(year),
+        month,
+        day,
+        secs: parse_time_of_day(time)?,
+    })
+}
+
+impl UnifiClient {
+    /// Sets how far the site's local time is ahead of UTC, in seconds, so schedules are checked against
+    /// the site's clock. Defaults to 0, UTC. The offset is fixed, for a site that observes daylight saving
+    /// time use [UnifiClient::with_site_time_zone].
+    pub fn with_site_utc_offset(self, offset_secs: i32) -> UnifiClient {
+        self.with_site_time_zone(offset_secs)
+    }
+
+    /// Fetches a schedule of an access policy by id, with its holiday group
+    pub async fn get_schedule(&self, schedule_id: &str) -> UnifiResult<Schedule> {
+        debug!("Sending get_schedule_request: {schedule_id}");
+        self.generic_request(
+            reqwest::Method::GET,
+            self.api_path(&format!(
+                "access_policies/schedules/{}",
+                encode_path_segment(schedule_id)
+            )),
+            None,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::clock::utc_time;
+
+    fn window(start: &str, end: &str) -> ScheduleWindow {
+        ScheduleWindow {
+            start_time: start.to_string(),
+            end_time: end.to_string(),
+        }
+    }
+
+    fn holiday(name: &str, start: &str, end: &str, repeat: bool) -> Holiday {
+        Holiday {
+            id: String::new(),
+            name: name.to_string(),
+            repeat,
+            start_time: start.to_string(),
+            end_time: end.to_string(),
+        }
+    }
+
+    /// Weekdays 09:00 to 17:00, Saturday mornings, closed Sunday
+    fn office_hours() -> Schedule {
+        let weekday = vec![window("09:00:00", "17:00:59")];
+        Schedule {
+            id: "s1".to_string(),
+            name: "Office hours".to_string(),
+            week_schedule: WeekSchedule {
+                sunday: vec![],
+                monday: weekday.clone(),
+                tuesday: weekday.clone(),
+                wednesday: weekday.clone(),
+                thursday: weekday.clone(),
+                friday: weekday,
+                saturday: vec![window("08:00:00", "12:00:59")],
+            },
+            holiday_group: None,
+            holiday_schedule: vec![],
+        }
+    }
+
+    fn utc(year: u64, month: u64, day: u64, h: u64, m: u64, s: u64) -> SystemTime {
+        utc_time(year, month, day, h, m, s).unwrap()
+    }
+
+    #[test]
+    fn local_time_applies_the_offset() {
+        // Monday 2024-01-01 23:30 UTC
+        let at = utc(2024, 1, 1, 23, 30, 0);
+        let local = LocalTime::at(at, 0);
+        assert_eq!((local.weekday, local.secs), (1, 23 * 3600 + 1800));
+        // An hour ahead it is already Tuesday
+        let local = LocalTime::at(at, 3600);
+        assert_eq!((local.year, local.month, local.day), (2024, 1, 2));
+        assert_eq!((local.weekday, local.secs), (2, 1800));
+        // Five hours behind it is still Monday evening
+        let local = LocalTime::at(at, -5 * 3600);
+        assert_eq!(
+            (local.day, local.weekday, local.secs),
+            (1, 1, 18 * 3600 + 1800)
+        );
+        // Across the end of the year
+        let local = LocalTime::at(utc(2023, 12, 31, 23, 0, 0), 7200);
+        assert_eq!((local.year, local.month, local.day), (2024, 1, 1));
+    }
+
+    #[test]
+    fn times_of_day_parse() {
+        assert_eq!(parse_time_of_day("00:00:00"), Some(0));
+        assert_eq!(parse_time_of_day("17:00:59"), Some(17 * 3600 + 59));
+        assert_eq!(parse_time_of_day("09:30"), Some(9 * 3600 + 1800));
+        assert_eq!(parse_time_of_day("23:59:59"), Some(SECS_PER_DAY as u32 - 1));
+        for bad in [
+            "",
+            "24:00:00",
+            "12:60:00",
+            "12:00:60",
+            "12",
+            "12:00:00:00",
+            "noon",
+        ] {
+            assert_eq!(parse_time_of_day(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn local_datetimes_parse_and_ignore_the_zone() {
+        let christmas = DateTime {
+            year: 2024,
+            month: 12,
+            day: 25,
+            secs: 0,
+        };
+        for spelling in [
+            "2024-12-25 00:00:00",
+            "2024-12-25T00:00:00Z",
+            "2024-12-25T00:00:00+02:00",
+            "2024-12-25T00:00:00.000Z",
+        ] {
+            assert_eq!(
+                parse_local_datetime(spelling),
+                Some(christmas),
+                "{spelling}"
+            );
+        }
+        for bad in [
+            "2024-12-25",
+            "2024-13-01 00:00:00",
+            "2024-12-25 25:00:00",
+            "",
+        ] {
+            assert_eq!(parse_local_datetime(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn week_windows_include_both_ends() {
+        let schedule = office_hours();
+        // Tuesday 2024-01-02
+        for (h, m, s, decision) in [
+            (8, 59, 59, ScheduleDecision::Closed),
+            (9, 0, 0, ScheduleDecision::Open),
+            (12, 0, 0, ScheduleDecision::Open),
+            (17, 0, 59, ScheduleDecision::Open),
+            (17, 1, 0, ScheduleDecision::Closed),
+        ] {
+            assert_eq!(
+                schedule.check(utc(2024, 1, 2, h, m, s), 0),
+                decision,
+                "{h}:{m}:{s}"
+            );
+        }
+    }
+
+    #[test]
+    fn each_weekday_uses_its_own_windows() {
+        let schedule = office_hours();
+        // Saturday 2024-01-06 and Sunday 2024-01-07
+        assert_eq!(
+            schedule.check(utc(2024, 1, 6, 10, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 6, 14, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 10, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+    }
+
+    #[test]
+    fn the_site_offset_moves_the_windows() {
+        let schedule = office_hours();
+        // 07:30 UTC on a Tuesday is 09:30 two hours east and 02:30 five hours west
+        let at = utc(2024, 1, 2, 7, 30, 0);
+        assert_eq!(schedule.check(at, 0), ScheduleDecision::Closed);
+        assert_eq!(schedule.check(at, 7200), ScheduleDecision::Open);
+        assert_eq!(schedule.check(at, -5 * 3600), ScheduleDecision::Closed);
+        // 03:00 UTC on a Saturday is still Friday evening five hours west, after hours
+        assert_eq!(
+            schedule.check(utc(2024, 1, 6, 3, 0, 0), -5 * 3600),
+            ScheduleDecision::Closed
+        );
+        // 22:00 UTC on a Saturday is Sunday morning two hours east, closed all day
+        assert_eq!(
+            schedule.check(utc(2024, 1, 6, 22, 0, 0), 7200),
+            ScheduleDecision::Closed
+        );
+    }
+
+    #[test]
+    fn holidays_replace_the_week_schedule() {
+        let mut schedule = office_hours();
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: "Public holidays".to_string(),
+            holidays: vec![holiday(
+                "New year",
+                "2024-01-01 00:00:00",
+                "2024-01-02 00:00:00",
+                false,
+            )],
+        });
+        // Monday 2024-01-01 would be open
+        let new_year = utc(2024, 1, 1, 10, 0, 0);
+        assert_eq!(
+            schedule.check(new_year, 0),
+            ScheduleDecision::ClosedForHoliday("New year".to_string())
+        );
+        schedule.holiday_schedule = vec![window("10:00:00", "11:00:59")];
+        assert_eq!(
+            schedule.check(new_year, 0),
+            ScheduleDecision::OpenForHoliday("New year".to_string())
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 1, 12, 0, 0), 0),
+            ScheduleDecision::ClosedForHoliday("New year".to_string())
+        );
+        // The end is excluded, the next day is back to normal
+        assert_eq!(
+            schedule.check(utc(2024, 1, 2, 10, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+        // Nor does it apply the following year
+        assert_eq!(
+            schedule.check(utc(2025, 1, 1, 10, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+    }
+
+    #[test]
+    fn holidays_are_in_local_time() {
+        let mut schedule = office_hours();
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: String::new(),
+            holidays: vec![holiday(
+                "Founders day",
+                "2024-01-03 00:00:00",
+                "2024-01-04 00:00:00",
+                false,
+            )],
+        });
+        // 09:30 on the 3rd five hours west is 14:30 UTC
+        let at = utc(2024, 1, 3, 14, 30, 0);
+        assert_eq!(
+            schedule.check(at, -5 * 3600),
+            ScheduleDecision::ClosedForHoliday("Founders day".to_string())
+        );
+        // 08:00 UTC on the 4th is still the 3rd ten hours west
+        let at = utc(2024, 1, 4, 8, 0, 0);
+        assert_eq!(schedule.check(at, 0), ScheduleDecision::Closed);
+        assert_eq!(
+            schedule.check(at + Duration::from_secs(3600), 0),
+            ScheduleDecision::Open
+        );
+        assert_eq!(
+            schedule.check(at, -10 * 3600),
+            ScheduleDecision::ClosedForHoliday("Founders day".to_string())
+        );
+    }
+
+    #[test]
+    fn repeating_holidays_recur_every_year() {
+        let mut schedule = office_hours();
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: String::new(),
+            holidays: vec![
+                holiday(
+                    "Christmas",
+                    "2020-12-25 00:00:00",
+                    "2020-12-26 00:00:00",
+                    true,
+                ),
+                // Over new year, wraps around
+                holiday(
+                    "Winter break",
+                    "2020-12-31 00:00:00",
+                    "2021-01-02 00:00:00",
+                    true,
+                ),
+            ],
+        });
+        // Wednesday 2024-12-25
+        assert_eq!(
+            schedule.check(utc(2024, 12, 25, 10, 0, 0), 0),
+            ScheduleDecision::ClosedForHoliday("Christmas".to_string())
+        );
+        // Thursday 2024-12-26
+        assert_eq!(
+            schedule.check(utc(2024, 12, 26, 10, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+        for at in [utc(2024, 12, 31, 10, 0, 0), utc(2025, 1, 1, 10, 0, 0)] {
+            assert_eq!(
+                schedule.check(at, 0),
+                ScheduleDecision::ClosedForHoliday("Winter break".to_string())
+            );
+        }
+        // Thursday 2025-01-02
+        assert_eq!(
+            schedule.check(utc(2025, 1, 2, 10, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+    }
+
+    #[test]
+    fn unreadable_windows_and_holidays_never_match() {
+        let mut schedule = office_hours();
+        schedule.week_schedule.tuesday = vec![window("nine", "17:00:00")];
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: String::new(),
+            holidays: vec![holiday("Broken", "soon", "later", false)],
+        });
+        assert_eq!(
+            schedule.check(utc(2024, 1, 2, 10, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 3, 10, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+    }
+
+    #[test]
+    fn windows_crossing_midnight_run_into_the_next_day() {
+        let mut schedule = office_hours();
+        // A night shift from Monday 22:00 to Tuesday 06:00
+        schedule.week_schedule.monday = vec![window("22:00:00", "06:00:00")];
+        for (day, h, m, s, decision) in [
+            (1, 21, 59, 59, ScheduleDecision::Closed),
+            (1, 22, 0, 0, ScheduleDecision::Open),
+            (1, 23, 59, 59, ScheduleDecision::Open),
+            (2, 0, 0, 0, ScheduleDecision::Open),
+            (2, 5, 59, 59, ScheduleDecision::Open),
+            (2, 6, 0, 0, ScheduleDecision::Open),
+            (2, 6, 0, 1, ScheduleDecision::Closed),
+            // Tuesday's own window still applies
+            (2, 9, 0, 0, ScheduleDecision::Open),
+            // Only the day after the window's own day
+            (3, 3, 0, 0, ScheduleDecision::Closed),
+        ] {
+            assert_eq!(
+                schedule.check(utc(2024, 1, day, h, m, s), 0),
+                decision,
+                "{day} {h}:{m}:{s}"
+            );
+        }
+        // Nor does Monday's morning pick up a window that only starts that night
+        assert_eq!(
+            schedule.check(utc(2024, 1, 1, 3, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+    }
+
+    #[test]
+    fn windows_crossing_midnight_wrap_around_the_week() {
+        let mut schedule = office_hours();
+        // Saturday night into Sunday, the only window that reaches Sunday
+        schedule.week_schedule.saturday = vec![window("20:00:00", "02:00:00")];
+        // Sunday 2024-01-07
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 1, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 3, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+        // And Sunday night into Monday morning
+        schedule.week_schedule.sunday = vec![window("23:00:00", "01:00:00")];
+        assert_eq!(
+            schedule.check(utc(2024, 1, 8, 0, 30, 0), 0),
+            ScheduleDecision::Open
+        );
+        // 02:30 UTC on a Sunday is 01:30 an hour west, still within Saturday night
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 2, 30, 0), -3600),
+            ScheduleDecision::Open
+        );
+    }
+
+    #[test]
+    fn week_schedules_are_checked_on_their_own() {
+        let mut week = office_hours().week_schedule;
+        week.friday = vec![window("18:00:00", "01:59:59")];
+        // Friday 2024-01-05 and the early hours of Saturday, two hours east
+        assert!(week.is_open_at(utc(2024, 1, 5, 16, 0, 0), 7200));
+        assert!(week.is_open_at(utc(2024, 1, 5, 23, 30, 0), 7200));
+        assert!(!week.is_open_at(utc(2024, 1, 6, 0, 0, 0), 7200));
+        assert!(week.is_open_at(utc(2024, 1, 6, 6, 0, 0), 7200));
+    }
+
+    #[test]
+    fn holiday_windows_crossing_midnight_run_into_the_next_day() {
+        let mut schedule = office_hours();
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: String::new(),
+            holidays: vec![holiday(
+                "New year",
+                "2023-12-31 00:00:00",
+                "2024-01-02 00:00:00",
+                false,
+            )],
+        });
+        schedule.holiday_schedule = vec![window("20:00:00", "02:00:00")];
+        let new_year = ScheduleDecision::OpenForHoliday("New year".to_string());
+        assert_eq!(schedule.check(utc(2023, 12, 31, 23, 0, 0), 0), new_year);
+        assert_eq!(schedule.check(utc(2024, 1, 1, 1, 0, 0), 0), new_year);
+        assert_eq!(
+            schedule.check(utc(2024, 1, 1, 3, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+    }
+
+    #[test]
+    fn windows_crossing_midnight_wrap_around_the_week() {
+        let mut schedule = office_hours();
+        // Saturday night into Sunday, the only window that reaches Sunday
+        schedule.week_schedule.saturday = vec![window("20:00:00", "02:00:00")];
+        // Sunday 2024-01-07
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 1, 0, 0), 0),
+            ScheduleDecision::Open
+        );
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 3, 0, 0), 0),
+            ScheduleDecision::Closed
+        );
+        // And Sunday night into Monday morning
+        schedule.week_schedule.sunday = vec![window("23:00:00", "01:00:00")];
+        assert_eq!(
+            schedule.check(utc(2024, 1, 8, 0, 30, 0), 0),
+            ScheduleDecision::Open
+        );
+        // 02:30 UTC on a Sunday is 01:30 an hour west, still within Saturday night
+        assert_eq!(
+            schedule.check(utc(2024, 1, 7, 2, 30, 0), -3600),
+            ScheduleDecision::Open
+        );
+    }
+
+    #[test]
+    fn holiday_windows_crossing_midnight_run_into_the_next_day() {
+        let mut schedule = office_hours();
+        schedule.holiday_group = Some(HolidayGroup {
+            id: "h1".to_string(),
+            name: String::new(),
+            holidays: vec![holiday(
+                "New year",
+                "2023-12-31 00:00:00",
+                "2024-01-02 00:00:00",
+                false,
+            )],
+        });
+        schedule.holiday_schedule = vec![window("20:00:00", "02:00:00")];
+        let party = |h| ScheduleDecision::OpenForHoliday("New year".to_string()).eq(h);
+        assert!(party(&schedule.check(utc(2023, 12, 31, 23, 0, 0), 0)));
+        assert!(party(&schedule.check(utc(2024, 1, 1, 1, 0, 0), 0)));
+        assert_eq!(
+            schedule.check(utc(2024, 1, 1, 3, 0, 0), 0),
+            ScheduleDecision::ClosedForHoliday("New year".to_string())
+        );
+    }
+
+    fn berlin() -> SiteTimeZone {
+        SiteTimeZone::from_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()
+    }
+
+    fn public_holidays(holidays: Vec<Holiday>) -> HolidayGroup {
+        HolidayGroup {
+            id: "h2".to_string(),
+            name: "Public holidays".to_string(),
+            holidays,
+        }
+    }
+
+    #[test]
+    fn schedules_follow_daylight_saving_time() {
+        let schedule = office_hours();
+        // 08:30 UTC is 09:30 in Berlin in winter, open, and 10:30 in summer
+        assert!(schedule.is_active_at(utc(2024, 1, 9, 8, 30, 0), &[], berlin()));
+        assert!(schedule.is_active_at(utc(2024, 7, 9, 8, 30, 0), &[], berlin()));
+        // 07:30 UTC is 08:30 in winter, closed, and 09:30 in summer, open
+        assert!(!schedule.is_active_at(utc(2024, 1, 9, 7, 30, 0), &[], berlin()));
+        assert!(schedule.is_active_at(utc(2024, 7, 9, 7, 30, 0), &[], berlin()));
+        assert_eq!(
+            schedule.check(utc(2024, 7, 9, 7, 30, 0), berlin()),
+            ScheduleDecision::Open
+        );
+        // A fixed offset doesn't move
+        assert!(!schedule.is_active_at(utc(2024, 7, 9, 7, 30, 0), &[], 3600));
+    }
+
+    #[test]
+    fn extra_holiday_groups_are_observed() {
+        let schedule = office_hours();
+        let groups = [public_holidays(vec![holiday(
+            "Founders day",
+            "2024-01-03 00:00:00",
+            "2024-01-04 00:00:00",
+            false,
+        )])];
+        let at = utc(2024, 1, 3, 10, 0, 0);
+        assert!(schedule.is_active_at(at, &[], 0));
+        assert!(!schedule.is_active_at(at, &groups, 0));
+        // The schedule's own group still applies alongside
+        let mut schedule = schedule;
+        schedule.holiday_group = Some(public_holidays(vec![holiday(
+            "New year",
+            "2024-01-01 00:00:00",
+            "2024-01-02 00:00:00",
+            false,
+        )]));
+        assert!(!schedule.is_active_at(utc(2024, 1, 1, 10, 0, 0), &groups, 0));
+        assert!(!schedule.is_active_at(at, &groups, 0));
+        assert!(schedule.is_active_at(utc(2024, 1, 2, 10, 0, 0), &groups, 0));
+    }
+
+    #[test]
+    fn next_transitions_find_window_edges() {
+        let schedule = office_hours();
+        // Tuesday 2024-01-02 before opening, then during the day, then after closing
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 2, 7, 0, 0), &[], 0),
+            Some(utc(2024, 1, 2, 9, 0, 0))
+        );
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 2, 9, 0, 0), &[], 0),
+            Some(utc(2024, 1, 2, 17, 1, 0))
+        );
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 2, 18, 0, 0), &[], 0),
+            Some(utc(2024, 1, 3, 9, 0, 0))
+        );
+        // Saturday afternoon waits over the closed Sunday
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 6, 13, 0, 0), &[], 0),
+            Some(utc(2024, 1, 8, 9, 0, 0))
+        );
+        // Read in local time, 09:00 two hours east is 07:00 UTC
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 2, 5, 0, 0), &[], 7200),
+            Some(utc(2024, 1, 2, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn next_transitions_skip_adjoining_windows_and_midnight() {
+        let mut schedule = office_hours();
+        // Monday night into Tuesday, joined up with Tuesday's own window
+        schedule.week_schedule.monday = vec![window("22:00:00", "08:59:59")];
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 1, 12, 0, 0), &[], 0),
+            Some(utc(2024, 1, 1, 22, 0, 0))
+        );
+        // Open past midnight and through to Tuesday evening
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 1, 22, 0, 0), &[], 0),
+            Some(utc(2024, 1, 2, 17, 1, 0))
+        );
+        // Open all day around the clock never changes
+        let always = window("00:00:00", "23:59:59");
+        schedule.week_schedule = WeekSchedule {
+            sunday: vec![always.clone()],
+            monday: vec![always.clone()],
+            tuesday: vec![always.clone()],
+            wednesday: vec![always.clone()],
+            thursday: vec![always.clone()],
+            friday: vec![always.clone()],
+            saturday: vec![always],
+        };
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 1, 12, 0, 0), &[], berlin()),
+            None
+        );
+        // Nor does closed all week
+        schedule.week_schedule = WeekSchedule::default();
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 1, 12, 0, 0), &[], 0),
+            None
+        );
+    }
+
+    #[test]
+    fn next_transitions_follow_holidays() {
+        let schedule = office_hours();
+        let groups = [public_holidays(vec![holiday(
+            "Founders day",
+            "2024-01-03 00:00:00",
+            "2024-01-04 00:00:00",
+            false,
+        )])];
+        // After Tuesday's close the holiday keeps Wednesday shut until Thursday
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 2, 18, 0, 0), &groups, 0),
+            Some(utc(2024, 1, 4, 9, 0, 0))
+        );
+        // A holiday starting midday closes an open door
+        let groups = [public_holidays(vec![holiday(
+            "Half day",
+            "2024-01-03 12:00:00",
+            "2024-01-04 00:00:00",
+            false,
+        )])];
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 1, 3, 10, 0, 0), &groups, 0),
+            Some(utc(2024, 1, 3, 12, 0, 0))
+        );
+        // A holiday schedule years ahead on a schedule closed all week is still found
+        let mut closed = office_hours();
+        closed.week_schedule = WeekSchedule::default();
+        closed.holiday_schedule = vec![window("10:00:00", "11:00:59")];
+        let groups = [public_holidays(vec![holiday(
+            "Open day",
+            "2030-05-01 00:00:00",
+            "2030-05-02 00:00:00",
+            false,
+        )])];
+        assert_eq!(
+            closed.next_transition_after(utc(2024, 1, 1, 0, 0, 0), &groups, 0),
+            Some(utc(2030, 5, 1, 10, 0, 0))
+        );
+    }
+
+    #[test]
+    fn next_transitions_cross_daylight_saving_changes() {
+        let mut schedule = office_hours();
+        // Saturday night 2024-03-30 into Sunday, the night Berlin's clocks go forward at 02:00
+        schedule.week_schedule.saturday = vec![window("22:00:00", "02:30:00")];
+        // 22:00 CET is 21:00 UTC. 02:30:01 is skipped, so the window closes when the clocks go forward at 01:00 UTC
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 3, 30, 12, 0, 0), &[], berlin()),
+            Some(utc(2024, 3, 30, 21, 0, 0))
+        );
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 3, 30, 21, 0, 0), &[], berlin()),
+            Some(utc(2024, 3, 31, 1, 0, 0))
+        );
+        // A window starting in the skipped hour opens when the clocks change
+        schedule.week_schedule.sunday = vec![window("02:15:00", "05:00:00")];
+        schedule.week_schedule.saturday = vec![];
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 3, 30, 12, 0, 0), &[], berlin()),
+            Some(utc(2024, 3, 31, 1, 0, 0))
+        );
+        // 05:00:01 CEST is 03:00:01 UTC
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 3, 31, 1, 0, 0), &[], berlin()),
+            Some(utc(2024, 3, 31, 3, 0, 1))
+        );
+        // When the clocks go back the repeated hour is open both times round
+        schedule.week_schedule.sunday = vec![window("02:00:00", "02:59:59")];
+        // 02:00 CEST on 2024-10-27 is 00:00 UTC, the second 02:59:59 CET is 01:59:59 UTC
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 10, 26, 12, 0, 0), &[], berlin()),
+            Some(utc(2024, 10, 27, 0, 0, 0))
+        );
+        assert_eq!(
+            schedule.next_transition_after(utc(2024, 10, 27, 0, 0, 0), &[], berlin()),
+            Some(utc(2024, 10, 27, 2, 0, 0))
+        );
+    }
+
+    #[test]
+    fn schedules_parse_from_the_controller_format() {
+        let schedule: Schedule = serde_json::from_value(serde_json::json!({
+            "id": "s1",
+            "name": "Members",
+            "type": "access",
+            "week_schedule": {
+                "sunday": [],
+                "monday": [{"start_time": "09:00:00", "end_time": "17:00:59"}],
+                "tuesday": null
+            },
+            "holiday_group_id": "h1",
+            "holiday_group": {
+                "id": "h1",
+                "name": "Holidays",
+                "holidays": [{
+                    "id": "x",
+                    "name": "New year",
+                    "description": "",
+                    "repeat": true,
+                    "start_time": "2023-01-01 00:00:00",
+                    "end_time": "2023-01-02 00:00:00"
+                }]
+            },
+            "holiday_schedule": null
+        }))
+        .unwrap();
+        assert_eq!(schedule.week_schedule.monday.len(), 1);
+        assert!(schedule.week_schedule.tuesday.is_empty());
+        assert!(schedule.holiday_schedule.is_empty());
+        assert!(schedule.holiday_group.unwrap().holidays[0].repeat);
+    }
+}
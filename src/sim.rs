@@ -0,0 +1,1163 @@
+//! A simulated controller for integration tests, see [Simulator].
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::clock::parse_rfc3339;
+use crate::{UnifiClient, UnifiError, UnifiResult};
+
+/// Token the simulator accepts unless another is set with [Simulator::with_token]
+pub const DEFAULT_SIM_TOKEN: &str = "unifi-access-sim";
+
+/// Initial contents of a [Simulator], read from JSON.
+///
+/// Every field is optional:
+/// ```json
+/// {
+///   "users": [{"id": "u1", "first_name": "Ada", "last_name": "Lovelace", "user_email": "ada@example.com",
+///              "access_policy_ids": ["p1"], "nfc_cards": [{"id": "100001", "token": "04a23bc1"}]}],
+///   "access_policies": [{"id": "p1", "name": "Members", "resources": [{"id": "d1", "type": "door"}], "schedule_id": "s1"}],
+///   "schedules": [{"id": "s1", "name": "Always", "week_schedule": {"monday": [{"start_time": "00:00:00", "end_time": "23:59:59"}]}}],
+///   "devices": [{"id": "reader1", "name": "Front door", "type": "UA-G2-PRO"}],
+///   "door_groups": [{"id": "g1", "group_name": "Workshop", "resources": [{"id": "d1", "type": "door"}]}],
+///   "topology": [],
+///   "nfc_cards": [{"display_id": "100002", "token": "04b1c2d3", "status": "disable"}],
+///   "system_log": [{"@timestamp": "2024-01-01T12:00:00Z", "_id": "e1", "topic": "door_openings", "_source": {}}],
+///   "reject_duplicate_emails": true,
+///   "empty_policies_for_missing_users": false,
+///   "moved_to_v2": ["users"],
+///   "overstated_total": 0
+/// }
+/// ```
+/// Cards held by seeded users don't need to be listed in `nfc_cards`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimSeed {
+    users: Vec<SimUser>,
+    access_policies: Vec<SimPolicy>,
+    devices: Vec<SimDevice>,
+    door_groups: Vec<SimDoorGroup>,
+    /// Returned as is by the schedule endpoint, looked up by their `id`
+    schedules: Vec<Value>,
+    /// Returned as is by the door group topology endpoint
+    topology: Vec<Value>,
+    nfc_cards: Vec<SimCardRecord>,
+    system_log: Vec<Value>,
+    /// Rejects creating a user with an email another user has, as some firmware does
+    reject_duplicate_emails: bool,
+    /// Answers a policy request for a user that doesn't exist with an empty list, as some firmware does
+    empty_policies_for_missing_users: bool,
+    /// Resources only served on `/api/v2/developer`, v1 requests for them answer as moved
+    moved_to_v2: Vec<String>,
+    /// Added to the total reported by every paged list, as firmware that still counts deleted records does
+    overstated_total: usize,
+}
+
+impl SimSeed {
+    pub fn from_json(json: &str) -> UnifiResult<SimSeed> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> UnifiResult<SimSeed> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimUser {
+    id: String,
+    #[serde(default)]
+    first_name: String,
+    #[serde(default)]
+    last_name: String,
+    #[serde(default)]
+    user_email: String,
+    #[serde(default)]
+    employee_number: String,
+    #[serde(default = "active")]
+    status: String,
+    #[serde(default)]
+    access_policy_ids: Vec<String>,
+    #[serde(default)]
+    nfc_cards: Vec<SimCard>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin_code: Option<String>,
+}
+
+fn active() -> String {
+    "ACTIVE".to_string()
+}
+
+/// A card as it appears on a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimCard {
+    id: String,
+    token: String,
+}
+
+/// A card as the controller lists it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimCardRecord {
+    display_id: String,
+    token: String,
+    /// Listed as the card's status when set, e.g. `disable`, otherwise no status is sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimResource {
+    id: String,
+    #[serde(rename = "type")]
+    resource_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimPolicy {
+    id: String,
+    name: String,
+    #[serde(default)]
+    resources: Vec<SimResource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimDevice {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    device_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SimDoorGroup {
+    id: String,
+    #[serde(alias = "name")]
+    group_name: String,
+    #[serde(default)]
+    resources: Vec<SimResource>,
+}
+
+/// An API request as the simulator received it, see [SimHandle::take_requests]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimRequest {
+    pub method: String,
+    /// The path without the query string, e.g. `/api/v1/developer/users`
+    pub path: String,
+    /// The query parameters, decoded
+    pub query: HashMap<String, String>,
+    /// The JSON body, null if there was none. A body that isn't JSON is kept as a string.
+    pub body: Value,
+    /// The `Authorization` header, if sent
+    pub authorization: Option<String>,
+}
+
+/// Makes API requests fail, see `POST /sim/fail-next`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimFault {
+    /// HTTP status of the failed responses, 503 makes the client treat the controller as unavailable
+    pub status: u16,
+    /// Error code in the response envelope
+    pub code: String,
+    pub msg: String,
+    /// Sent instead of the envelope when set, e.g. an HTML error page
+    pub body: Option<String>,
+    /// Number of requests that fail
+    pub count: u32,
+}
+
+impl Default for SimFault {
+    fn default() -> SimFault {
+        SimFault {
+            status: 200,
+            code: "CODE_SYSTEM_ERROR".to_string(),
+            msg: "injected failure".to_string(),
+            body: None,
+            count: 1,
+        }
+    }
+}
+
+impl SimFault {
+    fn response(&self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        match &self.body {
+            Some(body) => (status, body.clone()).into_response(),
+            None => (status, Json(envelope_error(&self.code, &self.msg))).into_response(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    device_id: String,
+    card: Option<SimCard>,
+}
+
+/// An error response, a controller error code and message
+type SimError = (&'static str, String);
+
+type SimResult = Result<Value, SimError>;
+
+struct SimState {
+    seed: SimSeed,
+    sessions: HashMap<String, Session>,
+    next_id: u64,
+    latency: Duration,
+    faults: VecDeque<SimFault>,
+    /// Temporary lock rules by door id, as last set
+    lock_rules: HashMap<String, Value>,
+    /// Unlock schedules by door id, as last set
+    unlock_schedules: HashMap<String, Value>,
+    /// Every API request received, oldest first
+    requests: Vec<SimRequest>,
+    /// API requests being answered right now
+    in_flight: usize,
+    /// The most API requests answered at once
+    max_in_flight: usize,
+}
+
+impl SimState {
+    fn new(mut seed: SimSeed) -> SimState {
+        // Cards held by seeded users are known to the controller too
+        for card in seed.users.iter().flat_map(|u| &u.nfc_cards) {
+            if !seed.nfc_cards.iter().any(|c| c.token == card.token) {
+                seed.nfc_cards.push(SimCardRecord {
+                    display_id: card.id.clone(),
+                    token: card.token.clone(),
+                    status: None,
+                });
+            }
+        }
+        SimState {
+            seed,
+            sessions: HashMap::new(),
+            next_id: 1,
+            latency: Duration::ZERO,
+            in_flight: 0,
+            max_in_flight: 0,
+            faults: VecDeque::new(),
+            lock_rules: HashMap::new(),
+            unlock_schedules: HashMap::new(),
+            requests: vec![],
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn new_uuid(&mut self) -> String {
+        let id = self.next_id();
+        format!("{id:08x}-0000-4000-8000-{id:012x}")
+    }
+
+    fn take_fault(&mut self) -> Option<SimFault> {
+        let fault = self.faults.front_mut()?;
+        fault.count = fault.count.saturating_sub(1);
+        let taken = fault.clone();
+        if fault.count == 0 {
+            self.faults.pop_front();
+        }
+        Some(taken)
+    }
+
+    fn handle(
+        &mut self,
+        method: &Method,
+        segments: &[&str],
+        query: &HashMap<String, String>,
+        body: Value,
+    ) -> SimResult {
+        match (method.as_str(), segments) {
+            ("GET", ["users"]) => {
+                let expand = query.get("expand[]").map(String::as_str) == Some("access_policy");
+                let users: Vec<Value> = self
+                    .seed
+                    .users
+                    .iter()
+                    .map(|u| self.user_json(u, expand))
+                    .collect();
+                Ok(paged(users, query, self.seed.overstated_total))
+            }
+            ("POST", ["users"]) => self.create_user(body),
+            ("GET", ["users", id]) => Ok(ok(self.user_json(self.user(id)?, false))),
+            ("PUT", ["users", id]) => self.update_user(id, body),
+            ("GET", ["users", id, "access_policies"]) => {
+                if self.seed.empty_policies_for_missing_users && self.user(id).is_err() {
+                    return Ok(ok(json!([])));
+                }
+                let user = self.user(id)?;
+                let policies: Vec<&SimPolicy> = user
+                    .access_policy_ids
+                    .iter()
+                    .filter_map(|p| self.seed.access_policies.iter().find(|s| &s.id == p))
+                    .collect();
+                Ok(ok(json!(policies)))
+            }
+            ("PUT", ["users", id, "access_policies"]) => self.assign_policies(id, body),
+            ("PUT", ["users", id, "nfc_cards"]) => self.assign_card(id, body),
+            ("PUT", ["users", id, "nfc_cards", "delete"]) => self.unassign_card(id, body),
+            ("PUT", ["users", id, "pin_codes"]) => self.set_pin(id, body),
+            ("GET", ["access_policies"]) => Ok(paged(
+                self.seed.access_policies.iter().map(|p| json!(p)).collect(),
+                query,
+                self.seed.overstated_total,
+            )),
+            ("GET", ["access_policies", "schedules", id]) => Ok(ok(self
+                .seed
+                .schedules
+                .iter()
+                .find(|schedule| schedule["id"] == *id)
+                .cloned()
+                .ok_or_else(|| {
+                    (
+                        "CODE_ACCESS_POLICY_SCHEDULE_NOT_FOUND",
+                        format!("schedule {id} does not exist"),
+                    )
+                })?)),
+            ("GET", ["devices"]) => Ok(ok(json!([self.seed.devices]))),
+            ("GET", ["door_groups"]) => Ok(ok(json!(self.seed.door_groups))),
+            ("GET", ["door_groups", "topology"]) => Ok(ok(json!(self.seed.topology))),
+            ("GET", ["door_groups", id]) => Ok(ok(json!(self.door_group(id)?))),
+            ("PUT", ["door_groups", id]) => self.update_door_group(id, body),
+            ("GET", ["doors", id, "lock_rule"]) => Ok(ok(self
+                .lock_rules
+                .get(*id)
+                .cloned()
+                .unwrap_or_else(|| json!({ "type": "schedule" })))),
+            ("PUT", ["doors", id, "lock_rule"]) => {
+                if body["type"] == "reset" {
+                    self.lock_rules.remove(*id);
+                } else {
+                    self.lock_rules.insert(id.to_string(), body);
+                }
+                Ok(ok(Value::Null))
+            }
+            ("GET", ["doors", id, "unlock_schedule"]) => Ok(ok(self
+                .unlock_schedules
+                .get(*id)
+                .cloned()
+                .unwrap_or(Value::Null))),
+            ("PUT", ["doors", id, "unlock_schedule"]) => {
+                self.unlock_schedules.insert(id.to_string(), body);
+                Ok(ok(Value::Null))
+            }
+            ("DELETE", ["doors", id, "unlock_schedule"]) => {
+                self.unlock_schedules.remove(*id);
+                Ok(ok(Value::Null))
+            }
+            ("POST", ["credentials", "nfc_cards", "sessions"]) => self.start_session(body),
+            ("GET", ["credentials", "nfc_cards", "sessions", id]) => {
+                let session = self.sessions.get(*id).ok_or(session_not_found())?;
+                match &session.card {
+                    Some(card) => Ok(ok(json!(card))),
+                    None => Err((
+                        "CODE_CREDS_NFC_READ_POLL_TOKEN_EMPTY",
+                        "no card scanned yet".to_string(),
+                    )),
+                }
+            }
+            ("DELETE", ["credentials", "nfc_cards", "sessions", id]) => {
+                self.sessions.remove(*id).ok_or(session_not_found())?;
+                Ok(ok(Value::Null))
+            }
+            ("GET", ["credentials", "nfc_cards", "tokens"]) => {
+                let keyword = query.get("keyword").map(String::as_str).unwrap_or_default();
+                let cards: Vec<Value> = self
+                    .seed
+                    .nfc_cards
+                    .iter()
+                    .filter(|c| c.display_id.contains(keyword) || c.token.contains(keyword))
+                    .map(|c| self.card_json(c))
+                    .collect();
+                Ok(paged(cards, query, self.seed.overstated_total))
+            }
+            ("GET", ["credentials", "nfc_cards", "tokens", token]) => {
+                Ok(ok(self.card_json(self.card(token)?)))
+            }
+            ("DELETE", ["credentials", "nfc_cards", "tokens", token]) => self.delete_card(token),
+            ("POST", ["credentials", "pin_codes"]) => Ok(ok(json!(self.unused_pin()))),
+            ("POST", ["system", "logs"]) => Ok(self.system_log(&body, query)),
+            _ => Err(("CODE_RESOURCE_NOT_FOUND", "no such endpoint".to_string())),
+        }
+    }
+
+    fn user(&self, id: &str) -> Result<&SimUser, SimError> {
+        self.seed
+            .users
+            .iter()
+            .find(|u| u.id == id)
+            .ok_or_else(|| ("CODE_USER_NOT_EXISTS", format!("user {id} does not exist")))
+    }
+
+    fn user_mut(&mut self, id: &str) -> Result<&mut SimUser, SimError> {
+        self.seed
+            .users
+            .iter_mut()
+            .find(|u| u.id == id)
+            .ok_or_else(|| ("CODE_USER_NOT_EXISTS", format!("user {id} does not exist")))
+    }
+
+    fn user_json(&self, user: &SimUser, expand_policies: bool) -> Value {
+        let mut value = json!({
+            "id": user.id,
+            "first_name": user.first_name,
+            "last_name": user.last_name,
+            "user_email": user.user_email,
+            "employee_number": user.employee_number,
+            "status": user.status,
+            "nfc_cards": user.nfc_cards,
+        });
+        if expand_policies {
+            value["access_policies"] = json!(user
+                .access_policy_ids
+                .iter()
+                .filter_map(|p| self.seed.access_policies.iter().find(|s| &s.id == p))
+                .collect::<Vec<_>>());
+        }
+        value
+    }
+
+    fn email_taken(&self, email: &str, except_user: Option<&str>) -> bool {
+        !email.is_empty()
+            && self.seed.users.iter().any(|u| {
+                Some(u.id.as_str()) != except_user && u.user_email.eq_ignore_ascii_case(email)
+            })
+    }
+
+    fn create_user(&mut self, body: Value) -> SimResult {
+        let first_name = string_field(&body, "first_name");
+        let last_name = string_field(&body, "last_name");
+        if first_name.is_empty() || last_name.is_empty() {
+            return Err(invalid("first_name and last_name are required"));
+        }
+        let user_email = string_field(&body, "user_email");
+        if self.seed.reject_duplicate_emails && self.email_taken(&user_email, None) {
+            return Err((
+                "CODE_USER_EMAIL_ERROR",
+                format!("email {user_email} is already in use"),
+            ));
+        }
+        let user = SimUser {
+            id: self.new_uuid(),
+            first_name,
+            last_name,
+            user_email,
+            employee_number: string_field(&body, "employee_number"),
+            status: active(),
+            access_policy_ids: vec![],
+            nfc_cards: vec![],
+            pin_code: None,
+        };
+        let value = self.user_json(&user, false);
+        self.seed.users.push(user);
+        Ok(ok(value))
+    }
+
+    fn update_user(&mut self, id: &str, body: Value) -> SimResult {
+        if let Some(email) = body.get("user_email").and_then(Value::as_str) {
+            if self.seed.reject_duplicate_emails && self.email_taken(email, Some(id)) {
+                return Err((
+                    "CODE_USER_EMAIL_ERROR",
+                    format!("email {email} is already in use"),
+                ));
+            }
+        }
+        let user = self.user_mut(id)?;
+        let fields: [(&str, &mut String); 5] = [
+            ("first_name", &mut user.first_name),
+            ("last_name", &mut user.last_name),
+            ("user_email", &mut user.user_email),
+            ("employee_number", &mut user.employee_number),
+            ("status", &mut user.status),
+        ];
+        for (name, field) in fields {
+            if let Some(value) = body.get(name).and_then(Value::as_str) {
+                *field = value.to_string();
+            }
+        }
+        Ok(ok(Value::Null))
+    }
+
+    fn assign_policies(&mut self, id: &str, body: Value) -> SimResult {
+        let policy_ids: Vec<String> = body
+            .get("access_policy_ids")
+            .cloned()
+            .and_then(|ids| serde_json::from_value(ids).ok())
+            .ok_or_else(|| invalid("access_policy_ids must be a list of ids"))?;
+        if let Some(unknown) = policy_ids
+            .iter()
+            .find(|p| !self.seed.access_policies.iter().any(|s| &&s.id == p))
+        {
+            return Err((
+                "CODE_NOT_EXISTS",
+                format!("access policy {unknown} does not exist"),
+            ));
+        }
+        self.user_mut(id)?.access_policy_ids = policy_ids;
+        Ok(ok(Value::Null))
+    }
+
+    fn card(&self, token: &str) -> Result<&SimCardRecord, SimError> {
+        self.seed
+            .nfc_cards
+            .iter()
+            .find(|c| c.token == token)
+            .ok_or_else(|| {
+                (
+                    "CODE_CREDS_NFC_CARD_INVALID",
+                    format!("unknown card {token}"),
+                )
+            })
+    }
+
+    fn card_holder(&self, token: &str) -> Option<&SimUser> {
+        self.seed
+            .users
+            .iter()
+            .find(|u| u.nfc_cards.iter().any(|c| c.token == token))
+    }
+
+    fn card_json(&self, card: &SimCardRecord) -> Value {
+        let mut json = json!({
+            "display_id": card.display_id,
+            "token": card.token,
+            "user_id": self.card_holder(&card.token).map(|u| &u.id),
+        });
+        if let Some(status) = &card.status {
+            json["status"] = json!(status);
+        }
+        json
+    }
+
+    fn assign_card(&mut self, id: &str, body: Value) -> SimResult {
+        let token = string_field(&body, "token");
+        self.user(id)?;
+        let card = self.card(&token)?.clone();
+        match self.card_holder(&token) {
+            Some(holder) if holder.id == id => return Ok(ok(Value::Null)),
+            Some(holder) => {
+                return Err((
+                    "CODE_CREDS_NFC_HAS_BIND_USER",
+                    format!("card is bound to user {}", holder.id),
+                ))
+            }
+            None => {}
+        }
+        self.user_mut(id)?.nfc_cards.push(SimCard {
+            id: card.display_id,
+            token,
+        });
+        Ok(ok(Value::Null))
+    }
+
+    fn unassign_card(&mut self, id: &str, body: Value) -> SimResult {
+        let token = string_field(&body, "token");
+        self.user_mut(id)?.nfc_cards.retain(|c| c.token != token);
+        Ok(ok(Value::Null))
+    }
+
+    fn delete_card(&mut self, token: &str) -> SimResult {
+        self.card(token)?;
+        if let Some(holder) = self.card_holder(token) {
+            return Err((
+                "CODE_CREDS_NFC_HAS_BIND_USER",
+                format!("card is bound to user {}", holder.id),
+            ));
+        }
+        self.seed.nfc_cards.retain(|c| c.token != token);
+        Ok(ok(Value::Null))
+    }
+
+    fn unused_pin(&mut self) -> String {
+        loop {
+            let pin = format!("{:06}", self.next_id() * 7919 % 1_000_000);
+            if !self
+                .seed
+                .users
+                .iter()
+                .any(|u| u.pin_code.as_deref() == Some(pin.as_str()))
+            {
+                return pin;
+            }
+        }
+    }
+
+    fn set_pin(&mut self, id: &str, body: Value) -> SimResult {
+        let pin = string_field(&body, "pin_code");
+        if !(4..=8).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err((
+                "CODE_CREDS_PIN_CODE_CREDS_LENGTH_INVALID",
+                "pin must be 4 to 8 digits".to_string(),
+            ));
+        }
+        if self
+            .seed
+            .users
+            .iter()
+            .any(|u| u.id != id && u.pin_code.as_deref() == Some(pin.as_str()))
+        {
+            return Err((
+                "CODE_CREDS_PIN_CODE_CREDS_ALREADY_EXIST",
+                "pin is in use".to_string(),
+            ));
+        }
+        self.user_mut(id)?.pin_code = Some(pin);
+        Ok(ok(Value::Null))
+    }
+
+    fn door_group(&self, id: &str) -> Result<&SimDoorGroup, SimError> {
+        self.seed
+            .door_groups
+            .iter()
+            .find(|g| g.id == id)
+            .ok_or_else(|| ("CODE_NOT_EXISTS", format!("door group {id} does not exist")))
+    }
+
+    fn update_door_group(&mut self, id: &str, body: Value) -> SimResult {
+        let door_ids: Vec<String> = body
+            .get("resources")
+            .cloned()
+            .and_then(|ids| serde_json::from_value(ids).ok())
+            .ok_or_else(|| invalid("resources must be a list of door ids"))?;
+        let group = self
+            .seed
+            .door_groups
+            .iter_mut()
+            .find(|g| g.id == id)
+            .ok_or_else(|| ("CODE_NOT_EXISTS", format!("door group {id} does not exist")))?;
+        if let Some(name) = body.get("group_name").and_then(Value::as_str) {
+            group.group_name = name.to_string();
+        }
+        group.resources = door_ids
+            .into_iter()
+            .map(|id| SimResource {
+                id,
+                resource_type: "door".to_string(),
+            })
+            .collect();
+        Ok(ok(Value::Null))
+    }
+
+    fn start_session(&mut self, body: Value) -> SimResult {
+        let device_id = string_field(&body, "device_id");
+        if !self.seed.devices.iter().any(|d| d.id == device_id) {
+            return Err((
+                "CODE_DEVICE_DEVICE_NOT_FOUND",
+                format!("device {device_id} does not exist"),
+            ));
+        }
+        let session_id = self.new_uuid();
+        self.sessions.insert(
+            session_id.clone(),
+            Session {
+                device_id,
+                card: None,
+            },
+        );
+        Ok(ok(json!({ "session_id": session_id })))
+    }
+
+    /// Completes the enrollment session open on the device with the card
+    fn scan(&mut self, device_id: &str, token: &str) -> Result<(), SimError> {
+        let known = self.card(token).ok().map(|c| c.display_id.clone());
+        let display_id = match known {
+            Some(display_id) => display_id,
+            None => {
+                let display_id = format!("{:06}", 100000 + self.next_id());
+                self.seed.nfc_cards.push(SimCardRecord {
+                    display_id: display_id.clone(),
+                    token: token.to_string(),
+                    status: None,
+                });
+                display_id
+            }
+        };
+        let session = self
+            .sessions
+            .values_mut()
+            .find(|s| s.device_id == device_id && s.card.is_none())
+            .ok_or_else(|| {
+                (
+                    "CODE_CREDS_NFC_READ_SESSION_NOT_FOUND",
+                    format!("no enrollment session waiting on device {device_id}"),
+                )
+            })?;
+        session.card = Some(SimCard {
+            id: display_id,
+            token: token.to_string(),
+        });
+        Ok(())
+    }
+
+    fn system_log(&self, body: &Value, query: &HashMap<String, String>) -> Value {
+        let topic = body.get("topic").and_then(Value::as_str).unwrap_or("all");
+        let secs = |name: &str| {
+            body.get(name)
+                .and_then(Value::as_u64)
+                .map(|s| UNIX_EPOCH + Duration::from_secs(s))
+        };
+        let (since, until) = (secs("since"), secs("until"));
+        let actor_id = body.get("actor_id").and_then(Value::as_str);
+        let mut hits: Vec<(Option<SystemTime>, Value)> = self
+            .seed
+            .system_log
+            .iter()
+            .filter(|hit| topic == "all" || hit.get("topic").and_then(Value::as_str) == Some(topic))
+            .filter(|hit| actor_id.is_none_or(|a| hit["_source"]["actor"]["id"] == a))
+            .map(|hit| {
+                let time = hit["@timestamp"].as_str().and_then(parse_rfc3339);
+                let mut hit = hit.clone();
+                if let Some(hit) = hit.as_object_mut() {
+                    hit.remove("topic");
+                }
+                (time, hit)
+            })
+            .filter(|(time, _)| {
+                since.is_none_or(|s| time.is_some_and(|t| t >= s))
+                    && until.is_none_or(|u| time.is_some_and(|t| t <= u))
+            })
+            .collect();
+        // Newest first, like the controller
+        hits.sort_by(|a, b| b.0.cmp(&a.0));
+        let (page_num, page_size) = page_params(query, 25);
+        let total = hits.len();
+        let page: Vec<Value> = hits
+            .into_iter()
+            .skip((page_num - 1) * page_size)
+            .take(page_size)
+            .map(|(_, hit)| hit)
+            .collect();
+        ok(json!({
+            "hits": page,
+            "pages": total.div_ceil(page_size),
+            "total": total,
+        }))
+    }
+}
+
+fn ok(data: Value) -> Value {
+    json!({ "code": "SUCCESS", "msg": "success", "data": data })
+}
+
+fn envelope_error(code: &str, msg: &str) -> Value {
+    json!({ "code": code, "msg": msg, "data": null })
+}
+
+fn invalid(msg: &str) -> SimError {
+    ("CODE_PARAMS_INVALID", msg.to_string())
+}
+
+fn session_not_found() -> SimError {
+    (
+        "CODE_CREDS_NFC_READ_SESSION_NOT_FOUND",
+        "session not found".to_string(),
+    )
+}
+
+fn string_field(body: &Value, name: &str) -> String {
+    body.get(name)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Page number (from 1) and size requested, defaulting to the whole list on one page
+fn page_params(query: &HashMap<String, String>, default_size: usize) -> (usize, usize) {
+    let param = |name: &str| query.get(name).and_then(|v| v.parse::<usize>().ok());
+    (
+        param("page_num").unwrap_or(1).max(1),
+        param("page_size").unwrap_or(default_size).max(1),
+    )
+}
+
+/// A list response with pagination, when no page is requested the whole list is returned
+fn paged(items: Vec<Value>, query: &HashMap<String, String>, overstated_by: usize) -> Value {
+    let (page_num, page_size) = page_params(query, items.len().max(1));
+    let total = items.len() + overstated_by;
+    let page: Vec<Value> = items
+        .into_iter()
+        .skip((page_num - 1) * page_size)
+        .take(page_size)
+        .collect();
+    let mut response = ok(json!(page));
+    response["pagination"] = json!({
+        "page_num": page_num,
+        "page_size": page_size,
+        "total": total,
+    });
+    response
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+struct Shared {
+    token: String,
+    state: Mutex<SimState>,
+}
+
+/// Answers every developer API request, counting the requests answered at once
+async fn api(
+    State(shared): State<Arc<Shared>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    {
+        let mut state = shared.state.lock().unwrap();
+        state.in_flight += 1;
+        state.max_in_flight = state.max_in_flight.max(state.in_flight);
+    }
+    let response = respond(&shared, method, uri, headers, body).await;
+    shared.state.lock().unwrap().in_flight -= 1;
+    response
+}
+
+async fn respond(
+    shared: &Shared,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let latency = {
+        let mut state = shared.state.lock().unwrap();
+        state.requests.push(SimRequest {
+            method: method.to_string(),
+            path: uri.path().to_string(),
+            query: parse_query(uri.query().unwrap_or_default()),
+            body: if body.is_empty() {
+                Value::Null
+            } else {
+                serde_json::from_slice(&body)
+                    .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()))
+            },
+            authorization: headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        });
+        state.latency
+    };
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+    let expected = format!("Bearer {}", shared.token);
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        == Some(expected.as_str());
+    if !authorized {
+        let error = envelope_error("CODE_UNAUTHORIZED", "invalid token");
+        return (StatusCode::UNAUTHORIZED, Json(error)).into_response();
+    }
+    let path = uri.path();
+    let Some(endpoint) = path
+        .strip_prefix("/api/v1/developer/")
+        .or_else(|| path.strip_prefix("/api/v2/developer/"))
+    else {
+        let error = envelope_error("CODE_RESOURCE_NOT_FOUND", "no such endpoint");
+        return (StatusCode::NOT_FOUND, Json(error)).into_response();
+    };
+    let mut state = shared.state.lock().unwrap();
+    let resource = endpoint.split('/').next().unwrap_or_default();
+    if path.starts_with("/api/v1/") && state.seed.moved_to_v2.iter().any(|r| r == resource) {
+        let error = envelope_error("CODE_API_VERSION_DEPRECATED", "moved to v2");
+        return Json(error).into_response();
+    }
+    if let Some(fault) = state.take_fault() {
+        debug!("Simulator failing {method} {path} with {fault:?}");
+        return fault.response();
+    }
+    let body: Value = if body.is_empty() {
+        Value::Null
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(body) => body,
+            Err(e) => {
+                let error = envelope_error("CODE_PARAMS_INVALID", &format!("invalid JSON: {e}"));
+                return Json(error).into_response();
+            }
+        }
+    };
+    let segments: Vec<String> = endpoint.split('/').map(percent_decode).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let query = parse_query(uri.query().unwrap_or_default());
+    let response = match state.handle(&method, &segments, &query, body) {
+        Ok(response) => response,
+        Err((code, msg)) => {
+            debug!("Simulator rejected {method} {path}: {code} {msg}");
+            envelope_error(code, &msg)
+        }
+    };
+    Json(response).into_response()
+}
+
+#[derive(Deserialize)]
+struct LatencyRequest {
+    millis: u64,
+}
+
+async fn set_latency(State(shared): State<Arc<Shared>>, Json(request): Json<LatencyRequest>) {
+    shared.state.lock().unwrap().latency = Duration::from_millis(request.millis);
+}
+
+async fn fail_next(State(shared): State<Arc<Shared>>, Json(fault): Json<SimFault>) {
+    shared.state.lock().unwrap().faults.push_back(fault);
+}
+
+#[derive(Deserialize)]
+struct ScanRequest {
+    device_id: String,
+    token: String,
+}
+
+async fn scan(State(shared): State<Arc<Shared>>, Json(request): Json<ScanRequest>) -> Response {
+    let scanned = shared
+        .state
+        .lock()
+        .unwrap()
+        .scan(&request.device_id, &request.token);
+    match scanned {
+        Ok(()) => Json(ok(Value::Null)).into_response(),
+        Err((code, msg)) => {
+            (StatusCode::CONFLICT, Json(envelope_error(code, &msg))).into_response()
+        }
+    }
+}
+
+async fn append_log(State(shared): State<Arc<Shared>>, Json(hit): Json<Value>) {
+    shared.state.lock().unwrap().seed.system_log.push(hit);
+}
+
+async fn dump_state(State(shared): State<Arc<Shared>>) -> Json<SimSeed> {
+    Json(shared.state.lock().unwrap().seed.clone())
+}
+
+/// A simulated controller serving the developer API this crate wraps, for integration tests.
+///
+/// It keeps users, access policies, NFC cards, enrollment sessions, doors and the system log
+/// in memory and answers over HTTPS with a freshly generated self-signed certificate, using the same
+/// response envelope and error codes as a real controller. Start it with optional [SimSeed] data
+/// and point a client at it with [SimHandle::client]. The `unifi-access-sim` binary runs it
+/// standalone for projects that don't use this crate.
+///
+/// ## Control endpoints
+/// Besides the developer API the simulator answers these, without authentication:
+/// - `POST /sim/latency` `{"millis": 250}` delays every API response, 0 turns it off
+/// - `POST /sim/fail-next` with a [SimFault] makes the next API requests fail
+/// - `POST /sim/scan` `{"device_id": "..", "token": ".."}` scans a card on a device, completing its enrollment session
+/// - `POST /sim/log` with a system log hit appends it to the log
+/// - `GET /sim/state` dumps the current state in the seed format
+///
+/// ## Fidelity
+/// The simulator follows the behaviour this crate relies on, not everything a controller does.
+/// Schedules are served as seeded but aren't enforced, visitors and door unlocking aren't simulated,
+/// and the system log only contains what was seeded or appended. A seeded log hit can carry a `topic`
+/// next to `_source`, a request for a topic other than `all` only returns hits with that topic.
+pub struct Simulator {
+    seed: SimSeed,
+    token: String,
+}
+
+impl Simulator {
+    pub fn new(seed: SimSeed) -> Simulator {
+        Simulator {
+            seed,
+            token: DEFAULT_SIM_TOKEN.to_string(),
+        }
+    }
+
+    /// Sets the API token requests must carry, [DEFAULT_SIM_TOKEN] by default
+    pub fn with_token(mut self, token: &str) -> Simulator {
+        self.token = token.to_string();
+        self
+    }
+
+    /// Starts serving on `addr` in the background, use port 0 to pick a free port.
+    /// The server stops when the returned handle is dropped.
+    pub async fn start(self, addr: SocketAddr) -> UnifiResult<SimHandle> {
+        let names = vec!["localhost".to_string(), addr.ip().to_string()];
+        let certificate = rcgen::generate_simple_self_signed(names).map_err(|e| {
+            UnifiError::Other(format!("Couldn't generate simulator certificate: {e}"))
+        })?;
+        let tls = RustlsConfig::from_pem(
+            certificate.cert.pem().into_bytes(),
+            certificate.key_pair.serialize_pem().into_bytes(),
+        )
+        .await?;
+        let shared = Arc::new(Shared {
+            token: self.token,
+            state: Mutex::new(SimState::new(self.seed)),
+        });
+        let app = Router::new()
+            .route("/sim/latency", post(set_latency))
+            .route("/sim/fail-next", post(fail_next))
+            .route("/sim/scan", post(scan))
+            .route("/sim/log", post(append_log))
+            .route("/sim/state", get(dump_state))
+            .fallback(api)
+            .with_state(shared.clone());
+        let server = axum_server::Handle::new();
+        let serving = axum_server::bind_rustls(addr, tls)
+            .handle(server.clone())
+            .serve(app.into_make_service());
+        tokio::spawn(async move {
+            if let Err(e) = serving.await {
+                error!("Simulator stopped: {e}");
+            }
+        });
+        let addr = server
+            .listening()
+            .await
+            .ok_or_else(|| UnifiError::Other(format!("Simulator couldn't listen on {addr}")))?;
+        info!("Simulated controller listening on https://{addr}");
+        Ok(SimHandle {
+            addr,
+            shared,
+            server,
+        })
+    }
+}
+
+/// A running [Simulator], stops serving when dropped
+pub struct SimHandle {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    server: axum_server::Handle,
+}
+
+impl SimHandle {
+    /// Address the simulator is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// A client for the simulator
+    pub fn client(&self) -> UnifiClient {
+        UnifiClient::new(&self.addr.ip().to_string(), &self.shared.token)
+            .with_port(self.addr.port())
+    }
+
+    /// Scans a card on a device, completing the enrollment session waiting there.
+    /// A token the simulator hasn't seen becomes a new card.
+    pub fn scan_card(&self, device_id: &str, token: &str) -> UnifiResult<()> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .scan(device_id, token)
+            .map_err(|(_, msg)| UnifiError::Other(msg))
+    }
+
+    /// Delays every API response by `latency`, zero turns it off
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.state.lock().unwrap().latency = latency;
+    }
+
+    /// The most API requests answered at once since the last call
+    pub fn take_max_in_flight(&self) -> usize {
+        let state = &mut *self.shared.state.lock().unwrap();
+        std::mem::replace(&mut state.max_in_flight, state.in_flight)
+    }
+
+    /// The API requests received since the last call, oldest first
+    pub fn take_requests(&self) -> Vec<SimRequest> {
+        std::mem::take(&mut self.shared.state.lock().unwrap().requests)
+    }
+
+    /// Makes the next `fault.count` API requests fail
+    pub fn fail_next(&self, fault: SimFault) {
+        self.shared.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// Appends a hit to the system log, in the format the log endpoint returns
+    pub fn push_log_event(&self, hit: Value) {
+        self.shared.state.lock().unwrap().seed.system_log.push(hit);
+    }
+
+    /// The temporary lock rule last set on the door, None if it follows its schedule
+    pub fn lock_rule(&self, door_id: &str) -> Option<Value> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .lock_rules
+            .get(door_id)
+            .cloned()
+    }
+
+    /// The unlock schedule last set on the door, None if it has none
+    pub fn unlock_schedule(&self, door_id: &str) -> Option<Value> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .unlock_schedules
+            .get(door_id)
+            .cloned()
+    }
+
+    /// The current state, in the seed format
+    pub fn state(&self) -> SimSeed {
+        self.shared.state.lock().unwrap().seed.clone()
+    }
+}
+
+impl Drop for SimHandle {
+    fn drop(&mut self) {
+        self.server.shutdown();
+    }
+}
@@ -0,0 +1,365 @@
+//! Export and restore of the configuration this crate can see on a controller.
+//!
+//! Only users (with their cards and policies) and access policies are covered, as those are the
+//! only resources the crate currently wraps. Objects are matched by stable keys rather than ids,
+//! since ids do not survive a controller being rebuilt.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::card_token::normalized_or_raw;
+use crate::{AccessPolicy, BulkExecutor, NfcCard, UnifiClient, UnifiError, UnifiResult, User};
+
+/// Version of the snapshot format, bumped when the layout changes incompatibly
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// A point in time copy of the users and policies on a controller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Format version of the snapshot
+    pub version: u32,
+    /// Seconds since the unix epoch when the snapshot was taken
+    pub taken_at: u64,
+    /// All users, including their cards and access policies
+    pub users: Vec<User>,
+    pub policies: Vec<AccessPolicy>,
+}
+
+impl Snapshot {
+    /// Writes the snapshot as a single JSON document
+    pub fn to_writer<W: Write>(&self, writer: W) -> UnifiResult<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a snapshot previously written with [Snapshot::to_writer]
+    pub fn from_reader<R: Read>(reader: R) -> UnifiResult<Snapshot> {
+        let snapshot: Snapshot = serde_json::from_reader(reader)?;
+        if snapshot.version > SNAPSHOT_VERSION {
+            return Err(UnifiError::Other(format!(
+                "Snapshot version {} is newer than supported version {SNAPSHOT_VERSION}",
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Controls how [UnifiClient::restore_snapshot] applies a snapshot
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Only report what would be done, nothing is written to the controller
+    pub dry_run: bool,
+    /// Also bring the policies and cards of users that already exist back in line with the snapshot.
+    /// If false drifted users are only reported.
+    pub update_drifted: bool,
+}
+
+/// The object a [RestoreEntry] refers to
+#[derive(Debug, Clone, Serialize)]
+pub enum RestoreObject {
+    /// Users are keyed by email, or employee number if they have no email
+    User {
+        key: String,
+    },
+    Policy {
+        name: String,
+    },
+}
+
+/// What happened (or would happen) to a single object during a restore
+#[derive(Debug, Clone, Serialize)]
+pub enum RestoreAction {
+    /// Object exists and matches the snapshot
+    Unchanged,
+    /// Object was missing and has been recreated with the given id
+    Created { id: String },
+    /// Object existed and was updated, listing what changed
+    Updated { changes: Vec<String> },
+    /// Object existed but differs from the snapshot, and updating wasn't requested
+    Drifted { changes: Vec<String> },
+    /// Dry run: object is missing and would be created
+    WouldCreate,
+    /// Dry run: object would be updated
+    WouldUpdate { changes: Vec<String> },
+    /// Object can't be restored by this crate
+    Skipped { reason: String },
+    /// Restoring this object failed, other objects are still processed
+    Failed { error: String },
+}
+
+/// A single line of a [RestoreReport]
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreEntry {
+    pub object: RestoreObject,
+    pub action: RestoreAction,
+}
+
+/// Per object outcome of [UnifiClient::restore_snapshot]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreReport {
+    pub entries: Vec<RestoreEntry>,
+}
+
+impl RestoreReport {
+    /// Entries that failed to restore
+    pub fn failures(&self) -> impl Iterator<Item = &RestoreEntry> {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.action, RestoreAction::Failed { .. }))
+    }
+}
+
+/// The stable key users are matched by, empty if the user has nothing to match on
+pub(crate) fn user_key(user: &User) -> String {
+    if !user.user_email.is_empty() {
+        user.user_email.to_lowercase()
+    } else {
+        user.employee_number.clone()
+    }
+}
+
+/// Maps the policy names held by a snapshot user onto policy ids on the current controller
+fn current_policy_ids(user: &User, policy_ids: &HashMap<&str, &str>) -> Vec<String> {
+    user.access_policies
+        .iter()
+        .flatten()
+        .filter_map(|p| match policy_ids.get(p.name.as_str()) {
+            Some(id) => Some(id.to_string()),
+            None => {
+                warn!(
+                    "Policy {} of user {} doesn't exist on the controller, not assigning",
+                    p.name,
+                    user_key(user)
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+impl UnifiClient {
+    /// Captures all users (with cards and policies) and access policies into a [Snapshot].
+    /// Makes one request per user to collect their policies, see [UnifiClient::get_all_users_with_access_information].
+    pub async fn export_snapshot(&self) -> UnifiResult<Snapshot> {
+        let taken_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let users = self.get_all_users_with_access_information().await?;
+        let policies = self.get_all_access_policies().await?;
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at,
+            users,
+            policies,
+        })
+    }
+
+    /// Recreates objects from the snapshot that are missing on the controller,
+    /// and optionally updates the ones that drifted.
+    ///
+    /// Users are matched by email (or employee number when they have no email), policies by name.
+    /// Missing users are registered and given their cards and policies back.
+    /// Missing policies are reported but not recreated, as the crate can't create policies.
+    ///
+    /// Only fails outright if the current state can't be read, individual object failures are recorded in the report.
+    /// Up to [MAX_CONCURRENT_REQUESTS](crate::MAX_CONCURRENT_REQUESTS) users are restored at once.
+    pub async fn restore_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        options: &RestoreOptions,
+    ) -> UnifiResult<RestoreReport> {
+        self.restore_snapshot_with(snapshot, options, &BulkExecutor::default())
+            .await
+    }
+
+    /// Like [UnifiClient::restore_snapshot], with the concurrency, progress reporting and cancellation
+    /// of `executor` applied to the users. Users not restored because the run was cancelled are
+    /// reported as skipped.
+    pub async fn restore_snapshot_with(
+        &self,
+        snapshot: &Snapshot,
+        options: &RestoreOptions,
+        executor: &BulkExecutor,
+    ) -> UnifiResult<RestoreReport> {
+        let current_policies = self.get_all_access_policies().await?;
+        let current_users = self.get_all_users_with_access_information().await?;
+        let mut report = RestoreReport::default();
+
+        let policy_ids: HashMap<&str, &str> = current_policies
+            .iter()
+            .map(|p| (p.name.as_str(), p.id.as_str()))
+            .collect();
+        for policy in &snapshot.policies {
+            let action = if policy_ids.contains_key(policy.name.as_str()) {
+                RestoreAction::Unchanged
+            } else {
+                RestoreAction::Skipped {
+                    reason: "Policy is missing and policies can't be created by this crate"
+                        .to_string(),
+                }
+            };
+            report.entries.push(RestoreEntry {
+                object: RestoreObject::Policy {
+                    name: policy.name.clone(),
+                },
+                action,
+            });
+        }
+
+        let existing: HashMap<String, &User> =
+            current_users.iter().map(|u| (user_key(u), u)).collect();
+        let (existing, policy_ids) = (&existing, &policy_ids);
+        let restored = executor
+            .run(&snapshot.users, |user| async move {
+                let key = user_key(user);
+                let action = if key.is_empty() {
+                    RestoreAction::Skipped {
+                        reason: "User has no email or employee number to match on".to_string(),
+                    }
+                } else {
+                    match existing.get(&key) {
+                        None => self.restore_missing_user(user, policy_ids, options).await,
+                        Some(current) => {
+                            self.restore_drifted_user(user, current, policy_ids, options)
+                                .await
+                        }
+                    }
+                };
+                UnifiResult::Ok(action)
+            })
+            .await;
+        for (user, action) in restored.results {
+            report.entries.push(RestoreEntry {
+                object: RestoreObject::User {
+                    key: user_key(user),
+                },
+                action: match action {
+                    Some(Ok(action)) => action,
+                    Some(Err(e)) => RestoreAction::Failed {
+                        error: e.to_string(),
+                    },
+                    None => RestoreAction::Skipped {
+                        reason: "The restore was cancelled".to_string(),
+                    },
+                },
+            });
+        }
+        Ok(report)
+    }
+
+    /// Assigns the given policies (if any) and cards to a user
+    async fn apply_user_access(
+        &self,
+        user_id: &str,
+        policies: Option<Vec<String>>,
+        cards: &[&NfcCard],
+    ) -> UnifiResult<()> {
+        if let Some(policies) = policies {
+            self.assign_access_policies(user_id, policies).await?;
+        }
+        for card in cards {
+            self.assign_nfc_card(user_id, card).await?;
+        }
+        Ok(())
+    }
+
+    async fn restore_missing_user(
+        &self,
+        user: &User,
+        policy_ids: &HashMap<&str, &str>,
+        options: &RestoreOptions,
+    ) -> RestoreAction {
+        if options.dry_run {
+            return RestoreAction::WouldCreate;
+        }
+        let id = match self
+            .register_user(
+                user.first_name.clone(),
+                user.last_name.clone(),
+                user.user_email.clone(),
+                user.employee_number.clone(),
+            )
+            .await
+        {
+            Ok(id) => id,
+            Err(e) => {
+                return RestoreAction::Failed {
+                    error: e.to_string(),
+                }
+            }
+        };
+        let policies = current_policy_ids(user, policy_ids);
+        let policies = (!policies.is_empty()).then_some(policies);
+        let cards: Vec<_> = user.nfc_cards.iter().collect();
+        match self.apply_user_access(&id, policies, &cards).await {
+            Ok(()) => RestoreAction::Created { id },
+            Err(e) => RestoreAction::Failed {
+                error: format!("User was created as {id}, but restoring its access failed: {e}"),
+            },
+        }
+    }
+
+    async fn restore_drifted_user(
+        &self,
+        user: &User,
+        current: &User,
+        policy_ids: &HashMap<&str, &str>,
+        options: &RestoreOptions,
+    ) -> RestoreAction {
+        let mut changes = vec![];
+
+        // If the snapshot doesn't know the user's policies we can't say they drifted
+        let mut new_policies = None;
+        if let Some(wanted) = &user.access_policies {
+            let wanted: HashSet<&str> = wanted.iter().map(|p| p.name.as_str()).collect();
+            let held: HashSet<&str> = current
+                .access_policies
+                .iter()
+                .flatten()
+                .map(|p| p.name.as_str())
+                .collect();
+            if wanted != held {
+                changes.push("access policies".to_string());
+                new_policies = Some(current_policy_ids(user, policy_ids));
+            }
+        }
+
+        // Snapshots may come from elsewhere with tokens in another format
+        let held_cards: HashSet<String> = current
+            .nfc_cards
+            .iter()
+            .map(|c| normalized_or_raw(c.token.expose()))
+            .collect();
+        let missing_cards: Vec<_> = user
+            .nfc_cards
+            .iter()
+            .filter(|c| !held_cards.contains(&normalized_or_raw(c.token.expose())))
+            .collect();
+        for card in &missing_cards {
+            changes.push(format!("nfc card {}", card.id));
+        }
+
+        if changes.is_empty() {
+            return RestoreAction::Unchanged;
+        }
+        if !options.update_drifted {
+            return RestoreAction::Drifted { changes };
+        }
+        if options.dry_run {
+            return RestoreAction::WouldUpdate { changes };
+        }
+        match self
+            .apply_user_access(&current.id, new_policies, &missing_cards)
+            .await
+        {
+            Ok(()) => RestoreAction::Updated { changes },
+            Err(e) => RestoreAction::Failed {
+                error: e.to_string(),
+            },
+        }
+    }
+}
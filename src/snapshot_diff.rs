@@ -0,0 +1,300 @@
+//! Changes between two [Snapshot]s, for spotting edits made outside of a source of truth.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use log::*;
+use serde::Serialize;
+
+use crate::snapshot::user_key;
+use crate::{AccessPolicy, Snapshot, User};
+
+/// A user field that changed, see [UserChange::Modified]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// How a user differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum UserChange {
+    Added {
+        key: String,
+        id: String,
+    },
+    Removed {
+        key: String,
+        id: String,
+    },
+    Modified {
+        key: String,
+        id: String,
+        fields: Vec<FieldChange>,
+        /// Display ids of the cards gained and lost
+        cards_added: Vec<String>,
+        cards_removed: Vec<String>,
+        /// Names of the policies gained and lost, empty if either snapshot doesn't know the user's policies
+        policies_added: Vec<String>,
+        policies_removed: Vec<String>,
+    },
+}
+
+/// How an access policy differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum PolicyChange {
+    Added {
+        name: String,
+        id: String,
+    },
+    Removed {
+        name: String,
+        id: String,
+    },
+    /// The doors and door groups the policy grants access to changed, by resource id
+    Modified {
+        name: String,
+        id: String,
+        resources_added: Vec<String>,
+        resources_removed: Vec<String>,
+    },
+}
+
+/// Result of [Snapshot::diff]. Display renders a compact, one line per change summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SnapshotDiff {
+    pub users: Vec<UserChange>,
+    pub policies: Vec<PolicyChange>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.policies.is_empty()
+    }
+}
+
+/// Sorted `after - before` and `before - after`
+fn set_changes(before: &BTreeSet<String>, after: &BTreeSet<String>) -> (Vec<String>, Vec<String>) {
+    (
+        after.difference(before).cloned().collect(),
+        before.difference(after).cloned().collect(),
+    )
+}
+
+/// The key a user is matched on, falling back to the id for users with no email or employee number
+fn match_key(user: &User) -> String {
+    let key = user_key(user);
+    if key.is_empty() {
+        format!("id:{}", user.id)
+    } else {
+        key
+    }
+}
+
+/// Indexes objects by key, keeping the first of any duplicates
+fn index_by<'a, T>(
+    items: &'a [T],
+    what: &str,
+    key: impl Fn(&T) -> String,
+) -> BTreeMap<String, &'a T> {
+    let mut index = BTreeMap::new();
+    for item in items {
+        let key = key(item);
+        if index.contains_key(&key) {
+            warn!("Several {what} share the key {key}, only the first is compared");
+            continue;
+        }
+        index.insert(key, item);
+    }
+    index
+}
+
+fn diff_user(key: &str, before: &User, after: &User) -> Option<UserChange> {
+    let mut fields = vec![];
+    let mut compare = |field: &str, before: &str, after: &str| {
+        if before != after {
+            fields.push(FieldChange {
+                field: field.to_string(),
+                before: before.to_string(),
+                after: after.to_string(),
+            });
+        }
+    };
+    compare("first_name", &before.first_name, &after.first_name);
+    compare("last_name", &before.last_name, &after.last_name);
+    compare("user_email", &before.user_email, &after.user_email);
+    compare(
+        "employee_number",
+        &before.employee_number,
+        &after.employee_number,
+    );
+    compare(
+        "status",
+        &format!("{:?}", before.status),
+        &format!("{:?}", after.status),
+    );
+
+    // Cards are compared by token, but reported by display id so tokens don't end up in reports
+    let card_tokens = |user: &User| -> BTreeMap<String, String> {
+        user.nfc_cards
+            .iter()
+            .map(|c| (c.token.expose().to_string(), c.id.clone()))
+            .collect()
+    };
+    let (before_cards, after_cards) = (card_tokens(before), card_tokens(after));
+    let cards_added = after_cards
+        .iter()
+        .filter(|(token, _)| !before_cards.contains_key(*token))
+        .map(|(_, id)| id.clone())
+        .collect::<Vec<_>>();
+    let cards_removed = before_cards
+        .iter()
+        .filter(|(token, _)| !after_cards.contains_key(*token))
+        .map(|(_, id)| id.clone())
+        .collect::<Vec<_>>();
+
+    let policy_names = |policies: &Vec<AccessPolicy>| -> BTreeSet<String> {
+        policies.iter().map(|p| p.name.clone()).collect()
+    };
+    let (policies_added, policies_removed) = match (&before.access_policies, &after.access_policies)
+    {
+        (Some(b), Some(a)) => set_changes(&policy_names(b), &policy_names(a)),
+        _ => (vec![], vec![]),
+    };
+
+    if fields.is_empty()
+        && cards_added.is_empty()
+        && cards_removed.is_empty()
+        && policies_added.is_empty()
+        && policies_removed.is_empty()
+    {
+        return None;
+    }
+    Some(UserChange::Modified {
+        key: key.to_string(),
+        id: after.id.clone(),
+        fields,
+        cards_added,
+        cards_removed,
+        policies_added,
+        policies_removed,
+    })
+}
+
+impl Snapshot {
+    /// What changed going from this snapshot to `newer`.
+    ///
+    /// Users are matched by email, or employee number if they have no email, and policies by name,
+    /// like [crate::UnifiClient::restore_snapshot]. A user or policy whose id changed was deleted and
+    /// recreated, so it is reported as removed and added rather than modified.
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        let before = index_by(&self.users, "users", match_key);
+        let after = index_by(&newer.users, "users", match_key);
+        for (key, old) in &before {
+            match after.get(key) {
+                Some(new) if new.id == old.id => diff.users.extend(diff_user(key, old, new)),
+                _ => diff.users.push(UserChange::Removed {
+                    key: key.clone(),
+                    id: old.id.clone(),
+                }),
+            }
+        }
+        for (key, new) in &after {
+            if !before.get(key).is_some_and(|old| old.id == new.id) {
+                diff.users.push(UserChange::Added {
+                    key: key.clone(),
+                    id: new.id.clone(),
+                });
+            }
+        }
+
+        let policy_name = |p: &AccessPolicy| p.name.clone();
+        let before = index_by(&self.policies, "policies", policy_name);
+        let after = index_by(&newer.policies, "policies", policy_name);
+        let resource_ids = |p: &AccessPolicy| -> BTreeSet<String> {
+            p.resources.iter().map(|r| r.id.clone()).collect()
+        };
+        for (name, old) in &before {
+            match after.get(name) {
+                Some(new) if new.id == old.id => {
+                    let (added, removed) = set_changes(&resource_ids(old), &resource_ids(new));
+                    if !added.is_empty() || !removed.is_empty() {
+                        diff.policies.push(PolicyChange::Modified {
+                            name: name.clone(),
+                            id: new.id.clone(),
+                            resources_added: added,
+                            resources_removed: removed,
+                        });
+                    }
+                }
+                _ => diff.policies.push(PolicyChange::Removed {
+                    name: name.clone(),
+                    id: old.id.clone(),
+                }),
+            }
+        }
+        for (name, new) in &after {
+            if !before.get(name).is_some_and(|old| old.id == new.id) {
+                diff.policies.push(PolicyChange::Added {
+                    name: name.clone(),
+                    id: new.id.clone(),
+                });
+            }
+        }
+        diff
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for change in &self.users {
+            match change {
+                UserChange::Added { key, .. } => writeln!(f, "+ user {key}")?,
+                UserChange::Removed { key, .. } => writeln!(f, "- user {key}")?,
+                UserChange::Modified {
+                    key,
+                    fields,
+                    cards_added,
+                    cards_removed,
+                    policies_added,
+                    policies_removed,
+                    ..
+                } => {
+                    let mut parts: Vec<String> = fields
+                        .iter()
+                        .map(|c| format!("{} {:?} -> {:?}", c.field, c.before, c.after))
+                        .collect();
+                    parts.extend(cards_added.iter().map(|c| format!("+card {c}")));
+                    parts.extend(cards_removed.iter().map(|c| format!("-card {c}")));
+                    parts.extend(policies_added.iter().map(|p| format!("+policy {p}")));
+                    parts.extend(policies_removed.iter().map(|p| format!("-policy {p}")));
+                    writeln!(f, "~ user {key}: {}", parts.join(", "))?
+                }
+            }
+        }
+        for change in &self.policies {
+            match change {
+                PolicyChange::Added { name, .. } => writeln!(f, "+ policy {name}")?,
+                PolicyChange::Removed { name, .. } => writeln!(f, "- policy {name}")?,
+                PolicyChange::Modified {
+                    name,
+                    resources_added,
+                    resources_removed,
+                    ..
+                } => writeln!(
+                    f,
+                    "~ policy {name}: +{} -{} resources",
+                    resources_added.len(),
+                    resources_removed.len()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! A versioned representation of [User] for storing outside the controller.
+//!
+//! The serde layout of [User] follows whatever the controller sends, so it can change between crate
+//! releases. The stable layout only changes together with [STABLE_SCHEMA_VERSION], and documents written
+//! with any earlier version keep loading.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AccessPolicy, NfcCard, UnifiError, UnifiResult, User, UserStatus};
+
+/// Current version of the stable layout, written into every document as `schema_version`
+pub const STABLE_SCHEMA_VERSION: u32 = 1;
+
+/// Layout of version 1, fields must never be renamed or retyped.
+/// A change means a new version with a conversion from this one in [User::from_stable_json].
+#[derive(Serialize, Deserialize)]
+struct StableUserV1 {
+    schema_version: u32,
+    id: String,
+    first_name: String,
+    last_name: String,
+    email: String,
+    employee_number: String,
+    /// `active`, `pending`, `deactivated` or `unknown`
+    status: String,
+    nfc_cards: Vec<StableNfcCardV1>,
+    /// None when the user was stored without fetching their policies
+    access_policies: Option<Vec<StablePolicyV1>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StableNfcCardV1 {
+    id: String,
+    token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StablePolicyV1 {
+    id: String,
+    name: String,
+}
+
+fn status_to_stable(status: &UserStatus) -> &'static str {
+    match status {
+        UserStatus::Active => "active",
+        UserStatus::Pending => "pending",
+        UserStatus::Deactivated => "deactivated",
+        UserStatus::Unknown => "unknown",
+    }
+}
+
+fn status_from_stable(status: &str) -> UserStatus {
+    match status {
+        "active" => UserStatus::Active,
+        "pending" => UserStatus::Pending,
+        "deactivated" => UserStatus::Deactivated,
+        _ => UserStatus::Unknown,
+    }
+}
+
+impl User {
+    /// The user in the current stable layout, tagged with [STABLE_SCHEMA_VERSION].
+    /// NFC card tokens are included in plain text so the user can be restored, store accordingly.
+    pub fn to_stable_json(&self) -> serde_json::Value {
+        let stable = StableUserV1 {
+            schema_version: STABLE_SCHEMA_VERSION,
+            id: self.id.clone(),
+            first_name: self.first_name.clone(),
+            last_name: self.last_name.clone(),
+            email: self.user_email.clone(),
+            employee_number: self.employee_number.clone(),
+            status: status_to_stable(&self.status).to_string(),
+            nfc_cards: self
+                .nfc_cards
+                .iter()
+                .map(|card| StableNfcCardV1 {
+                    id: card.id.clone(),
+                    token: card.token.expose().to_string(),
+                })
+                .collect(),
+            access_policies: self.access_policies.as_ref().map(|policies| {
+                policies
+                    .iter()
+                    .map(|policy| StablePolicyV1 {
+                        id: policy.id.clone(),
+                        name: policy.name.clone(),
+                    })
+                    .collect()
+            }),
+        };
+        serde_json::to_value(stable).expect("stable user layout always serializes")
+    }
+
+    /// Loads a user written by [User::to_stable_json] with this or any earlier schema version
+    pub fn from_stable_json(value: &serde_json::Value) -> UnifiResult<User> {
+        let version =
+            value
+                .get("schema_version")
+                .and_then(|v| v.as_u64())
+                .ok_or(UnifiError::Other(
+                    "Stable user has no schema_version".to_string(),
+                ))?;
+        let stable: StableUserV1 = match version {
+            1 => serde_json::from_value(value.clone())?,
+            _ => {
+                return Err(UnifiError::Other(format!(
+                    "Stable user schema version {version} is not supported, the newest is {STABLE_SCHEMA_VERSION}"
+                )))
+            }
+        };
+        Ok(User {
+            id: stable.id,
+            first_name: stable.first_name,
+            last_name: stable.last_name,
+            nfc_cards: stable
+                .nfc_cards
+                .into_iter()
+                .map(|card| NfcCard {
+                    id: card.id,
+                    token: card.token.into(),
+                })
+                .collect(),
+            employee_number: stable.employee_number,
+            user_email: stable.email,
+            status: status_from_stable(&stable.status),
+            access_policies: stable.access_policies.map(|policies| {
+                policies
+                    .into_iter()
+                    .map(|policy| AccessPolicy {
+                        id: policy.id,
+                        name: policy.name,
+                        resources: vec![],
+                        schedule_id: String::new(),
+                    })
+                    .collect()
+            }),
+        })
+    }
+}
@@ -0,0 +1,191 @@
+//! Persisting the state of long-lived helpers (grant registry, offline queue, log cursors) across restarts.
+//!
+//! Helpers store their state through one small key-value trait, [StateStore], so a single backend serves
+//! all of them. The crate provides [InMemoryStateStore] and [JsonFileStateStore]; implement the trait to
+//! keep state in redis, sqlite etc.
+//!
+//! ## Encoding
+//! Each value is a format version byte followed by the state as JSON. Fields added later are optional,
+//! so older state still reads, and a crate upgrade that changes a format migrates the old version on read.
+//! State written by a newer crate version fails with [UnifiError::UnsupportedStateVersion]
+//! rather than being misread.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{UnifiError, UnifiResult};
+
+/// Asynchronous key-value storage for helper state, values are opaque bytes
+pub trait StateStore: Send + Sync {
+    /// The value stored under `key`, None if there is none
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<Option<Vec<u8>>>>;
+    /// Stores `value` under `key`, replacing any previous value
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, UnifiResult<()>>;
+    /// Removes the value under `key`, removing a missing key is not an error
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<()>>;
+}
+
+/// Store kept in memory, lost when the process exits
+#[derive(Debug, Default)]
+pub struct InMemoryStateStore {
+    values: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl StateStore for InMemoryStateStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<Option<Vec<u8>>>> {
+        let value = self.values.lock().unwrap().get(key).cloned();
+        Box::pin(std::future::ready(Ok(value)))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, UnifiResult<()>> {
+        self.values.lock().unwrap().insert(key.to_string(), value);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        self.values.lock().unwrap().remove(key);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Store kept in a single local JSON file, an object of keys to hex encoded values.
+/// Every write rewrites the whole file, which suits the small amount of state helpers keep.
+#[derive(Debug)]
+pub struct JsonFileStateStore {
+    path: PathBuf,
+    // Serializes access to the file between threads of this process
+    lock: Mutex<()>,
+}
+
+impl JsonFileStateStore {
+    /// Uses the file at `path`, which is created on first write if it doesn't exist
+    pub fn new(path: impl Into<PathBuf>) -> JsonFileStateStore {
+        JsonFileStateStore {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> UnifiResult<BTreeMap<String, String>> {
+        match std::fs::read(&self.path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, values: &BTreeMap<String, String>) -> UnifiResult<()> {
+        // Write to a temporary file and rename so a crash can't leave a half written file
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(values)?)?;
+        std::fs::File::open(&tmp)?.sync_data()?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BTreeMap<String, String>)) -> UnifiResult<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut values = self.read()?;
+        change(&mut values);
+        self.write(&values)
+    }
+}
+
+impl StateStore for JsonFileStateStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<Option<Vec<u8>>>> {
+        let value = (|| {
+            let _guard = self.lock.lock().unwrap();
+            match self.read()?.get(key) {
+                Some(hex) => from_hex(hex).map(Some).ok_or_else(|| {
+                    UnifiError::Other(format!(
+                        "Value of {key} in {} isn't valid hex",
+                        self.path.display()
+                    ))
+                }),
+                None => Ok(None),
+            }
+        })();
+        Box::pin(std::future::ready(value))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>) -> BoxFuture<'a, UnifiResult<()>> {
+        let result = self.update(|values| {
+            values.insert(key.to_string(), to_hex(&value));
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        let result = self.update(|values| {
+            values.remove(key);
+        });
+        Box::pin(std::future::ready(result))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// State a helper persists, with its current format version
+pub(crate) trait VersionedState: Serialize + DeserializeOwned {
+    /// Written in front of the state, bump it when a change needs a migration
+    const VERSION: u8;
+
+    /// Converts the JSON of an older `version` into the current format.
+    /// No format has changed yet, so only the current version is accepted.
+    fn migrate(key: &str, version: u8, state: serde_json::Value) -> UnifiResult<serde_json::Value> {
+        if version == Self::VERSION {
+            Ok(state)
+        } else {
+            Err(UnifiError::UnsupportedStateVersion {
+                key: key.to_string(),
+                version,
+            })
+        }
+    }
+}
+
+/// Loads the state under `key`, None if nothing has been stored yet
+pub(crate) async fn load_state<T: VersionedState>(
+    store: &dyn StateStore,
+    key: &str,
+) -> UnifiResult<Option<T>> {
+    let Some(bytes) = store.get(key).await? else {
+        return Ok(None);
+    };
+    let Some((&version, json)) = bytes.split_first() else {
+        return Err(UnifiError::UnsupportedStateVersion {
+            key: key.to_string(),
+            version: 0,
+        });
+    };
+    let state = T::migrate(key, version, serde_json::from_slice(json)?)?;
+    Ok(Some(serde_json::from_value(state)?))
+}
+
+/// Stores `state` under `key` in the current format
+pub(crate) async fn save_state<T: VersionedState>(
+    store: &dyn StateStore,
+    key: &str,
+    state: &T,
+) -> UnifiResult<()> {
+    let mut bytes = vec![T::VERSION];
+    serde_json::to_writer(&mut bytes, state)?;
+    store.put(key, bytes).await
+}
@@ -0,0 +1,111 @@
+//! Credential counts for dashboards.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::{
+    has_more_pages, NfcCardListOptions, Page, UnifiClient, UnifiResult, User, UserStatus,
+    LIST_PAGE_SIZE,
+};
+
+/// Counts of users and their credentials, see [UnifiClient::credential_summary]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct CredentialSummary {
+    pub users_total: usize,
+    pub users_by_status: BTreeMap<UserStatus, usize>,
+    /// NFC cards assigned to users
+    pub nfc_cards_assigned: usize,
+    /// Every NFC card the controller knows, assigned or not, by the status it lists them with,
+    /// e.g. `assigned` or `disable`. Cards listed without a status count as `assigned` or `unassigned`.
+    #[serde(default)]
+    pub nfc_cards_by_status: BTreeMap<String, usize>,
+    /// Users with no NFC card
+    pub users_without_credentials: usize,
+}
+
+impl CredentialSummary {
+    fn add(&mut self, user: &User) {
+        self.users_total += 1;
+        *self.users_by_status.entry(user.status.clone()).or_default() += 1;
+        self.nfc_cards_assigned += user.nfc_cards.len();
+        if user.nfc_cards.is_empty() {
+            self.users_without_credentials += 1;
+        }
+    }
+}
+
+/// The fields of a listed card the summary needs
+#[derive(Deserialize)]
+struct CardStatus {
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    user_id: Option<String>,
+}
+
+impl CardStatus {
+    /// The status the controller lists, or whether the card is assigned if it lists none
+    fn status(&self) -> &str {
+        match (&self.status, &self.user_id) {
+            (Some(status), _) if !status.is_empty() => status.as_str(),
+            (_, Some(user_id)) if !user_id.is_empty() => "assigned",
+            _ => "unassigned",
+        }
+    }
+}
+
+impl UnifiClient {
+    /// Counts users by status, the NFC cards assigned to them, and every NFC card by status.
+    /// The controller has no aggregate endpoint, so every page of users and cards is fetched,
+    /// but only one page of each is held in memory at a time.
+    pub async fn credential_summary(&self) -> UnifiResult<CredentialSummary> {
+        let (mut summary, nfc_cards_by_status) =
+            futures::try_join!(self.summarize_users(), self.count_nfc_cards_by_status())?;
+        summary.nfc_cards_by_status = nfc_cards_by_status;
+        Ok(summary)
+    }
+
+    async fn summarize_users(&self) -> UnifiResult<CredentialSummary> {
+        let mut summary = CredentialSummary::default();
+        let mut page_num = 1;
+        loop {
+            let page = self.get_users_page(page_num, LIST_PAGE_SIZE).await?;
+            page.items.iter().for_each(|user| summary.add(user));
+            if !has_more_pages(
+                page.pagination.as_ref(),
+                page.items.is_empty(),
+                summary.users_total,
+            ) {
+                return Ok(summary);
+            }
+            page_num += 1;
+        }
+    }
+
+    async fn count_nfc_cards_by_status(&self) -> UnifiResult<BTreeMap<String, usize>> {
+        let mut counts = BTreeMap::new();
+        let mut fetched = 0;
+        let mut page_num = 1;
+        loop {
+            let page: Page<CardStatus> = self
+                .generic_request_page(
+                    &self.api_path("credentials/nfc_cards/tokens"),
+                    page_num,
+                    &NfcCardListOptions::from(LIST_PAGE_SIZE),
+                )
+                .await?;
+            fetched += page.items.len();
+            for card in &page.items {
+                *counts.entry(card.status().to_string()).or_default() += 1;
+            }
+            if !has_more_pages(page.pagination.as_ref(), page.items.is_empty(), fetched) {
+                return Ok(counts);
+            }
+            page_num += 1;
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! The site's time zone: how far its clock is ahead of UTC, and when daylight saving time moves it.
+//!
+//! The API doesn't say which zone the controller is in, so it is set on the client, see
+//! [UnifiClient::with_site_time_zone]. Zones with daylight saving time are given as POSIX `TZ` rules,
+//! e.g. `CET-1CEST,M3.5.0,M10.5.0/3`, the form found on the last line of every zoneinfo file,
+//! so no time zone database is needed.
+
+use std::time::SystemTime;
+
+use crate::clock::{civil_from_days, days_from_civil, unix_secs};
+use crate::{UnifiClient, UnifiError, UnifiResult};
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// A site's offset from UTC, optionally with daylight saving time.
+/// An offset in seconds converts into a fixed zone, e.g. `7200` for two hours east of UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SiteTimeZone {
+    /// Seconds ahead of UTC outside daylight saving time
+    standard_offset: i32,
+    daylight_saving: Option<DaylightSaving>,
+}
+
+/// When daylight saving time starts and ends each year, and the offset during it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DaylightSaving {
+    offset: i32,
+    start: TransitionRule,
+    end: TransitionRule,
+}
+
+/// A POSIX `Mm.w.d/time` rule: weekday `d`, Sunday is 0, of week `w` of month `m`, week 5 being the last,
+/// at `time` seconds past local midnight on the clock in force until then
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TransitionRule {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i32,
+}
+
+impl TransitionRule {
+    /// Days since the epoch of the date the rule falls on in `year`
+    fn day_in(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let next_month = if self.month == 12 {
+            days_from_civil(year + 1, 1, 1)
+        } else {
+            days_from_civil(year, self.month + 1, 1)
+        };
+        // The epoch was a Thursday
+        let first_weekday = (first + 4).rem_euclid(7) as u32;
+        let mut day = first
+            + i64::from((self.weekday + 7 - first_weekday) % 7)
+            + 7 * i64::from(self.week - 1);
+        // The fifth week means the last, which is the fourth in most months
+        while day >= next_month {
+            day -= 7;
+        }
+        day
+    }
+}
+
+impl SiteTimeZone {
+    /// UTC, the default
+    pub const UTC: SiteTimeZone = SiteTimeZone {
+        standard_offset: 0,
+        daylight_saving: None,
+    };
+
+    /// A zone `utc_offset_secs` ahead of UTC all year
+    pub fn fixed(utc_offset_secs: i32) -> SiteTimeZone {
+        SiteTimeZone {
+            standard_offset: utc_offset_secs,
+            daylight_saving: None,
+        }
+    }
+
+    /// Parses a POSIX `TZ` rule, e.g. `EST5EDT,M3.2.0,M11.1.0` or `<+1030>-10:30<+11>-11,M10.1.0,M4.1.0`.
+    /// Offsets count hours west of UTC, as POSIX has it. Daylight saving time needs its start and end as
+    /// `Mm.w.d[/time]` rules, the Julian day forms aren't supported.
+    /// Fails with [UnifiError::Validation] if the rule can't be read.
+    pub fn from_posix(tz: &str) -> UnifiResult<SiteTimeZone> {
+        parse_posix(tz.trim()).ok_or_else(|| UnifiError::Validation {
+            field: "time_zone".to_string(),
+            reason: format!("{tz:?} isn't a POSIX TZ rule like CET-1CEST,M3.5.0,M10.5.0/3"),
+        })
+    }
+
+    /// How far the site's clock is ahead of UTC at `at`, in seconds
+    pub fn utc_offset_at(&self, at: SystemTime) -> i32 {
+        self.offset_at(unix_secs(at))
+    }
+
+    /// True if daylight saving time is in force at `at`
+    pub fn is_daylight_saving_at(&self, at: SystemTime) -> bool {
+        self.daylight_saving.is_some() && self.offset_at(unix_secs(at)) != self.standard_offset
+    }
+
+    /// The offset at a number of seconds since the epoch
+    pub(crate) fn offset_at(&self, unix: i64) -> i32 {
+        let Some(dst) = &self.daylight_saving else {
+            return self.standard_offset;
+        };
+        let (year, _, _) =
+            civil_from_days((unix + i64::from(self.standard_offset)).div_euclid(SECS_PER_DAY));
+        let (start, end) = self.transitions_in(year, dst);
+        // South of the equator daylight saving time spans the new year
+        let in_dst = if start < end {
+            start <= unix && unix < end
+        } else {
+            unix >= start || unix < end
+        };
+        if in_dst {
+            dst.offset
+        } else {
+            self.standard_offset
+        }
+    }
+
+    /// Every offset the zone uses, the standard one first
+    pub(crate) fn offsets(&self) -> Vec<i32> {
+        let mut offsets = vec![self.standard_offset];
+        offsets.extend(self.daylight_saving.map(|dst| dst.offset));
+        offsets
+    }
+
+    /// The instants the offset changes in `year`, in seconds since the epoch, none for a fixed zone
+    pub(crate) fn transitions(&self, year: i64) -> Vec<i64> {
+        match &self.daylight_saving {
+            Some(dst) => {
+                let (start, end) = self.transitions_in(year, dst);
+                vec![start, end]
+            }
+            None => vec![],
+        }
+    }
+
+    /// When daylight saving time starts and ends in `year`, each read on the clock in force before it
+    fn transitions_in(&self, year: i64, dst: &DaylightSaving) -> (i64, i64) {
+        let start = dst.start.day_in(year) * SECS_PER_DAY + i64::from(dst.start.time)
+            - i64::from(self.standard_offset);
+        let end =
+            dst.end.day_in(year) * SECS_PER_DAY + i64::from(dst.end.time) - i64::from(dst.offset);
+        (start, end)
+    }
+}
+
+impl From<i32> for SiteTimeZone {
+    fn from(utc_offset_secs: i32) -> SiteTimeZone {
+        SiteTimeZone::fixed(utc_offset_secs)
+    }
+}
+
+fn parse_posix(tz: &str) -> Option<SiteTimeZone> {
+    let rest = skip_name(tz)?;
+    let (west, rest) = parse_duration(rest)?;
+    let standard_offset = -west;
+    if rest.is_empty() {
+        return Some(SiteTimeZone::fixed(standard_offset));
+    }
+    let rest = skip_name(rest)?;
+    // Daylight saving time is an hour ahead unless it says otherwise
+    let (offset, rest) = if rest.starts_with(',') {
+        (standard_offset + 3600, rest)
+    } else {
+        let (west, rest) = parse_duration(rest)?;
+        (-west, rest)
+    };
+    // Without rules POSIX leaves the dates to the implementation, there's no database to look them up in
+    let (start, rest) = parse_rule(rest.strip_prefix(',')?)?;
+    let (end, rest) = parse_rule(rest.strip_prefix(',')?)?;
+    rest.is_empty().then_some(SiteTimeZone {
+        standard_offset,
+        daylight_saving: Some(DaylightSaving { offset, start, end }),
+    })
+}
+
+/// Skips a zone abbreviation, at least three letters or anything quoted in `<>`
+fn skip_name(tz: &str) -> Option<&str> {
+    if let Some(quoted) = tz.strip_prefix('<') {
+        let (name, rest) = quoted.split_once('>')?;
+        return (name.len() >= 3).then_some(rest);
+    }
+    let len = tz
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(tz.len());
+    (len >= 3).then_some(&tz[len..])
+}
+
+/// Parses `[+-]hh[:mm[:ss]]` into seconds, hours may go up to 167 as in transition times
+fn parse_duration(tz: &str) -> Option<(i32, &str)> {
+    let (sign, mut rest) = match tz.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, tz.strip_prefix('+').unwrap_or(tz)),
+    };
+    let mut parts = [0; 3];
+    for (i, part) in parts.iter_mut().enumerate() {
+        if i > 0 {
+            let Some(after_colon) = rest.strip_prefix(':') else {
+                break;
+            };
+            rest = after_colon;
+        }
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if len == 0 || len > 3 {
+            return None;
+        }
+        *part = rest[..len].parse::<i32>().ok()?;
+        rest = &rest[len..];
+    }
+    let [h, m, s] = parts;
+    if h > 167 || m > 59 || s > 59 {
+        return None;
+    }
+    Some((sign * (h * 3600 + m * 60 + s), rest))
+}
+
+/// Parses `Mm.w.d[/time]`, the time defaults to 02:00
+fn parse_rule(tz: &str) -> Option<(TransitionRule, &str)> {
+    let tz = tz.strip_prefix('M')?;
+    let len = tz.find([',', '/']).unwrap_or(tz.len());
+    let mut fields = tz[..len].split('.').map(|n| n.parse::<u32>().ok());
+    let (Some(Some(month)), Some(Some(week)), Some(Some(weekday)), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+        return None;
+    }
+    let rest = &tz[len..];
+    let (time, rest) = match rest.strip_prefix('/') {
+        Some(time) => parse_duration(time)?,
+        None => (7200, rest),
+    };
+    Some((
+        TransitionRule {
+            month,
+            week,
+            weekday,
+            time,
+        },
+        rest,
+    ))
+}
+
+impl UnifiClient {
+    /// Sets the site's time zone, so schedules are checked against the site's clock across daylight
+    /// saving time changes. Defaults to UTC. See [SiteTimeZone::from_posix] for zones with daylight saving time.
+    pub fn with_site_time_zone(mut self, time_zone: impl Into<SiteTimeZone>) -> UnifiClient {
+        self.site_time_zone = time_zone.into();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::utc_time;
+
+    fn utc(year: u64, month: u64, day: u64, h: u64, m: u64, s: u64) -> SystemTime {
+        utc_time(year, month, day, h, m, s).unwrap()
+    }
+
+    fn berlin() -> SiteTimeZone {
+        SiteTimeZone::from_posix("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()
+    }
+
+    #[test]
+    fn fixed_zones_never_change() {
+        let zone = SiteTimeZone::from(-5 * 3600);
+        assert_eq!(zone, SiteTimeZone::fixed(-5 * 3600));
+        for at in [utc(2024, 1, 1, 0, 0, 0), utc(2024, 7, 1, 0, 0, 0)] {
+            assert_eq!(zone.utc_offset_at(at), -5 * 3600);
+            assert!(!zone.is_daylight_saving_at(at));
+        }
+        assert_eq!(SiteTimeZone::default(), SiteTimeZone::UTC);
+        assert_eq!(SiteTimeZone::from_posix("UTC0").unwrap(), SiteTimeZone::UTC);
+        assert_eq!(
+            SiteTimeZone::from_posix("<+0530>-5:30").unwrap(),
+            SiteTimeZone::fixed(5 * 3600 + 1800)
+        );
+        assert!(zone.transitions(2024).is_empty());
+    }
+
+    #[test]
+    fn transitions_fall_on_the_rule_dates() {
+        // The last Sunday of March 2024 was the 31st, of October the 27th
+        let rule = |month, week, weekday| TransitionRule {
+            month,
+            week,
+            weekday,
+            time: 0,
+        };
+        assert_eq!(rule(3, 5, 0).day_in(2024), days_from_civil(2024, 3, 31));
+        assert_eq!(rule(10, 5, 0).day_in(2024), days_from_civil(2024, 10, 27));
+        // The second Sunday of March and first of November 2024
+        assert_eq!(rule(3, 2, 0).day_in(2024), days_from_civil(2024, 3, 10));
+        assert_eq!(rule(11, 1, 0).day_in(2024), days_from_civil(2024, 11, 3));
+        // Week 5 of a month with five Fridays is the fifth, the last Friday of December
+        assert_eq!(rule(12, 5, 5).day_in(2023), days_from_civil(2023, 12, 29));
+    }
+
+    #[test]
+    fn daylight_saving_starts_and_ends_on_the_local_clock() {
+        let zone = berlin();
+        // 02:00 CET on 2024-03-31 is 01:00 UTC
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 31, 0, 59, 59)), 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 31, 1, 0, 0)), 7200);
+        assert!(zone.is_daylight_saving_at(utc(2024, 7, 1, 12, 0, 0)));
+        // 03:00 CEST on 2024-10-27 is also 01:00 UTC
+        assert_eq!(zone.utc_offset_at(utc(2024, 10, 27, 0, 59, 59)), 7200);
+        assert_eq!(zone.utc_offset_at(utc(2024, 10, 27, 1, 0, 0)), 3600);
+        assert!(!zone.is_daylight_saving_at(utc(2024, 12, 1, 12, 0, 0)));
+        assert_eq!(
+            zone.transitions(2024),
+            vec![
+                unix_secs(utc(2024, 3, 31, 1, 0, 0)),
+                unix_secs(utc(2024, 10, 27, 1, 0, 0))
+            ]
+        );
+        assert_eq!(zone.offsets(), vec![3600, 7200]);
+    }
+
+    #[test]
+    fn zones_west_of_utc_use_the_default_time() {
+        let zone = SiteTimeZone::from_posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // 02:00 EST on 2024-03-10 is 07:00 UTC, 02:00 EDT on 2024-11-03 is 06:00 UTC
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 10, 6, 59, 59)), -5 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 10, 7, 0, 0)), -4 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 11, 3, 5, 59, 59)), -4 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 11, 3, 6, 0, 0)), -5 * 3600);
+    }
+
+    #[test]
+    fn southern_daylight_saving_spans_the_new_year() {
+        // Sydney, from the first Sunday of October to the first Sunday of April
+        let zone = SiteTimeZone::from_posix("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(zone.utc_offset_at(utc(2024, 1, 15, 0, 0, 0)), 11 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 6, 15, 0, 0, 0)), 10 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 12, 15, 0, 0, 0)), 11 * 3600);
+        // 03:00 AEDT on 2024-04-07 is 16:00 UTC the day before
+        assert_eq!(zone.utc_offset_at(utc(2024, 4, 6, 15, 59, 59)), 11 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 4, 6, 16, 0, 0)), 10 * 3600);
+        // 02:00 AEST on 2024-10-06 is 16:00 UTC the day before
+        assert_eq!(zone.utc_offset_at(utc(2024, 10, 5, 15, 59, 59)), 10 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 10, 5, 16, 0, 0)), 11 * 3600);
+    }
+
+    #[test]
+    fn explicit_daylight_offsets_and_times_parse() {
+        // Lord Howe Island moves by half an hour
+        let zone = SiteTimeZone::from_posix("<+1030>-10:30<+11>-11,M10.1.0,M4.1.0").unwrap();
+        assert_eq!(zone.offsets(), vec![10 * 3600 + 1800, 11 * 3600]);
+        // Transition times past midnight and negative ones, as in Greenland
+        let zone = SiteTimeZone::from_posix("<-02>2<-01>,M3.5.0/-1,M10.5.0/0").unwrap();
+        assert_eq!(zone.offsets(), vec![-2 * 3600, -3600]);
+        // 2024-03-30 23:00 local is 2024-03-31 01:00 UTC
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 31, 0, 59, 59)), -2 * 3600);
+        assert_eq!(zone.utc_offset_at(utc(2024, 3, 31, 1, 0, 0)), -3600);
+    }
+
+    #[test]
+    fn unreadable_rules_are_rejected() {
+        for bad in [
+            "",
+            "CET",
+            "CE-1",
+            "CET-1CEST",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,J60,J300",
+            "CET-1CEST,M13.5.0,M10.5.0",
+            "CET-1CEST,M3.6.0,M10.5.0",
+            "CET-1CEST,M3.5.7,M10.5.0",
+            "CET-1CEST,M3.5.0,M10.5.0/3x",
+            "CET-200",
+            "<+01-1",
+            "Europe/Berlin",
+        ] {
+            match SiteTimeZone::from_posix(bad) {
+                Err(UnifiError::Validation { field, .. }) => assert_eq!(field, "time_zone"),
+                other => panic!("{bad:?} wasn't rejected: {other:?}"),
+            }
+        }
+    }
+}
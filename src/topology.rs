@@ -0,0 +1,121 @@
+//! Buildings, floors and the doors on them, from the door group topology endpoint.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ts")]
+use ts_rs::TS;
+
+use crate::{UnifiClient, UnifiResult};
+
+/// A building and everything in it, see [UnifiClient::fetch_building_topology]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct Building {
+    pub id: String,
+    pub name: String,
+    pub floors: Vec<Floor>,
+    /// Doors that belong to the building but aren't on any floor
+    pub unassigned_doors: Vec<DoorRef>,
+}
+
+/// A floor within a [Building]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct Floor {
+    pub id: String,
+    pub name: String,
+    pub doors: Vec<DoorRef>,
+}
+
+/// The id and name of a door
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(TS))]
+pub struct DoorRef {
+    pub id: String,
+    pub name: String,
+}
+
+/// A node of the raw topology response, buildings contain floors which contain doors
+#[derive(Debug, Deserialize)]
+struct TopologyNode {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type", default)]
+    node_type: String,
+    /// Child groups, e.g. the floors of a building
+    #[serde(default)]
+    resource_topologies: Vec<TopologyNode>,
+    /// Leaf resources, e.g. the doors on a floor
+    #[serde(default)]
+    resources: Vec<TopologyNode>,
+}
+
+impl TopologyNode {
+    fn doors(&self) -> Vec<DoorRef> {
+        self.resources
+            .iter()
+            .filter(|r| r.node_type == "door")
+            .map(|r| DoorRef {
+                id: r.id.clone(),
+                name: r.name.clone(),
+            })
+            .collect()
+    }
+}
+
+impl UnifiClient {
+    /// Fetches the buildings on the controller with their floors and doors
+    pub async fn fetch_building_topology(&self) -> UnifiResult<Vec<Building>> {
+        let nodes: Option<Vec<TopologyNode>> = self
+            .generic_request_optional(
+                reqwest::Method::GET,
+                self.api_path("door_groups/topology"),
+                None,
+            )
+            .await?;
+        Ok(nodes
+            .unwrap_or_default()
+            .iter()
+            .filter(|n| n.node_type == "building")
+            .map(|building| Building {
+                id: building.id.clone(),
+                name: building.name.clone(),
+                floors: building
+                    .resource_topologies
+                    .iter()
+                    .filter(|n| n.node_type == "floor")
+                    .map(|floor| Floor {
+                        id: floor.id.clone(),
+                        name: floor.name.clone(),
+                        doors: floor.doors(),
+                    })
+                    .collect(),
+                unassigned_doors: building.doors(),
+            })
+            .collect())
+    }
+}
+
+/// The building and floor a door is on, None if it isn't on any floor
+pub fn find_floor_for_door<'a>(
+    topology: &'a [Building],
+    door_id: &str,
+) -> Option<(&'a Building, &'a Floor)> {
+    topology.iter().find_map(|building| {
+        building
+            .floors
+            .iter()
+            .find(|floor| floor.doors.iter().any(|d| d.id == door_id))
+            .map(|floor| (building, floor))
+    })
+}
+
+/// The doors on the floor with the given id, empty if there is no such floor
+pub fn doors_on_floor<'a>(topology: &'a [Building], floor_id: &str) -> &'a [DoorRef] {
+    topology
+        .iter()
+        .flat_map(|building| &building.floors)
+        .find(|floor| floor.id == floor_id)
+        .map(|floor| floor.doors.as_slice())
+        .unwrap_or_default()
+}
@@ -0,0 +1,126 @@
+//! Generating the TypeScript bindings of the data types into one directory.
+//!
+//! Every type is written to `<Name>.ts` in a flat layout, with an `index.ts` re-exporting all of them,
+//! so a frontend can import from the directory and the files can be checked in and compared in CI.
+//! Run `cargo run --bin ts-export -- <dir>` to write them, add `--check` to only compare.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ts_rs::TS;
+
+#[cfg(feature = "schedules")]
+use crate::{
+    AccessEvaluation, AccessReason, DoorUnlockSchedule, EvaluationConfidence, Holiday,
+    HolidayGroup, PolicyEvaluation, PolicyOutcome, Schedule, ScheduleDecision, ScheduleWindow,
+    WeekSchedule,
+};
+use crate::{
+    AccessPolicy, Building, CredentialSummary, DoorGroup, DoorRef, Floor, NewUser, NfcCard,
+    NfcCardRecord, Pagination, PolicyResource, ResolvedPolicy, ResolvedResource, SystemLogTopic,
+    TemporaryGrant, UnifiError, UnifiResult, User, UserStatus, UserUpdate,
+};
+
+/// Adds the bindings of `T` to `files`
+fn add_binding<T: TS + 'static>(files: &mut BTreeMap<String, String>) -> UnifiResult<()> {
+    let contents = T::export_to_string().map_err(|e| {
+        UnifiError::Other(format!(
+            "Failed to generate TypeScript for {}: {e}",
+            T::name()
+        ))
+    })?;
+    files.insert(format!("{}.ts", T::name()), contents);
+    Ok(())
+}
+
+/// The contents of every bindings file by file name, including `index.ts`.
+/// Only the types of enabled features are included, the checked in bindings are made with the default ones.
+pub fn typescript_bindings() -> UnifiResult<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    // Keep in step with the types deriving TS
+    #[cfg(feature = "schedules")]
+    add_binding::<AccessEvaluation>(&mut files)?;
+    add_binding::<AccessPolicy>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<AccessReason>(&mut files)?;
+    add_binding::<Building>(&mut files)?;
+    add_binding::<CredentialSummary>(&mut files)?;
+    add_binding::<DoorGroup>(&mut files)?;
+    add_binding::<DoorRef>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<DoorUnlockSchedule>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<EvaluationConfidence>(&mut files)?;
+    add_binding::<Floor>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<Holiday>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<HolidayGroup>(&mut files)?;
+    add_binding::<NewUser>(&mut files)?;
+    add_binding::<NfcCard>(&mut files)?;
+    add_binding::<NfcCardRecord>(&mut files)?;
+    add_binding::<Pagination>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<PolicyEvaluation>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<PolicyOutcome>(&mut files)?;
+    add_binding::<PolicyResource>(&mut files)?;
+    add_binding::<ResolvedPolicy>(&mut files)?;
+    add_binding::<ResolvedResource>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<Schedule>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<ScheduleDecision>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<ScheduleWindow>(&mut files)?;
+    add_binding::<SystemLogTopic>(&mut files)?;
+    add_binding::<TemporaryGrant>(&mut files)?;
+    add_binding::<User>(&mut files)?;
+    add_binding::<UserStatus>(&mut files)?;
+    add_binding::<UserUpdate>(&mut files)?;
+    #[cfg(feature = "schedules")]
+    add_binding::<WeekSchedule>(&mut files)?;
+    let index = files
+        .keys()
+        .map(|file| {
+            let name = file.trim_end_matches(".ts");
+            format!("export type {{ {name} }} from \"./{name}\";\n")
+        })
+        .collect();
+    files.insert("index.ts".to_string(), index);
+    Ok(files)
+}
+
+/// Writes the bindings into `dir`, creating it if needed. Files of types that no longer exist are left alone.
+pub fn export_typescript(dir: impl AsRef<Path>) -> UnifiResult<()> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    for (file, contents) in typescript_bindings()? {
+        std::fs::write(dir.join(file), contents)?;
+    }
+    Ok(())
+}
+
+/// The files in `dir` that don't match the generated bindings: missing, different, or `.ts` files
+/// no type generates anymore. Empty if `dir` is up to date.
+pub fn stale_typescript(dir: impl AsRef<Path>) -> UnifiResult<Vec<String>> {
+    let dir = dir.as_ref();
+    let expected = typescript_bindings()?;
+    let mut stale = vec![];
+    for (file, contents) in &expected {
+        match std::fs::read_to_string(dir.join(file)) {
+            Ok(existing) if existing == *contents => {}
+            Ok(_) | Err(_) => stale.push(file.clone()),
+        }
+    }
+    if dir.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            let file = entry?.file_name().to_string_lossy().into_owned();
+            if file.ends_with(".ts") && !expected.contains_key(&file) {
+                stale.push(file);
+            }
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
@@ -0,0 +1,202 @@
+//! Local checks of input that the controller is known to reject, so mistakes are caught
+//! before a round trip and with a more useful message than `CODE_PARAMS_INVALID`.
+//!
+//! The rules are deliberately lenient (e.g. any unicode is fine in names), they only exist to
+//! catch obviously broken input. Disable them with [crate::UnifiClient::with_validation].
+
+use crate::{UnifiError, UnifiResult};
+
+fn invalid(field: &str, reason: &str) -> UnifiError {
+    UnifiError::Validation {
+        field: field.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Names must have at least one non whitespace character
+pub(crate) fn validate_name(field: &str, name: &str) -> UnifiResult<()> {
+    if name.trim().is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    Ok(())
+}
+
+/// Emails are optional, but if given must look like `local@domain.tld`
+pub(crate) fn validate_email(field: &str, email: &str) -> UnifiResult<()> {
+    if email.is_empty() {
+        return Ok(());
+    }
+    if email.chars().any(char::is_whitespace) {
+        return Err(invalid(field, "must not contain whitespace"));
+    }
+    match email.split_once('@') {
+        Some((local, domain))
+            if !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.') =>
+        {
+            Ok(())
+        }
+        _ => Err(invalid(
+            field,
+            "must be an email address like name@example.com",
+        )),
+    }
+}
+
+/// Checks a controller address is a bare hostname or IP, optionally followed by `:port`.
+/// Returns the host as it goes in a URL (IPv6 addresses in brackets) and the port if one was given.
+pub(crate) fn parse_host(field: &str, host: &str) -> UnifiResult<(String, Option<u16>)> {
+    let host = host.trim();
+    if host.is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    if host.contains("://") {
+        return Err(invalid(
+            field,
+            "must not include a scheme, e.g. use 192.168.1.1 rather than https://192.168.1.1",
+        ));
+    }
+    if host.contains('/') {
+        return Err(invalid(field, "must not include a path or trailing slash"));
+    }
+    // A bare IPv6 address has colons of its own, so a port can only follow a bracketed one
+    if let Ok(ip) = host.parse::<std::net::Ipv6Addr>() {
+        return Ok((format!("[{ip}]"), None));
+    }
+    let (name, port) = match host.strip_prefix('[') {
+        Some(bracketed) => {
+            let (ip, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| invalid(field, "is missing the closing ] of an IPv6 address"))?;
+            ip.parse::<std::net::Ipv6Addr>()
+                .map_err(|_| invalid(field, "is not a valid IPv6 address"))?;
+            match rest {
+                "" => (format!("[{ip}]"), None),
+                _ => match rest.strip_prefix(':') {
+                    Some(port) => (format!("[{ip}]"), Some(port)),
+                    None => return Err(invalid(field, "has unexpected text after the address")),
+                },
+            }
+        }
+        None => match host.split_once(':') {
+            Some((name, port)) => (name.to_string(), Some(port)),
+            None => (host.to_string(), None),
+        },
+    };
+    if !name.starts_with('[')
+        && (name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+    {
+        return Err(invalid(field, "must be a hostname or IP address"));
+    }
+    let port = port
+        .map(|port| {
+            port.parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| invalid(field, "has an invalid port"))
+        })
+        .transpose()?;
+    Ok((name, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(result: UnifiResult<impl std::fmt::Debug>) -> String {
+        match result {
+            Err(UnifiError::Validation { field, reason }) => {
+                assert_eq!(field, "f");
+                reason
+            }
+            other => panic!("wasn't rejected: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn names_need_a_visible_character() {
+        assert!(validate_name("f", "Zoë 🚪").is_ok());
+        assert_eq!(reason(validate_name("f", "")), "must not be empty");
+        assert_eq!(reason(validate_name("f", " \t\n")), "must not be empty");
+    }
+
+    #[test]
+    fn emails_are_optional_but_must_look_like_one() {
+        assert!(validate_email("f", "").is_ok());
+        assert!(validate_email("f", "a.b+tag@example.co.uk").is_ok());
+        assert_eq!(
+            reason(validate_email("f", "a b@example.com")),
+            "must not contain whitespace"
+        );
+        for email in [
+            "example.com",
+            "@example.com",
+            "a@b@example.com",
+            "a@localhost",
+            "a@.com",
+            "a@example.",
+        ] {
+            assert!(validate_email("f", email).is_err(), "{email}");
+        }
+    }
+
+    #[test]
+    fn hosts_are_bare_with_an_optional_port() {
+        assert_eq!(
+            parse_host("f", " 192.168.1.1 ").unwrap(),
+            ("192.168.1.1".to_string(), None)
+        );
+        assert_eq!(
+            parse_host("f", "unifi.local:12445").unwrap(),
+            ("unifi.local".to_string(), Some(12445))
+        );
+        assert_eq!(
+            parse_host("f", "fe80::1").unwrap(),
+            ("[fe80::1]".to_string(), None)
+        );
+        assert_eq!(
+            parse_host("f", "[fe80::1]:443").unwrap(),
+            ("[fe80::1]".to_string(), Some(443))
+        );
+    }
+
+    #[test]
+    fn hostile_hosts_are_rejected() {
+        assert!(reason(parse_host("f", "https://unifi.local")).contains("scheme"));
+        assert!(reason(parse_host("f", "unifi.local/../admin")).contains("path"));
+        assert!(reason(parse_host("f", "[fe80::1")).contains("closing ]"));
+        assert!(reason(parse_host("f", "[fe80::1]x")).contains("unexpected text"));
+        assert!(reason(parse_host("f", "[not-ipv6]")).contains("IPv6"));
+        for host in [
+            "user@unifi.local",
+            "uni fi.local",
+            "unifi.local?x=1",
+            ":443",
+            "unifi.local#x",
+        ] {
+            assert_eq!(
+                reason(parse_host("f", host)),
+                "must be a hostname or IP address",
+                "{host}"
+            );
+        }
+        for host in [
+            "unifi.local:0",
+            "unifi.local:65536",
+            "unifi.local:",
+            "unifi.local:http",
+        ] {
+            assert_eq!(
+                reason(parse_host("f", host)),
+                "has an invalid port",
+                "{host}"
+            );
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Parsing admin activity events into the admin, the action and its target.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::json;
+use unifi_access::{AdminAction, AdminActionKind, SystemLogEventWrapper};
+
+fn admin_event(event_type: &str, target: serde_json::Value) -> SystemLogEventWrapper {
+    serde_json::from_value(json!({
+        "@timestamp": "2024-01-01T12:00:00Z",
+        "_id": "e1",
+        "_source": {
+            "actor": {"id": "a1", "type": "user", "display_name": "Grace Hopper"},
+            "authentication": {},
+            "event": {"type": event_type, "display_message": "Deleted user"},
+            "target": target
+        }
+    }))
+    .unwrap()
+}
+
+#[test]
+fn parses_the_admin_and_target() {
+    let event = admin_event(
+        "access.user.delete",
+        json!([{"type": "user", "id": "u1", "display_name": "Ada Lovelace"}]),
+    );
+    let action = AdminAction::from_event(&event).unwrap();
+    assert_eq!(action.admin_name, "Grace Hopper");
+    assert_eq!(action.admin_id, "a1");
+    assert_eq!(action.action_kind, AdminActionKind::UserDeleted);
+    assert_eq!(action.target_type, "user");
+    assert_eq!(action.target_name, "Ada Lovelace");
+    assert_eq!(
+        action.timestamp,
+        UNIX_EPOCH + Duration::from_secs(1_704_110_400)
+    );
+    assert_eq!(action.details["display_message"], "Deleted user");
+}
+
+#[test]
+fn maps_event_types() {
+    let fixtures = [
+        ("access.user.create", AdminActionKind::UserCreated),
+        ("access.data.user.add", AdminActionKind::UserCreated),
+        ("access.user.update", AdminActionKind::UserUpdated),
+        ("access.data.user.remove", AdminActionKind::UserDeleted),
+        ("access.policy.create", AdminActionKind::PolicyCreated),
+        ("ACCESS.ACCESS_POLICY.EDIT", AdminActionKind::PolicyUpdated),
+        ("access.policy.delete", AdminActionKind::PolicyDeleted),
+        ("access.schedule.update", AdminActionKind::ScheduleChanged),
+        (
+            "access.door_group.create",
+            AdminActionKind::DoorGroupChanged,
+        ),
+        ("access.nfc_card.assign", AdminActionKind::CredentialChanged),
+        ("access.device.update", AdminActionKind::DeviceChanged),
+        ("access.door.remote_unlock", AdminActionKind::DoorUnlocked),
+        ("access.settings.update", AdminActionKind::SettingsChanged),
+        ("access.admin.login", AdminActionKind::Login),
+    ];
+    for (event_type, expected) in fixtures {
+        assert_eq!(AdminActionKind::parse(event_type), expected, "{event_type}");
+    }
+}
+
+#[test]
+fn keeps_unmatched_types() {
+    assert_eq!(
+        AdminActionKind::parse("access.visitor.invite"),
+        AdminActionKind::Unknown("access.visitor.invite".to_string())
+    );
+    let action = AdminAction::from_event(&admin_event("", json!([]))).unwrap();
+    assert_eq!(action.action_kind, AdminActionKind::Unknown(String::new()));
+    assert_eq!(action.target_type, "");
+}
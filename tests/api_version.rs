@@ -0,0 +1,71 @@
+//! The paths requests are sent to for each API version, read from the dry run plan.
+
+use unifi_access::{ApiVersion, DoorLockRule, UnifiClient};
+
+fn client() -> UnifiClient {
+    UnifiClient::new("127.0.0.1", "token").with_dry_run(true)
+}
+
+/// Paths of a user policy change and a door lock rule change, in that order
+async fn planned_paths(client: UnifiClient) -> Vec<String> {
+    client
+        .assign_access_policies("u1", vec!["p1".to_string()])
+        .await
+        .unwrap();
+    client
+        .set_door_lock_rule("d1", DoorLockRule::KeepLocked)
+        .await
+        .unwrap();
+    client
+        .take_planned_requests()
+        .into_iter()
+        .map(|request| request.path)
+        .collect()
+}
+
+#[tokio::test]
+async fn uses_v1_by_default() {
+    assert_eq!(
+        planned_paths(client()).await,
+        vec![
+            "/api/v1/developer/users/u1/access_policies",
+            "/api/v1/developer/doors/d1/lock_rule",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn uses_the_configured_version() {
+    assert_eq!(
+        planned_paths(client().with_api_version(ApiVersion::V2)).await,
+        vec![
+            "/api/v2/developer/users/u1/access_policies",
+            "/api/v2/developer/doors/d1/lock_rule",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn resource_overrides_win() {
+    let client = client()
+        .with_resource_api_version("users", ApiVersion::V2)
+        .with_api_version(ApiVersion::V1);
+    assert_eq!(
+        planned_paths(client).await,
+        vec![
+            "/api/v2/developer/users/u1/access_policies",
+            "/api/v1/developer/doors/d1/lock_rule",
+        ]
+    );
+
+    let client = self::client()
+        .with_api_version(ApiVersion::V2)
+        .with_resource_api_version("doors", ApiVersion::V1);
+    assert_eq!(
+        planned_paths(client).await,
+        vec![
+            "/api/v2/developer/users/u1/access_policies",
+            "/api/v1/developer/doors/d1/lock_rule",
+        ]
+    );
+}
@@ -0,0 +1,107 @@
+//! BulkExecutor ordering, failure isolation, cancellation and limits.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use unifi_access::{BulkExecutor, BulkProgress, CancellationToken, UnifiError};
+
+/// Sleeps for a time that shrinks with `n`, so operations finish out of order
+async fn slow_identity(n: usize) -> Result<usize, UnifiError> {
+    tokio::time::sleep(Duration::from_millis(5 * (10 - n as u64 % 10))).await;
+    Ok(n)
+}
+
+#[tokio::test]
+async fn keeps_input_order() {
+    let result = BulkExecutor::new().run(0..20, slow_identity).await;
+    let outcomes: Vec<usize> = result.succeeded().map(|(_, n)| *n).collect();
+    assert_eq!(outcomes, (0..20).collect::<Vec<_>>());
+    assert!(result.all_succeeded());
+}
+
+#[tokio::test]
+async fn a_failing_subset_does_not_stop_the_rest() {
+    let progress = Arc::new(Mutex::new(vec![]));
+    let reported = progress.clone();
+    let result = BulkExecutor::new()
+        .on_progress(move |p| reported.lock().unwrap().push(p))
+        .run(0..10, |n| async move {
+            if n % 3 == 0 {
+                Err(UnifiError::Other(format!("operation {n} failed")))
+            } else {
+                Ok(n)
+            }
+        })
+        .await;
+    let failed: Vec<usize> = result.failed().map(|(n, _)| *n).collect();
+    assert_eq!(failed, vec![0, 3, 6, 9]);
+    assert_eq!(result.succeeded().count(), 6);
+    let progress = progress.lock().unwrap();
+    assert_eq!(progress.len(), 10);
+    assert_eq!(
+        progress.last(),
+        Some(&BulkProgress {
+            done: 10,
+            total: 10,
+            failures: 4
+        })
+    );
+}
+
+#[tokio::test]
+async fn respects_the_concurrency_limit() {
+    let running = Arc::new(AtomicUsize::new(0));
+    let max_running = Arc::new(AtomicUsize::new(0));
+    let result = BulkExecutor::new()
+        .concurrency(3)
+        .run(0..12, |n| {
+            let (running, max_running) = (running.clone(), max_running.clone());
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, UnifiError>(n)
+            }
+        })
+        .await;
+    assert!(result.all_succeeded());
+    assert_eq!(max_running.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn stops_starting_operations_once_cancelled() {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    let result = BulkExecutor::new()
+        .concurrency(2)
+        .cancellation(token)
+        .on_progress(move |p| {
+            if p.done == 4 {
+                cancel.cancel();
+            }
+        })
+        .run(0..10, |n| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, UnifiError>(n)
+        })
+        .await;
+    let finished = result.succeeded().count();
+    // The operation running alongside the fourth one still finishes
+    assert!((4..=5).contains(&finished), "{finished} finished");
+    assert_eq!(result.not_started().count(), 10 - finished);
+    assert!(!result.all_succeeded());
+}
+
+#[tokio::test]
+async fn rate_limit_spaces_out_starts() {
+    let start = Instant::now();
+    let result = BulkExecutor::new()
+        .rate_limit(50.0)
+        .run(0..5, |n| async move { Ok::<_, UnifiError>(n) })
+        .await;
+    assert!(result.all_succeeded());
+    // Starts at 0, 20, 40, 60 and 80ms
+    assert!(start.elapsed() >= Duration::from_millis(80));
+}
@@ -0,0 +1,128 @@
+//! Credential providers as spelled by different firmware versions, all must map to the same provider.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::json;
+use unifi_access::{
+    estimate_occupancy, CredentialProvider, DoorOpening, OccupancyConfig, SystemLogEventWrapper,
+};
+
+/// A granted opening of door `d1` at 2024-01-01T12:00:00Z with the given authentication block
+fn opening_event(authentication: serde_json::Value) -> SystemLogEventWrapper {
+    serde_json::from_value(json!({
+        "@timestamp": "2024-01-01T12:00:00Z",
+        "_id": "e1",
+        "_source": {
+            "actor": {"id": "u1", "type": "user"},
+            "authentication": authentication,
+            "event": {"type": "access.door.unlock", "result": "ACCESS"},
+            "target": [{"type": "door", "id": "d1", "display_name": "Front"}]
+        }
+    }))
+    .unwrap()
+}
+
+fn provider_of(authentication: serde_json::Value) -> CredentialProvider {
+    DoorOpening::from_event(&opening_event(authentication))
+        .unwrap()
+        .provider
+}
+
+#[test]
+fn maps_every_known_spelling() {
+    let fixtures = [
+        // Upper case names
+        (
+            json!({"credential_provider": "NFC", "issuer": "04a23bc1"}),
+            CredentialProvider::Nfc,
+        ),
+        (
+            json!({"credential_provider": "PIN_CODE"}),
+            CredentialProvider::Pin,
+        ),
+        (
+            json!({"credential_provider": "TOUCH_PASS"}),
+            CredentialProvider::TouchPass,
+        ),
+        (
+            json!({"credential_provider": "REMOTE_UNLOCK"}),
+            CredentialProvider::RemoteUnlock,
+        ),
+        (
+            json!({"credential_provider": "REX"}),
+            CredentialProvider::ExitButton,
+        ),
+        // Lower case names
+        (
+            json!({"credential_provider": "nfc_card", "issuer": "04a23bc1"}),
+            CredentialProvider::Nfc,
+        ),
+        (
+            json!({"credential_provider": "pin"}),
+            CredentialProvider::Pin,
+        ),
+        (
+            json!({"credential_provider": "touch-pass"}),
+            CredentialProvider::TouchPass,
+        ),
+        (
+            json!({"credential_provider": "remote"}),
+            CredentialProvider::RemoteUnlock,
+        ),
+        (
+            json!({"credential_provider": "exit_button"}),
+            CredentialProvider::ExitButton,
+        ),
+    ];
+    for (authentication, expected) in fixtures {
+        assert_eq!(
+            provider_of(authentication.clone()),
+            expected,
+            "{authentication}"
+        );
+    }
+}
+
+#[test]
+fn keeps_unseen_providers() {
+    assert_eq!(
+        provider_of(json!({"credential_provider": "FACE_ID"})),
+        CredentialProvider::Unknown("FACE_ID".to_string())
+    );
+    assert_eq!(
+        provider_of(json!({})),
+        CredentialProvider::Unknown(String::new())
+    );
+    assert_eq!(
+        opening_event(json!(null)).source.credential_provider(),
+        None
+    );
+}
+
+#[test]
+fn serializes_as_a_string() {
+    assert_eq!(json!(CredentialProvider::TouchPass), json!("touch_pass"));
+    let parsed: CredentialProvider = serde_json::from_value(json!("NFC_CARD")).unwrap();
+    assert_eq!(parsed, CredentialProvider::Nfc);
+}
+
+#[test]
+fn occupancy_groups_openings_by_provider() {
+    let at = UNIX_EPOCH + Duration::from_secs(1_704_110_400);
+    let opening = |provider| DoorOpening {
+        door_id: "d1".to_string(),
+        at,
+        provider,
+    };
+    let openings = [
+        opening(CredentialProvider::Nfc),
+        opening(CredentialProvider::Nfc),
+        opening(CredentialProvider::Pin),
+    ];
+    let config = OccupancyConfig::new(Duration::from_secs(3600)).entrance("hall", "d1");
+    let report = estimate_occupancy(&openings, &config, at..at + Duration::from_secs(3600));
+    let hour = &report.areas["hall"].hours[0];
+    assert_eq!(hour.by_provider[&CredentialProvider::Nfc], 2);
+    assert_eq!(hour.by_provider[&CredentialProvider::Pin], 1);
+    assert_eq!(json!(hour.by_provider), json!({"nfc": 2, "pin": 1}));
+}
@@ -0,0 +1,121 @@
+//! Keeps the deprecation registry, MIGRATION.md and the source in agreement, so the pointers to
+//! replacements can't rot.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use unifi_access::{deprecation, DEPRECATIONS};
+
+/// The text of every source file of the crate
+fn sources() -> String {
+    fn read_dir(dir: &Path, text: &mut String) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                read_dir(&path, text);
+            } else if path.extension().is_some_and(|e| e == "rs") {
+                text.push_str(&fs::read_to_string(&path).unwrap());
+            }
+        }
+    }
+    let mut text = String::new();
+    read_dir(
+        &PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src"),
+        &mut text,
+    );
+    text
+}
+
+/// Whether `symbol`, a `Type` or `Type::method` path, is defined publicly in `sources`
+fn defined(sources: &str, symbol: &str) -> bool {
+    let (type_name, method) = match symbol.split_once("::") {
+        Some((type_name, method)) => (type_name, Some(method)),
+        None => (symbol, None),
+    };
+    let type_defined = [
+        format!("pub struct {type_name} "),
+        format!("pub struct {type_name}<"),
+        format!("pub enum {type_name} "),
+        format!("pub trait {type_name} "),
+    ]
+    .iter()
+    .any(|definition| sources.contains(definition.as_str()));
+    type_defined
+        && method.is_none_or(|method| {
+            sources.contains(&format!("pub fn {method}("))
+                || sources.contains(&format!("pub async fn {method}("))
+        })
+}
+
+#[test]
+fn replacements_exist() {
+    let sources = sources();
+    for entry in DEPRECATIONS {
+        assert!(defined(&sources, entry.symbol), "{} is gone", entry.symbol);
+        assert!(!entry.replacements.is_empty(), "{}", entry.symbol);
+        for replacement in entry.replacements {
+            assert!(
+                defined(&sources, replacement),
+                "{} names missing replacement {replacement}",
+                entry.symbol
+            );
+            assert!(
+                deprecation(replacement).is_none(),
+                "{} is replaced by deprecated {replacement}",
+                entry.symbol
+            );
+        }
+    }
+}
+
+#[test]
+fn every_deprecated_method_is_registered() {
+    let sources = sources();
+    let mut lines = sources.lines();
+    while let Some(line) = lines.next() {
+        if !line.trim_start().starts_with("#[deprecated") {
+            continue;
+        }
+        let signature = lines
+            .by_ref()
+            .find(|l| l.contains("fn "))
+            .expect("#[deprecated] on a function");
+        let name = signature
+            .split("fn ")
+            .nth(1)
+            .and_then(|rest| rest.split(['(', '<']).next())
+            .unwrap();
+        assert!(
+            DEPRECATIONS
+                .iter()
+                .any(|d| d.symbol.ends_with(&format!("::{name}"))),
+            "{name} is #[deprecated] but not in DEPRECATIONS"
+        );
+    }
+}
+
+#[test]
+fn migration_guide_covers_every_entry() {
+    let guide =
+        fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("MIGRATION.md")).unwrap();
+    for entry in DEPRECATIONS {
+        let heading = format!("## `{}`", entry.symbol);
+        let section = guide
+            .split_once(&heading)
+            .unwrap_or_else(|| panic!("MIGRATION.md has no section for {}", entry.symbol))
+            .1;
+        let section = section.split("\n## ").next().unwrap();
+        assert!(
+            section.contains(&format!("Deprecated since {}", entry.since)),
+            "{}",
+            entry.symbol
+        );
+        for replacement in entry.replacements {
+            assert!(
+                section.contains(replacement),
+                "MIGRATION.md section for {} doesn't mention {replacement}",
+                entry.symbol
+            );
+        }
+    }
+}
@@ -0,0 +1,532 @@
+//! The front desk workflows against a fake controller that fails whichever calls a test chooses.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use unifi_access::{
+    EnrollmentOptions, FrontDesk, FrontDeskBackend, MemberRef, NewUser, NfcCard, OnboardOutcome,
+    OnboardStep, ReplaceOutcome, ReplaceStep, Secret, Undo, UnifiError, UnifiResult, User,
+    UserStatus,
+};
+
+const DESK_READER: &str = "desk-reader";
+
+/// Records every call, by name and the id it was made for, and fails the calls named in `failing`
+#[derive(Default)]
+struct FakeController {
+    users: Mutex<Vec<User>>,
+    calls: Mutex<Vec<String>>,
+    failing: Mutex<HashSet<&'static str>>,
+}
+
+impl FakeController {
+    fn with_user(self, user: User) -> FakeController {
+        self.users.lock().unwrap().push(user);
+        self
+    }
+
+    fn failing(self, call: &'static str) -> FakeController {
+        self.failing.lock().unwrap().insert(call);
+        self
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn call(&self, name: &'static str, subject: &str) -> UnifiResult<()> {
+        let entry = if subject.is_empty() {
+            name.to_string()
+        } else {
+            format!("{name} {subject}")
+        };
+        self.calls.lock().unwrap().push(entry);
+        if self.failing.lock().unwrap().contains(name) {
+            return Err(UnifiError::Other(format!("{name} failed")));
+        }
+        Ok(())
+    }
+}
+
+impl FrontDeskBackend for FakeController {
+    fn find_user<'a>(&'a self, member: &'a MemberRef) -> BoxFuture<'a, UnifiResult<User>> {
+        Box::pin(async move {
+            self.call("find_user", "")?;
+            self.users
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|u| match member {
+                    MemberRef::Id(id) => u.id == *id,
+                    MemberRef::Email(email) => u.user_email.eq_ignore_ascii_case(email),
+                })
+                .cloned()
+                .ok_or_else(|| UnifiError::UserNotFound {
+                    user: format!("{member:?}"),
+                })
+        })
+    }
+
+    fn create_user<'a>(&'a self, new_user: &'a NewUser) -> BoxFuture<'a, UnifiResult<User>> {
+        Box::pin(async move {
+            self.call("create_user", &new_user.email)?;
+            let created = User {
+                first_name: new_user.first_name.clone(),
+                last_name: new_user.last_name.clone(),
+                user_email: new_user.email.clone(),
+                ..user("new", &[])
+            };
+            self.users.lock().unwrap().push(created.clone());
+            Ok(created)
+        })
+    }
+
+    fn enroll_card<'a>(
+        &'a self,
+        device_id: &'a str,
+        _options: EnrollmentOptions,
+        timeout: Duration,
+    ) -> BoxFuture<'a, UnifiResult<NfcCard>> {
+        Box::pin(async move {
+            self.call("enroll_card", device_id)
+                .map_err(|_| UnifiError::EnrollmentTimedOut {
+                    device_id: device_id.to_string(),
+                    timeout,
+                })?;
+            Ok(card("scanned"))
+        })
+    }
+
+    fn assign_card<'a>(
+        &'a self,
+        user_id: &'a str,
+        card: &'a NfcCard,
+    ) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move { self.call("assign_card", &format!("{} {user_id}", card.id)) })
+    }
+
+    fn remove_card<'a>(&'a self, card: &'a NfcCard) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move { self.call("remove_card", &card.id) })
+    }
+
+    fn assign_policies<'a>(
+        &'a self,
+        user_id: &'a str,
+        policy_ids: Vec<String>,
+    ) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move {
+            self.call(
+                "assign_policies",
+                &format!("{user_id} {}", policy_ids.join(",")),
+            )
+        })
+    }
+
+    fn remove_policies<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move { self.call("remove_policies", user_id) })
+    }
+
+    fn deactivate_user<'a>(&'a self, user_id: &'a str) -> BoxFuture<'a, UnifiResult<()>> {
+        Box::pin(async move { self.call("deactivate_user", user_id) })
+    }
+}
+
+fn card(id: &str) -> NfcCard {
+    NfcCard {
+        id: id.to_string(),
+        token: Secret::new(format!("{id}-token")),
+    }
+}
+
+fn user(id: &str, cards: &[&str]) -> User {
+    User {
+        id: id.to_string(),
+        first_name: "Ada".to_string(),
+        last_name: "Lovelace".to_string(),
+        nfc_cards: cards.iter().map(|c| card(c)).collect(),
+        employee_number: String::new(),
+        user_email: format!("{id}@example.com"),
+        status: UserStatus::Active,
+        access_policies: None,
+    }
+}
+
+fn member() -> NewUser {
+    NewUser {
+        first_name: "Ada".to_string(),
+        last_name: "Lovelace".to_string(),
+        email: "ada@example.com".to_string(),
+        employee_number: String::new(),
+        allow_duplicate_email: false,
+    }
+}
+
+fn desk(controller: FakeController) -> FrontDesk<FakeController> {
+    FrontDesk::new(controller, DESK_READER).with_policies(vec!["members".to_string()])
+}
+
+/// Onboards a member, expecting it to fail at `step`
+async fn onboard_failing(
+    controller: FakeController,
+    step: OnboardStep,
+) -> (FrontDesk<FakeController>, Vec<(String, bool)>) {
+    let desk = desk(controller);
+    let OnboardOutcome::Failed(failure) = desk.onboard_member(member()).await else {
+        panic!("onboarding should have failed at {step:?}");
+    };
+    assert_eq!(failure.step, step);
+    let undone = failure
+        .undone
+        .iter()
+        .map(|u| {
+            let what = match &u.undo {
+                Undo::RemoveCard(card) => format!("remove_card {}", card.id),
+                Undo::DeactivateUser(id) => format!("deactivate_user {id}"),
+            };
+            (what, u.error.is_none())
+        })
+        .collect();
+    (desk, undone)
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+#[tokio::test]
+async fn onboarding_runs_every_step() {
+    let desk = desk(FakeController::default());
+    let OnboardOutcome::Completed {
+        user,
+        card,
+        policy_ids,
+    } = desk.onboard_member(member()).await
+    else {
+        panic!("onboarding failed");
+    };
+    assert_eq!(user.id, "new");
+    assert_eq!(card.id, "scanned");
+    assert_eq!(policy_ids, strings(&["members"]));
+    assert_eq!(
+        desk.backend().calls(),
+        strings(&[
+            "find_user",
+            "create_user ada@example.com",
+            "enroll_card desk-reader",
+            "assign_card scanned new",
+            "assign_policies new members",
+        ])
+    );
+}
+
+#[tokio::test]
+async fn onboarding_without_policies_skips_assigning_them() {
+    let desk = FrontDesk::new(FakeController::default(), DESK_READER);
+    assert!(matches!(
+        desk.onboard_member(member()).await,
+        OnboardOutcome::Completed { .. }
+    ));
+    assert!(!desk
+        .backend()
+        .calls()
+        .iter()
+        .any(|c| c.starts_with("assign_policies")));
+}
+
+#[tokio::test]
+async fn onboarding_tidies_names_and_email() {
+    let desk = desk(FakeController::default());
+    let OnboardOutcome::Completed { user, .. } = desk
+        .onboard_member(NewUser {
+            first_name: "  ada ".to_string(),
+            last_name: "DE MORGAN".to_string(),
+            email: " Ada@Example.COM".to_string(),
+            employee_number: String::new(),
+            allow_duplicate_email: false,
+        })
+        .await
+    else {
+        panic!("onboarding failed");
+    };
+    assert_eq!(user.first_name, "Ada");
+    assert_eq!(user.last_name, "De Morgan");
+    assert_eq!(user.user_email, "ada@example.com");
+}
+
+#[tokio::test]
+async fn onboarding_a_taken_email_changes_nothing() {
+    let taken = User {
+        user_email: "ada@example.com".to_string(),
+        ..user("existing", &[])
+    };
+    let (desk, undone) = onboard_failing(
+        FakeController::default().with_user(taken),
+        OnboardStep::CreateUser,
+    )
+    .await;
+    assert!(undone.is_empty());
+    assert_eq!(desk.backend().calls(), strings(&["find_user"]));
+}
+
+#[tokio::test]
+async fn an_allowed_duplicate_email_is_onboarded() {
+    let taken = User {
+        user_email: "ada@example.com".to_string(),
+        ..user("existing", &[])
+    };
+    let desk = desk(FakeController::default().with_user(taken));
+    let outcome = desk
+        .onboard_member(member().with_duplicate_email_allowed(true))
+        .await;
+    assert!(matches!(outcome, OnboardOutcome::Completed { .. }));
+}
+
+#[tokio::test]
+async fn failed_email_check_changes_nothing() {
+    let (desk, undone) = onboard_failing(
+        FakeController::default().failing("find_user"),
+        OnboardStep::CreateUser,
+    )
+    .await;
+    assert!(undone.is_empty());
+    assert_eq!(desk.backend().calls(), strings(&["find_user"]));
+}
+
+#[tokio::test]
+async fn failed_creation_has_nothing_to_undo() {
+    let (_, undone) = onboard_failing(
+        FakeController::default().failing("create_user"),
+        OnboardStep::CreateUser,
+    )
+    .await;
+    assert!(undone.is_empty());
+}
+
+#[tokio::test]
+async fn failed_enrollment_deactivates_the_new_user() {
+    let (_, undone) = onboard_failing(
+        FakeController::default().failing("enroll_card"),
+        OnboardStep::EnrollCard,
+    )
+    .await;
+    assert_eq!(undone, vec![("deactivate_user new".to_string(), true)]);
+}
+
+#[tokio::test]
+async fn failed_card_assignment_removes_the_card_then_the_user() {
+    let (_, undone) = onboard_failing(
+        FakeController::default().failing("assign_card"),
+        OnboardStep::AssignCard,
+    )
+    .await;
+    assert_eq!(
+        undone,
+        vec![
+            ("remove_card scanned".to_string(), true),
+            ("deactivate_user new".to_string(), true),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_policy_assignment_removes_the_card_then_the_user() {
+    let (_, undone) = onboard_failing(
+        FakeController::default().failing("assign_policies"),
+        OnboardStep::AssignPolicies,
+    )
+    .await;
+    assert_eq!(
+        undone,
+        vec![
+            ("remove_card scanned".to_string(), true),
+            ("deactivate_user new".to_string(), true),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_failed_undo_is_reported_and_the_rest_still_undone() {
+    let desk = desk(
+        FakeController::default()
+            .failing("assign_policies")
+            .failing("remove_card"),
+    );
+    let OnboardOutcome::Failed(failure) = desk.onboard_member(member()).await else {
+        panic!("onboarding should have failed");
+    };
+    assert!(!failure.fully_undone());
+    assert!(failure.undone[0].error.is_some());
+    assert!(failure.undone[1].error.is_none());
+    assert!(desk
+        .backend()
+        .calls()
+        .contains(&"deactivate_user new".to_string()));
+}
+
+#[tokio::test]
+async fn replacing_removes_old_cards_before_enrolling() {
+    let desk = desk(FakeController::default().with_user(user("u1", &["old1", "old2"])));
+    let ReplaceOutcome::Completed {
+        user_id,
+        removed,
+        card,
+    } = desk
+        .replace_lost_card(&MemberRef::Id("u1".to_string()))
+        .await
+    else {
+        panic!("replacing failed");
+    };
+    assert_eq!(user_id, "u1");
+    assert_eq!(removed.len(), 2);
+    assert_eq!(card.id, "scanned");
+    assert_eq!(
+        desk.backend().calls(),
+        strings(&[
+            "find_user",
+            "remove_card old1",
+            "remove_card old2",
+            "enroll_card desk-reader",
+            "assign_card scanned u1",
+        ])
+    );
+}
+
+#[tokio::test]
+async fn replacing_for_an_unknown_member_changes_nothing() {
+    let desk = desk(FakeController::default());
+    let ReplaceOutcome::Failed { failure, removed } = desk
+        .replace_lost_card(&MemberRef::Email("nobody@example.com".to_string()))
+        .await
+    else {
+        panic!("replacing should have failed");
+    };
+    assert_eq!(failure.step, ReplaceStep::FindUser);
+    assert!(matches!(failure.error, UnifiError::UserNotFound { .. }));
+    assert!(removed.is_empty());
+}
+
+#[tokio::test]
+async fn replacing_doesnt_enroll_while_an_old_card_still_works() {
+    let desk = desk(
+        FakeController::default()
+            .with_user(user("u1", &["old1"]))
+            .failing("remove_card"),
+    );
+    let ReplaceOutcome::Failed { failure, removed } = desk
+        .replace_lost_card(&MemberRef::Id("u1".to_string()))
+        .await
+    else {
+        panic!("replacing should have failed");
+    };
+    assert_eq!(failure.step, ReplaceStep::RemoveOldCards);
+    assert!(removed.is_empty());
+    assert!(!desk
+        .backend()
+        .calls()
+        .iter()
+        .any(|c| c.starts_with("enroll_card")));
+}
+
+#[tokio::test]
+async fn failed_enrollment_keeps_the_old_cards_removed() {
+    let desk = desk(
+        FakeController::default()
+            .with_user(user("u1", &["old1"]))
+            .failing("enroll_card"),
+    );
+    let ReplaceOutcome::Failed { failure, removed } = desk
+        .replace_lost_card(&MemberRef::Id("u1".to_string()))
+        .await
+    else {
+        panic!("replacing should have failed");
+    };
+    assert_eq!(failure.step, ReplaceStep::EnrollCard);
+    assert!(matches!(
+        failure.error,
+        UnifiError::EnrollmentTimedOut { .. }
+    ));
+    assert!(failure.undone.is_empty());
+    assert_eq!(removed.len(), 1);
+}
+
+#[tokio::test]
+async fn failed_assignment_removes_the_new_card() {
+    let desk = desk(
+        FakeController::default()
+            .with_user(user("u1", &["old1"]))
+            .failing("assign_card"),
+    );
+    let ReplaceOutcome::Failed { failure, removed } = desk
+        .replace_lost_card(&MemberRef::Id("u1".to_string()))
+        .await
+    else {
+        panic!("replacing should have failed");
+    };
+    assert_eq!(failure.step, ReplaceStep::AssignCard);
+    assert!(failure.fully_undone());
+    assert!(
+        matches!(&failure.undone[..], [u] if matches!(&u.undo, Undo::RemoveCard(c) if c.id == "scanned"))
+    );
+    assert_eq!(removed.len(), 1);
+}
+
+#[tokio::test]
+async fn offboarding_keeps_cards_with_the_record() {
+    let desk = desk(FakeController::default().with_user(user("u1", &["old1"])));
+    let report = desk
+        .offboard_member(&MemberRef::Id("u1".to_string()), true)
+        .await
+        .unwrap();
+    assert!(report.removed_cards.is_empty());
+    assert_eq!(
+        desk.backend().calls(),
+        strings(&["find_user", "remove_policies u1", "deactivate_user u1"])
+    );
+}
+
+#[tokio::test]
+async fn offboarding_without_the_record_removes_cards() {
+    let desk = desk(FakeController::default().with_user(user("u1", &["old1", "old2"])));
+    let report = desk
+        .offboard_member(&MemberRef::Email("U1@example.com".to_string()), false)
+        .await
+        .unwrap();
+    assert_eq!(report.user_id, "u1");
+    assert_eq!(report.removed_cards.len(), 2);
+    assert!(report.failed_cards.is_empty());
+}
+
+#[tokio::test]
+async fn offboarding_reports_cards_it_couldnt_remove() {
+    let desk = desk(
+        FakeController::default()
+            .with_user(user("u1", &["old1"]))
+            .failing("remove_card"),
+    );
+    let report = desk
+        .offboard_member(&MemberRef::Id("u1".to_string()), false)
+        .await
+        .unwrap();
+    assert!(report.removed_cards.is_empty());
+    assert_eq!(report.failed_cards.len(), 1);
+}
+
+#[tokio::test]
+async fn offboarding_stops_if_policies_cant_be_removed() {
+    let desk = desk(
+        FakeController::default()
+            .with_user(user("u1", &["old1"]))
+            .failing("remove_policies"),
+    );
+    assert!(desk
+        .offboard_member(&MemberRef::Id("u1".to_string()), false)
+        .await
+        .is_err());
+    assert!(!desk
+        .backend()
+        .calls()
+        .iter()
+        .any(|c| c.starts_with("deactivate_user")));
+}
@@ -0,0 +1,288 @@
+//! Checks against a real controller, every test is ignored by default.
+//!
+//! The read-only tests need `UNIFI_TEST_HOST` and `UNIFI_TEST_TOKEN`:
+//!
+//! ```sh
+//! UNIFI_TEST_HOST=192.168.1.1 UNIFI_TEST_TOKEN=... cargo test --test live_controller -- --ignored
+//! ```
+//!
+//! Tests that change the controller also need `UNIFI_TEST_ALLOW_MUTATION=1`. They only touch users they
+//! create, named with [TEST_USER_PREFIX], and remove them afterwards even if an assertion fails.
+//! The enrollment test additionally needs the id of a reader in `UNIFI_TEST_ENROLL_DEVICE`.
+//! Tests whose variables aren't set pass without doing anything, with a note on stderr.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use unifi_access::{
+    DeactivateMode, NewUser, SystemLogOptions, SystemLogTopic, UnifiClient, UnifiError, UserStatus,
+    UserUpdate,
+};
+
+/// First name of every user the tests create, so leftovers are easy to find
+const TEST_USER_PREFIX: &str = "unifi-access-test";
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// The controller to test against, None (with a note) if it isn't configured
+fn read_only_client() -> Option<UnifiClient> {
+    let (Some(host), Some(token)) = (env("UNIFI_TEST_HOST"), env("UNIFI_TEST_TOKEN")) else {
+        eprintln!("UNIFI_TEST_HOST and UNIFI_TEST_TOKEN aren't set, skipping");
+        return None;
+    };
+    Some(UnifiClient::try_new(&host, &token).expect("invalid UNIFI_TEST_HOST or UNIFI_TEST_TOKEN"))
+}
+
+/// The controller, if tests are also allowed to change it
+fn mutating_client() -> Option<UnifiClient> {
+    if env("UNIFI_TEST_ALLOW_MUTATION").is_none() {
+        eprintln!("UNIFI_TEST_ALLOW_MUTATION isn't set, skipping");
+        return None;
+    }
+    read_only_client()
+}
+
+/// Removes the users a test created when dropped, so they are cleaned up even when the test panics.
+///
+/// Drop can't await, and the test's runtime may be the one panicking, so cleanup runs on a thread with
+/// its own runtime and client.
+struct CreatedUsers {
+    ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl CreatedUsers {
+    fn new() -> CreatedUsers {
+        CreatedUsers {
+            ids: Default::default(),
+        }
+    }
+
+    fn push(&self, id: &str) {
+        self.ids.lock().unwrap().push(id.to_string());
+    }
+}
+
+impl Drop for CreatedUsers {
+    fn drop(&mut self) {
+        let ids = std::mem::take(&mut *self.ids.lock().unwrap());
+        if ids.is_empty() {
+            return;
+        }
+        let cleanup = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to start cleanup runtime");
+            let client = read_only_client().expect("controller configuration disappeared");
+            runtime.block_on(remove_test_users(&client, &ids));
+        });
+        if cleanup.join().is_err() {
+            eprintln!(
+                "Cleanup of test users failed, remove users named {TEST_USER_PREFIX} by hand"
+            );
+        }
+    }
+}
+
+/// Strips and deactivates the users, then deletes them where the firmware supports it
+async fn remove_test_users(client: &UnifiClient, ids: &[String]) {
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    let result = client
+        .bulk_deactivate_users(&ids, DeactivateMode::Both)
+        .await
+        .expect("failed to deactivate test users");
+    for (id, e) in result.failed() {
+        eprintln!("Failed to deactivate test user {id}: {e}");
+    }
+    for id in ids {
+        // Not every firmware has a delete endpoint, a deactivated user without policies is harmless
+        let path = format!("/api/v1/developer/users/{id}");
+        if let Err(e) = client
+            .raw_request(reqwest::Method::DELETE, &path, None)
+            .await
+        {
+            eprintln!("Couldn't delete test user {id}, it is left deactivated: {e}");
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn lists_users_devices_and_policies() {
+    let Some(client) = read_only_client() else {
+        return;
+    };
+    client.get_all_users().await.expect("listing users");
+    client.get_devices().await.expect("listing devices");
+    client
+        .get_all_access_policies()
+        .await
+        .expect("listing policies");
+}
+
+#[tokio::test]
+#[ignore]
+async fn small_pages_add_up_to_the_full_user_list() {
+    let Some(client) = read_only_client() else {
+        return;
+    };
+    let all: HashSet<String> = client
+        .get_all_users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|u| u.id)
+        .collect();
+    let mut paged = vec![];
+    let mut page_num = 1;
+    loop {
+        let page = client.get_users_page(page_num, 2).await.unwrap();
+        assert!(page.items.len() <= 2, "page larger than requested");
+        let done = page.items.is_empty()
+            || page
+                .pagination
+                .is_none_or(|p| paged.len() + page.items.len() >= p.total as usize);
+        paged.extend(page.items.into_iter().map(|u| u.id));
+        if done {
+            break;
+        }
+        page_num += 1;
+    }
+    let unique: HashSet<String> = paged.iter().cloned().collect();
+    assert_eq!(unique.len(), paged.len(), "a user was listed on two pages");
+    assert_eq!(unique, all, "paged users differ from the full list");
+}
+
+#[tokio::test]
+#[ignore]
+async fn fetches_one_log_page() {
+    let Some(client) = read_only_client() else {
+        return;
+    };
+    let page = client
+        .fetch_system_log_page(1, SystemLogOptions::new(SystemLogTopic::All).page_size(5))
+        .await
+        .expect("fetching the system log");
+    assert!(page.events.len() <= 5, "page larger than requested");
+}
+
+#[tokio::test]
+#[ignore]
+async fn user_lifecycle() {
+    let Some(client) = mutating_client() else {
+        return;
+    };
+    let created = CreatedUsers::new();
+    let suffix = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let user = client
+        .create_user_full(&NewUser {
+            first_name: TEST_USER_PREFIX.to_string(),
+            last_name: format!("lifecycle-{suffix}"),
+            email: String::new(),
+            employee_number: String::new(),
+            allow_duplicate_email: false,
+        })
+        .await
+        .expect("creating the user");
+    created.push(&user.id);
+
+    let policies = client.get_all_access_policies().await.unwrap();
+    if let Some(policy) = policies.first() {
+        client
+            .assign_access_policies(&user.id, vec![policy.id.clone()])
+            .await
+            .expect("assigning a policy");
+        let assigned = client.get_access_policies_for_user(&user.id).await.unwrap();
+        assert!(assigned.iter().any(|p| p.id == policy.id));
+    } else {
+        eprintln!("No access policies on the controller, skipping assignment");
+    }
+
+    client
+        .update_user(
+            &user.id,
+            &UserUpdate {
+                status: Some(UserStatus::Deactivated),
+                ..Default::default()
+            },
+        )
+        .await
+        .expect("deactivating the user");
+    let fetched = client.get_user_by_id(&user.id).await.unwrap();
+    assert_eq!(fetched.status, UserStatus::Deactivated);
+}
+
+/// Passes on both firmware behaviors: rejecting a duplicate email, or silently accepting it
+#[tokio::test]
+#[ignore]
+async fn duplicate_emails_are_reported_consistently() {
+    let Some(client) = mutating_client() else {
+        return;
+    };
+    let created = CreatedUsers::new();
+    let suffix = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let new_user = NewUser {
+        first_name: TEST_USER_PREFIX.to_string(),
+        last_name: format!("duplicate-{suffix}"),
+        email: format!("{TEST_USER_PREFIX}-{suffix}@example.com"),
+        employee_number: String::new(),
+        allow_duplicate_email: false,
+    };
+    let first = client
+        .create_user(&new_user)
+        .await
+        .expect("creating the user");
+    created.push(&first);
+
+    let checked = client.clone().with_duplicate_email_check(true);
+    match checked.create_user(&new_user).await {
+        Err(UnifiError::EmailAlreadyExists { existing_user_id }) => {
+            assert_eq!(existing_user_id.as_deref(), Some(first.as_str()));
+        }
+        Ok(id) => {
+            created.push(&id);
+            panic!("the duplicate check let a second user with the email through");
+        }
+        Err(e) => panic!("expected EmailAlreadyExists, got {e}"),
+    }
+
+    match client.create_user(&new_user).await {
+        Err(UnifiError::EmailAlreadyExists { existing_user_id }) => {
+            eprintln!("Controller rejects duplicate emails");
+            assert!(existing_user_id.is_none_or(|id| id == first));
+        }
+        Ok(id) => {
+            eprintln!("Controller accepts duplicate emails");
+            created.push(&id);
+        }
+        Err(e) => panic!("expected EmailAlreadyExists or success, got {e}"),
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn enrollment_session_starts_and_ends() {
+    let Some(client) = mutating_client() else {
+        return;
+    };
+    let Some(device_id) = env("UNIFI_TEST_ENROLL_DEVICE") else {
+        eprintln!("UNIFI_TEST_ENROLL_DEVICE isn't set, skipping");
+        return;
+    };
+    let session_id = client
+        .start_nfc_enrollment_session(&device_id)
+        .await
+        .expect("starting an enrollment session");
+    client
+        .end_enrollment_session(&session_id)
+        .await
+        .expect("ending the enrollment session");
+}
@@ -0,0 +1,465 @@
+//! Pins the JSON shape of the public types, as the controller sends them and as they're stored.
+//!
+//! Each fixture is parsed, serialized again and compared with itself, so a renamed field, a changed
+//! enum spelling or a field that stops round-tripping fails here rather than against a controller.
+//! The fixtures are written by hand in the shapes the controller sends. The generated cases at the end
+//! cover values the fixtures don't, such as unicode names.
+
+use std::time::Duration;
+
+use proptest::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use unifi_access::{
+    AccessPolicy, AuditEntry, AuditOutcome, Building, DoorGroup, DoorGroupDiff, NewUser, NfcCard,
+    NfcCardRecord, Pagination, PolicyResource, QueuedMutation, ScheduledUnlock, Secret,
+    SystemLogTopic, TemporaryGrant, User, UserStatus, UserUpdate,
+};
+
+/// Parses `golden`, checks it serializes back to the same JSON and returns the parsed value
+#[track_caller]
+fn round_trip<T: Serialize + DeserializeOwned>(golden: &str) -> T {
+    let expected: Value = serde_json::from_str(golden).unwrap();
+    let parsed: T = serde_json::from_value(expected.clone())
+        .unwrap_or_else(|e| panic!("{e} parsing\n{golden}"));
+    let actual = serde_json::to_value(&parsed).unwrap();
+    assert!(
+        actual == expected,
+        "round trip changed the JSON\nexpected: {}\n  actual: {}",
+        serde_json::to_string_pretty(&expected).unwrap(),
+        serde_json::to_string_pretty(&actual).unwrap()
+    );
+    parsed
+}
+
+const USER: &str = r#"{
+    "id": "u1",
+    "first_name": "Ada",
+    "last_name": "Lovelace",
+    "nfc_cards": [{"id": "100001", "token": "04a23bc1"}],
+    "employee_number": "E1",
+    "user_email": "ada@example.com",
+    "status": "ACTIVE",
+    "access_policies": [
+        {"id": "p1", "name": "Members", "resources": [{"id": "d1", "type": "door"}]}
+    ]
+}"#;
+
+#[test]
+fn user() {
+    let user: User = round_trip(USER);
+    assert_eq!(user.nfc_cards[0].token.expose(), "04a23bc1");
+    assert_eq!(user.status, UserStatus::Active);
+    assert_eq!(
+        user.access_policies.unwrap()[0].resources[0].resource_type,
+        "door"
+    );
+}
+
+#[test]
+fn user_without_policies() {
+    let user: User = round_trip(
+        r#"{
+            "id": "u2",
+            "first_name": "Charles",
+            "last_name": "Babbage",
+            "nfc_cards": [],
+            "employee_number": "",
+            "user_email": "",
+            "status": "DEACTIVATED",
+            "access_policies": null
+        }"#,
+    );
+    assert!(user.access_policies.is_none());
+}
+
+#[test]
+fn user_with_firmware_quirks() {
+    // Nulls for lists and strings, no status, and policies in their expanded spelling
+    let user: User = serde_json::from_value(json!({
+        "id": "u1",
+        "first_name": "Ada",
+        "last_name": "Lovelace",
+        "nfc_cards": null,
+        "employee_number": null,
+        "user_email": "ada@example.com",
+        "access_policies": [{"policy_id": "p1", "policy_name": "Members"}, {"broken": true}]
+    }))
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&user).unwrap(),
+        json!({
+            "id": "u1",
+            "first_name": "Ada",
+            "last_name": "Lovelace",
+            "nfc_cards": [],
+            "employee_number": "",
+            "user_email": "ada@example.com",
+            "status": "UNKNOWN",
+            "access_policies": [{"id": "p1", "name": "Members", "resources": []}]
+        })
+    );
+}
+
+#[test]
+fn user_status_spellings() {
+    for (status, spelling) in [
+        (UserStatus::Active, "ACTIVE"),
+        (UserStatus::Pending, "PENDING"),
+        (UserStatus::Deactivated, "DEACTIVATED"),
+        (UserStatus::Unknown, "UNKNOWN"),
+    ] {
+        assert_eq!(serde_json::to_value(&status).unwrap(), json!(spelling));
+        assert_eq!(round_trip::<UserStatus>(&format!("\"{spelling}\"")), status);
+    }
+    // Statuses from newer firmware aren't an error
+    let status: UserStatus = serde_json::from_str("\"SUSPENDED\"").unwrap();
+    assert_eq!(status, UserStatus::Unknown);
+}
+
+#[test]
+fn new_user() {
+    let user: NewUser = round_trip(
+        r#"{
+            "first_name": "Ada",
+            "last_name": "Lovelace",
+            "email": "ada@example.com",
+            "employee_number": "E1",
+            "allow_duplicate_email": true
+        }"#,
+    );
+    assert!(user.allow_duplicate_email);
+}
+
+#[test]
+fn user_update_sends_only_changed_fields() {
+    let update: UserUpdate =
+        round_trip(r#"{"user_email": "ada@example.org", "status": "DEACTIVATED"}"#);
+    assert_eq!(update.email.as_deref(), Some("ada@example.org"));
+    assert!(update.first_name.is_none());
+    assert_eq!(
+        serde_json::to_value(UserUpdate::default()).unwrap(),
+        json!({})
+    );
+}
+
+#[test]
+fn nfc_card_record() {
+    let assigned: NfcCardRecord =
+        round_trip(r#"{"display_id": "100001", "token": "04a23bc1", "user_id": "u1"}"#);
+    assert_eq!(assigned.token, Secret::new("04a23bc1"));
+    let unassigned: NfcCardRecord =
+        round_trip(r#"{"display_id": "100002", "token": "04a23bc2", "user_id": null}"#);
+    assert!(unassigned.user_id.is_none());
+}
+
+#[test]
+fn secret_is_a_plain_string() {
+    let secret: Secret = round_trip(r#""04a23bc1""#);
+    assert_eq!(secret.expose(), "04a23bc1");
+}
+
+#[test]
+fn pagination() {
+    let pagination: Pagination = round_trip(r#"{"page_num": 2, "page_size": 25, "total": 51}"#);
+    assert_eq!(pagination.total, 51);
+}
+
+#[test]
+fn access_policy() {
+    let policy: AccessPolicy = round_trip(
+        r#"{
+            "id": "p2",
+            "name": "Workshop",
+            "resources": [{"id": "g1", "type": "door_group"}, {"id": "d2", "type": "door"}],
+            "schedule_id": "s1"
+        }"#,
+    );
+    assert_eq!(policy.resources.len(), 2);
+}
+
+#[test]
+fn system_log_topic_spellings() {
+    for (topic, spelling) in [
+        (SystemLogTopic::All, "all"),
+        (SystemLogTopic::DoorOpenings, "door_openings"),
+        (SystemLogTopic::Critical, "critical"),
+        (SystemLogTopic::Updates, "updates"),
+        (SystemLogTopic::DeviceEvents, "device_events"),
+        (SystemLogTopic::AdminActivity, "admin_activity"),
+        (SystemLogTopic::Visitor, "visitor"),
+        (
+            SystemLogTopic::Custom("door_alarms".to_string()),
+            "door_alarms",
+        ),
+    ] {
+        assert_eq!(serde_json::to_value(&topic).unwrap(), json!(spelling));
+        assert_eq!(
+            round_trip::<SystemLogTopic>(&format!("\"{spelling}\"")),
+            topic
+        );
+        assert_eq!(topic.as_str(), spelling);
+    }
+}
+
+#[test]
+fn door_group() {
+    let group: DoorGroup = round_trip(
+        r#"{"id": "g1", "group_name": "Ground floor", "resources": [{"id": "d1", "type": "door"}]}"#,
+    );
+    assert_eq!(group.door_ids().into_iter().collect::<Vec<_>>(), ["d1"]);
+    // Older firmware spells the name differently, it's written back the current way
+    let group: DoorGroup =
+        serde_json::from_value(json!({"id": "g1", "name": "Ground floor"})).unwrap();
+    assert_eq!(
+        serde_json::to_value(&group).unwrap(),
+        json!({"id": "g1", "group_name": "Ground floor", "resources": []})
+    );
+}
+
+#[test]
+fn door_group_diff() {
+    let diff: DoorGroupDiff = round_trip(r#"{"added": ["d2"], "removed": ["d3"]}"#);
+    assert_eq!(diff.added, ["d2"]);
+}
+
+#[test]
+fn building() {
+    let building: Building = round_trip(
+        r#"{
+            "id": "b1",
+            "name": "Main",
+            "floors": [{"id": "f1", "name": "Ground", "doors": [{"id": "d1", "name": "Front"}]}],
+            "unassigned_doors": [{"id": "d9", "name": "Shed"}]
+        }"#,
+    );
+    assert_eq!(building.floors[0].doors[0].name, "Front");
+}
+
+#[test]
+fn temporary_grant() {
+    let grant: TemporaryGrant = round_trip(
+        r#"{
+            "id": "u1-1",
+            "user_id": "u1",
+            "policy_ids": ["p1", "p2"],
+            "granted_at": 1700000000,
+            "expires_at": 1700003600
+        }"#,
+    );
+    assert_eq!(grant.expires_at - grant.granted_at, 3600);
+}
+
+#[test]
+fn queued_mutation() {
+    let mutation: QueuedMutation = round_trip(
+        r#"{
+            "id": "1700000000-0",
+            "operation": "assign_access_policies",
+            "targets": ["u1"],
+            "method": "PUT",
+            "path": "/api/v1/developer/users/u1/access_policies",
+            "body": {"access_policy_ids": ["p1"]},
+            "queued_at": 1700000000
+        }"#,
+    );
+    assert_eq!(mutation.targets, ["u1"]);
+    // Entries without targets leave the field out
+    let untargeted: QueuedMutation = round_trip(
+        r#"{
+            "id": "1700000000-1",
+            "operation": "raw_request",
+            "method": "DELETE",
+            "path": "/api/v1/developer/users/u1",
+            "body": null,
+            "queued_at": 1700000000
+        }"#,
+    );
+    assert!(untargeted.targets.is_empty());
+}
+
+#[test]
+fn scheduled_unlock() {
+    let unlock: ScheduledUnlock = round_trip(
+        r#"{"id": "s1", "door_id": "d1", "starts_at": 1704132000, "ends_at": 1704139200, "applied": true}"#,
+    );
+    assert!(unlock.applied);
+}
+
+#[test]
+fn audit_entries() {
+    let success: AuditEntry = round_trip(
+        r#"{
+            "timestamp": 1700000000,
+            "operation": "assign_access_policies",
+            "targets": ["u1"],
+            "payload": {"access_policy_ids": ["p1"]},
+            "outcome": "Success",
+            "duration": {"secs": 0, "nanos": 120000000},
+            "dry_run": false
+        }"#,
+    );
+    assert!(matches!(success.outcome, AuditOutcome::Success));
+    assert_eq!(success.duration, Duration::from_millis(120));
+    let failure: AuditEntry = round_trip(
+        r#"{
+            "timestamp": 1700000000,
+            "operation": "delete_user",
+            "targets": ["u1"],
+            "payload": null,
+            "outcome": {"Failure": {"error": "user not found"}},
+            "duration": {"secs": 1, "nanos": 0},
+            "dry_run": true
+        }"#,
+    );
+    assert!(
+        matches!(failure.outcome, AuditOutcome::Failure { error } if error == "user not found")
+    );
+}
+
+/// Serializes `value`, parses it back and serializes it again, both JSON documents must match
+fn reserialized<T: Serialize + DeserializeOwned>(value: &T) -> (Value, Value) {
+    let json = serde_json::to_value(value).unwrap();
+    let parsed: T = serde_json::from_value(json.clone()).unwrap();
+    (json, serde_json::to_value(&parsed).unwrap())
+}
+
+fn user_status() -> impl Strategy<Value = UserStatus> {
+    prop_oneof![
+        Just(UserStatus::Active),
+        Just(UserStatus::Pending),
+        Just(UserStatus::Deactivated),
+        Just(UserStatus::Unknown),
+    ]
+}
+
+fn access_policy() -> impl Strategy<Value = AccessPolicy> {
+    (
+        "[a-z0-9-]{1,36}",
+        ".{0,24}",
+        prop::collection::vec(("[a-z0-9-]{1,36}", "door|door_group"), 0..4),
+        "[a-z0-9-]{0,36}",
+    )
+        .prop_map(|(id, name, resources, schedule_id)| AccessPolicy {
+            id,
+            name,
+            resources: resources
+                .into_iter()
+                .map(|(id, resource_type)| PolicyResource { id, resource_type })
+                .collect(),
+            schedule_id,
+        })
+}
+
+fn user() -> impl Strategy<Value = User> {
+    (
+        (
+            "[a-z0-9-]{1,36}",
+            ".{0,24}",
+            ".{0,24}",
+            ".{0,12}",
+            ".{0,32}",
+        ),
+        prop::collection::vec(("[0-9]{6}", "[0-9a-f]{8,14}"), 0..3),
+        user_status(),
+        prop::option::of(prop::collection::vec(access_policy(), 0..3)),
+    )
+        .prop_map(
+            |(
+                (id, first_name, last_name, employee_number, user_email),
+                cards,
+                status,
+                access_policies,
+            )| User {
+                id,
+                first_name,
+                last_name,
+                nfc_cards: cards
+                    .into_iter()
+                    .map(|(id, token)| NfcCard {
+                        id,
+                        token: Secret::new(token),
+                    })
+                    .collect(),
+                employee_number,
+                user_email,
+                status,
+                access_policies,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn generated_users_round_trip(user in user()) {
+        let (json, again) = reserialized(&user);
+        prop_assert_eq!(json, again);
+    }
+
+    #[test]
+    fn generated_user_updates_round_trip(
+        first_name in prop::option::of(".{0,24}"),
+        email in prop::option::of(".{0,32}"),
+        status in prop::option::of(user_status()),
+    ) {
+        let update = UserUpdate {
+            first_name,
+            email,
+            status,
+            ..UserUpdate::default()
+        };
+        let (json, again) = reserialized(&update);
+        prop_assert_eq!(json, again);
+    }
+
+    #[test]
+    fn generated_system_log_topics_round_trip(topic in "[a-z_]{1,20}") {
+        let topic: SystemLogTopic = topic.parse().unwrap();
+        let (json, again) = reserialized(&topic);
+        prop_assert_eq!(&json, &again);
+        prop_assert_eq!(json, json!(topic.as_str()));
+    }
+
+    #[test]
+    fn generated_grants_round_trip(
+        id in "[a-z0-9-]{1,20}",
+        user_id in "[a-z0-9-]{1,36}",
+        policy_ids in prop::collection::vec("[a-z0-9-]{1,36}", 0..4),
+        granted_at in any::<u32>(),
+        lasts in any::<u32>(),
+    ) {
+        let grant = TemporaryGrant {
+            id,
+            user_id,
+            policy_ids,
+            granted_at: granted_at.into(),
+            expires_at: u64::from(granted_at) + u64::from(lasts),
+        };
+        let parsed: TemporaryGrant =
+            serde_json::from_value(serde_json::to_value(&grant).unwrap()).unwrap();
+        prop_assert_eq!(parsed, grant);
+    }
+
+    #[test]
+    fn generated_queued_mutations_round_trip(
+        operation in "[a-z_]{1,24}",
+        targets in prop::collection::vec("[a-z0-9-]{1,36}", 0..3),
+        method in "GET|POST|PUT|DELETE",
+        body in prop::option::of(any::<u32>()),
+        queued_at in any::<u32>(),
+    ) {
+        let mutation = QueuedMutation {
+            id: format!("{queued_at}-0"),
+            path: format!("/api/v1/developer/{operation}"),
+            operation,
+            targets,
+            method,
+            body: body.map(|n| json!({ "n": n })),
+            queued_at: queued_at.into(),
+        };
+        let parsed: QueuedMutation =
+            serde_json::from_value(serde_json::to_value(&mutation).unwrap()).unwrap();
+        prop_assert_eq!(parsed, mutation);
+    }
+}
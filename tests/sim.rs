@@ -0,0 +1,1760 @@
+//! Runs the client against the simulated controller, keeping the two in agreement on the protocol.
+//! Needs the `sim` feature: `cargo test --features sim --test sim`
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use unifi_access::{
+    AccessEvaluation, AccessReason, AdminActionKind, ApiErrorKind, ApiVersion, AuditOutcome,
+    BulkExecutor, CacheTtls, CachedUnifiClient, CancellationToken, ConcurrentEnrollment, Delivery,
+    DoorLockRule, DoorUnlockSchedule, EnrollmentOptions, EvaluationConfidence, GrantRegistry,
+    InMemoryAuditSink, InMemoryGrantRegistry, InMemoryJournal, MetricsRecorder, MutationJournal,
+    NewUser, NfcCard, PolicyOutcome, QueuedUnifiClient, ReplayOutcome, RequestMetrics, ResetUaCard,
+    RestoreAction, RestoreEntry, RestoreObject, RestoreOptions, ScheduleWindow, Secret, SimFault,
+    SimHandle, SimRequest, SimSeed, Simulator, SystemLogOptions, SystemLogTopic, TimeRange,
+    UnifiClient, UnifiError, UserListOptions, UserStatus, UserUpdate, WeekSchedule,
+    DEFAULT_SIM_TOKEN,
+};
+
+const SEED: &str = r#"{
+    "users": [
+        {"id": "u1", "first_name": "Ada", "last_name": "Lovelace", "user_email": "ada@example.com",
+         "access_policy_ids": ["p1"], "nfc_cards": [{"id": "100001", "token": "04a23bc1"}]}
+    ],
+    "access_policies": [
+        {"id": "p1", "name": "Members", "resources": [{"id": "d1", "type": "door"}]},
+        {"id": "p2", "name": "Workshop", "resources": [{"id": "g1", "type": "door_group"}]}
+    ],
+    "devices": [
+        {"id": "reader1", "name": "Front door", "type": "UA-G2-PRO"},
+        {"id": "hub1", "name": "Hub", "type": "UAH"}
+    ],
+    "door_groups": [
+        {"id": "g1", "group_name": "Workshop", "resources": [{"id": "d2", "type": "door"}]}
+    ],
+    "system_log": [
+        {"@timestamp": "2024-01-01T12:00:00Z", "_id": "e1", "topic": "door_openings",
+         "_source": {"actor": {"id": "u1"}, "authentication": {}, "event": {}, "target": []}},
+        {"@timestamp": "2024-01-01T13:00:00Z", "_id": "e2", "topic": "door_openings",
+         "_source": {"actor": {"id": "u2"}, "authentication": {}, "event": {}, "target": []}},
+        {"@timestamp": "2024-01-01T14:00:00Z", "_id": "e3", "topic": "critical",
+         "_source": {"actor": {"id": "u1"}, "authentication": {}, "event": {}, "target": []}}
+    ],
+    "reject_duplicate_emails": true
+}"#;
+
+async fn start() -> SimHandle {
+    Simulator::new(SimSeed::from_json(SEED).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn manages_users_and_policies() {
+    let sim = start().await;
+    let client = sim.client();
+    let id = client
+        .register_user(
+            "Grace".to_string(),
+            "Hopper".to_string(),
+            "grace@example.com".to_string(),
+            "42".to_string(),
+        )
+        .await
+        .unwrap();
+    client
+        .update_user(
+            &id,
+            &UserUpdate {
+                employee_number: Some("43".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let user = client.get_user_by_id(&id).await.unwrap();
+    assert_eq!(user.first_name, "Grace");
+    assert_eq!(user.employee_number, "43");
+
+    client
+        .assign_access_policies(&id, vec!["p2".to_string()])
+        .await
+        .unwrap();
+    let policies = client.get_access_policies_for_user(&id).await.unwrap();
+    assert_eq!(policies.len(), 1);
+    assert_eq!(policies[0].name, "Workshop");
+
+    let users = client
+        .get_all_users_with(UserListOptions::new().page_size(1).expand_access_policies())
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 2);
+    let ada = users.iter().find(|u| u.id == "u1").unwrap();
+    assert_eq!(ada.access_policies.as_ref().unwrap()[0].id, "p1");
+
+    match client
+        .assign_access_policies(&id, vec!["missing".to_string()])
+        .await
+    {
+        Err(e) => assert_eq!(e.kind(), Some(ApiErrorKind::NotFound)),
+        Ok(()) => panic!("assigning an unknown policy succeeded"),
+    }
+}
+
+#[tokio::test]
+async fn reports_duplicate_emails() {
+    let sim = start().await;
+    let result = sim
+        .client()
+        .register_user(
+            "Ada".to_string(),
+            "Byron".to_string(),
+            "ADA@example.com".to_string(),
+            String::new(),
+        )
+        .await;
+    match result {
+        Err(UnifiError::EmailAlreadyExists { existing_user_id }) => {
+            assert_eq!(existing_user_id.as_deref(), Some("u1"))
+        }
+        other => panic!("expected EmailAlreadyExists, got {other:?}"),
+    }
+}
+
+/// A simulator whose firmware silently creates users with an email another user has
+async fn start_allowing_duplicate_emails() -> SimHandle {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    seed["reject_duplicate_emails"] = json!(false);
+    Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+}
+
+/// Number of users in the simulator with the email, compared case insensitively
+fn users_with_email(sim: &SimHandle, email: &str) -> usize {
+    let state = serde_json::to_value(sim.state()).unwrap();
+    state["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|u| {
+            u["user_email"]
+                .as_str()
+                .is_some_and(|e| e.eq_ignore_ascii_case(email))
+        })
+        .count()
+}
+
+fn second_ada() -> NewUser {
+    NewUser {
+        first_name: "Ada".to_string(),
+        last_name: "Byron".to_string(),
+        email: "ADA@example.com".to_string(),
+        employee_number: String::new(),
+        allow_duplicate_email: false,
+    }
+}
+
+#[tokio::test]
+async fn duplicate_email_check_covers_firmware_that_allows_duplicates() {
+    let sim = start_allowing_duplicate_emails().await;
+    let client = sim.client().with_duplicate_email_check(true);
+    match client.create_user(&second_ada()).await {
+        Err(UnifiError::EmailAlreadyExists { existing_user_id }) => {
+            assert_eq!(existing_user_id.as_deref(), Some("u1"))
+        }
+        other => panic!("expected EmailAlreadyExists, got {other:?}"),
+    }
+    // Found by listing the users, nothing was created
+    let requests = sim.take_requests();
+    assert!(requests.iter().all(|r| r.method == "GET"), "{requests:?}");
+    assert_eq!(users_with_email(&sim, "ada@example.com"), 1);
+
+    // Without the check the firmware takes it
+    sim.client().create_user(&second_ada()).await.unwrap();
+    assert_eq!(users_with_email(&sim, "ada@example.com"), 2);
+}
+
+#[tokio::test]
+async fn allowed_duplicate_emails_skip_the_check() {
+    let sim = start_allowing_duplicate_emails().await;
+    let client = sim.client().with_duplicate_email_check(true);
+    let user = second_ada().with_duplicate_email_allowed(true);
+    let id = client.create_user(&user).await.unwrap();
+    assert_ne!(id, "u1");
+    // No lookup, straight to registering
+    let requests = sim.take_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(users_with_email(&sim, "ada@example.com"), 2);
+
+    // Firmware that rejects duplicates still does
+    let sim = start().await;
+    let client = sim.client().with_duplicate_email_check(true);
+    assert!(matches!(
+        client.create_user(&user).await,
+        Err(UnifiError::EmailAlreadyExists { .. })
+    ));
+}
+
+#[tokio::test]
+async fn rejected_emails_keep_the_controller_error_when_the_lookup_fails() {
+    let sim = start().await;
+    // The registration is rejected, then listing the users to find out why fails
+    sim.fail_next(SimFault {
+        code: "CODE_USER_EMAIL_ERROR".to_string(),
+        msg: "email is invalid".to_string(),
+        ..Default::default()
+    });
+    sim.fail_next(SimFault::default());
+    let error = sim.client().create_user(&second_ada()).await.unwrap_err();
+    match &error {
+        UnifiError::Api { code, endpoint, .. } => {
+            assert_eq!(code, "CODE_USER_EMAIL_ERROR");
+            assert!(endpoint.ends_with("/users"), "{endpoint}");
+        }
+        other => panic!("expected the controller's error, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn summarizes_credentials_across_pages() {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    // 250 users and 255 cards, three pages of each
+    seed["users"] = (0..250)
+        .map(|i| {
+            let status = match i % 5 {
+                0 => "DEACTIVATED",
+                1 => "PENDING",
+                _ => "ACTIVE",
+            };
+            let cards = if i % 2 == 0 {
+                json!([{"id": format!("2{i:05}"), "token": format!("{:08x}", 0x1000 + i)}])
+            } else {
+                json!([])
+            };
+            json!({"id": format!("m{i}"), "first_name": "Member", "status": status, "nfc_cards": cards})
+        })
+        .collect();
+    seed["nfc_cards"] = (0..130)
+        .map(|i| {
+            let mut card =
+                json!({"display_id": format!("3{i:05}"), "token": format!("{:08x}", 0x9000 + i)});
+            if i < 20 {
+                card["status"] = json!("disable");
+            }
+            card
+        })
+        .collect();
+    let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let summary = sim.client().credential_summary().await.unwrap();
+    assert_eq!(summary.users_total, 250);
+    assert_eq!(
+        summary.users_by_status,
+        BTreeMap::from([
+            (UserStatus::Active, 150),
+            (UserStatus::Pending, 50),
+            (UserStatus::Deactivated, 50)
+        ])
+    );
+    assert_eq!(summary.nfc_cards_assigned, 125);
+    assert_eq!(summary.users_without_credentials, 125);
+    assert_eq!(
+        summary.nfc_cards_by_status,
+        BTreeMap::from([
+            ("assigned".to_string(), 125),
+            ("unassigned".to_string(), 110),
+            ("disable".to_string(), 20)
+        ])
+    );
+
+    let mut pages: Vec<(String, String)> = sim
+        .take_requests()
+        .into_iter()
+        .map(|r| (r.path, r.query["page_num"].clone()))
+        .collect();
+    pages.sort();
+    let expected: Vec<(String, String)> = ["credentials/nfc_cards/tokens", "users"]
+        .into_iter()
+        .flat_map(|path| {
+            (1..=3).map(move |page| (format!("/api/v1/developer/{path}"), page.to_string()))
+        })
+        .collect();
+    assert_eq!(pages, expected);
+}
+
+#[tokio::test]
+async fn enrollment_only_resets_cards_when_confirmed() {
+    let sim = start().await;
+    let audit = Arc::new(InMemoryAuditSink::default());
+    let client = sim.client().with_audit_sink(audit.clone());
+    let sessions = "/api/v1/developer/credentials/nfc_cards/sessions";
+    let session_started = |requests: Vec<SimRequest>| {
+        requests
+            .into_iter()
+            .find(|r| r.method == "POST" && r.path == sessions)
+            .unwrap()
+    };
+    let audited_start = || {
+        audit
+            .entries()
+            .into_iter()
+            .rev()
+            .find(|e| e.operation == "start_nfc_enrollment_session")
+            .unwrap()
+    };
+
+    // By default a provisioned card is left alone
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default())
+        .await
+        .unwrap();
+    handle.cancel().await.unwrap();
+    assert_request(
+        &session_started(sim.take_requests()),
+        "POST",
+        sessions,
+        &[],
+        json!({"device_id": "reader1", "reset_ua_card": false}),
+    );
+    assert_eq!(
+        audited_start().payload,
+        json!({"device_id": "reader1", "reset_ua_card": false})
+    );
+
+    let reset = ResetUaCard::confirmed("Card moved over from the old site");
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default().with_reset(reset))
+        .await
+        .unwrap();
+    handle.cancel().await.unwrap();
+    assert_request(
+        &session_started(sim.take_requests()),
+        "POST",
+        sessions,
+        &[],
+        json!({"device_id": "reader1", "reset_ua_card": true}),
+    );
+    let entry = audited_start();
+    assert!(matches!(entry.outcome, AuditOutcome::Success));
+    assert_eq!(entry.targets, ["reader1"]);
+    // The reason is only recorded, never sent
+    assert_eq!(
+        entry.payload,
+        json!({
+            "device_id": "reader1",
+            "reset_ua_card": true,
+            "reset_reason": "Card moved over from the old site"
+        })
+    );
+}
+
+#[tokio::test]
+async fn enrolls_assigns_and_removes_a_card() {
+    let sim = start().await;
+    let client = sim.client();
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default())
+        .await
+        .unwrap();
+    let waiting = tokio::spawn({
+        let handle = handle.clone();
+        async move { handle.wait_for_card().await }
+    });
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    sim.scan_card("reader1", "04b1c2d3").unwrap();
+    let card: NfcCard = waiting.await.unwrap().unwrap();
+    assert_eq!(card.token.expose(), "04b1c2d3");
+
+    client.assign_nfc_card("u1", &card).await.unwrap();
+    assert_eq!(
+        client.fetch_nfc_card_user(&card).await.unwrap().as_deref(),
+        Some("u1")
+    );
+    assert_eq!(client.get_all_nfc_cards().await.unwrap().len(), 2);
+
+    client.remove_nfc_card(&card).await.unwrap();
+    let user = client.get_user_by_id("u1").await.unwrap();
+    assert_eq!(user.nfc_cards.len(), 1);
+    assert_eq!(client.get_all_nfc_cards().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn cancelled_enrollment_fails_the_wait() {
+    let sim = start().await;
+    let client = sim.client();
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default())
+        .await
+        .unwrap();
+    let waiting = tokio::spawn({
+        let handle = handle.clone();
+        async move { handle.wait_for_card().await }
+    });
+    handle.cancel().await.unwrap();
+    assert!(waiting.await.unwrap().is_err());
+    assert!(sim.scan_card("reader1", "04b1c2d3").is_err());
+}
+
+#[tokio::test]
+async fn reconciles_door_groups() {
+    let sim = start().await;
+    let client = sim.client();
+    let diff = client
+        .reconcile_door_group("g1", &["d2", "d3"])
+        .await
+        .unwrap();
+    assert_eq!(diff.added, vec!["d3".to_string()]);
+    assert!(diff.removed.is_empty());
+    let group = client.get_door_group("g1").await.unwrap();
+    assert_eq!(group.door_ids().len(), 2);
+}
+
+#[tokio::test]
+async fn filters_the_system_log() {
+    let sim = start().await;
+    let client = sim.client();
+    let openings = client
+        .fetch_system_log_all(SystemLogTopic::DoorOpenings)
+        .await
+        .unwrap();
+    let ids: Vec<&str> = openings.iter().map(|e| e.id.as_str()).collect();
+    assert_eq!(ids, vec!["e2", "e1"]);
+
+    let by_ada = client
+        .fetch_system_log_all(SystemLogOptions::new(SystemLogTopic::All).actor_id("u1"))
+        .await
+        .unwrap();
+    assert_eq!(by_ada.len(), 2);
+
+    sim.push_log_event(json!({
+        "@timestamp": "2024-01-02T09:00:00Z", "_id": "e4", "topic": "door_openings",
+        "_source": {"actor": {"id": "u1"}, "authentication": {}, "event": {}, "target": []}
+    }));
+    let openings = client
+        .fetch_system_log_all(SystemLogTopic::DoorOpenings)
+        .await
+        .unwrap();
+    assert_eq!(openings[0].id, "e4");
+}
+
+#[tokio::test]
+async fn injected_faults_reach_the_client() {
+    let sim = start().await;
+    let client = sim.client();
+
+    sim.fail_next(SimFault {
+        status: 503,
+        ..Default::default()
+    });
+    assert!(matches!(
+        client.get_user_by_id("u1").await,
+        Err(UnifiError::ControllerUnavailable { .. })
+    ));
+    client.get_user_by_id("u1").await.unwrap();
+
+    sim.fail_next(SimFault {
+        code: "CODE_USER_NOT_EXISTS".to_string(),
+        count: 2,
+        ..Default::default()
+    });
+    for _ in 0..2 {
+        let error = client.get_all_access_policies().await.unwrap_err();
+        assert_eq!(error.kind(), Some(ApiErrorKind::NotFound));
+    }
+    assert_eq!(client.get_all_access_policies().await.unwrap().len(), 2);
+
+    sim.fail_next(SimFault {
+        status: 502,
+        body: Some("<html>Bad Gateway</html>".to_string()),
+        ..Default::default()
+    });
+    assert!(client.get_all_access_policies().await.is_err());
+}
+
+#[tokio::test]
+async fn rejects_the_wrong_token() {
+    let sim = start().await;
+    let client = UnifiClient::new("127.0.0.1", "wrong").with_port(sim.addr().port());
+    assert!(client.get_user_by_id("u1").await.is_err());
+}
+
+#[tokio::test]
+async fn identifies_the_owner_of_a_found_card() {
+    let sim = start().await;
+    let client = sim.client();
+    let (card, user) = client.identify_card_owner("100001").await.unwrap().unwrap();
+    assert_eq!(card.token.expose(), "04a23bc1");
+    assert_eq!(user.unwrap().id, "u1");
+
+    let (card, _) = client
+        .identify_card_owner("04:A2:3B:C1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(card.display_id, "100001");
+
+    assert!(client
+        .identify_card_owner("999999")
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(client.search_nfc_cards("1000").await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn applies_and_clears_a_one_time_unlock() {
+    let sim = start().await;
+    let client = sim.client();
+    let start_at = UNIX_EPOCH + Duration::from_secs(1_704_132_000);
+    let window = TimeRange::new(start_at, start_at + Duration::from_secs(2 * 3600)).unwrap();
+    client.schedule_one_time_unlock("d1", window).await.unwrap();
+
+    let report = client
+        .process_scheduled_unlocks_at(start_at - Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert!(report.applied.is_empty());
+    assert_eq!(sim.lock_rule("d1"), None);
+
+    // A run half an hour late unlocks for the rest of the window only
+    let report = client
+        .process_scheduled_unlocks_at(start_at + Duration::from_secs(1800))
+        .await
+        .unwrap();
+    assert_eq!(report.applied.len(), 1);
+    assert_eq!(
+        sim.lock_rule("d1"),
+        Some(json!({"type": "custom", "interval": 90}))
+    );
+    let report = client
+        .process_scheduled_unlocks_at(start_at + Duration::from_secs(1860))
+        .await
+        .unwrap();
+    assert!(report.applied.is_empty());
+
+    let report = client
+        .process_scheduled_unlocks_at(window.end)
+        .await
+        .unwrap();
+    assert_eq!(report.cleared.len(), 1);
+    assert_eq!(sim.lock_rule("d1"), None);
+    assert!(client.list_scheduled_unlocks().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn a_nested_window_keeps_the_longer_one_open() {
+    let sim = start().await;
+    let client = sim.client();
+    let at = |minutes: u64| UNIX_EPOCH + Duration::from_secs(1_704_132_000 + minutes * 60);
+    let long = TimeRange::new(at(0), at(60)).unwrap();
+    let short = TimeRange::new(at(10), at(20)).unwrap();
+    client.schedule_one_time_unlock("d1", long).await.unwrap();
+    let nested = client.schedule_one_time_unlock("d1", short).await.unwrap();
+
+    client.process_scheduled_unlocks_at(at(0)).await.unwrap();
+    assert_eq!(
+        sim.lock_rule("d1"),
+        Some(json!({"type": "custom", "interval": 60}))
+    );
+    // The nested window starting doesn't shorten the unlock to its own end
+    let report = client.process_scheduled_unlocks_at(at(10)).await.unwrap();
+    assert_eq!(report.applied.len(), 1);
+    assert_eq!(
+        sim.lock_rule("d1"),
+        Some(json!({"type": "custom", "interval": 50}))
+    );
+    // Nor does it ending
+    let report = client.process_scheduled_unlocks_at(at(20)).await.unwrap();
+    assert_eq!(report.cleared.len(), 1);
+    assert_eq!(
+        sim.lock_rule("d1"),
+        Some(json!({"type": "custom", "interval": 50}))
+    );
+    let report = client.process_scheduled_unlocks_at(at(60)).await.unwrap();
+    assert_eq!(report.cleared.len(), 1);
+    assert_eq!(sim.lock_rule("d1"), None);
+    assert!(client.list_scheduled_unlocks().await.unwrap().is_empty());
+    assert!(!client.cancel_scheduled_unlock(&nested.id).await.unwrap());
+}
+
+/// The seed with a building, schedules and a second, deactivated user
+async fn start_with_schedules() -> SimHandle {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    seed["topology"] = json!([{"id": "b1", "name": "Main", "type": "building", "resource_topologies": [
+        {"id": "f1", "name": "Ground", "type": "floor", "resources": [
+            {"id": "d1", "name": "Front", "type": "door"},
+            {"id": "d2", "name": "Workshop", "type": "door"},
+            {"id": "d3", "name": "Office", "type": "door"}
+        ]}
+    ]}]);
+    let weekday = json!([{"start_time": "09:00:00", "end_time": "17:00:59"}]);
+    seed["schedules"] = json!([{
+        "id": "s1",
+        "name": "Office hours",
+        "week_schedule": {"monday": weekday, "tuesday": weekday, "wednesday": weekday,
+                          "thursday": weekday, "friday": weekday},
+        "holiday_group": {"id": "h1", "name": "Holidays", "holidays": [
+            {"name": "New year", "start_time": "2024-01-01 00:00:00", "end_time": "2024-01-02 00:00:00"}
+        ]},
+        "holiday_schedule": []
+    }]);
+    // p2's schedule doesn't exist
+    seed["access_policies"][0]["schedule_id"] = json!("s1");
+    seed["access_policies"][1]["schedule_id"] = json!("s2");
+    seed["users"][0]["access_policy_ids"] = json!(["p1", "p2"]);
+    seed["users"].as_array_mut().unwrap().push(json!({
+        "id": "u2", "first_name": "Charles", "last_name": "Babbage", "status": "DEACTIVATED",
+        "access_policy_ids": ["p1"]
+    }));
+    Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+}
+
+fn outcomes(evaluation: &AccessEvaluation) -> Vec<(&str, PolicyOutcome)> {
+    evaluation
+        .policies
+        .iter()
+        .map(|p| (p.policy_id.as_str(), p.outcome.clone()))
+        .collect()
+}
+
+fn matched(policy_id: &str, policy_name: &str) -> AccessReason {
+    AccessReason::PolicyMatched {
+        policy_id: policy_id.to_string(),
+        policy_name: policy_name.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn evaluates_access_against_policies_and_schedules() {
+    let sim = start_with_schedules().await;
+    let client = sim.client();
+    let at = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+    // Monday 2024-01-01, a holiday, and Tuesday 2024-01-02
+    let monday_10 = at(1_704_103_200);
+    let tuesday_10 = at(1_704_189_600);
+    let tuesday_20 = at(1_704_225_600);
+
+    let evaluation = client
+        .evaluate_access("u1", "d1", tuesday_10)
+        .await
+        .unwrap();
+    assert!(evaluation.granted);
+    assert_eq!(evaluation.reason, matched("p1", "Members"));
+    assert_eq!(
+        outcomes(&evaluation),
+        [
+            ("p1", PolicyOutcome::Grants),
+            ("p2", PolicyOutcome::WrongDoor)
+        ]
+    );
+    assert_eq!(
+        evaluation.confidence,
+        EvaluationConfidence::MayHaveOverrides
+    );
+
+    let evaluation = client
+        .evaluate_access("u1", "d1", tuesday_20)
+        .await
+        .unwrap();
+    assert!(!evaluation.granted);
+    assert_eq!(evaluation.reason, AccessReason::NoPolicyMatched);
+    assert_eq!(
+        evaluation.policies[0].outcome,
+        PolicyOutcome::OutsideSchedule
+    );
+
+    let evaluation = client.evaluate_access("u1", "d1", monday_10).await.unwrap();
+    assert!(!evaluation.granted);
+    assert_eq!(
+        evaluation.policies[0].outcome,
+        PolicyOutcome::HolidayExclusion("New year".to_string())
+    );
+
+    // Through the door group of p2, whose schedule can't be read
+    let evaluation = client
+        .evaluate_access("u1", "d2", tuesday_20)
+        .await
+        .unwrap();
+    assert!(evaluation.granted);
+    assert_eq!(evaluation.reason, matched("p2", "Workshop"));
+    assert_eq!(
+        outcomes(&evaluation),
+        [
+            ("p1", PolicyOutcome::WrongDoor),
+            ("p2", PolicyOutcome::ScheduleUnknown)
+        ]
+    );
+    assert_eq!(evaluation.confidence, EvaluationConfidence::ScheduleUnknown);
+
+    let evaluation = client
+        .evaluate_access("u1", "d3", tuesday_10)
+        .await
+        .unwrap();
+    assert!(!evaluation.granted);
+    assert_eq!(
+        outcomes(&evaluation),
+        [
+            ("p1", PolicyOutcome::WrongDoor),
+            ("p2", PolicyOutcome::WrongDoor)
+        ]
+    );
+
+    // 20:00 UTC is 17:00 three hours west, the last minute of the window
+    let west = sim.client().with_site_utc_offset(-3 * 3600);
+    let evaluation = west.evaluate_access("u1", "d1", tuesday_20).await.unwrap();
+    assert_eq!(evaluation.reason, matched("p1", "Members"));
+
+    // The policy would match, the user's status decides
+    let evaluation = client
+        .evaluate_access("u2", "d1", tuesday_10)
+        .await
+        .unwrap();
+    assert!(!evaluation.granted);
+    assert_eq!(
+        evaluation.reason,
+        AccessReason::UserNotActive(UserStatus::Deactivated)
+    );
+    assert_eq!(outcomes(&evaluation), [("p1", PolicyOutcome::Grants)]);
+
+    assert!(matches!(
+        client.evaluate_access("nobody", "d1", tuesday_10).await,
+        Err(UnifiError::UserNotFound { .. })
+    ));
+    assert!(matches!(
+        client.evaluate_access("u1", "d9", tuesday_10).await,
+        Err(UnifiError::Other(_))
+    ));
+}
+
+#[tokio::test]
+async fn door_unlock_schedules_round_trip() {
+    let sim = start().await;
+    let client = sim.client();
+    assert!(client
+        .get_door_unlock_schedule("d1")
+        .await
+        .unwrap()
+        .is_none());
+
+    let opening_hours = vec![ScheduleWindow {
+        start_time: "09:00:00".to_string(),
+        end_time: "17:00:59".to_string(),
+    }];
+    let schedule = DoorUnlockSchedule {
+        week_schedule: WeekSchedule {
+            monday: opening_hours.clone(),
+            saturday: opening_hours,
+            ..Default::default()
+        },
+        first_person_in: true,
+    };
+    client
+        .set_door_unlock_schedule("d1", schedule)
+        .await
+        .unwrap();
+    let req = sim.take_requests().pop().unwrap();
+    assert_eq!(req.method, "PUT");
+    assert_eq!(req.path, "/api/v1/developer/doors/d1/unlock_schedule");
+    let stored = sim.unlock_schedule("d1").unwrap();
+    assert_eq!(stored["first_person_in"], json!(true));
+    assert_eq!(
+        stored["week_schedule"]["monday"],
+        json!([{"start_time": "09:00:00", "end_time": "17:00:59"}])
+    );
+    assert_eq!(stored["week_schedule"]["sunday"], json!([]));
+
+    let read = client
+        .get_door_unlock_schedule("d1")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(read.first_person_in);
+    assert_eq!(read.week_schedule.saturday.len(), 1);
+    assert!(read.week_schedule.tuesday.is_empty());
+    // Monday 2024-01-01 10:00 UTC, and Tuesday
+    let monday_10 = UNIX_EPOCH + Duration::from_secs(1_704_103_200);
+    assert!(read.unlocks_at(monday_10, 0));
+    assert!(!read.unlocks_at(monday_10 + Duration::from_secs(86400), 0));
+    // Other doors are untouched
+    assert!(client
+        .get_door_unlock_schedule("d2")
+        .await
+        .unwrap()
+        .is_none());
+
+    client.clear_door_unlock_schedule("d1").await.unwrap();
+    let req = sim.take_requests().pop().unwrap();
+    assert_eq!(req.method, "DELETE");
+    assert_eq!(sim.unlock_schedule("d1"), None);
+    assert!(client
+        .get_door_unlock_schedule("d1")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn evaluating_now_takes_lock_rules_into_account() {
+    let sim = start_with_schedules().await;
+    let client = sim.client();
+
+    client
+        .set_door_lock_rule("d1", DoorLockRule::KeepLocked)
+        .await
+        .unwrap();
+    let evaluation = client.evaluate_access("u1", "d1", None).await.unwrap();
+    assert!(!evaluation.granted);
+    assert_eq!(evaluation.reason, AccessReason::DoorKeptLocked);
+
+    client
+        .set_door_lock_rule("d1", DoorLockRule::KeepUnlocked)
+        .await
+        .unwrap();
+    // Even for a deactivated user
+    let evaluation = client.evaluate_access("u2", "d1", None).await.unwrap();
+    assert!(evaluation.granted);
+    assert_eq!(evaluation.reason, AccessReason::DoorUnlocked);
+    // Rules only apply now, a given time is decided by the policies
+    let tuesday_10 = UNIX_EPOCH + Duration::from_secs(1_704_189_600);
+    let evaluation = client
+        .evaluate_access("u2", "d1", Some(tuesday_10))
+        .await
+        .unwrap();
+    assert!(!evaluation.granted);
+
+    client
+        .set_door_lock_rule("d1", DoorLockRule::Reset)
+        .await
+        .unwrap();
+    let evaluation = client.evaluate_access("u2", "d1", None).await.unwrap();
+    assert_eq!(
+        evaluation.reason,
+        AccessReason::UserNotActive(UserStatus::Deactivated)
+    );
+}
+
+#[tokio::test]
+async fn drops_a_window_missed_entirely() {
+    let sim = start().await;
+    let client = sim.client();
+    let start_at = UNIX_EPOCH + Duration::from_secs(1_704_132_000);
+    let window = TimeRange::new(start_at, start_at + Duration::from_secs(3600)).unwrap();
+    client.schedule_one_time_unlock("d1", window).await.unwrap();
+    let report = client
+        .process_scheduled_unlocks_at(window.end + Duration::from_secs(60))
+        .await
+        .unwrap();
+    assert_eq!(report.missed.len(), 1);
+    assert_eq!(sim.lock_rule("d1"), None);
+    assert!(client.list_scheduled_unlocks().await.unwrap().is_empty());
+    assert!(TimeRange::new(window.end, window.start).is_err());
+}
+
+#[tokio::test]
+async fn retries_a_failed_unlock() {
+    let sim = start().await;
+    let client = sim.client();
+    let start_at = UNIX_EPOCH + Duration::from_secs(1_704_132_000);
+    let window = TimeRange::new(start_at, start_at + Duration::from_secs(3600)).unwrap();
+    let unlock = client.schedule_one_time_unlock("d1", window).await.unwrap();
+    sim.fail_next(SimFault {
+        status: 503,
+        ..Default::default()
+    });
+    let report = client.process_scheduled_unlocks_at(start_at).await.unwrap();
+    assert_eq!(report.failed.len(), 1);
+    let report = client.process_scheduled_unlocks_at(start_at).await.unwrap();
+    assert_eq!(report.applied.len(), 1);
+    assert!(sim.lock_rule("d1").is_some());
+
+    assert!(client.cancel_scheduled_unlock(&unlock.id).await.unwrap());
+    assert_eq!(sim.lock_rule("d1"), None);
+    assert!(!client.cancel_scheduled_unlock(&unlock.id).await.unwrap());
+}
+
+#[tokio::test]
+async fn fetches_admin_activity_in_a_range() {
+    let sim = start().await;
+    let client = sim.client();
+    for (id, timestamp) in [
+        ("a1", "2024-02-01T10:00:00Z"),
+        ("a2", "2024-03-01T10:00:00Z"),
+    ] {
+        sim.push_log_event(json!({
+            "@timestamp": timestamp, "_id": id, "topic": "admin_activity",
+            "_source": {
+                "actor": {"id": "admin1", "display_name": "Grace Hopper"},
+                "authentication": {},
+                "event": {"type": "access.policy.update"},
+                "target": [{"type": "access_policy", "id": "p1", "display_name": "Members"}]
+            }
+        }));
+    }
+    let since = UNIX_EPOCH + Duration::from_secs(1_706_745_600); // 2024-02-01
+    let actions = client
+        .fetch_admin_activity(since, since + Duration::from_secs(7 * 86_400))
+        .await
+        .unwrap();
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].action_kind, AdminActionKind::PolicyUpdated);
+    assert_eq!(actions[0].target_name, "Members");
+}
+
+#[tokio::test]
+async fn policies_of_a_missing_user_fail_with_user_not_found() {
+    // Firmware that answers with an error code, and firmware that answers with an empty list
+    for empty_for_missing in [false, true] {
+        let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+        seed["empty_policies_for_missing_users"] = json!(empty_for_missing);
+        let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+            .start("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let client = sim.client();
+        let error = client
+            .get_access_policies_for_user("typo")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, UnifiError::UserNotFound { .. }),
+            "{empty_for_missing}: {error:?}"
+        );
+
+        client
+            .remove_all_access_policies_from_user("u1")
+            .await
+            .unwrap();
+        assert!(client
+            .get_access_policies_for_user("u1")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Without the check only the error code gives a missing user away
+        let unchecked = client
+            .with_user_existence_check(false)
+            .get_access_policies_for_user("typo")
+            .await;
+        assert_eq!(unchecked.is_ok(), empty_for_missing, "{unchecked:?}");
+    }
+}
+
+#[tokio::test]
+async fn reports_or_follows_a_moved_endpoint() {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    seed["moved_to_v2"] = json!(["users"]);
+    let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let error = sim.client().get_user_by_id("u1").await.unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            UnifiError::EndpointMoved { resource, to: ApiVersion::V2, .. } if resource == "users"
+        ),
+        "{error:?}"
+    );
+    // Other resources are still served on v1
+    sim.client().get_all_access_policies().await.unwrap();
+
+    let retrying = sim.client().with_moved_endpoint_retry(true);
+    assert_eq!(retrying.get_user_by_id("u1").await.unwrap().id, "u1");
+    let switched = sim
+        .client()
+        .with_resource_api_version("users", ApiVersion::V2);
+    assert_eq!(switched.get_user_by_id("u1").await.unwrap().id, "u1");
+}
+
+#[tokio::test]
+async fn queues_while_unavailable_and_replays_through_the_client() {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    seed["nfc_cards"] = json!([{"display_id": "100002", "token": "04b1c2d3"}]);
+    let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let journal = Arc::new(InMemoryJournal::default());
+    let unavailable = || SimFault {
+        status: 503,
+        ..SimFault::default()
+    };
+    let card = NfcCard {
+        id: "100002".to_string(),
+        token: Secret::new("04:B1:C2:D3"),
+    };
+
+    let queued = QueuedUnifiClient::new(sim.client(), journal.clone());
+    sim.fail_next(unavailable());
+    assert!(matches!(
+        queued
+            .assign_access_policies("u1", vec!["p1".to_string(), "p2".to_string()])
+            .await
+            .unwrap(),
+        Delivery::Queued
+    ));
+    // Tokens only go in the journal when allowed
+    sim.fail_next(unavailable());
+    assert!(queued.assign_nfc_card("u1", &card).await.is_err());
+    let queued =
+        QueuedUnifiClient::new(sim.client(), journal.clone()).allow_card_tokens_in_journal();
+    sim.fail_next(unavailable());
+    assert!(matches!(
+        queued.assign_nfc_card("u1", &card).await.unwrap(),
+        Delivery::Queued
+    ));
+    // Validation runs before anything is sent or queued
+    let invalid = queued
+        .register_user(
+            " ".to_string(),
+            "Hopper".to_string(),
+            "grace@example.com".to_string(),
+            "7".to_string(),
+        )
+        .await;
+    assert!(matches!(invalid, Err(UnifiError::Validation { .. })));
+    sim.fail_next(unavailable());
+    let registration = queued
+        .register_user(
+            "Grace".to_string(),
+            "Hopper".to_string(),
+            "grace@example.com".to_string(),
+            "7".to_string(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(registration, Delivery::Queued));
+
+    let pending = queued.pending().await.unwrap();
+    assert_eq!(pending.len(), 3);
+    assert_eq!(pending[1].targets, ["u1", "100002"]);
+    assert_eq!(pending[1].body, Some(json!({"token": "04b1c2d3"})));
+
+    let report = queued.replay_pending().await.unwrap();
+    assert_eq!(report.remaining, 0);
+    assert!(report
+        .entries
+        .iter()
+        .all(|(_, outcome)| matches!(outcome, ReplayOutcome::Applied)));
+    assert!(queued.pending().await.unwrap().is_empty());
+    let client = sim.client();
+    assert_eq!(
+        client
+            .get_access_policies_for_user("u1")
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        client.get_user_by_id("u1").await.unwrap().nfc_cards.len(),
+        2
+    );
+    assert!(client
+        .get_all_users()
+        .await
+        .unwrap()
+        .iter()
+        .any(|user| user.user_email == "grace@example.com"));
+
+    // A registration that went through despite the timeout isn't made twice
+    journal.append(&pending[2]).await.unwrap();
+    let report = queued.replay_pending().await.unwrap();
+    assert!(matches!(
+        report.entries[0].1,
+        ReplayOutcome::SkippedConflict { .. }
+    ));
+}
+
+#[tokio::test]
+async fn dropping_a_starting_enrollment_frees_the_device() {
+    let sim = start().await;
+    let client = sim.client();
+    sim.set_latency(Duration::from_millis(500));
+    let started = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.start_enrollment("reader1", EnrollmentOptions::default()),
+    )
+    .await;
+    assert!(started.is_err(), "should still be waiting on the simulator");
+    sim.set_latency(Duration::ZERO);
+
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default())
+        .await
+        .unwrap();
+    handle.cancel().await.unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_enrollments_on_one_device() {
+    let sim = start().await;
+    let client = sim.client();
+    sim.set_latency(Duration::from_millis(100));
+    let (first, second) = tokio::join!(
+        client.start_enrollment("reader1", EnrollmentOptions::default()),
+        client.start_enrollment("reader1", EnrollmentOptions::default()),
+    );
+    sim.set_latency(Duration::ZERO);
+    let handle = match (first, second) {
+        (Ok(handle), Err(UnifiError::EnrollmentInProgress { device_id }))
+        | (Err(UnifiError::EnrollmentInProgress { device_id }), Ok(handle)) => {
+            assert_eq!(device_id, "reader1");
+            handle
+        }
+        (first, second) => panic!(
+            "expected one session and one conflict, got {:?} and {:?}",
+            first.err(),
+            second.err()
+        ),
+    };
+
+    // A waiting enrollment starts once the running one has its card
+    let waiting = tokio::spawn({
+        let client = client.clone();
+        async move {
+            let options = EnrollmentOptions {
+                on_conflict: ConcurrentEnrollment::Wait,
+                poll_interval: Some(Duration::from_millis(50)),
+                ..EnrollmentOptions::default()
+            };
+            client.start_enrollment("reader1", options).await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!waiting.is_finished());
+    let scanned = tokio::spawn({
+        let handle = handle.clone();
+        async move { handle.wait_for_card().await }
+    });
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    sim.scan_card("reader1", "04b1c2d3").unwrap();
+    assert_eq!(scanned.await.unwrap().unwrap().token.expose(), "04b1c2d3");
+    let next = waiting.await.unwrap().unwrap();
+    assert_ne!(next.session_id(), handle.session_id());
+    next.cancel().await.unwrap();
+}
+
+/// Counts the requests made through a client
+#[derive(Default)]
+struct RequestCount(std::sync::atomic::AtomicUsize);
+
+impl MetricsRecorder for RequestCount {
+    fn record_request(&self, _request: &RequestMetrics) {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn paging_stops_at_the_total_or_an_empty_page() {
+    // (users, page size, total overstated by, pages expected)
+    let cases: [(usize, u32, usize, usize); 8] = [
+        (4, 2, 0, 2),
+        (5, 2, 0, 3),
+        (3, 2, 0, 2),
+        (2, 2, 0, 1),
+        (1, 2, 0, 1),
+        (0, 2, 0, 1),
+        (3, 2, 2, 3),
+        (4, 2, 1, 3),
+    ];
+    for (users, page_size, overstated_total, pages) in cases {
+        let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+        seed["users"] = (1..=users)
+            .map(|i| json!({ "id": format!("u{i}") }))
+            .collect();
+        seed["overstated_total"] = json!(overstated_total);
+        let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+            .start("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let requests = Arc::new(RequestCount::default());
+        let client = sim.client().with_metrics_recorder(requests.clone());
+        let case = format!("{users} users, {page_size} per page, total +{overstated_total}");
+
+        let all = client.get_all_users_with(page_size).await.unwrap();
+        assert_eq!(all.len(), users, "{case}");
+        assert_eq!(
+            requests.0.swap(0, std::sync::atomic::Ordering::SeqCst),
+            pages,
+            "{case}"
+        );
+
+        let lenient = client.get_all_users_lenient_with(page_size).await.unwrap();
+        assert_eq!(lenient.items.len(), users, "{case}");
+        assert!(lenient.failures.is_empty(), "{case}");
+        assert_eq!(
+            requests.0.load(std::sync::atomic::Ordering::SeqCst),
+            pages,
+            "{case}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn grants_and_revokes_temporary_access() {
+    let sim = start().await;
+    let client = sim.client();
+    let policies = |client: UnifiClient| async move {
+        let mut ids: Vec<String> = client
+            .get_access_policies_for_user("u1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+    let expired = SystemTime::now() - Duration::from_secs(1);
+
+    // p1 was held before the grant, so it isn't the grant's to revoke
+    let grant = client
+        .grant_temporary_access("u1", &["p1", "p2"], expired)
+        .await
+        .unwrap();
+    assert_eq!(grant.policy_ids, ["p2"]);
+    assert_eq!(policies(client.clone()).await, ["p1", "p2"]);
+    let revoked = client.revoke_expired_grants().await.unwrap();
+    assert_eq!(revoked, [grant]);
+    assert_eq!(policies(client.clone()).await, ["p1"]);
+    // Nothing left to revoke, running again changes nothing
+    assert!(client.revoke_expired_grants().await.unwrap().is_empty());
+    assert_eq!(policies(client.clone()).await, ["p1"]);
+}
+
+#[tokio::test]
+async fn an_expired_grant_keeps_a_policy_another_grant_covers() {
+    let sim = start().await;
+    let registry = Arc::new(InMemoryGrantRegistry::default());
+    let client = sim.client().with_grant_registry(registry.clone());
+    let long = client
+        .grant_temporary_access("u1", &["p2"], SystemTime::now() + Duration::from_secs(3600))
+        .await
+        .unwrap();
+    let short = client
+        .grant_temporary_access("u1", &["p2"], SystemTime::now() - Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(short.policy_ids, ["p2"]);
+
+    assert_eq!(client.revoke_expired_grants().await.unwrap(), [short]);
+    let held = client.get_access_policies_for_user("u1").await.unwrap();
+    assert!(held.iter().any(|p| p.id == "p2"));
+    assert!(client.revoke_expired_grants().await.unwrap().is_empty());
+    assert_eq!(registry.grants().await.unwrap(), [long]);
+}
+
+/// A cached client over the simulator, with the requests it makes counted
+async fn cached_client(sim: &SimHandle) -> (CachedUnifiClient, Arc<RequestCount>) {
+    let requests = Arc::new(RequestCount::default());
+    let client = sim.client().with_metrics_recorder(requests.clone());
+    (
+        CachedUnifiClient::new(client, CacheTtls::default()),
+        requests,
+    )
+}
+
+fn take(requests: &RequestCount) -> usize {
+    requests.0.swap(0, std::sync::atomic::Ordering::SeqCst)
+}
+
+#[tokio::test(start_paused = true)]
+async fn cache_serves_reads_until_the_ttl_expires() {
+    let sim = start().await;
+    let (cached, requests) = cached_client(&sim).await;
+    assert_eq!(cached.get_all_users().await.unwrap().len(), 1);
+    assert_eq!(cached.get_all_users().await.unwrap().len(), 1);
+    assert_eq!(take(&requests), 1);
+
+    // Made behind the cache's back, so only seen once the entry expires
+    sim.client()
+        .register_user(
+            "Grace".to_string(),
+            "Hopper".to_string(),
+            "grace@example.com".to_string(),
+            "7".to_string(),
+        )
+        .await
+        .unwrap();
+    tokio::time::advance(CacheTtls::default().users - Duration::from_secs(1)).await;
+    assert_eq!(cached.get_all_users().await.unwrap().len(), 1);
+    assert_eq!(take(&requests), 0);
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(cached.get_all_users().await.unwrap().len(), 2);
+    assert_eq!(take(&requests), 1);
+
+    cached.invalidate_all().await;
+    cached.get_all_users().await.unwrap();
+    assert_eq!(take(&requests), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cache_is_invalidated_by_mutations_through_it() {
+    let sim = start().await;
+    let (cached, requests) = cached_client(&sim).await;
+    assert_eq!(
+        cached
+            .get_access_policies_for_user("u1")
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    cached.get_user_by_id("u1").await.unwrap();
+    cached.get_user_by_id("u1").await.unwrap();
+    let fetched = take(&requests);
+
+    cached
+        .assign_access_policies("u1", vec!["p1".to_string(), "p2".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(take(&requests), 1);
+    assert_eq!(
+        cached
+            .get_access_policies_for_user("u1")
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    cached.get_user_by_id("u1").await.unwrap();
+    assert_eq!(take(&requests), fetched);
+
+    let card = NfcCard {
+        id: "100001".to_string(),
+        token: Secret::new("04a23bc1"),
+    };
+    cached.remove_nfc_card(&card).await.unwrap();
+    take(&requests);
+    assert!(cached
+        .get_user_by_id("u1")
+        .await
+        .unwrap()
+        .nfc_cards
+        .is_empty());
+    assert_eq!(take(&requests), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn cache_coalesces_concurrent_fetches() {
+    let sim = start().await;
+    let (cached, requests) = cached_client(&sim).await;
+    sim.set_latency(Duration::from_millis(500));
+    let (a, b, c) = tokio::join!(
+        cached.get_all_access_policies(),
+        cached.get_all_access_policies(),
+        cached.get_all_access_policies(),
+    );
+    assert_eq!(a.unwrap().len(), 2);
+    assert_eq!(b.unwrap().len(), 2);
+    assert_eq!(c.unwrap().len(), 2);
+    assert_eq!(take(&requests), 1);
+}
+
+/// Checks the method, path, query and body of a request the simulator received, and that it was authorized
+#[track_caller]
+fn assert_request(
+    request: &SimRequest,
+    method: &str,
+    path: &str,
+    query: &[(&str, &str)],
+    body: serde_json::Value,
+) {
+    assert_eq!(request.method, method, "{request:?}");
+    assert_eq!(request.path, path, "{request:?}");
+    let query: HashMap<String, String> = query
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    assert_eq!(request.query, query, "{request:?}");
+    assert_eq!(request.body, body, "{request:?}");
+    assert_eq!(
+        request.authorization,
+        Some(format!("Bearer {DEFAULT_SIM_TOKEN}")),
+        "{request:?}"
+    );
+}
+
+#[tokio::test]
+async fn users_list_request_and_response_shape() {
+    let mut seed: serde_json::Value = serde_json::from_str(SEED).unwrap();
+    seed["users"].as_array_mut().unwrap().push(
+        json!({"id": "u2", "first_name": "Grace", "last_name": "Hopper", "status": "DEACTIVATED"}),
+    );
+    let sim = Simulator::new(serde_json::from_value(seed).unwrap())
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let users = sim
+        .client()
+        .get_all_users_with(UserListOptions::new().page_size(1).expand_access_policies())
+        .await
+        .unwrap();
+
+    let requests = sim.take_requests();
+    assert_eq!(requests.len(), 2);
+    for (request, page) in requests.iter().zip(["1", "2"]) {
+        assert_request(
+            request,
+            "GET",
+            "/api/v1/developer/users",
+            &[
+                ("page_num", page),
+                ("page_size", "1"),
+                ("expand[]", "access_policy"),
+            ],
+            serde_json::Value::Null,
+        );
+    }
+    assert_eq!(users.len(), 2);
+    let ada = &users[0];
+    assert_eq!(
+        (
+            ada.id.as_str(),
+            ada.first_name.as_str(),
+            ada.last_name.as_str()
+        ),
+        ("u1", "Ada", "Lovelace")
+    );
+    assert_eq!(ada.user_email, "ada@example.com");
+    assert_eq!(ada.status, UserStatus::Active);
+    assert_eq!(ada.nfc_cards[0].id, "100001");
+    assert_eq!(ada.nfc_cards[0].token.expose(), "04a23bc1");
+    let policies = ada.access_policies.as_ref().unwrap();
+    assert_eq!(
+        (policies[0].id.as_str(), policies[0].name.as_str()),
+        ("p1", "Members")
+    );
+    assert_eq!(policies[0].resources[0].id, "d1");
+    assert_eq!(policies[0].resources[0].resource_type, "door");
+    assert_eq!(users[1].status, UserStatus::Deactivated);
+    assert!(users[1].nfc_cards.is_empty());
+}
+
+#[tokio::test]
+async fn user_crud_request_and_response_shape() {
+    let sim = start().await;
+    let client = sim.client();
+    let id = client
+        .register_user(
+            "Grace".to_string(),
+            "Hopper".to_string(),
+            "grace@example.com".to_string(),
+            "7".to_string(),
+        )
+        .await
+        .unwrap();
+    let mut requests = sim.take_requests();
+    let onboard_time = requests[0]
+        .body
+        .as_object_mut()
+        .unwrap()
+        .remove("onboard_time");
+    assert!(onboard_time.is_some_and(|time| time.is_u64()));
+    assert_request(
+        &requests[0],
+        "POST",
+        "/api/v1/developer/users",
+        &[],
+        json!({"first_name": "Grace", "last_name": "Hopper", "user_email": "grace@example.com", "employee_number": "7"}),
+    );
+    assert_eq!(requests.len(), 1);
+
+    client
+        .update_user(
+            &id,
+            &UserUpdate {
+                last_name: Some("Murray Hopper".to_string()),
+                status: Some(UserStatus::Deactivated),
+                ..UserUpdate::default()
+            },
+        )
+        .await
+        .unwrap();
+    let user = client.get_user_by_id(&id).await.unwrap();
+    let requests = sim.take_requests();
+    let path = format!("/api/v1/developer/users/{id}");
+    assert_request(
+        &requests[0],
+        "PUT",
+        &path,
+        &[],
+        json!({"last_name": "Murray Hopper", "status": "DEACTIVATED"}),
+    );
+    assert_request(&requests[1], "GET", &path, &[], serde_json::Value::Null);
+    assert_eq!(user.id, id);
+    assert_eq!(user.last_name, "Murray Hopper");
+    assert_eq!(user.employee_number, "7");
+    assert_eq!(user.status, UserStatus::Deactivated);
+}
+
+#[tokio::test]
+async fn policy_assignment_request_and_response_shape() {
+    let sim = start().await;
+    let client = sim.client();
+    client
+        .assign_access_policies("u1", vec!["p1".to_string(), "p2".to_string()])
+        .await
+        .unwrap();
+    let policies = client.get_access_policies_for_user("u1").await.unwrap();
+    let requests = sim.take_requests();
+    let path = "/api/v1/developer/users/u1/access_policies";
+    assert_request(
+        &requests[0],
+        "PUT",
+        path,
+        &[],
+        json!({"access_policy_ids": ["p1", "p2"]}),
+    );
+    assert_request(&requests[1], "GET", path, &[], serde_json::Value::Null);
+    assert_eq!(requests.len(), 2);
+    assert_eq!(policies[1].id, "p2");
+    assert_eq!(policies[1].name, "Workshop");
+    assert_eq!(policies[1].resources[0].resource_type, "door_group");
+}
+
+#[tokio::test]
+async fn enrollment_session_request_and_response_shape() {
+    let sim = start().await;
+    let client = sim.client();
+    let session_id = client
+        .start_nfc_enrollment_session("reader1")
+        .await
+        .unwrap();
+    assert!(client
+        .get_nfc_enrollment_session_status(&session_id)
+        .await
+        .unwrap()
+        .is_none());
+    sim.scan_card("reader1", "04b1c2d3").unwrap();
+    let card = client
+        .get_nfc_enrollment_session_status(&session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    client.end_enrollment_session(&session_id).await.unwrap();
+
+    let requests = sim.take_requests();
+    let sessions = "/api/v1/developer/credentials/nfc_cards/sessions";
+    let session = format!("{sessions}/{session_id}");
+    assert_request(
+        &requests[0],
+        "POST",
+        sessions,
+        &[],
+        json!({"device_id": "reader1", "reset_ua_card": false}),
+    );
+    assert_request(&requests[1], "GET", &session, &[], serde_json::Value::Null);
+    assert_request(&requests[2], "GET", &session, &[], serde_json::Value::Null);
+    assert_request(
+        &requests[3],
+        "DELETE",
+        &session,
+        &[],
+        serde_json::Value::Null,
+    );
+    assert_eq!(requests.len(), 4);
+    assert_eq!(card.token.expose(), "04b1c2d3");
+    assert!(!card.id.is_empty());
+}
+
+#[tokio::test]
+async fn card_deletion_request_shape() {
+    let sim = start().await;
+    let card = NfcCard {
+        id: "100001".to_string(),
+        token: Secret::new("04:A2:3B:C1"),
+    };
+    sim.client().remove_nfc_card(&card).await.unwrap();
+
+    let requests = sim.take_requests();
+    let token = "/api/v1/developer/credentials/nfc_cards/tokens/04a23bc1";
+    assert_request(&requests[0], "GET", token, &[], serde_json::Value::Null);
+    assert_request(
+        &requests[1],
+        "PUT",
+        "/api/v1/developer/users/u1/nfc_cards/delete",
+        &[],
+        json!({"token": "04a23bc1"}),
+    );
+    assert_request(&requests[2], "DELETE", token, &[], serde_json::Value::Null);
+    assert_eq!(requests.len(), 3);
+}
+
+#[tokio::test]
+async fn lock_rule_request_shapes() {
+    let sim = start().await;
+    let client = sim.client();
+    let rules = [
+        (DoorLockRule::KeepLocked, json!({"type": "keep_lock"})),
+        (DoorLockRule::KeepUnlocked, json!({"type": "keep_unlock"})),
+        // Whole minutes, rounded up, at least one
+        (
+            DoorLockRule::Custom(Duration::from_secs(90)),
+            json!({"type": "custom", "interval": 2}),
+        ),
+        (
+            DoorLockRule::Custom(Duration::from_secs(5)),
+            json!({"type": "custom", "interval": 1}),
+        ),
+        (DoorLockRule::LockEarly, json!({"type": "lock_early"})),
+        (DoorLockRule::Reset, json!({"type": "reset"})),
+    ];
+    for (rule, body) in rules {
+        client.set_door_lock_rule("d1", rule).await.unwrap();
+        let requests = sim.take_requests();
+        assert_request(
+            &requests[0],
+            "PUT",
+            "/api/v1/developer/doors/d1/lock_rule",
+            &[],
+            body,
+        );
+        assert_eq!(requests.len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn system_log_request_and_response_shape() {
+    let sim = start().await;
+    let since = UNIX_EPOCH + Duration::from_secs(1_704_110_000);
+    let events = sim
+        .client()
+        .fetch_system_log(SystemLogOptions::new(SystemLogTopic::DoorOpenings).since(since))
+        .await
+        .unwrap();
+
+    let requests = sim.take_requests();
+    assert_eq!(requests.len(), 1);
+    assert_request(
+        &requests[0],
+        "POST",
+        "/api/v1/developer/system/logs",
+        &[],
+        json!({"topic": "door_openings", "since": 1_704_110_000}),
+    );
+    // Newest first
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].id, "e2");
+    assert_eq!(events[0].timestamp, "2024-01-01T13:00:00Z");
+    assert_eq!(events[0].source.actor["id"], "u2");
+    assert_eq!(events[1].id, "e1");
+}
+
+#[tokio::test]
+async fn policy_assignments_come_from_one_expanded_users_list() {
+    let sim = start().await;
+    let assignments = sim.client().get_policy_assignments(false).await.unwrap();
+
+    let requests = sim.take_requests();
+    assert_eq!(requests.len(), 1);
+    assert_request(
+        &requests[0],
+        "GET",
+        "/api/v1/developer/users",
+        &[
+            ("page_num", "1"),
+            ("page_size", "100"),
+            ("expand[]", "access_policy"),
+        ],
+        serde_json::Value::Null,
+    );
+    assert_eq!(
+        assignments,
+        HashMap::from([("p1".to_string(), vec!["u1".to_string()])])
+    );
+    let unused = sim.client().get_unused_policies(false).await.unwrap();
+    assert_eq!(
+        unused.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+        ["p2"]
+    );
+}
+
+#[tokio::test]
+async fn restores_snapshot_users_on_the_bulk_executor() {
+    let sim = start().await;
+    let client = sim.client();
+    let mut snapshot = client.export_snapshot().await.unwrap();
+    snapshot.users.push(
+        serde_json::from_value(json!({
+            "id": "gone", "first_name": "Grace", "last_name": "Hopper",
+            "user_email": "grace@example.com", "access_policies": [{"id": "p2", "name": "Workshop"}]
+        }))
+        .unwrap(),
+    );
+    let options = RestoreOptions::default();
+    let user_actions = |entries: Vec<RestoreEntry>| -> Vec<(String, RestoreAction)> {
+        entries
+            .into_iter()
+            .filter_map(|entry| match entry.object {
+                RestoreObject::User { key } => Some((key, entry.action)),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let cancellation = CancellationToken::new();
+    cancellation.cancel();
+    let report = client
+        .restore_snapshot_with(
+            &snapshot,
+            &options,
+            &BulkExecutor::new().cancellation(cancellation),
+        )
+        .await
+        .unwrap();
+    let actions = user_actions(report.entries);
+    assert_eq!(actions.len(), 2);
+    for (_, action) in &actions {
+        assert!(
+            matches!(action, RestoreAction::Skipped { reason } if reason.contains("cancelled")),
+            "{action:?}"
+        );
+    }
+
+    let report = client.restore_snapshot(&snapshot, &options).await.unwrap();
+    let actions = user_actions(report.entries);
+    assert_eq!(actions[0].0, "ada@example.com");
+    assert!(
+        matches!(actions[0].1, RestoreAction::Unchanged),
+        "{actions:?}"
+    );
+    assert_eq!(actions[1].0, "grace@example.com");
+    let RestoreAction::Created { id } = &actions[1].1 else {
+        panic!("{actions:?}");
+    };
+    let grace = client.get_user_by_id(id).await.unwrap();
+    assert_eq!(grace.user_email, "grace@example.com");
+    let policies = client.get_access_policies_for_user(id).await.unwrap();
+    assert_eq!(policies[0].id, "p2");
+}
+
+#[tokio::test]
+async fn bulk_requests_respect_the_concurrency_limit_at_the_controller() {
+    let sim = start().await;
+    let client = sim.client();
+    sim.set_latency(Duration::from_millis(50));
+    let result = BulkExecutor::new()
+        .concurrency(3)
+        .run(0..12, |_| client.get_user_by_id("u1"))
+        .await;
+    sim.set_latency(Duration::ZERO);
+
+    assert!(result.all_succeeded());
+    assert_eq!(sim.take_requests().len(), 12);
+    assert_eq!(sim.take_max_in_flight(), 3);
+}
@@ -0,0 +1,99 @@
+//! Captures everything the client logs at trace level through an enrollment flow and checks that no card
+//! token leaks into the logs or metric labels.
+//! Needs the `sim` feature: `cargo test --features sim --test sim_logging`
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use unifi_access::{
+    EnrollmentOptions, MetricsRecorder, NfcCard, RequestMetrics, SimSeed, Simulator,
+};
+
+/// All letters, so it would pass for a resource name in a path
+const TOKEN: &str = "abcdefab";
+
+static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // The simulator's own logs describe the controller side, which knows the token anyway
+        metadata.target().starts_with("unifi_access")
+            && !metadata.target().starts_with("unifi_access::sim")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            LINES
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", record.target(), record.args()));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[derive(Default)]
+struct Endpoints(Mutex<Vec<String>>);
+
+impl MetricsRecorder for Endpoints {
+    fn record_request(&self, request: &RequestMetrics) {
+        self.0.lock().unwrap().push(request.endpoint.to_string());
+    }
+}
+
+#[tokio::test]
+async fn enrollment_flow_logs_no_token() {
+    log::set_logger(&CaptureLogger).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let seed = SimSeed::from_json(
+        r#"{
+            "users": [{"id": "u1", "first_name": "Ada", "last_name": "Lovelace", "user_email": "ada@example.com"}],
+            "devices": [{"id": "reader1", "name": "Front door", "type": "UA-G2-PRO"}]
+        }"#,
+    )
+    .unwrap();
+    let sim = Simulator::new(seed)
+        .start("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let endpoints = Arc::new(Endpoints::default());
+    let client = sim.client().with_metrics_recorder(endpoints.clone());
+
+    let handle = client
+        .start_enrollment("reader1", EnrollmentOptions::default())
+        .await
+        .unwrap();
+    let waiting = tokio::spawn({
+        let handle = handle.clone();
+        async move { handle.wait_for_card().await }
+    });
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    sim.scan_card("reader1", TOKEN).unwrap();
+    let card: NfcCard = waiting.await.unwrap().unwrap();
+    assert_eq!(card.token.expose(), TOKEN);
+    client.assign_nfc_card("u1", &card).await.unwrap();
+    client.fetch_nfc_card_user(&card).await.unwrap();
+    client.get_user_by_id("u1").await.unwrap();
+    client.remove_nfc_card(&card).await.unwrap();
+
+    let lines = LINES.lock().unwrap();
+    assert!(
+        lines.iter().any(|line| line.contains("Sending request")),
+        "nothing was captured"
+    );
+    for line in lines.iter() {
+        assert!(!line.contains(TOKEN), "token logged: {line}");
+    }
+    let endpoints = endpoints.0.lock().unwrap();
+    assert!(endpoints.contains(&"/api/v1/developer/credentials/nfc_cards/tokens/{id}".to_string()));
+    for endpoint in endpoints.iter() {
+        assert!(
+            !endpoint.contains(TOKEN),
+            "token in metric label: {endpoint}"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Persisted helper state must stay readable across crate versions, these pin the stored formats.
+//!
+//! The fixtures are the bytes each format version writes. When a format changes, keep the old
+//! fixture and add one for the new version, both must keep loading.
+
+use std::sync::Arc;
+
+use unifi_access::{
+    GrantRegistry, InMemoryStateStore, JsonFileStateStore, MutationJournal, QueuedMutation,
+    StateStore, StoredGrantRegistry, StoredJournal, TemporaryGrant, UnifiError,
+};
+
+/// A grant registry with one grant, as written by format version 1
+const GRANTS_V1: &str = r#"{"grants":[{"id":"u1-1","user_id":"u1","policy_ids":["p1"],"granted_at":1700000000,"expires_at":1700003600}]}"#;
+
+/// A journal with one queued mutation, as written by format version 1
+const JOURNAL_V1: &str = r#"{"pending":[{"operation":"assign_access_policies","method":"PUT","path":"/api/v1/developer/users/u1/access_policies","body":{"access_policy_ids":["p1"]},"queued_at":1700000000}]}"#;
+
+fn versioned(version: u8, json: &str) -> Vec<u8> {
+    let mut bytes = vec![version];
+    bytes.extend_from_slice(json.as_bytes());
+    bytes
+}
+
+fn grant() -> TemporaryGrant {
+    TemporaryGrant {
+        id: "u1-1".to_string(),
+        user_id: "u1".to_string(),
+        policy_ids: vec!["p1".to_string()],
+        granted_at: 1700000000,
+        expires_at: 1700003600,
+    }
+}
+
+#[tokio::test]
+async fn reads_version_1_grants() {
+    let store = Arc::new(InMemoryStateStore::default());
+    store
+        .put("unifi_access/grants", versioned(1, GRANTS_V1))
+        .await
+        .unwrap();
+    let registry = StoredGrantRegistry::new(store);
+    assert_eq!(registry.grants().await.unwrap(), vec![grant()]);
+}
+
+#[tokio::test]
+async fn writes_grants_in_the_current_format() {
+    let store = Arc::new(InMemoryStateStore::default());
+    let registry = StoredGrantRegistry::new(store.clone());
+    registry.record(&grant()).await.unwrap();
+    let stored = store.get("unifi_access/grants").await.unwrap().unwrap();
+    assert_eq!(stored, versioned(1, GRANTS_V1));
+}
+
+#[tokio::test]
+async fn ignores_fields_it_does_not_know() {
+    let store = Arc::new(InMemoryStateStore::default());
+    let with_extra = GRANTS_V1.replacen("{\"grants\"", "{\"added_later\":true,\"grants\"", 1);
+    store
+        .put("unifi_access/grants", versioned(1, &with_extra))
+        .await
+        .unwrap();
+    let registry = StoredGrantRegistry::new(store);
+    assert_eq!(registry.grants().await.unwrap(), vec![grant()]);
+}
+
+#[tokio::test]
+async fn rejects_state_from_a_newer_version() {
+    let store = Arc::new(InMemoryStateStore::default());
+    store
+        .put("unifi_access/grants", versioned(200, GRANTS_V1))
+        .await
+        .unwrap();
+    let registry = StoredGrantRegistry::new(store);
+    match registry.grants().await {
+        Err(UnifiError::UnsupportedStateVersion { key, version }) => {
+            assert_eq!(key, "unifi_access/grants");
+            assert_eq!(version, 200);
+        }
+        other => panic!("expected UnsupportedStateVersion, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn reads_version_1_journal() {
+    let store = Arc::new(InMemoryStateStore::default());
+    store
+        .put("unifi_access/journal", versioned(1, JOURNAL_V1))
+        .await
+        .unwrap();
+    let journal = StoredJournal::new(store.clone());
+    let pending = journal.pending().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].operation, "assign_access_policies");
+    journal.remove(&pending).await.unwrap();
+    assert!(journal.pending().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn removing_replayed_entries_keeps_later_appends() {
+    let mutation = |id: &str| QueuedMutation {
+        id: id.to_string(),
+        operation: "remove_all_access_policies_from_user".to_string(),
+        targets: vec!["u1".to_string()],
+        method: "PUT".to_string(),
+        path: "/api/v1/developer/users/u1/access_policies".to_string(),
+        body: Some(serde_json::json!({"access_policy_ids": []})),
+        queued_at: 1700000000,
+    };
+    let journal = StoredJournal::new(Arc::new(InMemoryStateStore::default()));
+    journal.append(&mutation("a")).await.unwrap();
+    let replayed = journal.pending().await.unwrap();
+    // Queued while the replay was running, identical but for its id
+    journal.append(&mutation("b")).await.unwrap();
+    journal.remove(&replayed).await.unwrap();
+    assert_eq!(journal.pending().await.unwrap(), vec![mutation("b")]);
+}
+
+#[tokio::test]
+async fn json_file_store_survives_reopening() {
+    let path = std::env::temp_dir().join(format!(
+        "unifi_access_state_store_{}.json",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    {
+        let registry = StoredGrantRegistry::new(Arc::new(JsonFileStateStore::new(&path)));
+        registry.record(&grant()).await.unwrap();
+    }
+    let reopened = StoredGrantRegistry::new(Arc::new(JsonFileStateStore::new(&path)));
+    assert_eq!(reopened.grants().await.unwrap(), vec![grant()]);
+    reopened.remove("u1-1").await.unwrap();
+    assert!(reopened.grants().await.unwrap().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}